@@ -0,0 +1,70 @@
+/* C interface to the ray tracer; see src/ffi.rs, which this declares. Link against the
+ * library `cargo build --release` makes (libgraphics.so, .dylib or .dll). Functions
+ * returning int give 0 on success and -1 on failure, with the reason in rt_last_error().
+ * tests/ffi_header.rs checks this against src/ffi.rs, so change the two together. */
+
+#ifndef GRAPHICS_H
+#define GRAPHICS_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* A matte surface, for rt_scene_add_sphere. */
+#define RT_DIFFUSE 0
+/* A perfect mirror. */
+#define RT_MIRROR 1
+/* Clear glass. */
+#define RT_GLASS 2
+
+/* A scene being put together for rendering. */
+typedef struct RtScene RtScene;
+
+/* Why the last failing call on this thread failed, valid until the next one fails. */
+const char *rt_last_error(void);
+
+/* An empty width by height scene, seen from the origin along +y with a 90 degree field
+ * of view, 16 samples per pixel and 8 bounces; NULL if either size is 0. */
+RtScene *rt_scene_new(uint32_t width, uint32_t height);
+
+/* Loads the scene file at path, or gives NULL if it can't be read or parsed. */
+RtScene *rt_scene_load(const char *path);
+
+/* Frees a scene from rt_scene_new or rt_scene_load; NULL is ignored. */
+void rt_scene_free(RtScene *scene);
+
+uint32_t rt_scene_width(const RtScene *scene);
+
+uint32_t rt_scene_height(const RtScene *scene);
+
+/* Puts the camera at (x, y, z) looking along (dx, dy, dz), taking in fov_degrees from
+ * the left of the image to the right. */
+int rt_scene_set_camera(RtScene *scene,
+                        double x, double y, double z,
+                        double dx, double dy, double dz,
+                        double fov_degrees);
+
+/* Sets the samples taken per pixel and the bounces paths are followed for. */
+int rt_scene_set_quality(RtScene *scene, uint16_t samples, uint16_t max_depth);
+
+/* Adds a sphere of radius at (x, y, z) whose surface, one of RT_DIFFUSE, RT_MIRROR or
+ * RT_GLASS, reflects (r, g, b) out of 1 and glows with that color times emission. */
+int rt_scene_add_sphere(RtScene *scene,
+                        double x, double y, double z,
+                        double radius,
+                        double r, double g, double b,
+                        double emission,
+                        uint32_t material);
+
+/* Renders scene into pixels as 8-bit RGB, row by row from the top, which must hold len
+ * bytes, at least three for every pixel. */
+int rt_render(const RtScene *scene, uint8_t *pixels, size_t len);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* GRAPHICS_H */
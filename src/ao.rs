@@ -0,0 +1,60 @@
+use rayon::prelude::*;
+
+use crate::config::Config;
+use crate::film::Film;
+use crate::linalg::{Float, Vector3};
+use crate::profile::Profiler;
+use crate::random::LocalRng;
+use crate::shapes::Ray;
+use crate::trace::{closest_hit, filtered_ray, to_world, Color, RayKind};
+
+/// How an ambient occlusion render looks around each point it sees.
+#[derive(Debug, Clone, Copy)]
+pub struct Occlusion {
+    /// Rays cast from each point seen.
+    pub rays: u32,
+    /// How far an object can be and still shade a point; infinite to count any.
+    pub distance: Float
+}
+
+/// The share of the rays from where `ray` lands, drawn around the normal weighted by
+/// the cosine, that get `occlusion.distance` away without hitting anything. Rays that
+/// see nothing count as unoccluded.
+pub fn visibility(config: &Config, ray: Ray, occlusion: &Occlusion) -> Float {
+    let hit = match closest_hit(&config.objects, ray, RayKind::Camera) {
+        Some((_, hit)) => hit,
+        None => return 1.0
+    };
+    let facing = if hit.front_face { hit.normal } else { -hit.normal };
+    let open = (0..occlusion.rays)
+        .filter(|_| {
+            let dir = to_world(facing, Vector3::rand_hemi());
+            closest_hit(&config.objects, Ray::new(hit.point, dir), RayKind::Shadow).is_none_or(|(_, hit)| hit.t >= occlusion.distance)
+        })
+        .count();
+    open as Float / occlusion.rays.max(1) as Float
+}
+
+/// Renders how open the scene is around what each pixel sees, from black where it is
+/// hemmed in to white, averaging `num_tries` rays per pixel, weighted by the scene's
+/// filter, into one sample.
+pub fn make_ao_image(config: &Config, occlusion: &Occlusion, profiler: &Profiler) -> Film {
+    let mut film = Film::new(config.width, config.height);
+    film.par_rows_mut().for_each(|mut row| {
+        let y = row.y;
+        profiler.span("tile", || format!("row {}", y), || {
+            let mut rng = LocalRng;
+            for x in 0..config.width {
+                let (total, weights) = (0..config.num_tries)
+                    .map(|_| {
+                        let (ray, weight) = filtered_ray(config, x, y, &mut rng);
+                        (visibility(config, ray, occlusion) * weight, weight)
+                    })
+                    .fold((0.0, 0.0), |(total, weights), (value, weight)| (total + value, weights + weight));
+                let open = if weights > 0.0 { total / weights } else { 0.0 };
+                row.add_sample(x, Color::WHITE.scale(open));
+            }
+        });
+    });
+    film
+}
@@ -0,0 +1,114 @@
+use std::path::Path;
+
+use image::{ImageBuffer, Luma};
+use rayon::prelude::*;
+
+use crate::config::{Config, ConfigError, ConfigResult};
+use crate::linalg::{Float, Vector3};
+use crate::shapes::Ray;
+use crate::film::Film;
+use crate::output::{save_image, write_pfm};
+use crate::trace::{closest_hit, primary_ray, project_from, Color, RayKind};
+
+/// Ground truth about what the primary ray through a pixel hits.
+#[derive(Debug, Clone, Copy)]
+pub struct Aov {
+    /// Distance along the ray, infinite on a miss.
+    pub depth: Float,
+    pub normal: Vector3,
+    /// Index into `Config::objects`.
+    pub object: Option<usize>,
+    pub albedo: Color
+}
+
+impl Aov {
+    pub fn miss() -> Aov {
+        Aov {
+            depth: Float::INFINITY,
+            normal: Vector3::new(0.0, 0.0, 0.0),
+            object: None,
+            albedo: Color::BLACK
+        }
+    }
+}
+
+pub fn aov_at(config: &Config, x: u32, y: u32) -> Aov {
+    let ray = primary_ray(config, x, y);
+    match closest_hit(&config.objects, ray, RayKind::Camera) {
+        None => Aov::miss(),
+        Some((index, hit)) => {
+            let obj = &config.objects[index];
+            Aov {
+                depth: hit.t,
+                normal: hit.normal,
+                object: Some(index),
+                albedo: obj.color
+            }
+        }
+    }
+}
+
+pub fn make_aovs(config: &Config) -> Vec<Vec<Aov>> {
+    (0..config.height).into_par_iter().map(|y| {
+        (0..config.width).map(|x| aov_at(config, x, y)).collect()
+    }).collect()
+}
+
+/// How far the point the center of each pixel sees has moved across the image since the
+/// camera was at `previous`, with half the field of view `previous_fov`, in pixels right and then down, with a third value of 0 for
+/// each pixel as PFM files want, row by row from the top. Where nothing is seen, the
+/// background moves as something infinitely far away would; points that were behind the
+/// camera didn't move.
+pub fn motion_vectors(config: &Config, previous: Ray, previous_fov: Float) -> Vec<Float> {
+    (0..config.height).into_par_iter().flat_map_iter(|y| {
+        (0..config.width).flat_map(move |x| {
+            let ray = primary_ray(config, x, y);
+            let point = match closest_hit(&config.objects, ray, RayKind::Camera) {
+                Some((_, hit)) => hit.point,
+                // Infinitely far away, the direction is all that matters.
+                None => previous.pos + ray.dir
+            };
+            let (now_x, now_y) = (x as Float + 0.5, y as Float + 0.5);
+            match project_from(config, previous, previous_fov, point) {
+                Some((then_x, then_y)) => [now_x - then_x, now_y - then_y, 0.0],
+                None => [0.0; 3]
+            }
+        })
+    }).collect()
+}
+
+/// Writes `<prefix>_depth.pfm`, `<prefix>_normal.pfm`, `<prefix>_albedo.png` and
+/// `<prefix>_id.png` (16-bit, object index + 1 with 0 for the background).
+pub fn save_aovs(aovs: &[Vec<Aov>], prefix: &Path) -> ConfigResult<()> {
+    let height = aovs.len() as u32;
+    let width = aovs.first().map_or(0, |row| row.len()) as u32;
+    let with_suffix = |suffix: &str| {
+        let mut name = prefix.as_os_str().to_owned();
+        name.push(suffix);
+        name
+    };
+    let pixels = || aovs.iter().flatten();
+
+    let depth: Vec<Float> = pixels().map(|aov| aov.depth).collect();
+    write_pfm(with_suffix("_depth.pfm").as_ref(), width, height, 1, &depth)?;
+
+    let normal: Vec<Float> = pixels()
+        .flat_map(|aov| [aov.normal.x, aov.normal.y, aov.normal.z])
+        .collect();
+    write_pfm(with_suffix("_normal.pfm").as_ref(), width, height, 3, &normal)?;
+
+    let mut albedo = Film::new(width, height);
+    for (y, row) in aovs.iter().enumerate() {
+        for (x, aov) in row.iter().enumerate() {
+            albedo.add_sample(x as u32, y as u32, aov.albedo);
+        }
+    }
+    save_image(&albedo, 1.0, with_suffix("_albedo.png").as_ref())?;
+
+    let ids = ImageBuffer::from_fn(width, height, |x, y| {
+        let id = aovs[y as usize][x as usize].object.map_or(0, |index| index + 1);
+        Luma([id.min(u16::MAX as usize) as u16])
+    });
+    let path = with_suffix("_id.png");
+    ids.save(&path).map_err(|err| ConfigError::ImageError(path.into(), err))
+}
@@ -0,0 +1,85 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use crate::config::ConfigResult;
+use crate::ies::Ies;
+use crate::mesh::{Accel, Model};
+use crate::texture::Texture;
+
+/// Model files and textures loaded so far, so that every scene parsed with the same
+/// `Assets` reuses them (and their BVHs) instead of loading each one again.
+#[derive(Debug, Default)]
+pub struct Assets {
+    meshes: Mutex<HashMap<(PathBuf, Accel), Arc<Model>>>,
+    opacities: Mutex<HashMap<PathBuf, Arc<Texture>>>,
+    profiles: Mutex<HashMap<PathBuf, Arc<Ies>>>,
+    /// Other files read while loading scenes, which aren't kept here.
+    read: Mutex<Vec<PathBuf>>
+}
+
+impl Assets {
+    pub fn new() -> Self {
+        Assets::default()
+    }
+
+    /// The model in the OBJ, PLY or STL file at `path`, traced with `accel`, loading it on first use.
+    pub fn mesh(&self, path: &Path, accel: Accel) -> ConfigResult<Arc<Model>> {
+        // The same file reached through different relative paths is still the same file.
+        let key = (path.canonicalize().unwrap_or_else(|_| path.to_path_buf()), accel);
+        let mut meshes = self.meshes.lock().unwrap();
+        if let Some(mesh) = meshes.get(&key) {
+            return Ok(Arc::clone(mesh));
+        }
+        let mesh = Arc::new(Model::load(path, accel)?);
+        meshes.insert(key, Arc::clone(&mesh));
+        Ok(mesh)
+    }
+
+    /// Records that a scene read the file at `path`, for `files`.
+    pub fn note(&self, path: &Path) {
+        self.read.lock().unwrap().push(path.canonicalize().unwrap_or_else(|_| path.to_path_buf()));
+    }
+
+    /// Every file loaded so far.
+    pub fn files(&self) -> Vec<PathBuf> {
+        let mut files = vec![];
+        for ((path, _), model) in self.meshes.lock().unwrap().iter() {
+            files.push(path.clone());
+            files.extend(model.libraries.iter().cloned());
+        }
+        files.extend(self.opacities.lock().unwrap().keys().cloned());
+        files.extend(self.profiles.lock().unwrap().keys().cloned());
+        files.extend(self.read.lock().unwrap().iter().cloned());
+        files
+    }
+
+    /// How opaque the image at `path` is, loading it on first use.
+    pub fn opacity(&self, path: &Path) -> ConfigResult<Arc<Texture>> {
+        let key = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        let mut opacities = self.opacities.lock().unwrap();
+        if let Some(texture) = opacities.get(&key) {
+            return Ok(Arc::clone(texture));
+        }
+        let texture = Arc::new(Texture::opacity(path)?);
+        opacities.insert(key, Arc::clone(&texture));
+        Ok(texture)
+    }
+
+    /// The light fixture's profile in the IES file at `path`, loading it on first use.
+    pub fn profile(&self, path: &Path) -> ConfigResult<Arc<Ies>> {
+        let key = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        let mut profiles = self.profiles.lock().unwrap();
+        if let Some(profile) = profiles.get(&key) {
+            return Ok(Arc::clone(profile));
+        }
+        let profile = Arc::new(Ies::load(path)?);
+        profiles.insert(key, Arc::clone(&profile));
+        Ok(profile)
+    }
+
+    /// How many distinct meshes have been loaded.
+    pub fn mesh_count(&self) -> usize {
+        self.meshes.lock().unwrap().len()
+    }
+}
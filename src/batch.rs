@@ -0,0 +1,57 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::assets::Assets;
+use crate::config::{load_config, ConfigError, ConfigResult};
+use crate::output::save_image;
+use crate::profile::Profiler;
+use crate::trace::make_image;
+
+/// One scene to render and where to save it.
+#[derive(Debug, Clone)]
+pub struct Job {
+    pub scene: PathBuf,
+    pub output: PathBuf
+}
+
+/// Reads a batch manifest: one `<scene> <output>` pair per line, with `//` comments
+/// and blank lines skipped. Scenes are relative to the manifest, outputs to `out_dir`.
+pub fn read_manifest(path: &Path, out_dir: &Path) -> ConfigResult<Vec<Job>> {
+    let raw = fs::read_to_string(path).map_err(ConfigError::IOError)?;
+    let dir = path.parent().unwrap_or(Path::new("."));
+    let mut jobs = vec![];
+    for (num, line) in raw.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with("//") {
+            continue;
+        }
+        match line.split_whitespace().collect::<Vec<_>>()[..] {
+            [scene, output] => jobs.push(Job { scene: dir.join(scene), output: out_dir.join(output) }),
+            _ => return Err(ConfigError::InvalidAsset(
+                path.to_path_buf(),
+                format!("line {}: expected `<scene> <output>`", num + 1)
+            ))
+        }
+    }
+    Ok(jobs)
+}
+
+/// Renders `jobs` one after another, loading every mesh they share only once.
+/// `progress` hears about each job before it starts.
+pub fn render_batch(jobs: &[Job], profiler: &Profiler, mut progress: impl FnMut(usize, &Job)) -> ConfigResult<()> {
+    let assets = Assets::new();
+    for (index, job) in jobs.iter().enumerate() {
+        progress(index, job);
+        let config = profiler.span("stage", || format!("parse {}", job.scene.display()), || {
+            load_config(&job.scene, &assets)
+        })?;
+        let result = profiler.span("stage", || format!("render {}", job.scene.display()), || {
+            make_image(&config, profiler)
+        });
+        if let Some(dir) = job.output.parent() {
+            fs::create_dir_all(dir).map_err(ConfigError::IOError)?;
+        }
+        save_image(&result, 1.0, &job.output)?;
+    }
+    Ok(())
+}
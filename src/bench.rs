@@ -0,0 +1,80 @@
+use std::time::{Duration, Instant};
+
+use crate::config::{parse_config, ConfigResult};
+use crate::profile::Profiler;
+use crate::trace::{make_image_with, rays_cast};
+
+/// Every run renders from this seed, so each one does exactly the same work.
+pub const SEED: u64 = 0x5eed;
+
+/// The reference scene: a closed room with diffuse, rough and smooth mirror, glass and
+/// coated spheres, small enough to render in a few seconds. Changing it makes timings
+/// from before the change incomparable.
+pub const SCENE: &str = "\
+0 -9.9 0
+0 1 0
+160 120
+0.785398
+6 8
+0.000785398
+1 4
+yellow 0 opaque sphere 6 8 -7 3
+white 1 opaque sphere 0 -5.5 40 30.5
+white 0 mirror sphere -6 8 -7 3
+white 0 mirror roughness=0.3 sphere -4 14 5 3
+white 0 glass ior=1.5 sphere 0 5 -7 3
+red 0 opaque coat=0.5,0.1 sphere 4 14 5 3
+red 0 opaque plane -10 0 0 1 0 0
+green 0 opaque plane 0 20 0 0 -1 0
+blue 0 opaque plane 10 0 0 -1 0 0
+white 0 opaque plane 0 -10 0 0 1 0
+white 0 opaque plane 0 0 -10 0 0 1
+white 0 opaque plane 0 0 10 0 0 -1
+";
+
+/// Timings of one render of the reference scene.
+#[derive(Debug, Clone, Copy)]
+pub struct BenchRun {
+    pub parse: Duration,
+    pub render: Duration,
+    /// Converting the film to an 8-bit image, as saving it would.
+    pub quantize: Duration,
+    pub rays: u64,
+    pub samples: u64
+}
+
+impl BenchRun {
+    pub fn total(&self) -> Duration {
+        self.parse + self.render + self.quantize
+    }
+
+    pub fn rays_per_sec(&self) -> f64 {
+        self.rays as f64 / self.render.as_secs_f64().max(1e-9)
+    }
+
+    pub fn samples_per_sec(&self) -> f64 {
+        self.samples as f64 / self.render.as_secs_f64().max(1e-9)
+    }
+}
+
+/// Parses, renders and quantizes the reference scene once, timing each stage.
+pub fn bench_run(profiler: &Profiler) -> ConfigResult<BenchRun> {
+    let start = Instant::now();
+    let config = profiler.span("stage", || "parse".to_string(), || parse_config(SCENE))?;
+    let parse = start.elapsed();
+
+    let start = Instant::now();
+    let rays = rays_cast();
+    let film = profiler.span("stage", || "render".to_string(), || {
+        make_image_with(&config, profiler, Some(SEED), |_| ())
+    });
+    let render = start.elapsed();
+    let rays = rays_cast() - rays;
+
+    let start = Instant::now();
+    profiler.span("stage", || "quantize".to_string(), || film.to_rgb8(1.0));
+    let quantize = start.elapsed();
+
+    let samples = config.width as u64 * config.height as u64 * config.num_tries as u64;
+    Ok(BenchRun { parse, render, quantize, rays, samples })
+}
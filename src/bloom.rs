@@ -0,0 +1,86 @@
+use std::str::FromStr;
+
+use crate::film::Film;
+use crate::linalg::Float;
+use crate::trace::Color;
+
+/// A glow spread around whatever is brighter than the image can show, like light
+/// scattering in a lens or an eye.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Bloom {
+    /// Brightness, where 255 is full white, above which light spreads.
+    pub threshold: Float,
+    /// Standard deviation of the spread, in pixels.
+    pub radius: Float,
+    /// How much of the spread light is added back.
+    pub strength: Float
+}
+
+impl FromStr for Bloom {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || format!("Expected bloom as THRESHOLD,RADIUS,STRENGTH but got {:?}", s);
+        let values: Vec<Float> = s.split(',')
+            .map(|value| value.trim().parse().map_err(|_| err()))
+            .collect::<Result<_, _>>()?;
+        match values[..] {
+            [threshold, radius, strength] if radius >= 0.0 && strength >= 0.0 => Ok(Bloom { threshold, radius, strength }),
+            _ => Err(err())
+        }
+    }
+}
+
+/// Weights of a Gaussian with standard deviation `sigma`, out to three of them either side.
+fn kernel(sigma: Float) -> Vec<Float> {
+    let reach = (3.0 * sigma).ceil() as i64;
+    let weights: Vec<Float> = (-reach..=reach)
+        .map(|i| (-(i * i) as Float / (2.0 * sigma * sigma)).exp())
+        .collect();
+    let total: Float = weights.iter().sum();
+    weights.into_iter().map(|weight| weight / total).collect()
+}
+
+/// Blurs a `width` by `height` image along its rows, or down its columns; light blurred
+/// past the edge is lost.
+fn blur_along(pixels: &[Color], width: usize, height: usize, weights: &[Float], horizontal: bool) -> Vec<Color> {
+    let reach = (weights.len() / 2) as i64;
+    let mut out = vec![Color::BLACK; pixels.len()];
+    for y in 0..height {
+        for x in 0..width {
+            let mut sum = Color::BLACK;
+            for (k, weight) in weights.iter().enumerate() {
+                let offset = k as i64 - reach;
+                let (nx, ny) = if horizontal { (x as i64 + offset, y as i64) } else { (x as i64, y as i64 + offset) };
+                if (0..width as i64).contains(&nx) && (0..height as i64).contains(&ny) {
+                    sum += pixels[ny as usize * width + nx as usize].scale(*weight);
+                }
+            }
+            out[y * width + x] = sum;
+        }
+    }
+    out
+}
+
+/// The pixels of `film` multiplied by `scale`, with `bloom` added around the bright ones,
+/// as a film of one sample per pixel to be saved unscaled.
+pub fn apply_bloom(film: &Film, scale: Float, bloom: &Bloom) -> Film {
+    let (width, height) = (film.width() as usize, film.height() as usize);
+    let pixels: Vec<Color> = film.pixels().into_iter().map(|pixel| pixel.scale(scale)).collect();
+    let excess = |value: Float| (value - bloom.threshold).max(0.0);
+    let bright: Vec<Color> = pixels.iter().map(|p| Color::new(excess(p.x), excess(p.y), excess(p.z))).collect();
+
+    let spread = if bloom.radius > 0.0 {
+        let weights = kernel(bloom.radius);
+        let rows = blur_along(&bright, width, height, &weights, true);
+        blur_along(&rows, width, height, &weights, false)
+    } else {
+        bright
+    };
+
+    let mut out = Film::new(film.width(), film.height());
+    for (i, (pixel, glow)) in pixels.iter().zip(&spread).enumerate() {
+        out.add_sample((i % width) as u32, (i / width) as u32, *pixel + glow.scale(bloom.strength));
+    }
+    out
+}
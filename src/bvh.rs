@@ -0,0 +1,284 @@
+use rayon::prelude::*;
+
+use crate::linalg::{Aabb, Float, Vector3};
+use crate::shapes::Ray;
+use crate::simd::LANES;
+use crate::stats;
+
+/// Primitives per leaf before a node is worth splitting; a full leaf fills one triangle packet.
+const LEAF_SIZE: usize = LANES;
+
+/// Distance along a ray at which it enters `bounds`, if it does before `t_max`.
+fn entry(bounds: &Aabb, origin: &[Float; 3], inv_dir: &[Float; 3], t_max: Float) -> Option<Float> {
+    bounds.span(origin, inv_dir, t_max).map(|(near, _)| near)
+}
+
+#[derive(Debug)]
+pub(crate) enum Node {
+    /// Primitives `order[start..end]`, in the `leaf`th leaf.
+    Leaf { bounds: Aabb, start: usize, end: usize, leaf: usize },
+    Inner { bounds: Aabb, left: usize, right: usize }
+}
+
+impl Node {
+    pub(crate) fn bounds(&self) -> &Aabb {
+        match self {
+            Node::Leaf { bounds, .. } | Node::Inner { bounds, .. } => bounds
+        }
+    }
+}
+
+/// A bounding volume hierarchy over primitives known only by their boxes.
+#[derive(Debug)]
+pub struct Bvh {
+    nodes: Vec<Node>,
+    /// Primitive indices, grouped so that every leaf owns a contiguous run.
+    order: Vec<usize>
+}
+
+pub(crate) fn to_array(v: Vector3) -> [Float; 3] {
+    [v.x, v.y, v.z]
+}
+
+/// Nodes with fewer primitives than this are built on the current thread.
+const PARALLEL_THRESHOLD: usize = 4096;
+/// Buckets of centroids tried as split positions along the chosen axis.
+const BINS: usize = 16;
+
+/// Reorders `order` so that a split's two halves are `order[..mid]` and `order[mid..]`,
+/// and returns `mid`, or `None` if the primitives are better left in one leaf.
+fn partition(bounds: &[Aabb], order: &mut [usize]) -> Option<usize> {
+    if order.len() <= LEAF_SIZE {
+        return None;
+    }
+    let centroids = order.iter()
+        .fold(Aabb::empty(), |acc, &i| {
+            let c = [0, 1, 2].map(|axis| bounds[i].center(axis));
+            acc.union(&Aabb { min: c, max: c })
+        });
+    let axis = (0..3)
+        .max_by(|&a, &b| {
+            let extent = |axis: usize| centroids.max[axis] - centroids.min[axis];
+            extent(a).total_cmp(&extent(b))
+        })
+        .unwrap();
+    let (lo, hi) = (centroids.min[axis], centroids.max[axis]);
+    if hi - lo <= 0.0 {
+        // Centroids in one spot can't be told apart by position; halve them instead.
+        return Some(order.len() / 2);
+    }
+
+    let bin = |i: usize| (((bounds[i].center(axis) - lo) / (hi - lo) * BINS as Float) as usize).min(BINS - 1);
+    let mut bins = [(0usize, Aabb::empty()); BINS];
+    for &i in order.iter() {
+        let (count, bin_bounds) = &mut bins[bin(i)];
+        *count += 1;
+        *bin_bounds = bin_bounds.union(&bounds[i]);
+    }
+    // Costs of splitting after each bin, from sweeps in both directions.
+    let mut left_costs = [0.0; BINS - 1];
+    let (mut count, mut acc) = (0, Aabb::empty());
+    for (k, cost) in left_costs.iter_mut().enumerate() {
+        count += bins[k].0;
+        acc = acc.union(&bins[k].1);
+        *cost = count as Float * acc.surface_area();
+    }
+    let (mut count, mut acc) = (0, Aabb::empty());
+    let mut best: Option<(Float, usize)> = None;
+    for k in (1..BINS).rev() {
+        count += bins[k].0;
+        acc = acc.union(&bins[k].1);
+        let cost = left_costs[k - 1] + count as Float * acc.surface_area();
+        let left_count = order.len() - count;
+        if left_count > 0 && count > 0 && best.is_none_or(|(best_cost, _)| cost < best_cost) {
+            best = Some((cost, k));
+        }
+    }
+
+    let (_, split_bin) = best?;
+    let mut mid = 0;
+    for j in 0..order.len() {
+        if bin(order[j]) < split_bin {
+            order.swap(j, mid);
+            mid += 1;
+        }
+    }
+    Some(mid)
+}
+
+/// Builds the nodes over `order`, which starts at `start` in the whole ordering, with
+/// the subtree's root first and child indices counted from it.
+fn build_subtree(bounds: &[Aabb], order: &mut [usize], start: usize) -> Vec<Node> {
+    let mut nodes = vec![];
+    if order.len() < PARALLEL_THRESHOLD {
+        build_into(bounds, order, start, &mut nodes);
+        return nodes;
+    }
+
+    let node_bounds = order.par_iter().fold(Aabb::empty, |acc, &i| acc.union(&bounds[i]))
+        .reduce(Aabb::empty, |a, b| a.union(&b));
+    let mid = match partition(bounds, order) {
+        Some(mid) => mid,
+        None => return vec![Node::Leaf { bounds: node_bounds, start, end: start + order.len(), leaf: 0 }]
+    };
+    let (left_order, right_order) = order.split_at_mut(mid);
+    let (left, right) = rayon::join(
+        || build_subtree(bounds, left_order, start),
+        || build_subtree(bounds, right_order, start + mid)
+    );
+
+    // Each half numbered its nodes from its own root; move them past what comes before.
+    let shift = |nodes: Vec<Node>, by: usize| nodes.into_iter().map(move |node| match node {
+        Node::Inner { bounds, left, right } => Node::Inner { bounds, left: left + by, right: right + by },
+        leaf => leaf
+    });
+    let right_root = 1 + left.len();
+    nodes.reserve(right_root + right.len());
+    nodes.push(Node::Inner { bounds: node_bounds, left: 1, right: right_root });
+    nodes.extend(shift(left, 1));
+    nodes.extend(shift(right, right_root));
+    nodes
+}
+
+/// Adds the nodes over `order` (starting at `start` in the whole ordering) to `nodes`
+/// and returns the index of their root.
+fn build_into(bounds: &[Aabb], order: &mut [usize], start: usize, nodes: &mut Vec<Node>) -> usize {
+    let node_bounds = order.iter().fold(Aabb::empty(), |acc, &i| acc.union(&bounds[i]));
+    let index = nodes.len();
+    let mid = match partition(bounds, order) {
+        Some(mid) => mid,
+        None => {
+            nodes.push(Node::Leaf { bounds: node_bounds, start, end: start + order.len(), leaf: 0 });
+            return index;
+        }
+    };
+
+    // Reserve this node's slot; its children land after it.
+    nodes.push(Node::Leaf { bounds: node_bounds, start, end: start + order.len(), leaf: 0 });
+    let (left_order, right_order) = order.split_at_mut(mid);
+    let left = build_into(bounds, left_order, start, nodes);
+    let right = build_into(bounds, right_order, start + mid, nodes);
+    nodes[index] = Node::Inner { bounds: node_bounds, left, right };
+    index
+}
+
+impl Bvh {
+    /// Builds the hierarchy with binned SAH splits, building large subtrees on separate
+    /// threads. `bounds` holds each primitive's box.
+    pub fn build(bounds: &[Aabb]) -> Bvh {
+        let mut order: Vec<usize> = (0..bounds.len()).collect();
+        let mut nodes = if bounds.is_empty() { vec![] } else { build_subtree(bounds, &mut order, 0) };
+        // Subtrees were built apart, so leaves are only numbered once they're together.
+        let leaves = nodes.iter_mut().filter_map(|node| match node {
+            Node::Leaf { leaf, .. } => Some(leaf),
+            Node::Inner { .. } => None
+        });
+        for (number, leaf) in leaves.enumerate() {
+            *leaf = number;
+        }
+        Bvh { nodes, order }
+    }
+
+    /// The box holding every primitive, or `None` if there are none.
+    pub fn bounds(&self) -> Option<Aabb> {
+        self.nodes.first().map(|root| *root.bounds())
+    }
+
+    /// The nodes, root first, for walking the tree on the GPU.
+    #[cfg(feature = "gpu")]
+    pub(crate) fn nodes(&self) -> &[Node] {
+        &self.nodes
+    }
+
+    /// Primitive indices, of which each leaf owns `order[start..end]`.
+    #[cfg(feature = "gpu")]
+    pub(crate) fn order(&self) -> &[usize] {
+        &self.order
+    }
+
+    /// Bytes the hierarchy takes up, not counting the primitives.
+    pub fn memory(&self) -> usize {
+        std::mem::size_of_val(&self.nodes[..]) + std::mem::size_of_val(&self.order[..])
+    }
+
+    /// The primitives of each leaf, in the order the leaves are numbered.
+    pub fn leaves(&self) -> Vec<&[usize]> {
+        self.nodes.iter()
+            .filter_map(|node| match node {
+                Node::Leaf { start, end, .. } => Some(&self.order[*start..*end]),
+                Node::Inner { .. } => None
+            })
+            .collect()
+    }
+
+    /// The closest primitive along `ray` and its distance, where `hit` intersects a single one.
+    pub fn closest(&self, ray: Ray, hit: impl Fn(usize) -> Option<Float>) -> Option<(usize, Float)> {
+        self.closest_leaf(ray, |_, items| {
+            items.iter()
+                .filter_map(|&i| hit(i).map(|t| (i, t)))
+                .min_by(|(_, a), (_, b)| a.total_cmp(b))
+        })
+    }
+
+    /// Like `closest`, but with `hit` finding the closest of the primitives in a whole leaf,
+    /// given its number (see `leaves`) and contents.
+    pub fn closest_leaf(&self, ray: Ray, hit: impl Fn(usize, &[usize]) -> Option<(usize, Float)>) -> Option<(usize, Float)> {
+        let origin = to_array(ray.pos);
+        let inv_dir = [1.0 / ray.dir.x, 1.0 / ray.dir.y, 1.0 / ray.dir.z];
+
+        let mut best: Option<(usize, Float)> = None;
+        let mut visited = 0;
+        let mut stack = vec![];
+        if !self.nodes.is_empty() {
+            stack.push(0);
+        }
+        while let Some(index) = stack.pop() {
+            visited += 1;
+            let t_max = best.map_or(Float::INFINITY, |(_, t)| t);
+            let node = &self.nodes[index];
+            if entry(node.bounds(), &origin, &inv_dir, t_max).is_none() {
+                continue;
+            }
+            match node {
+                Node::Leaf { start, end, leaf, .. } => {
+                    match (hit(*leaf, &self.order[*start..*end]), best) {
+                        (Some((_, t)), Some((_, best_t))) if t >= best_t => (),
+                        (Some(found), _) => best = Some(found),
+                        (None, _) => ()
+                    }
+                }
+                Node::Inner { left, right, .. } => {
+                    // Visit the nearer child first so the farther one is more often culled.
+                    let entry = |child: usize| entry(self.nodes[child].bounds(), &origin, &inv_dir, t_max);
+                    let (near, far) = match (entry(*left), entry(*right)) {
+                        (Some(l), Some(r)) if r < l => (*right, *left),
+                        _ => (*left, *right)
+                    };
+                    stack.push(far);
+                    stack.push(near);
+                }
+            }
+        }
+        stats::record(|counters| counters.nodes += visited);
+        best
+    }
+
+    /// Calls `visit` on every primitive whose box, grown by `eps`, contains `point`.
+    pub fn containing(&self, point: Vector3, eps: Float, mut visit: impl FnMut(usize)) {
+        let point = to_array(point);
+        let mut stack = vec![];
+        if !self.nodes.is_empty() {
+            stack.push(0);
+        }
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            if !node.bounds().contains(&point, eps) {
+                continue;
+            }
+            match node {
+                Node::Leaf { start, end, .. } => self.order[*start..*end].iter().for_each(|&i| visit(i)),
+                Node::Inner { left, right, .. } => stack.extend([*left, *right])
+            }
+        }
+    }
+}
@@ -0,0 +1,777 @@
+use std::convert::TryInto;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::UNIX_EPOCH;
+
+use crate::assets::Assets;
+use crate::camera::{FocusKey, Lens};
+use crate::config::{load_config, Config, ConfigError, ConfigResult, Warning};
+use crate::filter::Filter;
+use crate::guiding::PathGuide;
+use crate::heightfield::{Heightfield, Source};
+use crate::ies::Ies;
+use crate::irradiance::IrradianceCache;
+use crate::light::Light;
+use crate::linalg::{Float, Vector3};
+use crate::mesh::{Accel, Face, Mesh, MeshData, Vertices};
+use crate::sampler::Sampler;
+use crate::sdf::Sdf;
+use crate::shapes::{Capsule, Ellipsoid, Plane, Ray, Shape, Sphere, Triangle};
+use crate::sky::Sky;
+use crate::texture::{Cutout, Texture};
+use crate::trace::{Backface, Cauchy, Coat, Color, Glass, Material, Medium, Object, Principled, ThinGlass, Visibility};
+use crate::transform::{Transform, Transformed};
+
+const MAGIC: &[u8; 4] = b"RTSC";
+/// Bump whenever the layout below changes, so stale caches are ignored.
+const VERSION: u32 = 26;
+/// Numbers are stored at the precision the tracer was built with.
+const FLOAT_SIZE: u8 = std::mem::size_of::<Float>() as u8;
+
+/// Where the cache for the scene at `path` lives: next to it, with `.cache` appended.
+pub fn cache_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".cache");
+    PathBuf::from(name)
+}
+
+/// 64-bit FNV-1a, which unlike std's hashers gives the same value across builds and runs.
+pub fn source_hash(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3))
+}
+
+/// The size and modification time of the file at `path`, which change when it is rewritten.
+fn stamp(path: &Path) -> Option<(u64, u64)> {
+    let metadata = fs::metadata(path).ok()?;
+    let modified = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+    Some((metadata.len(), modified.as_nanos() as u64))
+}
+
+/// Loads the scene at `path`, from its binary cache when that was written for
+/// exactly the same source and the files it loads are unchanged, and otherwise
+/// by parsing it and refreshing the cache.
+/// Failing to write the cache is not an error; the next run just parses again.
+pub fn load_cached(path: &Path) -> ConfigResult<Config> {
+    let raw = fs::read(path).map_err(|err| ConfigError::InFile(path.to_path_buf(), Box::new(ConfigError::IOError(err))))?;
+    let hash = source_hash(&raw);
+    let cache = cache_path(path);
+
+    if let Some(config) = fs::read(&cache).ok().and_then(|bytes| decode(&bytes, hash)) {
+        return Ok(config);
+    }
+    let assets = Assets::new();
+    let config = load_config(path, &assets)?;
+    if let Some(bytes) = encode(&config, hash, &assets.files()) {
+        let _ = fs::write(&cache, bytes);
+    }
+    Ok(config)
+}
+
+/// Serializes `config`, loaded from `files` besides its source, or gives `None`
+/// if one of its shapes can't be cached.
+pub fn encode(config: &Config, hash: u64, files: &[PathBuf]) -> Option<Vec<u8>> {
+    let mut out = Writer(MAGIC.to_vec());
+    out.u32(VERSION);
+    out.u8(FLOAT_SIZE);
+    out.u64(hash);
+    out.u32(files.len() as u32);
+    for file in files {
+        let (len, modified) = stamp(file)?;
+        out.str(file.to_str()?);
+        out.u64(len);
+        out.u64(modified);
+    }
+
+    out.vec(config.pov.pos);
+    out.vec(config.pov.dir);
+    out.u32(config.width);
+    out.u32(config.height);
+    out.float(config.fov);
+    match config.lens {
+        Some(Lens { radius, focus, blades, rotation }) => {
+            out.u8(1);
+            out.float(radius);
+            out.float(focus);
+            out.u32(blades);
+            out.float(rotation);
+        }
+        None => out.u8(0)
+    }
+    out.u32(config.focus_keys.len() as u32);
+    for key in &config.focus_keys {
+        out.u32(key.frame);
+        out.float(key.focal_length);
+        out.float(key.focus);
+    }
+    out.vec(config.up);
+    out.float(config.roll);
+    out.u16(config.max_depth);
+    out.u16(config.num_tries);
+    out.u8(config.filter as u8);
+    out.u8(config.sampler as u8);
+    out.u8(config.spectral as u8);
+    out.u16(config.caustic_split);
+    match &config.irradiance_cache {
+        Some(cache) => {
+            out.u8(1);
+            out.float(cache.accuracy);
+            out.u32(cache.rays);
+            out.float(cache.min_spacing);
+            out.float(cache.max_spacing);
+        }
+        None => out.u8(0)
+    }
+    match &config.path_guide {
+        Some(guide) => {
+            out.u8(1);
+            out.u32(guide.passes);
+        }
+        None => out.u8(0)
+    }
+    out.medium(config.fog);
+    out.u8(config.accel as u8);
+    match config.sky {
+        Some(sky) => {
+            out.u8(1);
+            out.vec(sky.sun);
+            out.float(sky.turbidity);
+            out.float(sky.strength);
+        }
+        None => out.u8(0)
+    }
+    out.u32(config.lights.len() as u32);
+    for light in &config.lights {
+        out.light(light)?;
+    }
+
+    out.u32(config.objects.len() as u32);
+    let (mut meshes, mut textures) = (vec![], vec![]);
+    for obj in &config.objects {
+        out.shape(&*obj.shape, &mut meshes)?;
+        out.vec(obj.color);
+        out.vec(obj.lum);
+        out.material(&obj.material);
+        out.u8(obj.backface as u8);
+        match &obj.cutout {
+            Some(cutout) => {
+                out.u8(1);
+                out.texture(&cutout.texture, &mut textures)?;
+                out.float(cutout.threshold);
+            }
+            None => out.u8(0)
+        }
+        let Visibility { camera, shadows, reflections } = obj.visibility;
+        [camera, shadows, reflections].iter().for_each(|&seen| out.u8(seen as u8));
+        out.u64(obj.line as u64);
+    }
+
+    out.u32(config.warnings.len() as u32);
+    for warning in &config.warnings {
+        out.u64(warning.line as u64);
+        out.str(&warning.message);
+    }
+    Some(out.0)
+}
+
+/// Deserializes a cache written by `encode`, or gives `None` if it is corrupt,
+/// from another version or precision, for a source other than the one hashing to
+/// `hash`, or if a file the scene loads has changed since.
+pub fn decode(bytes: &[u8], hash: u64) -> Option<Config> {
+    let mut input = Reader(bytes);
+    if input.take(4)? != MAGIC || input.u32()? != VERSION || input.u8()? != FLOAT_SIZE || input.u64()? != hash {
+        return None;
+    }
+    for _ in 0..input.u32()? {
+        let file = PathBuf::from(input.str()?);
+        if stamp(&file)? != (input.u64()?, input.u64()?) {
+            return None;
+        }
+    }
+
+    let pov = Ray { pos: input.vec()?, dir: input.vec()? };
+    let width = input.u32()?;
+    let height = input.u32()?;
+    let fov = input.float()?;
+    let lens = match input.flag()? {
+        true => Some(Lens { radius: input.float()?, focus: input.float()?, blades: input.u32()?, rotation: input.float()? }),
+        false => None
+    };
+    let focus_keys = (0..input.u32()?)
+        .map(|_| Some(FocusKey { frame: input.u32()?, focal_length: input.float()?, focus: input.float()? }))
+        .collect::<Option<Vec<_>>>()?;
+    let up = input.vec()?;
+    let roll = input.float()?;
+    let max_depth = input.u16()?;
+    let num_tries = input.u16()?;
+    let filter = match input.u8()? {
+        0 => Filter::Box,
+        1 => Filter::Tent,
+        2 => Filter::Gaussian,
+        3 => Filter::Mitchell,
+        _ => return None
+    };
+    let sampler = match input.u8()? {
+        0 => Sampler::Random,
+        1 => Sampler::BlueNoise,
+        2 => Sampler::Cmj,
+        _ => return None
+    };
+    let spectral = input.flag()?;
+    let caustic_split = input.u16()?;
+    let irradiance_cache = match input.flag()? {
+        true => Some(IrradianceCache::new(input.float()?, input.u32()?, input.float()?, input.float()?)),
+        false => None
+    };
+    let path_guide = match input.flag()? {
+        true => Some(PathGuide::new(input.u32()?)),
+        false => None
+    };
+    let fog = input.medium()?;
+    let accel = match input.u8()? {
+        0 => Accel::Bvh,
+        1 => Accel::KdTree,
+        _ => return None
+    };
+    let sky = match input.flag()? {
+        true => Some(Sky::new(input.vec()?, input.float()?, input.float()?)),
+        false => None
+    };
+    let lights = (0..input.u32()?).map(|_| input.light()).collect::<Option<Vec<_>>>()?;
+
+    let count = input.u32()?;
+    let mut objects = vec![];
+    let (mut meshes, mut textures) = (vec![], vec![]);
+    for _ in 0..count {
+        let shape = input.shape(&mut meshes, accel)?;
+        let color = input.vec()?;
+        let lum = input.vec()?;
+        let material = input.material()?;
+        let backface = match input.u8()? {
+            0 => Backface::Show,
+            1 => Backface::Cull,
+            2 => Backface::Flip,
+            _ => return None
+        };
+        let cutout = match input.flag()? {
+            true => Some(Cutout { texture: input.texture(&mut textures)?, threshold: input.float()? }),
+            false => None
+        };
+        let visibility = Visibility { camera: input.flag()?, shadows: input.flag()?, reflections: input.flag()? };
+        let line = input.u64()? as usize;
+        objects.push(Object { shape, color, lum, material, backface, cutout, visibility, line });
+    }
+    let warnings = (0..input.u32()?)
+        .map(|_| Some(Warning { line: input.u64()? as usize, message: input.str()? }))
+        .collect::<Option<Vec<_>>>()?;
+
+    if !input.0.is_empty() {
+        return None;
+    }
+    Some(Config {
+        objects, pov, width, height, fov, lens, focus_keys, up, roll, max_depth, num_tries, filter, sampler, spectral,
+        caustic_split, irradiance_cache, path_guide, fog, sky, lights, light_tree: Default::default(),
+        glowing: Default::default(), accel, warnings
+    })
+}
+
+fn shape(name: &str, params: &[Float]) -> Option<Box<dyn Shape>> {
+    let v = |i: usize| Vector3::new(params[i], params[i + 1], params[i + 2]);
+    Some(match (name, params.len()) {
+        ("sphere", 4) => Box::new(Sphere { center: v(0), radius: params[3] }),
+        ("plane", 6) => Box::new(Plane { point: v(0), norm: v(3) }),
+        ("triangle", 9) => Box::new(Triangle::new(v(0), v(3), v(6))),
+        ("capsule", 7) => Box::new(Capsule { a: v(0), b: v(3), radius: params[6] }),
+        ("ellipsoid", 6) => Box::new(Ellipsoid { center: v(0), radii: v(3) }),
+        ("sdf", _) => Box::new(Sdf::from_params(params)?),
+        _ => return None
+    })
+}
+
+struct Writer(Vec<u8>);
+
+impl Writer {
+    fn u8(&mut self, value: u8) {
+        self.0.push(value);
+    }
+
+    fn u16(&mut self, value: u16) {
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn u64(&mut self, value: u64) {
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn float(&mut self, value: Float) {
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn vec(&mut self, value: Vector3) {
+        self.float(value.x);
+        self.float(value.y);
+        self.float(value.z);
+    }
+
+    fn coords(&mut self, value: [Float; 3]) {
+        value.iter().for_each(|&x| self.float(x));
+    }
+
+    fn str(&mut self, value: &str) {
+        self.u32(value.len() as u32);
+        self.0.extend_from_slice(value.as_bytes());
+    }
+
+    /// Writes a mesh's faces only where it is first used, and its index in `meshes`
+    /// wherever else, so objects sharing one still share it once loaded. The parts of
+    /// a model likewise write their vertices once, with the first part's.
+    fn shape<'a>(&mut self, shape: &'a dyn Shape, meshes: &mut Vec<&'a Arc<MeshData>>) -> Option<()> {
+        if let Some((name, params)) = shape.params() {
+            self.u8(0);
+            self.str(name);
+            self.u32(params.len() as u32);
+            params.iter().for_each(|&param| self.float(param));
+            return Some(());
+        }
+        if let Some(placed) = shape.as_transformed() {
+            self.u8(3);
+            self.vec(placed.transform.translate);
+            self.vec(placed.transform.rotate);
+            self.vec(placed.transform.scale);
+            return self.shape(&*placed.shape, meshes);
+        }
+        if let Some(field) = shape.as_heightfield() {
+            self.u8(4);
+            match &field.source {
+                Source::Image(file) => {
+                    self.u8(0);
+                    self.str(file.to_str()?);
+                }
+                Source::Noise { seed, cells } => {
+                    self.u8(1);
+                    self.u64(*seed);
+                    self.u32(*cells);
+                }
+            }
+            self.vec(field.origin);
+            self.vec(field.size);
+            let (columns, rows) = field.samples();
+            self.u32(columns as u32);
+            self.u32(rows as u32);
+            field.heights().iter().for_each(|&height| self.float(height));
+            return Some(());
+        }
+        let mesh = shape.as_mesh()?;
+        match meshes.iter().position(|&data| Arc::ptr_eq(data, &mesh.data)) {
+            Some(index) => {
+                self.u8(2);
+                self.u32(index as u32);
+            }
+            None => {
+                self.u8(1);
+                let data = &mesh.data;
+                match meshes.iter().position(|other| Arc::ptr_eq(other.vertices(), data.vertices())) {
+                    Some(index) => {
+                        self.u8(1);
+                        self.u32(index as u32);
+                    }
+                    None => {
+                        let Vertices { positions, normals, uvs } = &**data.vertices();
+                        self.u8(0);
+                        self.u32(positions.len() as u32);
+                        positions.iter().for_each(|&position| self.coords(position));
+                        self.u32(normals.len() as u32);
+                        normals.iter().for_each(|&normal| self.coords(normal));
+                        self.u32(uvs.len() as u32);
+                        for &(u, v) in uvs {
+                            self.float(u);
+                            self.float(v);
+                        }
+                    }
+                }
+                self.u32(data.len() as u32);
+                for face in data.faces() {
+                    face.positions.iter().for_each(|&i| self.u32(i));
+                    for indices in [face.normals, face.uvs] {
+                        match indices {
+                            Some(indices) => {
+                                self.u8(1);
+                                indices.iter().for_each(|&i| self.u32(i));
+                            }
+                            None => self.u8(0)
+                        }
+                    }
+                }
+                meshes.push(&mesh.data);
+            }
+        }
+        self.vec(mesh.offset);
+        self.float(mesh.scale);
+        match &mesh.file {
+            Some(file) => {
+                self.u8(1);
+                self.str(file.to_str()?);
+            }
+            None => self.u8(0)
+        }
+        Some(())
+    }
+
+    fn medium(&mut self, medium: Option<Medium>) {
+        match medium {
+            Some(Medium { scattering, absorption, g }) => {
+                self.u8(1);
+                self.float(scattering);
+                self.float(absorption);
+                self.float(g);
+            }
+            None => self.u8(0)
+        }
+    }
+
+    /// Like meshes, writes a texture's values only where it is first used.
+    fn texture<'a>(&mut self, texture: &'a Arc<Texture>, textures: &mut Vec<&'a Arc<Texture>>) -> Option<()> {
+        if let Some(index) = textures.iter().position(|&known| Arc::ptr_eq(known, texture)) {
+            self.u8(1);
+            self.u32(index as u32);
+            return Some(());
+        }
+        self.u8(0);
+        self.str(texture.file.to_str()?);
+        let (width, height) = texture.size();
+        self.u32(width as u32);
+        self.u32(height as u32);
+        texture.values().iter().for_each(|&value| self.float(value));
+        textures.push(texture);
+        Some(())
+    }
+
+    fn light(&mut self, light: &Light) -> Option<()> {
+        match *light {
+            Light::Directional { dir, radius, irradiance } => {
+                self.u8(0);
+                self.vec(dir);
+                self.float(radius);
+                self.vec(irradiance);
+            }
+            Light::Spot { pos, dir, inner, outer, intensity, ref profile } => {
+                self.u8(1);
+                self.vec(pos);
+                self.vec(dir);
+                self.float(inner);
+                self.float(outer);
+                self.vec(intensity);
+                match profile {
+                    Some(profile) => {
+                        self.u8(1);
+                        self.str(profile.file.to_str()?);
+                        let (vertical, horizontal) = profile.angles();
+                        for angles in [vertical, horizontal] {
+                            self.u32(angles.len() as u32);
+                            angles.iter().for_each(|&angle| self.float(angle));
+                        }
+                        profile.values().iter().for_each(|&value| self.float(value));
+                    }
+                    None => self.u8(0)
+                }
+            }
+        }
+        Some(())
+    }
+
+    fn material(&mut self, material: &Material) {
+        match material {
+            Material::Mirror(roughness) => {
+                self.u8(0);
+                self.float(*roughness);
+            }
+            Material::Translucent(clearness, glass) => {
+                self.u8(1);
+                self.float(*clearness);
+                self.float(glass.ior);
+                match glass.cauchy {
+                    Some(Cauchy { a, b }) => { self.u8(1); self.float(a); self.float(b); }
+                    None => self.u8(0)
+                }
+                match glass.absorption {
+                    Some(sigma) => { self.u8(1); self.vec(sigma); }
+                    None => self.u8(0)
+                }
+                self.medium(glass.medium);
+                self.tint(glass.tint);
+            }
+            Material::Coated(coat, base) => {
+                self.u8(2);
+                self.float(coat.strength);
+                self.float(coat.roughness);
+                self.material(base);
+            }
+            Material::Thin(thin) => {
+                self.u8(3);
+                self.float(thin.ior);
+                match thin.film {
+                    Some(thickness) => { self.u8(1); self.float(thickness); }
+                    None => self.u8(0)
+                }
+                self.tint(thin.tint);
+            }
+            Material::Principled(principled) => {
+                self.u8(4);
+                self.float(principled.metallic);
+                self.float(principled.roughness);
+                self.float(principled.specular);
+                self.float(principled.transmission);
+                self.float(principled.ior);
+            }
+            Material::Catcher(reflection) => {
+                self.u8(5);
+                self.float(*reflection);
+            }
+        }
+    }
+
+    fn tint(&mut self, tint: Option<Color>) {
+        match tint {
+            Some(tint) => { self.u8(1); self.vec(tint); }
+            None => self.u8(0)
+        }
+    }
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        if self.0.len() < len {
+            return None;
+        }
+        let (head, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Some(head)
+    }
+
+    fn array<const N: usize>(&mut self) -> Option<[u8; N]> {
+        self.take(N)?.try_into().ok()
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.array().map(u8::from_le_bytes)
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        self.array().map(u16::from_le_bytes)
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        self.array().map(u32::from_le_bytes)
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        self.array().map(u64::from_le_bytes)
+    }
+
+    fn float(&mut self) -> Option<Float> {
+        self.array().map(Float::from_le_bytes)
+    }
+
+    fn vec(&mut self) -> Option<Color> {
+        Some(Vector3::new(self.float()?, self.float()?, self.float()?))
+    }
+
+    fn coords(&mut self) -> Option<[Float; 3]> {
+        Some([self.float()?, self.float()?, self.float()?])
+    }
+
+    /// Three indices into a list `len` long, or `None` if any is past its end.
+    fn indices(&mut self, len: usize) -> Option<[u32; 3]> {
+        let indices = [self.u32()?, self.u32()?, self.u32()?];
+        indices.iter().all(|&i| (i as usize) < len).then_some(indices)
+    }
+
+    fn str(&mut self) -> Option<String> {
+        let len = self.u32()? as usize;
+        String::from_utf8(self.take(len)?.to_vec()).ok()
+    }
+
+    fn shape(&mut self, meshes: &mut Vec<Arc<MeshData>>, accel: Accel) -> Option<Box<dyn Shape>> {
+        let data = match self.u8()? {
+            0 => {
+                let name = self.str()?;
+                let params = (0..self.u32()?).map(|_| self.float()).collect::<Option<Vec<_>>>()?;
+                return shape(&name, &params);
+            }
+            1 => {
+                let vertices = match self.u8()? {
+                    0 => {
+                        let positions = (0..self.u32()?).map(|_| self.coords()).collect::<Option<Vec<_>>>()?;
+                        let normals = (0..self.u32()?).map(|_| self.coords()).collect::<Option<Vec<_>>>()?;
+                        let uvs = (0..self.u32()?).map(|_| self.uv()).collect::<Option<Vec<_>>>()?;
+                        Arc::new(Vertices { positions, normals, uvs })
+                    }
+                    1 => Arc::clone(meshes.get(self.u32()? as usize)?.vertices()),
+                    _ => return None
+                };
+                let faces = (0..self.u32()?)
+                    .map(|_| {
+                        let positions = self.indices(vertices.positions.len())?;
+                        let normals = match self.flag()? {
+                            true => Some(self.indices(vertices.normals.len())?),
+                            false => None
+                        };
+                        let uvs = match self.flag()? {
+                            true => Some(self.indices(vertices.uvs.len())?),
+                            false => None
+                        };
+                        Some(Face { positions, normals, uvs })
+                    })
+                    .collect::<Option<Vec<_>>>()?;
+                meshes.push(Arc::new(MeshData::with_accel(vertices, faces, accel)));
+                Arc::clone(meshes.last()?)
+            }
+            2 => Arc::clone(meshes.get(self.u32()? as usize)?),
+            3 => {
+                let transform = Transform::new(self.vec()?, self.vec()?, self.vec()?);
+                return Some(Box::new(Transformed { shape: self.shape(meshes, accel)?, transform }));
+            }
+            4 => {
+                let source = match self.u8()? {
+                    0 => Source::Image(PathBuf::from(self.str()?)),
+                    1 => Source::Noise { seed: self.u64()?, cells: self.u32()? },
+                    _ => return None
+                };
+                let (origin, size) = (self.vec()?, self.vec()?);
+                let (columns, rows) = (self.u32()? as usize, self.u32()? as usize);
+                if columns < 2 || rows < 2 {
+                    return None;
+                }
+                let heights = (0..columns.checked_mul(rows)?).map(|_| self.float()).collect::<Option<Vec<_>>>()?;
+                return Some(Box::new(Heightfield::new(source, origin, size, columns, rows, heights)));
+            }
+            _ => return None
+        };
+        let offset = self.vec()?;
+        let scale = self.float()?;
+        let file = match self.flag()? {
+            true => Some(PathBuf::from(self.str()?)),
+            false => None
+        };
+        Some(Box::new(Mesh { data, file, offset, scale }))
+    }
+
+    fn flag(&mut self) -> Option<bool> {
+        match self.u8()? {
+            0 => Some(false),
+            1 => Some(true),
+            _ => None
+        }
+    }
+
+    /// `Some(None)` for a cache that says there's no medium; `None` for a corrupt one.
+    fn medium(&mut self) -> Option<Option<Medium>> {
+        Some(match self.flag()? {
+            true => Some(Medium { scattering: self.float()?, absorption: self.float()?, g: self.float()? }),
+            false => None
+        })
+    }
+
+    fn texture(&mut self, textures: &mut Vec<Arc<Texture>>) -> Option<Arc<Texture>> {
+        if self.flag()? {
+            return textures.get(self.u32()? as usize).cloned();
+        }
+        let file = PathBuf::from(self.str()?);
+        let (width, height) = (self.u32()? as usize, self.u32()? as usize);
+        if width == 0 || height == 0 {
+            return None;
+        }
+        let values = (0..width.checked_mul(height)?).map(|_| self.float()).collect::<Option<Vec<_>>>()?;
+        textures.push(Arc::new(Texture::new(file, width, height, values)));
+        textures.last().cloned()
+    }
+
+    fn uv(&mut self) -> Option<(Float, Float)> {
+        Some((self.float()?, self.float()?))
+    }
+
+    fn light(&mut self) -> Option<Light> {
+        Some(match self.u8()? {
+            // Their directions were normalized before they were written.
+            0 => Light::Directional { dir: self.vec()?, radius: self.float()?, irradiance: self.vec()? },
+            1 => Light::Spot {
+                pos: self.vec()?,
+                dir: self.vec()?,
+                inner: self.float()?,
+                outer: self.float()?,
+                intensity: self.vec()?,
+                profile: match self.flag()? {
+                    true => Some(Arc::new(self.profile()?)),
+                    false => None
+                }
+            },
+            _ => return None
+        })
+    }
+
+    fn profile(&mut self) -> Option<Ies> {
+        let file = PathBuf::from(self.str()?);
+        let vertical = (0..self.u32()?).map(|_| self.float()).collect::<Option<Vec<_>>>()?;
+        let horizontal = (0..self.u32()?).map(|_| self.float()).collect::<Option<Vec<_>>>()?;
+        if vertical.len() < 2 || horizontal.is_empty() {
+            return None;
+        }
+        let values = (0..vertical.len() * horizontal.len()).map(|_| self.float()).collect::<Option<Vec<_>>>()?;
+        Some(Ies::new(file, vertical, horizontal, values))
+    }
+
+    fn material(&mut self) -> Option<Material> {
+        Some(match self.u8()? {
+            0 => Material::Mirror(self.float()?),
+            1 => {
+                let clearness = self.float()?;
+                let ior = self.float()?;
+                let cauchy = match self.flag()? {
+                    true => Some(Cauchy { a: self.float()?, b: self.float()? }),
+                    false => None
+                };
+                let absorption = match self.flag()? {
+                    true => Some(self.vec()?),
+                    false => None
+                };
+                let medium = self.medium()?;
+                let tint = self.tint()?;
+                Material::Translucent(clearness, Glass { ior, cauchy, absorption, medium, tint })
+            }
+            2 => {
+                let coat = Coat { strength: self.float()?, roughness: self.float()? };
+                Material::Coated(coat, Box::new(self.material()?))
+            }
+            3 => {
+                let ior = self.float()?;
+                let film = match self.flag()? {
+                    true => Some(self.float()?),
+                    false => None
+                };
+                Material::Thin(ThinGlass { ior, film, tint: self.tint()? })
+            }
+            4 => Material::Principled(Principled {
+                metallic: self.float()?,
+                roughness: self.float()?,
+                specular: self.float()?,
+                transmission: self.float()?,
+                ior: self.float()?
+            }),
+            5 => Material::Catcher(self.float()?),
+            _ => return None
+        })
+    }
+
+    fn tint(&mut self) -> Option<Option<Color>> {
+        Some(match self.flag()? {
+            true => Some(self.vec()?),
+            false => None
+        })
+    }
+}
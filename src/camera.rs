@@ -0,0 +1,107 @@
+use rand::Rng;
+
+use crate::linalg::{consts::PI, Float, Vector3};
+use crate::random;
+use crate::shapes::Ray;
+
+/// A thin lens in front of the camera, which keeps only things at the focus distance
+/// sharp and blurs the rest into the shape of its aperture.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Lens {
+    /// Radius of the aperture; the wider, the blurrier.
+    pub radius: Float,
+    /// Distance along the view direction to the plane in focus.
+    pub focus: Float,
+    /// Number of straight blades making the aperture a polygon, or 0 for a round one.
+    pub blades: u32,
+    /// Turn of the polygon about the view direction, in radians from a corner pointing
+    /// to the right of the picture.
+    pub rotation: Float
+}
+
+impl Lens {
+    /// A point on the aperture, in units of the lens plane's own axes.
+    fn sample_aperture(&self, rng: &mut impl Rng) -> (Float, Float) {
+        if self.blades < 3 {
+            let (u, v) = random::gen_pair(rng);
+            let r = self.radius * u.sqrt();
+            let phi = 2.0 * PI * v;
+            return (r * phi.cos(), r * phi.sin());
+        }
+        // Every blade's triangle from the center is the same size, so pick one evenly
+        // and a point evenly inside it.
+        let wedge = 2.0 * PI / self.blades as Float;
+        let blade = ((rng.gen::<Float>() * self.blades as Float) as u32).min(self.blades - 1);
+        let corner = |i: u32| {
+            let angle = self.rotation + i as Float * wedge;
+            (self.radius * angle.cos(), self.radius * angle.sin())
+        };
+        let ((x1, y1), (x2, y2)) = (corner(blade), corner(blade + 1));
+        let (mut a, mut b) = random::gen_pair(rng);
+        if a + b > 1.0 {
+            a = 1.0 - a;
+            b = 1.0 - b;
+        }
+        (a * x1 + b * x2, a * y1 + b * y2)
+    }
+
+    /// `ray`, from a pinhole camera with the given `axes`, moved to start at a random point
+    /// of the aperture and aimed at where it would have crossed the plane in focus.
+    pub fn focus_ray(&self, ray: Ray, [right, up, forward]: [Vector3; 3], rng: &mut impl Rng) -> Ray {
+        let focal_point = ray.get_point(self.focus / ray.dir.dot(forward));
+        let (u, v) = self.sample_aperture(rng);
+        let pos = ray.pos + right.scale(u) + up.scale(v);
+        Ray::new(pos, focal_point - pos)
+    }
+}
+
+/// The right, up and forward unit axes of a camera looking along `dir`, with `up` upward
+/// in its picture before it turns `roll` radians about `dir`.
+pub fn axes(dir: Vector3, up: Vector3, roll: Float) -> [Vector3; 3] {
+    let forward = dir.normalize();
+    let right = forward.cross(up);
+    // Looking straight along `up`, any right will do.
+    let right = if right.size() > 1e-9 { right.normalize() } else { forward.ons().0 };
+    let up = right.cross(forward);
+    let (sin, cos) = roll.sin_cos();
+    [right.scale(cos) + up.scale(sin), up.scale(cos) - right.scale(sin), forward]
+}
+
+/// Width of the sensor focal lengths are given for: a 36 mm full-frame one, in meters.
+pub const SENSOR_WIDTH: Float = 0.036;
+
+/// The lens's focal length and focus distance from some frame of an animation on, eased
+/// linearly into the next key's.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FocusKey {
+    pub frame: u32,
+    /// Focal length, in millimeters.
+    pub focal_length: Float,
+    /// Distance to the plane in focus, in meters like the rest of the scene.
+    pub focus: Float
+}
+
+impl FocusKey {
+    /// Half the horizontal field of view the key gives. A thin lens focused at `focus`
+    /// sits `f s / (s - f)` from the sensor rather than `f`, so focusing closer narrows
+    /// the view ("focus breathing").
+    pub fn fov(&self) -> Float {
+        let f = self.focal_length / 1000.0;
+        let image = f * self.focus / (self.focus - f);
+        (SENSOR_WIDTH / 2.0 / image).atan()
+    }
+}
+
+/// The key at `frame` of `keys`, sorted by frame: between two keys, each value is eased
+/// linearly from one to the other, and before the first or after the last it is held.
+pub fn key_at(keys: &[FocusKey], frame: u32) -> Option<FocusKey> {
+    let next = keys.iter().position(|key| key.frame >= frame);
+    let (a, b) = match next {
+        Some(0) => return keys.first().map(|key| FocusKey { frame, ..*key }),
+        Some(i) => (keys[i - 1], keys[i]),
+        None => return keys.last().map(|key| FocusKey { frame, ..*key })
+    };
+    let t = (frame - a.frame) as Float / (b.frame - a.frame) as Float;
+    let mix = |x: Float, y: Float| x + (y - x) * t;
+    Some(FocusKey { frame, focal_length: mix(a.focal_length, b.focal_length), focus: mix(a.focus, b.focus) })
+}
@@ -1,21 +1,132 @@
 use std::collections::HashMap;
 use std::convert::TryInto;
+use std::fmt;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use std::sync::{Arc, OnceLock, RwLock};
 
-use crate::linalg::Vector3;
-use crate::shapes::{Plane, Ray, Shape, Sphere};
-use crate::trace::{Color, Material, Object};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
 
+use crate::assets::Assets;
+use crate::camera::{self, FocusKey, Lens};
+use crate::expr::{names, parse_let, substitute, substituted_names, ExprError, Variables};
+use crate::filter::Filter;
+use crate::guiding::PathGuide;
+use crate::heightfield::Heightfield;
+use crate::irradiance::IrradianceCache;
+use crate::light::{AreaLight, Light, LightTree};
+use crate::linalg::{consts::PI, Float, Vector3};
+use crate::mesh::{Accel, Mesh, Model};
+use crate::sampler::Sampler;
+use crate::sdf::{Part, Sdf};
+use crate::shapes::{Capsule, Ellipsoid, Plane, Ray, Shape, Sphere};
+use crate::sky::Sky;
+use crate::texture::Cutout;
+use crate::trace::{Backface, Cauchy, Coat, Color, Glass, Material, Medium, Object, Principled, ThinGlass, Visibility};
+use crate::transform::{Transform, Transformed};
+
+/// Where and why a scene line could not be parsed.
+#[derive(Debug)]
+pub struct ParseError {
+    pub line: usize,
+    pub column: usize,
+    pub text: String,
+    pub expected: String,
+    pub found: String
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}, column {}: expected {}, found {}", self.line, self.column, self.expected, self.found)?;
+        if f.alternate() {
+            let gutter = " ".repeat(self.line.to_string().len());
+            write!(f, "\n {} | {}", self.line, self.text)?;
+            write!(f, "\n {} | {}^", gutter, " ".repeat(self.column.saturating_sub(1)))?;
+        }
+        Ok(())
+    }
+}
+
+/// Something in a scene that parsed but looks like a mistake.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Warning {
+    pub line: usize,
+    pub message: String
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// Why a scene couldn't be loaded, or an image saved. Errors from other libraries are
+/// left to `source`, so the message of each only says what it adds.
 #[derive(Debug)]
 pub enum ConfigError {
-    ImageError(image::ImageError),
+    /// The image at this path couldn't be saved; the cause is the `source`.
+    ImageError(PathBuf, image::ImageError),
+    /// Reading or writing failed; shown as the I/O error itself.
     IOError(std::io::Error),
-    InvalidShape(String),
-    InvalidObject(String),
-    InvalidLine(String),
-    NotEnoughLines
+    InvalidShape(ParseError),
+    InvalidObject(ParseError),
+    InvalidLine(ParseError),
+    InvalidExpression(ParseError),
+    NotEnoughLines(String),
+    /// A file the scene refers to, and what is wrong with it.
+    InvalidAsset(PathBuf, String),
+    /// The scene file an error happened in, which its lines and columns count from.
+    InFile(PathBuf, Box<ConfigError>)
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (kind, err) = match self {
+            ConfigError::ImageError(path, _) => return write!(f, "Could not save image {}", path.display()),
+            ConfigError::IOError(err) => return write!(f, "{}", err),
+            ConfigError::NotEnoughLines(usage) => 
+                return write!(f, "Scene ended early: expected a line with {}", usage),
+            ConfigError::InvalidAsset(path, message) =>
+                return write!(f, "Could not load {}: {}", path.display(), message),
+            ConfigError::InFile(path, err) if f.alternate() => return write!(f, "In {}: {:#}", path.display(), err),
+            ConfigError::InFile(path, err) => return write!(f, "In {}: {}", path.display(), err),
+            ConfigError::InvalidShape(err) => ("Invalid shape", err),
+            ConfigError::InvalidObject(err) => ("Invalid object", err),
+            ConfigError::InvalidLine(err) => ("Invalid setting", err),
+            ConfigError::InvalidExpression(err) => ("Invalid expression", err)
+        };
+        if f.alternate() {
+            write!(f, "{} at {:#}", kind, err)
+        } else {
+            write!(f, "{} at {}", kind, err)
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ConfigError::ImageError(_, err) => Some(err),
+            // Shown as itself, so what's behind it comes next.
+            ConfigError::IOError(err) => err.source(),
+            ConfigError::InFile(_, err) => err.source(),
+            _ => None
+        }
+    }
+}
+
+impl ConfigError {
+    /// The error with no file around it.
+    pub fn innermost(&self) -> &ConfigError {
+        match self {
+            ConfigError::InFile(_, err) => err.innermost(),
+            err => err
+        }
+    }
 }
 
 pub type ConfigResult<Ret> = Result<Ret, ConfigError>;
@@ -25,15 +136,177 @@ pub struct Config {
     pub pov: Ray, 
     pub width: u32,
     pub height: u32, 
-    pub fov: f64,
+    /// Half the horizontal field of view, in radians; the vertical follows from the
+    /// aspect ratio.
+    pub fov: Float,
+    /// The lens blurring what is out of focus; a pinhole without one.
+    pub lens: Option<Lens>,
+    /// Focal lengths and focus distances keyed over an animation's frames, sorted by frame,
+    /// that `fov` and the lens's focus follow.
+    pub focus_keys: Vec<FocusKey>,
+    /// Which way is up in the picture, before `roll`: +z unless the scene says otherwise.
+    pub up: Vector3,
+    /// Turn of the camera about its view direction, in radians, counterclockwise as seen
+    /// from behind it.
+    pub roll: Float,
     pub max_depth: u16,
     pub num_tries: u16,
-    pub max_variation: f64
+    /// How samples around each pixel are weighted into it.
+    pub filter: Filter,
+    /// Where the numbers samples draw come from.
+    pub sampler: Sampler,
+    pub spectral: bool,
+    /// Continuations traced where a path first meets glass after a diffuse bounce.
+    pub caustic_split: u16,
+    /// Where diffuse light bounced between surfaces is gathered once and reused, instead
+    /// of traced afresh for every pixel.
+    pub irradiance_cache: Option<IrradianceCache>,
+    /// What diffuse bounces learn to be drawn toward before the render, if anything.
+    pub path_guide: Option<PathGuide>,
+    /// The medium filling the space between objects.
+    pub fog: Option<Medium>,
+    /// What rays that leave the scene see; black without one.
+    pub sky: Option<Sky>,
+    /// Lights other than glowing objects and the sky's sun.
+    pub lights: Vec<Light>,
+    /// The hierarchy spot and point lights are drawn from once there are many, built
+    /// from `lights` the first time it's needed.
+    pub(crate) light_tree: OnceLock<Option<LightTree>>,
+    /// Which of `objects` glow, found the first time it's needed.
+    pub(crate) glowing: OnceLock<Vec<usize>>,
+    /// The structure meshes are traced with.
+    pub accel: Accel,
+    /// What looked wrong in the scene without stopping it from loading.
+    pub warnings: Vec<Warning>
+}
+
+impl Config {
+    /// Whether the camera is held the usual way: +z up and not rolled.
+    pub fn upright(&self) -> bool {
+        (self.up.x, self.up.y, self.up.z, self.roll) == (0.0, 0.0, 1.0, 0.0)
+    }
+
+    /// Sets the field of view, and the lens's focus if there is a lens, to what the focus
+    /// keys give at `frame`, leaving them be without keys.
+    pub fn focus_at(&mut self, frame: u32) {
+        if let Some(key) = camera::key_at(&self.focus_keys, frame) {
+            self.fov = key.fov();
+            if let Some(lens) = &mut self.lens {
+                lens.focus = key.focus;
+            }
+        }
+    }
+
+    /// The hierarchy to draw spot and point lights from, if there are enough for one.
+    pub fn light_tree(&self) -> Option<&LightTree> {
+        self.light_tree.get_or_init(|| LightTree::new(&self.lights)).as_ref()
+    }
+
+    /// The indices of the objects that give off light of their own.
+    pub fn glowing(&self) -> &[usize] {
+        self.glowing.get_or_init(|| {
+            let glows = |lum: Color| lum.x > 0.0 || lum.y > 0.0 || lum.z > 0.0;
+            (0..self.objects.len()).filter(|&i| glows(self.objects[i].lum)).collect()
+        })
+    }
+
+    /// The glowing objects whose shapes can be drawn points on, to be aimed at as lights.
+    pub fn area_lights(&self) -> impl Iterator<Item = AreaLight<'_>> {
+        self.glowing().iter().filter_map(move |&i| AreaLight::of(&self.objects[i]))
+    }
+}
+
+/// A whitespace-separated word of a scene line and the (1-based) column it starts at.
+#[derive(Debug, Clone, Copy)]
+struct Token<'a> {
+    text: &'a str,
+    column: usize
+}
+
+struct Line<'a> {
+    num: usize,
+    text: &'a str,
+    tokens: Vec<Token<'a>>
+}
+
+impl<'a> Line<'a> {
+    fn new(num: usize, text: &'a str) -> Self {
+        let mut tokens = vec![];
+        let mut start = None;
+        for (i, c) in text.char_indices().chain(std::iter::once((text.len(), ' '))) {
+            match (start, c.is_whitespace()) {
+                (None, false) => start = Some(i),
+                (Some(begin), true) => {
+                    tokens.push(Token { text: &text[begin..i], column: begin + 1 });
+                    start = None;
+                }
+                _ => ()
+            }
+        }
+        Line { num, text, tokens }
+    }
+
+    /// Blames `token`, or the end of the line if it is missing.
+    fn error(&self, token: Option<&Token>, expected: &str) -> ParseError {
+        let (column, found) = match token {
+            Some(token) => (token.column, format!("{:?}", token.text)),
+            None => (self.text.len() + 1, "end of line".to_string())
+        };
+        ParseError {
+            line: self.num,
+            column,
+            text: self.text.to_string(),
+            expected: expected.to_string(),
+            found
+        }
+    }
+}
+
+/// Parses exactly one `T` per entry of `names` from `tokens`, describing the 
+/// expected format as `usage` followed by the names when something is off.
+fn parse_args<T: FromStr, const N: usize>(
+    line: &Line, tokens: &[Token], usage: &str, names: [&str; N]
+) -> Result<[T; N], ParseError> {
+    let format = names.iter()
+        .map(|name| format!("<{}>", name))
+        .fold(usage.to_string(), |acc, name| if acc.is_empty() { name } else { acc + " " + &name });
+
+    let mut values = Vec::with_capacity(N);
+    for (i, name) in names.iter().enumerate() {
+        let token = tokens.get(i);
+        let value = token
+            .and_then(|token| token.text.parse().ok())
+            .ok_or_else(|| line.error(token, &format!("<{}> in `{}`", name, format)))?;
+        values.push(value);
+    }
+    if let Some(extra) = tokens.get(N) {
+        return Err(line.error(Some(extra), &format!("end of line after `{}`", format)));
+    }
+    values.try_into().map_err(|_| line.error(None, &format))
+}
+
+fn nonzero(line: &Line, token: Option<&Token>, vec: Vector3, what: &str) -> Result<Vector3, ParseError> {
+    if vec.size() == 0.0 {
+        Err(line.error(token, &format!("a nonzero {}", what)))
+    } else {
+        Ok(vec)
+    }
+}
+
+/// Settings that can only be given once, the last one winning.
+const ONCE: [&str; 11] = [
+    "spectral", "caustic_split", "irradiance_cache", "path_guiding", "fog", "sky", "up", "lens", "filter", "sampler", "accel"
+];
+
+/// Where a scene's relative paths start from, and the files it can share with other scenes.
+pub struct Loader<'a> {
+    pub dir: &'a Path,
+    pub assets: &'a Assets
 }
 
 trait FromString: Shape {
     fn name() -> String;
-    fn from_string(parts: &[&str]) -> Box<dyn Shape>;
+    fn from_string(line: &Line, parts: &[Token], loader: &Loader) -> ConfigResult<Box<dyn Shape>>;
 }
 
 impl FromString for Sphere {
@@ -41,17 +314,14 @@ impl FromString for Sphere {
         "sphere".to_string()
     }
 
-    fn from_string(parts: &[&str]) -> Box<dyn Shape> {
-        if parts.len() != 4 {
-            panic!("Invalid configuration for sphere: {:?}", parts);
-        }
-
-        let parts: Vec<_> = parts.iter().map(|part| part.parse().unwrap()).collect();
+    fn from_string(line: &Line, parts: &[Token], _loader: &Loader) -> ConfigResult<Box<dyn Shape>> {
+        let [x, y, z, radius] = parse_args(line, parts, "sphere", ["x", "y", "z", "radius"])
+            .map_err(ConfigError::InvalidShape)?;
 
-        Box::new(Sphere { 
-            center: Vector3::new(parts[0], parts[1], parts[2]), 
-            radius: parts[3]
-        })
+        Ok(Box::new(Sphere { 
+            center: Vector3::new(x, y, z), 
+            radius
+        }))
     }
 }
 
@@ -60,130 +330,1191 @@ impl FromString for Plane {
         "plane".to_string()
     }
 
-    fn from_string(parts: &[&str]) -> Box<dyn Shape> {
-        if parts.len() != 6 {
-            panic!("Invalid configuration for sphere: {:?}", parts);
+    fn from_string(line: &Line, parts: &[Token], _loader: &Loader) -> ConfigResult<Box<dyn Shape>> {
+        let [x, y, z, nx, ny, nz] = parse_args(line, parts, "plane", ["x", "y", "z", "nx", "ny", "nz"])
+            .map_err(ConfigError::InvalidShape)?;
+        let norm = nonzero(line, parts.get(3), Vector3::new(nx, ny, nz), "normal")
+            .map_err(ConfigError::InvalidShape)?;
+
+        Ok(Box::new(Plane::new(Vector3::new(x, y, z), norm)))
+    }
+}
+
+impl FromString for Capsule {
+    fn name() -> String {
+        "capsule".to_string()
+    }
+
+    /// Parses `capsule <x1> <y1> <z1> <x2> <y2> <z2> <radius>`, running between the two points.
+    fn from_string(line: &Line, parts: &[Token], _loader: &Loader) -> ConfigResult<Box<dyn Shape>> {
+        let [x1, y1, z1, x2, y2, z2, radius] = parse_args(line, parts, "capsule", ["x1", "y1", "z1", "x2", "y2", "z2", "radius"])
+            .map_err(ConfigError::InvalidShape)?;
+        if radius <= 0.0 {
+            return Err(ConfigError::InvalidShape(line.error(parts.get(6), "a positive <radius>")));
         }
 
-        let parts: Vec<_> = parts.iter().map(|part| part.parse().unwrap()).collect();
+        Ok(Box::new(Capsule { a: Vector3::new(x1, y1, z1), b: Vector3::new(x2, y2, z2), radius }))
+    }
+}
 
-        Box::new(Plane { 
-            point: Vector3::new(parts[0], parts[1], parts[2]), 
-            norm: Vector3::new(parts[3], parts[4], parts[5]).normalize()
-        })
+impl FromString for Ellipsoid {
+    fn name() -> String {
+        "ellipsoid".to_string()
+    }
+
+    /// Parses `ellipsoid <x> <y> <z> <rx> <ry> <rz>`, reaching each radius along its axis.
+    fn from_string(line: &Line, parts: &[Token], _loader: &Loader) -> ConfigResult<Box<dyn Shape>> {
+        let [x, y, z, rx, ry, rz] = parse_args(line, parts, "ellipsoid", ["x", "y", "z", "rx", "ry", "rz"])
+            .map_err(ConfigError::InvalidShape)?;
+        if let Some(i) = [rx, ry, rz].iter().position(|&radius| radius <= 0.0) {
+            return Err(ConfigError::InvalidShape(line.error(parts.get(3 + i), "positive radii")));
+        }
+
+        Ok(Box::new(Ellipsoid { center: Vector3::new(x, y, z), radii: Vector3::new(rx, ry, rz) }))
     }
 }
 
-fn parse_nums<T: FromStr, const N: usize>(line: &str) -> ConfigResult<[T; N]> {
-    let err = || ConfigError::InvalidLine(line.to_string());
-    line.split(" ")
-        .map(|num| num.parse::<T>().map_err(|_| err()))
-        .collect::<ConfigResult<Vec<_>>>()?
-        .try_into()
-        .map_err(|_| err())
+impl FromString for Heightfield {
+    fn name() -> String {
+        "heightfield".to_string()
+    }
+
+    /// Parses `heightfield <file> <x> <y> <z> <width> <depth> <height>` or
+    /// `heightfield noise <seed> <cells> <x> <y> <z> <width> <depth> <height>`, the terrain
+    /// filling the box from `(x, y, z)` as far as the sizes given.
+    fn from_string(line: &Line, parts: &[Token], loader: &Loader) -> ConfigResult<Box<dyn Shape>> {
+        let fail = |token: Option<&Token>, expected: &str| ConfigError::InvalidShape(line.error(token, expected));
+        let noise = match parts.first().map(|token| token.text) {
+            Some("noise") => {
+                let seed = parts.get(1).and_then(|token| token.text.parse().ok())
+                    .ok_or_else(|| fail(parts.get(1), "<seed> in `heightfield noise <seed> <cells>`"))?;
+                let cells = parts.get(2).and_then(|token| token.text.parse().ok()).filter(|&cells: &u32| cells >= 1)
+                    .ok_or_else(|| fail(parts.get(2), "a whole number of <cells>, at least 1"))?;
+                Some((seed, cells))
+            }
+            Some(_) => None,
+            None => return Err(fail(None, "<file> or `noise` in `heightfield <file>`"))
+        };
+        let (usage, rest) = match noise {
+            Some(_) => ("heightfield noise <seed> <cells>", &parts[3..]),
+            None => ("heightfield <file>", &parts[1..])
+        };
+        let names = ["x", "y", "z", "width", "depth", "height"];
+        let [x, y, z, width, depth, height] = parse_args(line, rest, usage, names)
+            .map_err(ConfigError::InvalidShape)?;
+        if let Some(i) = [width, depth, height].iter().position(|&size| size <= 0.0) {
+            return Err(fail(rest.get(3 + i), &format!("a positive <{}>", names[3 + i])));
+        }
+        let (origin, size) = (Vector3::new(x, y, z), Vector3::new(width, depth, height));
+        Ok(Box::new(match noise {
+            Some((seed, cells)) => Heightfield::noise(seed, cells, origin, size),
+            None => {
+                let path = loader.dir.join(parts[0].text);
+                loader.assets.note(&path);
+                Heightfield::load(&path, origin, size)?
+            }
+        }))
+    }
 }
 
-fn parse_vec(line: &str) -> ConfigResult<Vector3> {
-    let [x, y, z] = parse_nums(line)?;
-    Ok(Vector3::new(x, y, z))
+impl FromString for Sdf {
+    fn name() -> String {
+        "sdf".to_string()
+    }
+
+    /// Parses `sdf <blend> <part> [<part> ...]`, where a part is `sphere <x> <y> <z> <radius>`,
+    /// `box <x> <y> <z> <hx> <hy> <hz> <radius>`, `torus <x> <y> <z> <major> <minor>`,
+    /// `knot <x> <y> <z> <radius> <tube> <p> <q>`, `mandelbulb <x> <y> <z> <size> <power> <iterations>`
+    /// or `menger <x> <y> <z> <size> <iterations>`.
+    fn from_string(line: &Line, parts: &[Token], _loader: &Loader) -> ConfigResult<Box<dyn Shape>> {
+        let fail = |token: Option<&Token>, expected: &str| ConfigError::InvalidShape(line.error(token, expected));
+        let [blend]: [Float; 1] = parse_args(line, &parts[..parts.len().min(1)], "sdf", ["blend"])
+            .map_err(ConfigError::InvalidShape)?;
+        if !(blend >= 0.0 && blend.is_finite()) {
+            return Err(fail(parts.first(), "a nonnegative <blend>"));
+        }
+
+        let mut sdf_parts = vec![];
+        let mut i = 1;
+        loop {
+            let kind = parts.get(i);
+            let names: &[&str] = match kind.map(|token| token.text) {
+                Some("sphere") => &["x", "y", "z", "radius"],
+                Some("box") => &["x", "y", "z", "hx", "hy", "hz", "radius"],
+                Some("torus") => &["x", "y", "z", "major", "minor"],
+                Some("knot") => &["x", "y", "z", "radius", "tube", "p", "q"],
+                Some("mandelbulb") => &["x", "y", "z", "size", "power", "iterations"],
+                Some("menger") => &["x", "y", "z", "size", "iterations"],
+                None if !sdf_parts.is_empty() => break,
+                _ => return Err(fail(kind, "a part of the field (box, knot, mandelbulb, menger, sphere, torus)"))
+            };
+            let kind = kind.unwrap().text;
+            let args = &parts[i + 1..(i + 1 + names.len()).min(parts.len())];
+            let usage = names.iter().fold(kind.to_string(), |usage, name| format!("{} <{}>", usage, name));
+            let values = names.iter().enumerate()
+                .map(|(j, name)| args.get(j)
+                    .and_then(|token| token.text.parse().ok())
+                    .ok_or_else(|| fail(args.get(j), &format!("<{}> in `{}`", name, usage))))
+                .collect::<ConfigResult<Vec<Float>>>()?;
+            let positive = |j: usize| if values[j] > 0.0 {
+                Ok(values[j])
+            } else {
+                Err(fail(args.get(j), &format!("a positive <{}>", names[j])))
+            };
+            let whole = |j: usize| if values[j] >= 1.0 && values[j].fract() == 0.0 {
+                Ok(values[j] as u32)
+            } else {
+                Err(fail(args.get(j), &format!("a whole number of <{}>, at least 1", names[j])))
+            };
+            let center = Vector3::new(values[0], values[1], values[2]);
+            sdf_parts.push(match kind {
+                "sphere" => Part::Sphere { center, radius: positive(3)? },
+                "box" => {
+                    if values[3..].iter().any(|&value| value < 0.0) {
+                        return Err(fail(args.get(3), "nonnegative half sizes and radius"));
+                    }
+                    Part::RoundedBox { center, half: Vector3::new(values[3], values[4], values[5]), radius: values[6] }
+                }
+                "torus" => Part::Torus { center, major: positive(3)?, minor: positive(4)? },
+                "mandelbulb" => {
+                    let size = positive(3)?;
+                    if values[4] < 2.0 || !values[4].is_finite() {
+                        return Err(fail(args.get(4), "a <power> of at least 2"));
+                    }
+                    Part::Mandelbulb { center, size, power: values[4], iterations: whole(5)? }
+                }
+                "menger" => Part::Menger { center, size: positive(3)?, iterations: whole(4)? },
+                _ => Part::knot(center, positive(3)?, positive(4)?, whole(5)?, whole(6)?)
+            });
+            i += 1 + names.len();
+        }
+        Ok(Box::new(Sdf::new(sdf_parts, blend)))
+    }
 }
 
-fn parse_shape(parts: &[&str]) -> ConfigResult<Box<dyn Shape>> {
-    let fail = || {
-        let fail_str = parts.iter().cloned().collect::<Vec<_>>().join(" ");
-        ConfigError::InvalidShape(fail_str)
-    };
+/// Parses `mesh <file> <x> <y> <z> <scale>` into the loaded model and its placement.
+fn parse_mesh(line: &Line, parts: &[Token], loader: &Loader, accel: Accel) -> ConfigResult<(Arc<Model>, PathBuf, Vector3, Float)> {
+    let file = parts.first()
+        .ok_or_else(|| ConfigError::InvalidShape(line.error(None, "<file> in `mesh <file> <x> <y> <z> <scale>`")))?;
+    let [x, y, z, scale] = parse_args(line, &parts[1..], "mesh <file>", ["x", "y", "z", "scale"])
+        .map_err(ConfigError::InvalidShape)?;
+    if scale <= 0.0 {
+        return Err(ConfigError::InvalidShape(line.error(parts.get(4), "a positive <scale>")));
+    }
 
-    let mut parts = parts.iter().cloned().filter(|part| *part != "");
+    let path = loader.dir.join(file.text);
+    let model = loader.assets.mesh(&path, accel)?;
+    Ok((model, path, Vector3::new(x, y, z), scale))
+}
+
+/// A shape a library adds to those scene files can make, once it is registered with
+/// `register_shape`.
+pub trait ShapeArgs: Shape + Sized + 'static {
+    /// The word object lines name the shape by.
+    fn name() -> String;
 
-    let shape_name = parts.next().ok_or_else(fail)?;
-    let rest_parts: Vec<_> = parts.collect();
+    /// The shape the words after its name on an object line describe, with relative paths
+    /// starting from `loader.dir`. Otherwise, the index in `args` of the word that is
+    /// wrong, or `args.len()` if one is missing, and what was expected there, as in
+    /// "<radius> in `blob <x> <y> <z> <radius>`". Options like `translate=` are taken out
+    /// first.
+    fn from_args(args: &[&str], loader: &Loader) -> Result<Self, (usize, String)>;
+}
 
-    let shape_parsers: HashMap<_, _> = {
-        let pairs: [(String, &dyn Fn(&[&str]) -> Box<dyn Shape>); 2] = [
-            (Sphere::name(), &Sphere::from_string),
-            (Plane::name(), &Plane::from_string),
+/// Makes a shape from the words after its name on an object line.
+type ShapeParser = fn(&Line, &[Token], &Loader) -> ConfigResult<Box<dyn Shape>>;
+
+/// Every shape object lines can name, by name; built once and added to by `register_shape`.
+fn shape_parsers() -> &'static RwLock<HashMap<String, ShapeParser>> {
+    static PARSERS: OnceLock<RwLock<HashMap<String, ShapeParser>>> = OnceLock::new();
+    PARSERS.get_or_init(|| {
+        let pairs: [(String, ShapeParser); 6] = [
+            (Sphere::name(), Sphere::from_string),
+            (Plane::name(), Plane::from_string),
+            (Capsule::name(), Capsule::from_string),
+            (Ellipsoid::name(), Ellipsoid::from_string),
+            (Sdf::name(), Sdf::from_string),
+            (Heightfield::name(), Heightfield::from_string),
         ];
-        pairs.iter().cloned().collect()
-    };
+        RwLock::new(IntoIterator::into_iter(pairs).collect())
+    })
+}
+
+/// Lets scene files make `T`s, by `T::name()`, in any scene parsed from now on. A name
+/// already taken, even by a built-in shape, goes to `T` instead.
+pub fn register_shape<T: ShapeArgs>() {
+    fn parse<T: ShapeArgs>(line: &Line, parts: &[Token], loader: &Loader) -> ConfigResult<Box<dyn Shape>> {
+        let args: Vec<&str> = parts.iter().map(|token| token.text).collect();
+        match T::from_args(&args, loader) {
+            Ok(shape) => Ok(Box::new(shape)),
+            Err((index, expected)) => Err(ConfigError::InvalidShape(line.error(parts.get(index), &expected)))
+        }
+    }
+    shape_parsers().write().unwrap().insert(T::name(), parse::<T>);
+}
+
+fn parse_shape(line: &Line, parts: &[Token], loader: &Loader) -> ConfigResult<Box<dyn Shape>> {
+    let shape_name = parts.first();
+    let parser = shape_name.and_then(|name| shape_parsers().read().unwrap().get(name.text).copied());
+    let parser = parser.ok_or_else(|| {
+        // Meshes are parsed by `parse_object`, since one can make several objects.
+        let mut names: Vec<_> = shape_parsers().read().unwrap().keys().cloned().chain(["mesh".to_string()]).collect();
+        names.sort();
+        ConfigError::InvalidShape(line.error(shape_name, &format!("a shape ({})", names.join(", "))))
+    })?;
+    parser(line, &parts[1..], loader)
+}
+
+/// Checks the coefficients of a medium given at `token`.
+fn medium(line: &Line, token: Option<&Token>, [scattering, absorption, g]: [Float; 3]) -> Result<Medium, ParseError> {
+    if scattering < 0.0 || absorption < 0.0 {
+        Err(line.error(token, "nonnegative scattering and absorption"))
+    } else if g <= -1.0 || g >= 1.0 {
+        Err(line.error(token, "an anisotropy `g` strictly between -1 and 1"))
+    } else {
+        Ok(Medium { scattering, absorption, g })
+    }
+}
+
+/// The `key=value` tokens of an object line, which may appear anywhere after the color.
+struct Options<'a> {
+    entries: Vec<(Token<'a>, &'a str, &'a str)>,
+    used: Vec<bool>
+}
+
+impl<'a> Options<'a> {
+    /// Separates the options in `tokens` from the positional words.
+    fn split(tokens: &[Token<'a>]) -> (Self, Vec<Token<'a>>) {
+        let (options, positional): (Vec<_>, Vec<_>) = tokens.iter()
+            .enumerate()
+            .partition(|(i, token)| *i > 0 && token.text.contains('='));
+        let entries: Vec<_> = options.into_iter()
+            .map(|(_, token)| {
+                let (key, value) = token.text.split_once('=').unwrap();
+                (*token, key, value)
+            })
+            .collect();
+        let used = vec![false; entries.len()];
+        (Options { entries, used }, positional.into_iter().map(|(_, token)| *token).collect())
+    }
+
+    fn take(&mut self, key: &str) -> Option<(Token<'a>, &'a str)> {
+        let index = self.entries.iter().position(|(_, k, _)| *k == key)?;
+        self.used[index] = true;
+        let (token, _, value) = self.entries[index];
+        Some((token, value))
+    }
+
+    /// Where `key` was given, for errors about its value.
+    fn token(&self, key: &str) -> Option<Token<'a>> {
+        self.entries.iter().find(|(_, k, _)| *k == key).map(|(token, _, _)| *token)
+    }
 
-    let parser = shape_parsers.get(shape_name).ok_or_else(fail)?;
-    Ok((parser)(&rest_parts))
+    /// Parses `key=<a>,<b>,...` into one number per entry of `names`.
+    fn nums<const N: usize>(&mut self, line: &Line, key: &str, names: [&str; N]) -> Result<Option<[Float; N]>, ParseError> {
+        let (token, value) = match self.take(key) {
+            Some(found) => found,
+            None => return Ok(None)
+        };
+        let format = format!("{}={}", key, names.map(|name| format!("<{}>", name)).join(","));
+        let fail = || line.error(Some(&token), &format!("`{}`", format));
+
+        let values: Vec<Float> = value.split(',')
+            .map(|num| num.parse().map_err(|_| fail()))
+            .collect::<Result<_, _>>()?;
+        values.try_into().map(Some).map_err(|_| fail())
+    }
+
+    /// Fails on the first option nobody asked for, listing the `known` ones.
+    fn finish(&self, line: &Line, known: &[&str]) -> Result<(), ParseError> {
+        let expected = if known.is_empty() {
+            "no options for this object".to_string()
+        } else {
+            format!("one of the options {}", known.join(", "))
+        };
+        match self.used.iter().position(|used| !used) {
+            Some(index) => Err(line.error(Some(&self.entries[index].0), &expected)),
+            None => Ok(())
+        }
+    }
 }
 
-fn parse_object(raw: &str, col_scale: f64, lum_scale: f64) -> ConfigResult<Object> {
-    let fail = || {
-        let fail_str = raw.to_string();
-        ConfigError::InvalidObject(fail_str)
+/// Where an object's `translate=<x>,<y>,<z>`, `rotate=<x>,<y>,<z>` (degrees about each
+/// axis in turn) and `scale=<s>` or `scale=<x>,<y>,<z>` options put its shape.
+fn parse_transform(line: &Line, options: &mut Options) -> Result<Transform, ParseError> {
+    let [tx, ty, tz] = options.nums(line, "translate", ["x", "y", "z"])?.unwrap_or([0.0; 3]);
+    let [rx, ry, rz] = options.nums(line, "rotate", ["x", "y", "z"])?.unwrap_or([0.0; 3]);
+    let [sx, sy, sz] = match options.take("scale") {
+        None => [1.0; 3],
+        Some((token, value)) => {
+            let values: Option<Vec<Float>> = value.split(',').map(|num| num.parse().ok()).collect();
+            let factors = match values.as_deref() {
+                Some(&[s]) => [s; 3],
+                Some(&[x, y, z]) => [x, y, z],
+                _ => return Err(line.error(Some(&token), "`scale=<s>` or `scale=<x>,<y>,<z>`"))
+            };
+            if factors.iter().any(|&s| !(s > 0.0 && s.is_finite())) {
+                return Err(line.error(Some(&token), "positive `scale` factors"));
+            }
+            factors
+        }
     };
-    
-    let mut parts = raw.split(" ");
+    Ok(Transform::new(Vector3::new(tx, ty, tz), Vector3::new(rx, ry, rz), Vector3::new(sx, sy, sz)))
+}
+
+/// The copies of an object a `grid` or `scatter` line makes, as how far each is moved
+/// from where the object is given, and the object's part of the line. A grid is
+/// `grid <nx> <ny> <nz> <dx> <dy> <dz> of <object>`: `nx` copies `dx` apart along x, each
+/// repeated `ny` times `dy` apart along y and so on. A scatter is
+/// `scatter <count> <seed> <min_x> <min_y> <min_z> <max_x> <max_y> <max_z> of <object>`:
+/// copies moved to points drawn evenly from the box, the same ones for the same seed.
+fn parse_generator<'a>(line: &Line<'a>) -> Result<(Vec<Vector3>, Line<'a>), ParseError> {
+    let keyword = line.tokens[0].text;
+    let of = line.tokens.iter().position(|token| token.text == "of")
+        .ok_or_else(|| line.error(None, &format!("`of <object>` after the numbers of `{}`", keyword)))?;
+    let object_line = Line { num: line.num, text: line.text, tokens: line.tokens[of + 1..].to_vec() };
+    let whole = |value: Float, at: usize| if value >= 0.0 && value.fract() == 0.0 {
+        Ok(value as u64)
+    } else {
+        Err(line.error(line.tokens.get(at), "a whole number of copies"))
+    };
+
+    let offsets = if keyword == "grid" {
+        let [nx, ny, nz, dx, dy, dz]: [Float; 6] = parse_args(
+            line, &line.tokens[1..of], "grid", ["nx", "ny", "nz", "dx", "dy", "dz"]
+        )?;
+        let (nx, ny, nz) = (whole(nx, 1)?, whole(ny, 2)?, whole(nz, 3)?);
+        let mut offsets = vec![];
+        for k in 0..nz {
+            for j in 0..ny {
+                for i in 0..nx {
+                    offsets.push(Vector3::new(i as Float * dx, j as Float * dy, k as Float * dz));
+                }
+            }
+        }
+        offsets
+    } else {
+        let [count, seed, x0, y0, z0, x1, y1, z1]: [Float; 8] = parse_args(
+            line, &line.tokens[1..of], "scatter", ["count", "seed", "min_x", "min_y", "min_z", "max_x", "max_y", "max_z"]
+        )?;
+        let (count, seed) = (whole(count, 1)?, whole(seed, 2)?);
+        if x0 > x1 || y0 > y1 || z0 > z1 {
+            return Err(line.error(line.tokens.get(3), "a box's smaller corner before its larger one"));
+        }
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut between = |min: Float, max: Float| min + (max - min) * rng.gen::<Float>();
+        (0..count).map(|_| Vector3::new(between(x0, x1), between(y0, y1), between(z0, z1))).collect()
+    };
+    Ok((offsets, object_line))
+}
+
+/// `object` moved by `transform` and then by those of the groups it is in, outermost first.
+fn place(object: Object, transform: &Transform, groups: &[(&str, usize, Transform)]) -> Object {
+    let transforms = std::iter::once(transform).chain(groups.iter().rev().map(|(_, _, transform)| transform));
+    // A group's transform applies after those of the objects and groups in it.
+    let shape = transforms.fold(object.shape, |shape, &transform| {
+        if transform.is_identity() { shape } else { Box::new(Transformed { shape, transform }) }
+    });
+    Object { shape, ..object }
+}
+
+/// Parses an object line. A mesh whose file assigns materials makes one object per
+/// material, with the line's color and material kept for faces the file leaves bare.
+fn parse_object(
+    line: &Line,
+    col_scale: Float,
+    lum_scale: Float,
+    loader: &Loader,
+    accel: Accel,
+    warnings: &mut Vec<Warning>
+) -> ConfigResult<Vec<Object>> {
+    let fail = |token: Option<&Token>, expected: &str| ConfigError::InvalidObject(line.error(token, expected));
+
+    let (mut options, tokens) = Options::split(&line.tokens);
+    let mut parts = tokens.iter().enumerate();
+    let mut next = || parts.next().map(|(_, token)| token);
 
     let color = {
-        let color_str = parts.next().ok_or_else(fail)?;
-        Color::from_string(color_str)
-            .ok_or_else(fail)?
+        let token = next();
+        token
+            .and_then(|token| Color::from_string(token.text))
+            .ok_or_else(|| fail(token, "a color (a name like `red`, `rgb(r,g,b)`, `#rrggbb` or a temperature like `3200K`)"))?
             .scale(col_scale)
     };
-    let lum = {
-        let lum_const: f64 = parts.next().ok_or_else(fail)?.parse().map_err(|_| fail())?;
-        color.scale(lum_const).scale(lum_scale / col_scale)
+    // The luminance waits for the shape when it's the power of the whole surface.
+    let lum_token = next();
+    let units = [Unit::Watts, Unit::Lumens, Unit::Nits];
+    let (lum_const, lum_unit) = strength(lum_token, &units)
+        .ok_or_else(|| fail(lum_token, &strength_usage("<luminance> after the color", &units)))?;
+    // `emit` gives the light a color of its own rather than the surface's.
+    let glow = match options.take("emit") {
+        None => color.scale(lum_scale / col_scale),
+        Some((token, value)) => Color::from_string(value)
+            .ok_or_else(|| fail(Some(&token), "`emit=<color>`"))?
+            .scale(lum_scale)
+    };
+    let lum = |area: Option<Float>| -> ConfigResult<Color> {
+        let per_area = |power: Float| match area {
+            Some(area) => Ok(power / (PI * area)),
+            None => Err(fail(lum_token, "a shape whose area is known, for a power in `W` or `lm`"))
+        };
+        let radiance = match lum_unit {
+            None => lum_const,
+            Some(Unit::Watts) => per_area(lum_const)?,
+            Some(Unit::Lumens) => per_area(lum_const / LUMENS_PER_WATT)?,
+            Some(_) => lum_const / LUMENS_PER_WATT
+        };
+        Ok(glow.scale(radiance))
+    };
+    let token = next();
+    // `tint` is the color white light takes on going through glass.
+    let tint = |options: &mut Options| -> ConfigResult<Option<Color>> {
+        match options.take("tint") {
+            None => Ok(None),
+            Some((token, value)) => Color::from_string(value).map(Some).ok_or_else(|| fail(Some(&token), "`tint=<color>`"))
+        }
+    };
+    let mut translucent = |clearness| -> ConfigResult<Material> {
+        let cauchy = options.nums(line, "cauchy", ["a", "b"])
+            .map_err(ConfigError::InvalidObject)?
+            .map(|[a, b]| Cauchy { a, b });
+        let ior = options.nums(line, "ior", ["n"]).map_err(ConfigError::InvalidObject)?;
+        let ior = match (ior, cauchy) {
+            (Some(_), Some(_)) => return Err(fail(options.token("ior").as_ref(), "either `ior` or `cauchy`, not both")),
+            (Some([n]), None) if n <= 0.0 => return Err(fail(options.token("ior").as_ref(), "a positive `ior`")),
+            (Some([n]), None) => n,
+            (None, _) => Glass::default().ior
+        };
+
+        // `absorb` is the color white light fades to after `absorb_depth` units inside.
+        let absorb = match options.take("absorb") {
+            None => None,
+            Some((token, value)) => Some(Color::from_string(value).ok_or_else(|| {
+                fail(Some(&token), "`absorb=<color>`")
+            })?)
+        };
+        let [depth] = options.nums(line, "absorb_depth", ["depth"])
+            .map_err(ConfigError::InvalidObject)?
+            .unwrap_or([1.0]);
+        if depth <= 0.0 {
+            return Err(fail(options.token("absorb_depth").as_ref(), "a positive `absorb_depth`"));
+        }
+        let absorption = absorb.map(|color| {
+            let sigma = |channel: Float| -(channel / 255.0).clamp(1e-6, 1.0).ln() / depth;
+            Color::new(sigma(color.x), sigma(color.y), sigma(color.z))
+        });
+
+        let medium = match options.nums(line, "medium", ["scattering", "absorption", "g"]).map_err(ConfigError::InvalidObject)? {
+            Some(values) => Some(medium(line, options.token("medium").as_ref(), values).map_err(ConfigError::InvalidObject)?),
+            None => None
+        };
+
+        let tint = tint(&mut options)?;
+        Ok(Material::Translucent(clearness, Glass { ior, cauchy, absorption, medium, tint }))
+    };
+    let (mut material, mut known) = match token.map(|token| token.text) {
+        Some("mirror") => {
+            let [roughness] = options.nums(line, "roughness", ["r"])
+                .map_err(ConfigError::InvalidObject)?
+                .unwrap_or([0.0]);
+            if !(0.0..=1.0).contains(&roughness) {
+                return Err(fail(options.token("roughness").as_ref(), "`roughness` between 0 and 1"));
+            }
+            (Material::Mirror(roughness), vec!["roughness"])
+        }
+        Some("glass") => (translucent(1.0)?, vec!["ior", "cauchy", "absorb", "absorb_depth", "medium", "tint"]),
+        Some("opaque") => (Material::Translucent(0.0, Glass::default()), vec![]),
+        Some("principled") => {
+            let default = Principled::default();
+            let mut dial = |key: &str, default: Float| -> ConfigResult<Float> {
+                let [value] = options.nums(line, key, ["value"])
+                    .map_err(ConfigError::InvalidObject)?
+                    .unwrap_or([default]);
+                if !(0.0..=1.0).contains(&value) {
+                    return Err(fail(options.token(key).as_ref(), &format!("`{}` between 0 and 1", key)));
+                }
+                Ok(value)
+            };
+            let metallic = dial("metallic", default.metallic)?;
+            let roughness = dial("roughness", default.roughness)?;
+            let specular = dial("specular", default.specular)?;
+            let transmission = dial("transmission", default.transmission)?;
+            let [ior] = options.nums(line, "ior", ["n"])
+                .map_err(ConfigError::InvalidObject)?
+                .unwrap_or([default.ior]);
+            if ior <= 0.0 {
+                return Err(fail(options.token("ior").as_ref(), "a positive `ior`"));
+            }
+            let principled = Principled { metallic, roughness, specular, transmission, ior };
+            (Material::Principled(principled), vec!["metallic", "roughness", "specular", "transmission", "ior"])
+        }
+        Some("thin") => {
+            let [ior] = options.nums(line, "ior", ["n"])
+                .map_err(ConfigError::InvalidObject)?
+                .unwrap_or([ThinGlass::default().ior]);
+            if ior <= 0.0 {
+                return Err(fail(options.token("ior").as_ref(), "a positive `ior`"));
+            }
+            let film = options.nums(line, "film", ["thickness"]).map_err(ConfigError::InvalidObject)?;
+            if film.is_some_and(|[thickness]| thickness <= 0.0) {
+                return Err(fail(options.token("film").as_ref(), "a positive `film` thickness"));
+            }
+            let tint = tint(&mut options)?;
+            (Material::Thin(ThinGlass { ior, film: film.map(|[thickness]| thickness), tint }), vec!["ior", "film", "tint"])
+        }
+        Some("catcher") => {
+            let [reflection] = options.nums(line, "reflect", ["fraction"])
+                .map_err(ConfigError::InvalidObject)?
+                .unwrap_or([0.0]);
+            if !(0.0..=1.0).contains(&reflection) {
+                return Err(fail(options.token("reflect").as_ref(), "`reflect` between 0 and 1"));
+            }
+            (Material::Catcher(reflection), vec!["reflect"])
+        }
+        Some("translucent") => {
+            let token = next();
+            let clearness = token
+                .and_then(|token| token.text.parse().ok())
+                .ok_or_else(|| fail(token, "<clearness> after `translucent`"))?;
+            (translucent(clearness)?, vec!["ior", "cauchy", "absorb", "absorb_depth", "medium", "tint"])
+        }
+        _ => return Err(fail(token, "a material (mirror, glass, thin, opaque, translucent <clearness>, principled, catcher)"))
+    };
+
+    // Any material can be varnished.
+    if let Some([strength, roughness]) = options.nums(line, "coat", ["strength", "roughness"])
+        .map_err(ConfigError::InvalidObject)?
+    {
+        if !(0.0..=1.0).contains(&strength) || !(0.0..=1.0).contains(&roughness) {
+            return Err(fail(options.token("coat").as_ref(), "`coat` strength and roughness between 0 and 1"));
+        }
+        material = Material::Coated(Coat { strength, roughness }, Box::new(material));
+    }
+    let cutout = match options.take("cutout") {
+        None if options.token("cutout_threshold").is_some() => {
+            return Err(fail(options.token("cutout_threshold").as_ref(), "a `cutout=<file>` for the `cutout_threshold`"));
+        }
+        None => None,
+        Some((_, file)) => {
+            let [threshold] = options.nums(line, "cutout_threshold", ["opacity"])
+                .map_err(ConfigError::InvalidObject)?
+                .unwrap_or([Cutout::DEFAULT_THRESHOLD]);
+            if !(0.0..=1.0).contains(&threshold) {
+                return Err(fail(options.token("cutout_threshold").as_ref(), "a `cutout_threshold` between 0 and 1"));
+            }
+            Some(Cutout { texture: loader.assets.opacity(&loader.dir.join(file))?, threshold })
+        }
+    };
+    let backface = match options.take("backface") {
+        None => Backface::default(),
+        Some((token, value)) => value.parse().map_err(|_| fail(Some(&token), "`backface=show`, `cull` or `flip`"))?
     };
-    let material = match parts.next().ok_or_else(fail)? {
-        "mirror" => Material::Mirror,
-        "glass" => Material::Translucent(1.0),
-        "opaque" => Material::Translucent(0.0),
-        "translucent" => {
-            let clearness = parts.next().ok_or_else(fail)?;
-            let clearness = clearness.parse().map_err(|_| fail())?;
-            Material::Translucent(clearness)
+    // Lighting rigs hide lamps from the camera, or keep fill cards from casting shadows.
+    let mut seen = |key: &str| -> ConfigResult<bool> {
+        match options.take(key) {
+            None => Ok(true),
+            Some((token, value)) => value.parse().map_err(|_| fail(Some(&token), &format!("`{}=true` or `false`", key)))
         }
-        _ => return Err(fail())
     };
+    let visibility = Visibility {
+        camera: seen("camera_visible")?,
+        shadows: seen("casts_shadows")?,
+        reflections: seen("visible_in_reflections")?
+    };
+    // Any shape can be moved, turned and stretched.
+    let transform = parse_transform(line, &mut options).map_err(ConfigError::InvalidObject)?;
+    let place = |shape: Box<dyn Shape>| -> Box<dyn Shape> {
+        if transform.is_identity() { shape } else { Box::new(Transformed { shape, transform }) }
+    };
+    known.extend([
+        "coat", "emit", "cutout", "cutout_threshold", "backface", "camera_visible", "casts_shadows",
+        "visible_in_reflections", "translate", "rotate", "scale"
+    ]);
+    options.finish(line, &known).map_err(ConfigError::InvalidObject)?;
     
-    let shape = parse_shape(&parts.collect::<Vec<_>>())?;
-    Ok(Object { shape, color, lum, material })
+    let rest = parts.next().map_or(tokens.len(), |(i, _)| i);
+    if tokens.get(rest).map(|token| token.text) == Some("mesh") {
+        let (model, file, offset, scale) = parse_mesh(line, &tokens[rest + 1..], loader, accel)?;
+        warnings.extend(model.warnings.iter().map(|warning| Warning {
+            line: line.num,
+            message: format!("{}: {}", file.display(), warning)
+        }));
+        // Only a mesh that is the whole file can be written back as that file.
+        let file = Some(file).filter(|_| model.parts.len() == 1);
+        let shapes: Vec<_> = model.parts.iter()
+            .map(|part| place(Box::new(Mesh { data: Arc::clone(&part.data), file: file.clone(), offset, scale })))
+            .collect();
+        // A power is spread over the faces the line's luminance is for.
+        let area = shapes.iter().zip(&model.parts)
+            .filter(|(_, part)| part.material.is_none())
+            .map(|(shape, _)| shape.area())
+            .sum();
+        let lum = lum(area)?;
+        return Ok(shapes.into_iter().zip(&model.parts).map(|(shape, part)| {
+            match &part.material {
+                Some(mtl) => Object {
+                    shape,
+                    color: mtl.color.scale(col_scale),
+                    lum: mtl.lum.scale(lum_scale),
+                    material: mtl.material.clone(),
+                    backface,
+                    cutout: cutout.clone(),
+                    visibility,
+                    line: line.num
+                },
+                None => Object { shape, color, lum, material: material.clone(), backface, cutout: cutout.clone(), visibility, line: line.num }
+            }
+        }).collect());
+    }
+    let shape = place(parse_shape(line, &tokens[rest..], loader)?);
+    let lum = lum(shape.area())?;
+    Ok(vec![Object { shape, color, lum, material, backface, cutout, visibility, line: line.num }])
+}
+
+fn parse_vec(line: &Line, names: [&str; 3]) -> ConfigResult<Vector3> {
+    let [x, y, z] = parse_args(line, &line.tokens, "", names).map_err(ConfigError::InvalidLine)?;
+    Ok(Vector3::new(x, y, z))
+}
+
+fn parse_nums<T: FromStr, const N: usize>(line: &Line, names: [&str; N]) -> ConfigResult<[T; N]> {
+    parse_args(line, &line.tokens, "", names).map_err(ConfigError::InvalidLine)
 }
 
-fn parse_pov(pos_line: &str, dir_line: &str) -> ConfigResult<Ray> {
-    let pos = parse_vec(pos_line)?;
-    let dir = parse_vec(dir_line)?;
+/// Half the horizontal field of view, in radians, from either that or `<degrees> deg`,
+/// the whole of it in degrees.
+fn parse_fov(line: &Line) -> ConfigResult<Float> {
+    let fov = match line.tokens.get(1).map(|token| token.text) {
+        Some("deg") => {
+            if let Some(extra) = line.tokens.get(2) {
+                return Err(ConfigError::InvalidLine(line.error(Some(extra), "end of line after `<degrees> deg`")));
+            }
+            let [degrees]: [Float; 1] = parse_args(line, &line.tokens[..1], "", ["degrees"])
+                .map_err(ConfigError::InvalidLine)?;
+            degrees.to_radians() / 2.0
+        }
+        _ => {
+            let [fov] = parse_nums(line, ["fov"])?;
+            fov
+        }
+    };
+    // Rays are spread over a flat image plane, which can't reach as far as 90 degrees
+    // to either side.
+    if !(fov > 0.0 && fov < PI / 2.0) {
+        return Err(ConfigError::InvalidLine(line.error(line.tokens.first(), "a field of view between 0 and 180 degrees")));
+    }
+    Ok(fov)
+}
+
+/// The camera from its position and either its view direction or `look_at <x> <y> <z>`,
+/// a point it looks at.
+fn parse_pov(pos_line: &Line, dir_line: &Line) -> ConfigResult<Ray> {
+    let pos = parse_vec(pos_line, ["x", "y", "z"])?;
+    let dir = match dir_line.tokens.first().map(|token| token.text) {
+        Some("look_at") => {
+            let [x, y, z]: [Float; 3] = parse_args(dir_line, &dir_line.tokens[1..], "look_at", ["x", "y", "z"])
+                .map_err(ConfigError::InvalidLine)?;
+            Vector3::new(x, y, z) - pos
+        }
+        _ => parse_vec(dir_line, ["dx", "dy", "dz"])?
+    };
+    let dir = nonzero(dir_line, dir_line.tokens.first(), dir, "view direction")
+        .map_err(ConfigError::InvalidLine)?;
     Ok(Ray::new(pos, dir))
 }
 
+/// Marks the latest definitions of `names` as used.
+fn mark_used<'a>(defined: &mut [(String, usize, bool)], names: impl IntoIterator<Item = &'a str>) {
+    for name in names {
+        if let Some(def) = defined.iter_mut().rev().find(|(defined, _, _)| defined == name) {
+            def.2 = true;
+        }
+    }
+}
+
+/// A warning for a line with a number that isn't one, which is usually an expression
+/// gone wrong, like the square root of something negative.
+fn nan_warning(line: &Line) -> Option<Warning> {
+    let nan = line.tokens.iter()
+        .flat_map(|token| token.text.split(['=', ',']))
+        .any(|word| word.parse::<Float>().is_ok_and(Float::is_nan));
+    nan.then(|| Warning { line: line.num, message: "NaN where a number belongs".to_string() })
+}
+
+/// Numbers the meaningful lines of `raw`, evaluating `let` definitions and `${expr}` uses.
+/// Variables in `overrides` keep their given value even if the scene defines them.
+/// Definitions never used are added to `warnings`.
+fn preprocess(raw: &str, overrides: &Variables, warnings: &mut Vec<Warning>) -> ConfigResult<Vec<(usize, String)>> {
+    let mut vars = overrides.clone();
+    let mut lines = vec![];
+    // Every `let` the scene's values come from, by name and line, and whether anything
+    // used it before it was defined again.
+    let mut defined: Vec<(String, usize, bool)> = vec![];
+    for (num, line) in raw.split('\n').enumerate() {
+        if line.trim().is_empty() || line.trim_start().starts_with("//") {
+            continue;
+        }
+        let fail = |prefix: usize| move |err: ExprError| ConfigError::InvalidExpression(ParseError {
+            line: num + 1,
+            column: prefix + err.offset + 1,
+            text: line.to_string(),
+            expected: err.expected,
+            found: err.found
+        });
+
+        if let Some(def) = line.strip_prefix("let ") {
+            let (name, value) = parse_let(def, &vars).map_err(fail("let ".len()))?;
+            let expr = def.split_once('=').map_or("", |(_, expr)| expr);
+            mark_used(&mut defined, names(expr));
+            // A definition the caller overrode was never going to be used.
+            if !overrides.contains_key(&name) {
+                vars.insert(name.clone(), value);
+                defined.push((name, num + 1, false));
+            }
+        } else {
+            mark_used(&mut defined, substituted_names(line));
+            lines.push((num + 1, substitute(line, &vars).map_err(fail(0))?));
+        }
+    }
+    warnings.extend(defined.into_iter()
+        .filter(|(_, _, used)| !used)
+        .map(|(name, line, _)| Warning { line, message: format!("`{}` is never used", name) }));
+    Ok(lines)
+}
 
 pub fn parse_config(raw: &str) -> ConfigResult<Config> {
-    let mut lines = raw
-        .split("\n")
-        .filter(|line| *line != "")
-        .filter(|line| !line.starts_with("//"));
-    let mut next_line = || lines.next().ok_or_else(|| ConfigError::NotEnoughLines);
+    parse_config_with(raw, &Variables::new())
+}
+
+/// Parses a scene as if it began with a `let` for each of `overrides`, which win over
+/// the scene's own definitions of the same names.
+pub fn parse_config_with(raw: &str, overrides: &Variables) -> ConfigResult<Config> {
+    parse_config_in(raw, overrides, &Loader { dir: Path::new("."), assets: &Assets::new() })
+}
+
+/// Parses a scene with its files found and shared through `loader`.
+pub fn parse_config_in(raw: &str, overrides: &Variables, loader: &Loader) -> ConfigResult<Config> {
+    let mut warnings = vec![];
+    let lines = preprocess(raw, overrides, &mut warnings)?;
+    let lines: Vec<_> = lines.iter().map(|(num, text)| Line::new(*num, text)).collect();
+    warnings.extend(lines.iter().flat_map(|line| nan_warning(line)));
+    let mut lines = lines.iter();
+    let mut next_line = |usage: &str| lines.next().ok_or_else(|| ConfigError::NotEnoughLines(usage.to_string()));
     
-    let pov = parse_pov(next_line()?, next_line()?)?;
-    let [width, height] = parse_nums(next_line()?)?;
-    let [fov] = parse_nums(next_line()?)?;
-    let [max_depth, num_tries] = parse_nums(next_line()?)?;
-    let [max_variation] = parse_nums(next_line()?)?;
-
-    let [col_scale, lum_scale] = parse_nums(next_line()?)?;
-    let objects: Vec<_> = lines
-        .map(|line| parse_object(line, col_scale, lum_scale))
-        .collect::<Result<Vec<_>, _>>()?;
-
-    Ok(Config { 
+    let pov = parse_pov(next_line("the camera position")?, next_line("the view direction")?)?;
+    let size_line = next_line("<width> <height>")?;
+    let [width, height] = parse_nums(size_line, ["width", "height"])?;
+    if width == 0 || height == 0 {
+        let message = format!("a {}x{} image has no pixels to render", width, height);
+        warnings.push(Warning { line: size_line.num, message });
+    }
+    let fov = parse_fov(next_line("<fov>")?)?;
+    let samples_line = next_line("<max_depth> <num_tries>")?;
+    let [max_depth, num_tries] = parse_nums(samples_line, ["max_depth", "num_tries"])?;
+    if max_depth == 0 || num_tries == 0 {
+        let message = "a `max_depth` or `num_tries` of 0 renders the image black".to_string();
+        warnings.push(Warning { line: samples_line.num, message });
+    }
+    // Once the angle primary rays were jittered by. Pixels are now sampled over `filter`
+    // instead, so the line is only checked, for old scenes to keep loading.
+    let [_max_variation]: [Float; 1] = parse_nums(next_line("<max_variation>")?, ["max_variation"])?;
+
+    let [col_scale, lum_scale] = parse_nums(next_line("<col_scale> <lum_scale>")?, ["col_scale", "lum_scale"])?;
+    let lines: Vec<&Line> = lines.collect();
+    // Meshes are built for the accelerator as they're parsed, so it's settled first,
+    // wherever its line is.
+    let mut accel = Accel::default();
+    for line in lines.iter().filter(|line| line.tokens[0].text == "accel") {
+        let [kind] = parse_args(line, &line.tokens[1..], "accel", ["bvh|kdtree"])
+            .map_err(ConfigError::InvalidLine)?;
+        accel = kind;
+    }
+
+    let mut objects = vec![];
+    let mut spectral = false;
+    let mut caustic_split = 1;
+    let mut irradiance_cache = None;
+    let mut path_guide = None;
+    let mut fog = None;
+    let mut sky = None;
+    let mut lights = vec![];
+    let mut lens = None;
+    let mut focus_keys: Vec<FocusKey> = vec![];
+    let mut filter = Filter::default();
+    let mut sampler = Sampler::default();
+    let (mut up, mut roll) = (Vector3::new(0.0, 0.0, 1.0), 0.0);
+    // Where each setting that can only be given once was last given.
+    let mut settings: HashMap<&str, usize> = HashMap::new();
+    // The groups objects are inside of, outermost first, with the lines they start on.
+    let mut groups: Vec<(&str, usize, Transform)> = vec![];
+    for line in lines {
+        let keyword = line.tokens[0].text;
+        // Settings and lights are for the whole scene, so they can't be put in groups.
+        if !groups.is_empty() && (ONCE.contains(&keyword) || ["sun", "spot", "point"].contains(&keyword)) {
+            return Err(ConfigError::InvalidLine(line.error(line.tokens.first(), "an object, `group` or `end` inside a group")));
+        }
+        if ONCE.contains(&keyword) {
+            if let Some(previous) = settings.insert(keyword, line.num) {
+                let message = format!("`{}` replaces the one on line {}", keyword, previous);
+                warnings.push(Warning { line: line.num, message });
+            }
+        }
+        match keyword {
+            "spectral" => {
+                parse_args::<Float, 0>(line, &line.tokens[1..], "spectral", [])
+                    .map_err(ConfigError::InvalidLine)?;
+                spectral = true;
+            }
+            "caustic_split" => {
+                let [split] = parse_args(line, &line.tokens[1..], "caustic_split", ["n"])
+                    .map_err(ConfigError::InvalidLine)?;
+                if split == 0 {
+                    return Err(ConfigError::InvalidLine(line.error(line.tokens.get(1), "at least one continuation")));
+                }
+                caustic_split = split;
+            }
+            "irradiance_cache" => {
+                let [accuracy, rays, min_spacing, max_spacing]: [Float; 4] = parse_args(
+                    line, &line.tokens[1..], "irradiance_cache", ["accuracy", "rays", "min_spacing", "max_spacing"]
+                ).map_err(ConfigError::InvalidLine)?;
+                if accuracy <= 0.0 {
+                    return Err(ConfigError::InvalidLine(line.error(line.tokens.get(1), "a positive accuracy")));
+                }
+                if rays < 1.0 || rays.fract() != 0.0 {
+                    return Err(ConfigError::InvalidLine(line.error(line.tokens.get(2), "a whole number of rays")));
+                }
+                if min_spacing <= 0.0 {
+                    return Err(ConfigError::InvalidLine(line.error(line.tokens.get(3), "a positive spacing")));
+                }
+                if max_spacing < min_spacing {
+                    return Err(ConfigError::InvalidLine(line.error(line.tokens.get(4), "a spacing at least `min_spacing`")));
+                }
+                irradiance_cache = Some(IrradianceCache::new(accuracy, rays as u32, min_spacing, max_spacing));
+            }
+            "path_guiding" => {
+                let [passes] = parse_args(line, &line.tokens[1..], "path_guiding", ["passes"])
+                    .map_err(ConfigError::InvalidLine)?;
+                if passes == 0 {
+                    return Err(ConfigError::InvalidLine(line.error(line.tokens.get(1), "at least one training pass")));
+                }
+                path_guide = Some(PathGuide::new(passes));
+            }
+            "fog" => {
+                let [scattering, absorption, g] = parse_args(
+                    line, &line.tokens[1..], "fog", ["scattering", "absorption", "g"]
+                ).map_err(ConfigError::InvalidLine)?;
+                fog = Some(medium(line, line.tokens.get(1), [scattering, absorption, g]).map_err(ConfigError::InvalidLine)?);
+            }
+            "sky" => {
+                let [x, y, z, turbidity, strength]: [Float; 5] = parse_args(
+                    line, &line.tokens[1..], "sky", ["sun_x", "sun_y", "sun_z", "turbidity", "strength"]
+                ).map_err(ConfigError::InvalidLine)?;
+                let sun = nonzero(line, line.tokens.get(1), Vector3::new(x, y, z), "sun direction")
+                    .map_err(ConfigError::InvalidLine)?;
+                if !(1.7..=10.0).contains(&turbidity) {
+                    return Err(ConfigError::InvalidLine(line.error(line.tokens.get(4), "a turbidity between 1.7 and 10")));
+                }
+                if strength < 0.0 {
+                    return Err(ConfigError::InvalidLine(line.error(line.tokens.get(5), "a nonnegative strength")));
+                }
+                // Like object luminances, the sky's brightness is in the scene's units.
+                sky = Some(Sky::new(sun, turbidity, strength * lum_scale));
+            }
+            "up" => {
+                let [x, y, z, angle]: [Float; 4] = parse_args(line, &line.tokens[1..], "up", ["x", "y", "z", "roll"])
+                    .map_err(ConfigError::InvalidLine)?;
+                if Vector3::new(x, y, z).cross(pov.dir).size() == 0.0 {
+                    return Err(ConfigError::InvalidLine(line.error(line.tokens.get(1), "an up direction across the view")));
+                }
+                up = Vector3::new(x, y, z).normalize();
+                roll = angle;
+            }
+            "lens" => {
+                let [radius, focus, blades, rotation]: [Float; 4] = parse_args(
+                    line, &line.tokens[1..], "lens", ["radius", "focus", "blades", "rotation"]
+                ).map_err(ConfigError::InvalidLine)?;
+                if radius < 0.0 {
+                    return Err(ConfigError::InvalidLine(line.error(line.tokens.get(1), "a nonnegative radius")));
+                }
+                if focus <= 0.0 {
+                    return Err(ConfigError::InvalidLine(line.error(line.tokens.get(2), "a positive focus distance")));
+                }
+                if blades < 0.0 || blades.fract() != 0.0 || blades == 1.0 || blades == 2.0 {
+                    return Err(ConfigError::InvalidLine(line.error(line.tokens.get(3), "0 blades for a round aperture, or at least 3")));
+                }
+                lens = Some(Lens { radius, focus, blades: blades as u32, rotation });
+            }
+            "focus_key" => {
+                let [frame, focal_length, focus]: [Float; 3] = parse_args(
+                    line, &line.tokens[1..], "focus_key", ["frame", "focal_length", "focus"]
+                ).map_err(ConfigError::InvalidLine)?;
+                if frame < 0.0 || frame.fract() != 0.0 {
+                    return Err(ConfigError::InvalidLine(line.error(line.tokens.get(1), "a whole, nonnegative frame")));
+                }
+                if focal_length <= 0.0 {
+                    return Err(ConfigError::InvalidLine(line.error(line.tokens.get(2), "a positive focal length")));
+                }
+                // Nothing nearer than a focal length away can be brought into focus.
+                if focus <= focal_length / 1000.0 {
+                    return Err(ConfigError::InvalidLine(line.error(line.tokens.get(3), "a focus distance beyond the focal length")));
+                }
+                if focus_keys.iter().any(|key| key.frame == frame as u32) {
+                    return Err(ConfigError::InvalidLine(line.error(line.tokens.get(1), "a frame without a focus key yet")));
+                }
+                focus_keys.push(FocusKey { frame: frame as u32, focal_length, focus });
+            }
+            "filter" => {
+                let [kind] = parse_args(line, &line.tokens[1..], "filter", ["box|tent|gaussian|mitchell"])
+                    .map_err(ConfigError::InvalidLine)?;
+                filter = kind;
+            }
+            "sampler" => {
+                let [kind] = parse_args(line, &line.tokens[1..], "sampler", ["random|bluenoise|cmj"])
+                    .map_err(ConfigError::InvalidLine)?;
+                sampler = kind;
+            }
+            "sun" => lights.push(parse_sun(line, lum_scale).map_err(ConfigError::InvalidLine)?),
+            "spot" | "point" => lights.push(parse_fixture(line, lum_scale, loader)?),
+            "accel" => (),
+            "group" => {
+                let (mut options, tokens) = Options::split(&line.tokens);
+                let name = tokens.get(1)
+                    .ok_or_else(|| ConfigError::InvalidLine(line.error(None, "<name> in `group <name>`")))?;
+                if let Some(extra) = tokens.get(2) {
+                    return Err(ConfigError::InvalidLine(line.error(Some(extra), "translate, rotate or scale options after `group <name>`")));
+                }
+                let transform = parse_transform(line, &mut options).map_err(ConfigError::InvalidLine)?;
+                options.finish(line, &["translate", "rotate", "scale"]).map_err(ConfigError::InvalidLine)?;
+                groups.push((name.text, line.num, transform));
+            }
+            "end" => {
+                parse_args::<Float, 0>(line, &line.tokens[1..], "end", []).map_err(ConfigError::InvalidLine)?;
+                if groups.pop().is_none() {
+                    return Err(ConfigError::InvalidLine(line.error(line.tokens.first(), "a `group` for `end` to close")));
+                }
+            }
+            "grid" | "scatter" => {
+                let (offsets, object_line) = parse_generator(line).map_err(ConfigError::InvalidLine)?;
+                if offsets.is_empty() {
+                    warnings.push(Warning { line: line.num, message: format!("`{}` makes no objects", keyword) });
+                }
+                for (i, offset) in offsets.into_iter().enumerate() {
+                    // Every copy is parsed the same way, so only the first one's warnings are new.
+                    let mut copy_warnings = vec![];
+                    let parsed = parse_object(&object_line, col_scale, lum_scale, loader, accel, &mut copy_warnings)?;
+                    if i == 0 {
+                        warnings.extend(copy_warnings);
+                    }
+                    let moved = Transform::new(offset, Vector3::new(0.0, 0.0, 0.0), Vector3::new(1.0, 1.0, 1.0));
+                    objects.extend(parsed.into_iter().map(|object| place(object, &moved, &groups)));
+                }
+            }
+            _ => {
+                let parsed = parse_object(line, col_scale, lum_scale, loader, accel, &mut warnings)?;
+                objects.extend(parsed.into_iter().map(|object| place(object, &Transform::default(), &groups)));
+            }
+        }
+    }
+    if let Some((name, num, _)) = groups.last() {
+        return Err(ConfigError::NotEnoughLines(format!("`end` closing group `{}` from line {}", name, num)));
+    }
+
+    warnings.sort_by_key(|warning| warning.line);
+    focus_keys.sort_by_key(|key| key.frame);
+    let mut config = Config { 
         objects,
         pov,
         width,
         height,
         fov,
+        lens,
+        focus_keys,
+        up,
+        roll,
         max_depth,
         num_tries,
-        max_variation
+        filter,
+        sampler,
+        spectral,
+        caustic_split,
+        irradiance_cache,
+        path_guide,
+        fog,
+        sky,
+        lights,
+        light_tree: OnceLock::new(),
+        glowing: OnceLock::new(),
+        accel,
+        warnings
+    };
+    // A still is the first frame, so keys take the place of the header's field of view.
+    config.focus_at(0);
+    Ok(config)
+}
+
+/// Lumens in a watt of light at the wavelength the eye is keenest on, for turning the
+/// units of brightness into those of power.
+const LUMENS_PER_WATT: Float = 683.0;
+
+/// A physical unit a light's strength can be given in by writing it after the number, as
+/// in `60W`, instead of the scene's own units of luminance.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Unit {
+    /// Power given off in all directions, in watts.
+    Watts,
+    /// Power given off in all directions as the eye sees it, in lumens.
+    Lumens,
+    /// Light per unit solid angle, in candela (lumens per steradian).
+    Candela,
+    /// Light reaching a surface square-on, in lux (lumens per square meter).
+    Lux,
+    /// How bright a glowing surface looks, in nits (candela per square meter).
+    Nits
+}
+
+impl Unit {
+    fn suffix(self) -> &'static str {
+        match self {
+            Unit::Watts => "W",
+            Unit::Lumens => "lm",
+            Unit::Candela => "cd",
+            Unit::Lux => "lx",
+            Unit::Nits => "nt"
+        }
+    }
+}
+
+/// A strength like `60W`, in one of `units`, or a bare number in the scene's units.
+fn strength(token: Option<&Token>, units: &[Unit]) -> Option<(Float, Option<Unit>)> {
+    let text = token?.text;
+    for &unit in units {
+        if let Some(number) = text.strip_suffix(unit.suffix()) {
+            return number.parse().ok().map(|value| (value, Some(unit)));
+        }
+    }
+    text.parse().ok().map(|value| (value, None))
+}
+
+/// What `strength` expects of a token, in words.
+fn strength_usage(what: &str, units: &[Unit]) -> String {
+    let examples: Vec<_> = units.iter().map(|unit| format!("`{}`", unit.suffix())).collect();
+    format!("{} in the scene's units or followed by {}", what, examples.join(", "))
+}
+
+/// The color starting a light's line, before its numbers.
+fn light_color(line: &Line, usage: &str) -> Result<Color, ParseError> {
+    let token = line.tokens.get(1);
+    token
+        .and_then(|token| Color::from_string(token.text))
+        .ok_or_else(|| line.error(token, &format!("a color in `{}`", usage)))
+}
+
+/// A directional light from `sun <color> <strength> <x> <y> <z> <diameter>`: light from
+/// the direction `(x, y, z)`, spread over a disc `diameter` radians across, that gives a
+/// surface square-on to it `color` scaled by `strength` in the units of luminance, or by
+/// that many lux (`100000lx`).
+fn parse_sun(line: &Line, lum_scale: Float) -> Result<Light, ParseError> {
+    let color = light_color(line, "sun <color> <strength> <x> <y> <z> <diameter>")?;
+    let units = [Unit::Lux];
+    let strength = match strength(line.tokens.get(2), &units) {
+        Some((lux, Some(Unit::Lux))) => lux / LUMENS_PER_WATT,
+        Some((strength, _)) => strength,
+        None => return Err(line.error(line.tokens.get(2), &strength_usage("a strength", &units)))
+    };
+    let [x, y, z, diameter]: [Float; 4] = parse_args(
+        line, &line.tokens[3..], "sun <color> <strength>", ["x", "y", "z", "diameter"]
+    )?;
+    if strength < 0.0 {
+        return Err(line.error(line.tokens.get(2), "a nonnegative strength"));
+    }
+    let dir = nonzero(line, line.tokens.get(3), Vector3::new(x, y, z), "direction")?;
+    if !(0.0..PI).contains(&diameter) {
+        return Err(line.error(line.tokens.get(6), "a diameter from 0 up to pi"));
+    }
+    Ok(Light::directional(dir, diameter / 2.0, color.scale(strength * lum_scale)))
+}
+
+/// A spot light from `spot <color> <intensity> <x> <y> <z> <dx> <dy> <dz> <inner> <outer>`:
+/// a point at `(x, y, z)` shining along `(dx, dy, dz)`, fully out to `inner` radians off
+/// that axis and fading out by `outer`. A surface square-on to it one unit away gets
+/// `color` scaled by `intensity` in the units of luminance. The intensity can also be in
+/// candela (`64cd`), or the watts (`60W`) or lumens (`800lm`) of a bulb shining every
+/// way, of which the cone gives the part inside it; either way light falls off with the
+/// square of the distance.
+fn parse_spot(line: &Line, lum_scale: Float) -> Result<Light, ParseError> {
+    let color = light_color(line, "spot <color> <intensity> <x> <y> <z> <dx> <dy> <dz> <inner> <outer>")?;
+    let intensity = point_intensity(line)?;
+    let [x, y, z, dx, dy, dz, inner, outer]: [Float; 8] = parse_args(
+        line, &line.tokens[3..], "spot <color> <intensity>", ["x", "y", "z", "dx", "dy", "dz", "inner", "outer"]
+    )?;
+    if intensity < 0.0 {
+        return Err(line.error(line.tokens.get(2), "a nonnegative intensity"));
+    }
+    let dir = nonzero(line, line.tokens.get(6), Vector3::new(dx, dy, dz), "direction")?;
+    if !(0.0..=PI).contains(&outer) {
+        return Err(line.error(line.tokens.get(10), "an outer angle from 0 to pi"));
+    }
+    if !(0.0..=outer).contains(&inner) {
+        return Err(line.error(line.tokens.get(9), "an inner angle from 0 to the outer one"));
+    }
+    Ok(Light::spot(Vector3::new(x, y, z), dir, inner, outer, color.scale(intensity * lum_scale)))
+}
+
+/// A point light's intensity from the third token of its line, in scene units.
+fn point_intensity(line: &Line) -> Result<Float, ParseError> {
+    let units = [Unit::Watts, Unit::Lumens, Unit::Candela];
+    Ok(match strength(line.tokens.get(2), &units) {
+        Some((watts, Some(Unit::Watts))) => watts / (4.0 * PI),
+        Some((lumens, Some(Unit::Lumens))) => lumens / (4.0 * PI * LUMENS_PER_WATT),
+        Some((candela, Some(_))) => candela / LUMENS_PER_WATT,
+        Some((intensity, None)) => intensity,
+        None => return Err(line.error(line.tokens.get(2), &strength_usage("an intensity", &units)))
+    })
+}
+
+/// A point light from `point <color> <intensity> <x> <y> <z>`: a spot light shining the
+/// same every way.
+fn parse_point(line: &Line, lum_scale: Float) -> Result<Light, ParseError> {
+    let color = light_color(line, "point <color> <intensity> <x> <y> <z>")?;
+    let intensity = point_intensity(line)?;
+    let [x, y, z]: [Float; 3] = parse_args(line, &line.tokens[3..], "point <color> <intensity>", ["x", "y", "z"])?;
+    if intensity < 0.0 {
+        return Err(line.error(line.tokens.get(2), "a nonnegative intensity"));
+    }
+    let down = Vector3::new(0.0, 0.0, -1.0);
+    Ok(Light::spot(Vector3::new(x, y, z), down, PI, PI, color.scale(intensity * lum_scale)))
+}
+
+/// A `spot` or `point` light, either of which can take `ies=<file>`: the measured
+/// profile of a real fixture, shaping its light around `dir` as straight down (a point
+/// light's is down the z axis). The intensity is then that of the profile's brightest
+/// direction.
+fn parse_fixture(line: &Line, lum_scale: Float, loader: &Loader) -> ConfigResult<Light> {
+    let (mut options, tokens) = Options::split(&line.tokens);
+    let positional = Line { num: line.num, text: line.text, tokens };
+    let light = match positional.tokens[0].text {
+        "spot" => parse_spot(&positional, lum_scale),
+        _ => parse_point(&positional, lum_scale)
+    }.map_err(ConfigError::InvalidLine)?;
+    let profile = options.take("ies").map(|(_, file)| loader.assets.profile(&loader.dir.join(file))).transpose()?;
+    options.finish(line, &["ies"]).map_err(ConfigError::InvalidLine)?;
+    Ok(match profile {
+        Some(profile) => light.with_profile(profile),
+        None => light
     })
 }
 
-pub fn parse_config_file(path: &PathBuf) -> ConfigResult<Config> {
-    fs::read_to_string(path)
-        .map_err(ConfigError::IOError)
-        .and_then(|contents| parse_config(&contents))
+pub fn parse_config_file(path: &Path) -> ConfigResult<Config> {
+    load_config(path, &Assets::new())
+}
+
+/// Parses the scene file at `path`, reusing and adding to the files already in `assets`.
+/// `.gltf` and `.glb` files are imported rather than parsed.
+pub fn load_config(path: &Path, assets: &Assets) -> ConfigResult<Config> {
+    if let Some("gltf" | "glb") = path.extension().and_then(|ext| ext.to_str()) {
+        return crate::import::load_gltf(path, assets);
+    }
+    let in_file = |err| ConfigError::InFile(path.to_path_buf(), Box::new(err));
+    let raw = fs::read_to_string(path).map_err(|err| in_file(ConfigError::IOError(err)))?;
+    let dir = path.parent().unwrap_or(Path::new("."));
+    parse_config_in(&raw, &Variables::new(), &Loader { dir, assets }).map_err(in_file)
 }
\ No newline at end of file
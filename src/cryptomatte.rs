@@ -0,0 +1,184 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use image::{ImageBuffer, Luma};
+use rayon::prelude::*;
+
+use crate::config::{Config, ConfigError, ConfigResult};
+use crate::export::material_name;
+use crate::linalg::Float;
+use crate::output::{write_exr, Channels};
+use crate::random::LocalRng;
+use crate::trace::{camera_sample, closest_hit, primary_ray, RayKind};
+
+/// How many of the things seen through a pixel a Cryptomatte layer keeps, the most covering
+/// first: two to each set of four channels.
+const RANKS: usize = 6;
+
+/// What an ID pass tells apart.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Key {
+    Object,
+    Material
+}
+
+impl Key {
+    /// The layer's name, as compositors know it.
+    fn layer(self) -> &'static str {
+        match self {
+            Key::Object => "CryptoObject",
+            Key::Material => "CryptoMaterial"
+        }
+    }
+}
+
+/// A name for each of the scene's objects under `key`: objects by the scene line they come
+/// from, materials by their color and settings, so that a mesh's parts, or everything that
+/// looks alike, go together.
+pub fn names(config: &Config, key: Key) -> Vec<String> {
+    config.objects.iter().enumerate().map(|(index, obj)| match key {
+        Key::Object if obj.line > 0 => format!("line {}", obj.line),
+        Key::Object => format!("object {}", index),
+        Key::Material => material_name(obj.color, &obj.material)
+    }).collect()
+}
+
+/// A number for what the center of each pixel sees under `key`, row by row from the top:
+/// from 1 in the order the scene first names them, with 0 for the background.
+pub fn id_pass(config: &Config, key: Key) -> Vec<usize> {
+    let mut numbers = HashMap::new();
+    let ids: Vec<usize> = names(config, key).into_iter().map(|name| {
+        let next = numbers.len() + 1;
+        *numbers.entry(name).or_insert(next)
+    }).collect();
+    (0..config.height).into_par_iter().flat_map_iter(|y| {
+        let ids = &ids;
+        (0..config.width)
+            .map(move |x| closest_hit(&config.objects, primary_ray(config, x, y), RayKind::Camera).map_or(0, |(i, _)| ids[i]))
+    }).collect()
+}
+
+/// Writes `id_pass` as a 16-bit PNG at `path`.
+pub fn save_id_pass(config: &Config, key: Key, path: &Path) -> ConfigResult<()> {
+    let ids = id_pass(config, key);
+    let image = ImageBuffer::from_fn(config.width, config.height, |x, y| {
+        Luma([ids[(y * config.width + x) as usize].min(u16::MAX as usize) as u16])
+    });
+    image.save(path).map_err(|err| ConfigError::ImageError(path.to_path_buf(), err))
+}
+
+/// Writes an OpenEXR file at `path` of nothing but the `cryptomatte` layers for `keys`.
+pub fn save_cryptomatte(config: &Config, keys: &[Key], path: &Path) -> ConfigResult<()> {
+    let (channels, attributes) = cryptomatte(config, keys);
+    write_exr(path, config.width, config.height, &channels, &attributes)
+}
+
+/// A Cryptomatte layer for each of `keys`, with the header attributes describing them: for
+/// every pixel, the hashed names of what the scene's samples there saw under that key,
+/// with how much of the pixel each covers, and a manifest naming the hashes.
+// Cryptomatte stores everything as `f32`, which narrowing is a no-op with the `f32` feature.
+#[allow(clippy::unnecessary_cast)]
+pub fn cryptomatte(config: &Config, keys: &[Key]) -> (Channels, Vec<(String, String)>) {
+    // The objects each pixel's samples see, with their filter weights; misses count toward
+    // the whole without covering anything.
+    let pixels: Vec<(Vec<(usize, Float)>, Float)> = (0..config.height).into_par_iter().flat_map_iter(|y| {
+        (0..config.width).map(move |x| {
+            let mut seen: Vec<(usize, Float)> = vec![];
+            let mut total = 0.0;
+            for _ in 0..config.num_tries {
+                let (ray, weight) = camera_sample(config, x, y, &mut LocalRng);
+                total += weight;
+                if let Some((index, _)) = closest_hit(&config.objects, ray, RayKind::Camera) {
+                    match seen.iter_mut().find(|(seen, _)| *seen == index) {
+                        Some((_, coverage)) => *coverage += weight,
+                        None => seen.push((index, weight))
+                    }
+                }
+            }
+            (seen, total)
+        })
+    }).collect();
+
+    let mut channels = vec![];
+    let mut attributes = vec![];
+    for &key in keys {
+        let names = names(config, key);
+        let hashes: Vec<u32> = names.iter().map(|name| to_float_bits(murmur3(name.as_bytes()))).collect();
+        let mut ranks: Vec<Vec<f32>> = (0..2 * RANKS).map(|_| Vec::with_capacity(pixels.len())).collect();
+        for (seen, total) in &pixels {
+            let mut coverage: Vec<(u32, Float)> = vec![];
+            for &(index, weight) in seen {
+                match coverage.iter_mut().find(|(hash, _)| *hash == hashes[index]) {
+                    Some((_, sum)) => *sum += weight,
+                    None => coverage.push((hashes[index], weight))
+                }
+            }
+            coverage.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+            coverage.resize(RANKS.max(coverage.len()), (0, 0.0));
+            for (rank, &(hash, sum)) in coverage.iter().take(RANKS).enumerate() {
+                let fraction = if *total != 0.0 { sum / total } else { 0.0 };
+                ranks[2 * rank].push(f32::from_bits(hash));
+                ranks[2 * rank + 1].push(fraction as f32);
+            }
+        }
+
+        // Each set of channels holds two ranks: an ID and its coverage, then another.
+        let layer = key.layer();
+        for (i, values) in ranks.into_iter().enumerate() {
+            let name = format!("{}{:02}.{}", layer, i / 4, ["R", "G", "B", "A"][i % 4]);
+            channels.push((name, values));
+        }
+        let mut manifest: Vec<_> = names.iter().zip(&hashes).collect();
+        manifest.sort();
+        manifest.dedup();
+        let entries: Vec<String> = manifest.iter()
+            .map(|(name, hash)| format!("\"{}\":\"{:08x}\"", escape(name), hash))
+            .collect();
+        let id = &format!("{:08x}", murmur3(layer.as_bytes()))[..7];
+        attributes.push((format!("cryptomatte/{}/name", id), layer.to_string()));
+        attributes.push((format!("cryptomatte/{}/hash", id), "MurmurHash3_32".to_string()));
+        attributes.push((format!("cryptomatte/{}/conversion", id), "uint32_to_float32".to_string()));
+        attributes.push((format!("cryptomatte/{}/manifest", id), format!("{{{}}}", entries.join(","))));
+    }
+    (channels, attributes)
+}
+
+/// `name` as it goes between the quotes of a JSON string.
+fn escape(name: &str) -> String {
+    name.chars().flat_map(|c| match c {
+        '"' | '\\' => vec!['\\', c],
+        c => vec![c]
+    }).collect()
+}
+
+/// A hash of `bytes` made, like Cryptomatte's own, with 32-bit MurmurHash3 and seed 0.
+fn murmur3(bytes: &[u8]) -> u32 {
+    const C1: u32 = 0xcc9e_2d51;
+    const C2: u32 = 0x1b87_3593;
+    let mix = |k: u32| k.wrapping_mul(C1).rotate_left(15).wrapping_mul(C2);
+
+    let mut hash = 0u32;
+    let blocks = bytes.chunks_exact(4);
+    let tail = blocks.remainder();
+    for block in blocks {
+        let k = u32::from_le_bytes([block[0], block[1], block[2], block[3]]);
+        hash = (hash ^ mix(k)).rotate_left(13).wrapping_mul(5).wrapping_add(0xe654_6b64);
+    }
+    if !tail.is_empty() {
+        let k = tail.iter().rev().fold(0u32, |k, &byte| (k << 8) | byte as u32);
+        hash ^= mix(k);
+    }
+    hash ^= bytes.len() as u32;
+    hash ^= hash >> 16;
+    hash = hash.wrapping_mul(0x85eb_ca6b);
+    hash ^= hash >> 13;
+    hash = hash.wrapping_mul(0xc2b2_ae35);
+    hash ^ (hash >> 16)
+}
+
+/// `hash` changed, as Cryptomatte does, so that its bits read as a float are neither
+/// infinite, NaN nor denormal.
+fn to_float_bits(hash: u32) -> u32 {
+    let exponent = (hash >> 23) & 0xff;
+    if exponent == 0 || exponent == 0xff { hash ^ (1 << 23) } else { hash }
+}
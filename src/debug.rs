@@ -0,0 +1,98 @@
+use std::str::FromStr;
+
+use rayon::prelude::*;
+
+use crate::config::Config;
+use crate::film::Film;
+use crate::linalg::Float;
+use crate::profile::Profiler;
+use crate::random::LocalRng;
+use crate::shapes::Hit;
+use crate::stats;
+use crate::trace::{closest_hit, primary_ray, sample_pixel, Color, RayKind};
+
+/// What a debug render shows in false color instead of the lit scene.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DebugMode {
+    /// The shading normal, its x, y and z mapped from -1..1 to red, green and blue.
+    Normals,
+    /// Distance to what the pixel sees, white up close fading to black at the farthest.
+    Depth,
+    /// Surface coordinates, wrapped to 0..1, as red and green.
+    Uv,
+    /// How many surfaces a sample's paths meet, on average, from black through blue,
+    /// red and yellow to white at `max_depth`.
+    Bounces
+}
+
+impl FromStr for DebugMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "normals" => Ok(DebugMode::Normals),
+            "depth" => Ok(DebugMode::Depth),
+            "uv" => Ok(DebugMode::Uv),
+            "bounces" => Ok(DebugMode::Bounces),
+            _ => Err(format!("Expected normals, depth, uv or bounces but got {:?}", s))
+        }
+    }
+}
+
+/// `value`, from 0 to 1, on a ramp from black through blue, red and yellow to white.
+pub fn heat(value: Float) -> Color {
+    const STOPS: [Color; 5] = [
+        Color::BLACK,
+        Color::BLUE,
+        Color::RED,
+        Color::YELLOW,
+        Color::WHITE
+    ];
+    let scaled = value.clamp(0.0, 1.0) * (STOPS.len() - 1) as Float;
+    let i = (scaled as usize).min(STOPS.len() - 2);
+    let frac = scaled - i as Float;
+    STOPS[i].scale(1.0 - frac) + STOPS[i + 1].scale(frac)
+}
+
+/// Renders `mode` through the center of every pixel, or for `DebugMode::Bounces`,
+/// averaged over `num_tries` samples. Pixels that see nothing are black.
+pub fn make_debug_image(config: &Config, mode: DebugMode, profiler: &Profiler) -> Film {
+    let hit_at = |x: u32, y: u32| -> Option<Hit> {
+        closest_hit(&config.objects, primary_ray(config, x, y), RayKind::Camera).map(|(_, hit)| hit)
+    };
+    let rows: Vec<Vec<Option<Color>>> = (0..config.height).into_par_iter().map(|y| {
+        profiler.span("tile", || format!("row {}", y), || (0..config.width).map(|x| match mode {
+            DebugMode::Normals => hit_at(x, y).map(|hit| {
+                let n = hit.normal;
+                Color::new(n.x + 1.0, n.y + 1.0, n.z + 1.0).scale(255.0 / 2.0)
+            }),
+            // Scaled to the farthest depth once every row is in.
+            DebugMode::Depth => hit_at(x, y).map(|hit| Color::new(hit.t, hit.t, hit.t)),
+            DebugMode::Uv => hit_at(x, y).map(|hit| {
+                let (u, v) = hit.uv;
+                Color::new(u.rem_euclid(1.0), v.rem_euclid(1.0), 0.0).scale(255.0)
+            }),
+            DebugMode::Bounces => {
+                stats::take();
+                for _ in 0..config.num_tries {
+                    sample_pixel(config, x, y, &mut LocalRng);
+                }
+                let mean = stats::take().bounces as Float / config.num_tries.max(1) as Float;
+                Some(heat(mean / config.max_depth.max(1) as Float))
+            }
+        }).collect())
+    }).collect();
+
+    let far = rows.iter().flatten().flatten().map(|depth| depth.x).fold(0.0, Float::max);
+    let mut film = Film::new(config.width, config.height);
+    for (y, row) in rows.into_iter().enumerate() {
+        for (x, color) in row.into_iter().enumerate() {
+            let color = match (mode, color) {
+                (DebugMode::Depth, Some(depth)) => Color::WHITE.scale(1.0 - depth.x / far),
+                (_, color) => color.unwrap_or(Color::BLACK)
+            };
+            film.add_sample(x as u32, y as u32, color);
+        }
+    }
+    film
+}
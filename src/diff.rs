@@ -0,0 +1,154 @@
+use std::sync::Arc;
+
+use crate::config::Config;
+use crate::film::Region;
+use crate::irradiance::IrradianceCache;
+use crate::linalg::{Float, Vector3};
+use crate::shapes::Shape;
+use crate::trace::{project, Object};
+
+/// How an edited scene differs from the one rendered before it, as far as the samples
+/// already taken go.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change {
+    /// Nothing that shows: the samples all still hold.
+    Nothing,
+    /// Only objects that give off no light, at these indices into the old scene's objects
+    /// and the new one's. Pixels that see them need sampling again; for a quick preview,
+    /// what they do to light elsewhere, like the shadows they cast, is let go.
+    Objects { old: Vec<usize>, new: Vec<usize> },
+    /// The camera, the image or the lighting: every sample is out of date.
+    Everything
+}
+
+fn same_shape(a: &dyn Shape, b: &dyn Shape) -> bool {
+    if let (Some(a), Some(b)) = (a.as_transformed(), b.as_transformed()) {
+        return a.transform == b.transform && same_shape(&*a.shape, &*b.shape);
+    }
+    match (a.params(), b.params(), a.as_mesh(), b.as_mesh()) {
+        (Some(a), Some(b), _, _) => a == b,
+        (None, None, Some(a), Some(b)) => a.offset == b.offset && a.scale == b.scale
+            && (Arc::ptr_eq(&a.data, &b.data) || a.data.triangles().eq(b.data.triangles())),
+        _ => matches!((a.as_heightfield(), b.as_heightfield()), (Some(a), Some(b)) if a == b)
+    }
+}
+
+/// Whether two objects look the same, wherever their lines are.
+fn same_object(a: &Object, b: &Object) -> bool {
+    a.color == b.color && a.lum == b.lum && a.material == b.material && a.backface == b.backface
+        && a.cutout == b.cutout && a.visibility == b.visibility
+        && same_shape(&*a.shape, &*b.shape)
+}
+
+/// Whether two scenes' irradiance caches, if they have them, are set up alike, whatever
+/// they have measured so far.
+fn same_cache(a: &Option<IrradianceCache>, b: &Option<IrradianceCache>) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => a.accuracy == b.accuracy && a.rays == b.rays
+            && a.min_spacing == b.min_spacing && a.max_spacing == b.max_spacing,
+        (a, b) => a.is_none() && b.is_none()
+    }
+}
+
+/// What has to be rendered again after `old` is edited into `new`.
+pub fn diff(old: &Config, new: &Config) -> Change {
+    // How many samples a pass takes, how caustics are split up and what meshes are traced
+    // with only change the noise or the speed, not what the samples converge to.
+    let same_view = old.pov.pos == new.pov.pos && old.pov.dir == new.pov.dir
+        && old.width == new.width && old.height == new.height
+        && old.fov == new.fov && old.lens == new.lens && old.up == new.up && old.roll == new.roll
+        && old.filter == new.filter;
+    let same_light = old.max_depth == new.max_depth && old.spectral == new.spectral
+        && same_cache(&old.irradiance_cache, &new.irradiance_cache)
+        && old.fog == new.fog && old.sky == new.sky && old.lights == new.lights;
+    if !same_view || !same_light {
+        return Change::Everything;
+    }
+
+    // Objects are matched in order first, since most edits leave most of them in place,
+    // then anywhere, in case lines were added or moved.
+    let mut matched_old = vec![false; old.objects.len()];
+    let mut unmatched_new = vec![];
+    for (i, object) in new.objects.iter().enumerate() {
+        if i < old.objects.len() && !matched_old[i] && same_object(&old.objects[i], object) {
+            matched_old[i] = true;
+        } else {
+            unmatched_new.push(i);
+        }
+    }
+    let mut changed_new = vec![];
+    for i in unmatched_new {
+        let found = old.objects.iter().enumerate()
+            .position(|(j, object)| !matched_old[j] && same_object(object, &new.objects[i]));
+        match found {
+            Some(j) => matched_old[j] = true,
+            None => changed_new.push(i)
+        }
+    }
+    let changed_old: Vec<usize> = (0..old.objects.len()).filter(|&j| !matched_old[j]).collect();
+
+    let glows = |object: &Object| object.lum.x > 0.0 || object.lum.y > 0.0 || object.lum.z > 0.0;
+    if changed_old.iter().any(|&j| glows(&old.objects[j])) || changed_new.iter().any(|&i| glows(&new.objects[i])) {
+        Change::Everything
+    } else if changed_old.is_empty() && changed_new.is_empty() {
+        Change::Nothing
+    } else {
+        Change::Objects { old: changed_old, new: changed_new }
+    }
+}
+
+/// Corners of a box holding `shape`, or `None` if it has no bounds or they aren't known.
+pub(crate) fn corners(shape: &dyn Shape) -> Option<Vec<Vector3>> {
+    if let Some(placed) = shape.as_transformed() {
+        // The box's corners, placed, still hold the placed shape, and more tightly than
+        // a box around them would.
+        let inner = corners(&*placed.shape)?;
+        return Some(inner.into_iter().map(|corner| placed.transform.point(corner)).collect());
+    }
+    Some(shape.bounds()?.corners().to_vec())
+}
+
+/// The pixels of `config` whose samples can see any of `objects`, or `None` if that
+/// could be anywhere, as for planes, objects reaching behind the camera or a lens blurring
+/// things across the image.
+fn region_seeing(config: &Config, objects: &[usize]) -> Option<Region> {
+    if objects.is_empty() {
+        return Some(Region { x: 0..0, y: 0..0 });
+    }
+    if config.lens.is_some() {
+        return None;
+    }
+    let (mut min_x, mut min_y) = (Float::INFINITY, Float::INFINITY);
+    let (mut max_x, mut max_y) = (Float::NEG_INFINITY, Float::NEG_INFINITY);
+    for &i in objects {
+        let seen: Vec<_> = corners(&*config.objects[i].shape)?.into_iter().map(|corner| project(config, corner)).collect();
+        // Wholly behind the camera it can't be seen at all; partly, it could be anywhere.
+        if seen.iter().all(Option::is_none) {
+            continue;
+        }
+        for point in seen {
+            let (x, y) = point?;
+            min_x = min_x.min(x);
+            min_y = min_y.min(y);
+            max_x = max_x.max(x);
+            max_y = max_y.max(y);
+        }
+    }
+    // Samples land up to the filter's radius from the pixel they count toward, and a
+    // pixel more covers rounding.
+    if min_x > max_x {
+        return Some(Region { x: 0..0, y: 0..0 });
+    }
+    let margin = config.filter.radius() + 1.0;
+    let clamp = |value: Float, size: u32| value.max(0.0).min(size as Float) as u32;
+    let x = clamp((min_x - margin).floor(), config.width)..clamp((max_x + margin).ceil(), config.width);
+    let y = clamp((min_y - margin).floor(), config.height)..clamp((max_y + margin).ceil(), config.height);
+    Some(Region { x, y })
+}
+
+/// The pixels that need sampling again after the objects of a `Change::Objects` change:
+/// those that could see them as they were in `old` or as they are in `new`. `None` means
+/// the whole image.
+pub fn dirty_region(old: &Config, old_objects: &[usize], new: &Config, new_objects: &[usize]) -> Option<Region> {
+    Some(region_seeing(old, old_objects)?.union(&region_seeing(new, new_objects)?))
+}
@@ -0,0 +1,120 @@
+use std::sync::OnceLock;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+const BAYER_SIZE: usize = 8;
+const BLUE_NOISE_SIZE: usize = 64;
+
+/// Threshold in [0, 1) from an 8×8 Bayer matrix, tiled over the image.
+pub fn bayer(x: u32, y: u32) -> f64 {
+    // Bit-reverse the interleaved bits of x ^ y and y.
+    let (x, y) = (x as usize % BAYER_SIZE, y as usize % BAYER_SIZE);
+    let xor = x ^ y;
+    let mut rank = 0;
+    for bit in 0..3 {
+        rank = (rank << 2) | (((xor >> bit) & 1) << 1) | ((y >> bit) & 1);
+    }
+    (rank as f64 + 0.5) / (BAYER_SIZE * BAYER_SIZE) as f64
+}
+
+/// Threshold in [0, 1) from a 64×64 blue-noise mask, tiled over the image.
+pub fn blue_noise(x: u32, y: u32) -> f64 {
+    let mask = blue_noise_mask();
+    let index = (y as usize % BLUE_NOISE_SIZE) * BLUE_NOISE_SIZE + x as usize % BLUE_NOISE_SIZE;
+    (mask[index] as f64 + 0.5) / mask.len() as f64
+}
+
+/// Threshold in [0, 1) from the blue-noise mask shifted around the torus for `layer`,
+/// by steps of the R2 sequence, so that different layers are all but independent.
+pub fn blue_noise_layer(x: u32, y: u32, layer: u32) -> f64 {
+    let shift = |alpha: f64| ((layer as f64 * alpha).fract() * BLUE_NOISE_SIZE as f64) as u32;
+    blue_noise(x + shift(0.754_877_666_246_692_8), y + shift(0.569_840_290_998_053_2))
+}
+
+fn blue_noise_mask() -> &'static [u32] {
+    static MASK: OnceLock<Vec<u32>> = OnceLock::new();
+    MASK.get_or_init(|| void_and_cluster(BLUE_NOISE_SIZE, 1.5))
+}
+
+/// Ranks every pixel of a `size`×`size` torus with Ulichney's void-and-cluster
+/// method, so that thresholding the ranks at any level gives evenly spread points.
+fn void_and_cluster(size: usize, sigma: f64) -> Vec<u32> {
+    let n = size * size;
+    let kernel: Vec<f64> = (0..n).map(|i| {
+        let wrap = |d: usize| d.min(size - d) as f64;
+        let (dx, dy) = (wrap(i % size), wrap(i / size));
+        (-(dx * dx + dy * dy) / (2.0 * sigma * sigma)).exp()
+    }).collect();
+
+    // How crowded each pixel's neighborhood is, given which pixels are on.
+    struct Field<'a> { size: usize, kernel: &'a [f64], on: Vec<bool>, energy: Vec<f64> }
+    impl Field<'_> {
+        fn toggle(&mut self, i: usize) {
+            self.on[i] = !self.on[i];
+            let sign = if self.on[i] { 1.0 } else { -1.0 };
+            let (x, y) = (i % self.size, i / self.size);
+            for (j, energy) in self.energy.iter_mut().enumerate() {
+                let dx = (j % self.size + self.size - x) % self.size;
+                let dy = (j / self.size + self.size - y) % self.size;
+                *energy += sign * self.kernel[dy * self.size + dx];
+            }
+        }
+
+        fn tightest_cluster(&self) -> usize {
+            self.pick(true, |a, b| a > b)
+        }
+
+        fn largest_void(&self) -> usize {
+            self.pick(false, |a, b| a < b)
+        }
+
+        fn pick(&self, on: bool, better: impl Fn(f64, f64) -> bool) -> usize {
+            (0..self.on.len())
+                .filter(|&i| self.on[i] == on)
+                .reduce(|best, i| if better(self.energy[i], self.energy[best]) { i } else { best })
+                .unwrap()
+        }
+    }
+
+    let mut field = Field { size, kernel: &kernel, on: vec![false; n], energy: vec![0.0; n] };
+    let mut rng = StdRng::seed_from_u64(0);
+    let initial = n / 10;
+    while field.on.iter().filter(|&&on| on).count() < initial {
+        let i = rng.gen_range(0..n);
+        if !field.on[i] {
+            field.toggle(i);
+        }
+    }
+
+    // Spread the initial points out until moving the most crowded one doesn't help.
+    loop {
+        let cluster = field.tightest_cluster();
+        field.toggle(cluster);
+        let void = field.largest_void();
+        field.toggle(void);
+        if void == cluster {
+            break;
+        }
+    }
+    let initial_on = field.on.clone();
+
+    let mut ranks = vec![0; n];
+    // Ranks below the initial points come from removing the most crowded first...
+    for rank in (0..initial).rev() {
+        let cluster = field.tightest_cluster();
+        field.toggle(cluster);
+        ranks[cluster] = rank as u32;
+    }
+    // ...and ranks above from filling the emptiest spots.
+    let removed: Vec<usize> = (0..n).filter(|&i| initial_on[i] && !field.on[i]).collect();
+    for i in removed {
+        field.toggle(i);
+    }
+    for rank in initial..n {
+        let void = field.largest_void();
+        field.toggle(void);
+        ranks[void] = rank as u32;
+    }
+    ranks
+}
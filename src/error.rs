@@ -0,0 +1,80 @@
+use std::fmt;
+use std::io;
+
+use crate::config::ConfigError;
+
+/// What stopped a scene that loaded from being rendered, a render from being served, or
+/// golden renders from matching.
+#[derive(Debug)]
+pub enum RenderError {
+    /// Not even a single pixel's frame buffers fit in the memory allowed.
+    OutOfMemory,
+    /// No worker could render the tiles it was given; the last failure is the `source`.
+    Workers(io::Error),
+    /// The port couldn't be listened on; why is the `source`.
+    Listen(u16, io::Error),
+    /// This many golden scenes, out of the second number, no longer match their references.
+    Golden(usize, usize),
+    /// The render was asked to stop before it finished.
+    Cancelled
+}
+
+impl fmt::Display for RenderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RenderError::OutOfMemory => write!(f, "Not enough memory for even a single pixel"),
+            RenderError::Workers(_) => write!(f, "Could not render on the workers"),
+            RenderError::Listen(port, _) => write!(f, "Could not listen on port {}", port),
+            RenderError::Golden(failed, total) =>
+                write!(f, "{} of {} golden images differ from their references", failed, total),
+            RenderError::Cancelled => write!(f, "The render was cancelled")
+        }
+    }
+}
+
+impl std::error::Error for RenderError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            RenderError::OutOfMemory | RenderError::Golden(..) | RenderError::Cancelled => None,
+            RenderError::Workers(err) | RenderError::Listen(_, err) => Some(err)
+        }
+    }
+}
+
+/// Anything that can stop a run: loading the scene or saving its image, or rendering it.
+#[derive(Debug)]
+pub enum Error {
+    Config(ConfigError),
+    Render(RenderError)
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Each shows as the error inside, alternate form and all.
+        match self {
+            Error::Config(err) => fmt::Display::fmt(err, f),
+            Error::Render(err) => fmt::Display::fmt(err, f)
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Config(err) => err.source(),
+            Error::Render(err) => err.source()
+        }
+    }
+}
+
+impl From<ConfigError> for Error {
+    fn from(err: ConfigError) -> Self {
+        Error::Config(err)
+    }
+}
+
+impl From<RenderError> for Error {
+    fn from(err: RenderError) -> Self {
+        Error::Render(err)
+    }
+}
@@ -0,0 +1,406 @@
+use std::collections::hash_map::{Entry, HashMap};
+use std::fmt::{self, Write};
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+
+use crate::config::{Config, ConfigError, ConfigResult};
+use crate::filter::Filter;
+use crate::heightfield::Source;
+use crate::light::Light;
+use crate::linalg::Float;
+use crate::mesh::{Accel, Mesh};
+use crate::sampler::Sampler;
+use crate::sdf::Sdf;
+use crate::shapes::Shape;
+use crate::texture::Cutout;
+use crate::trace::{Backface, Color, Glass, Material, Object, Principled, ThinGlass};
+use crate::transform::Transform;
+
+/// Colors a scene can call by name, so exported scenes read like hand-written ones.
+const NAMED: [(&str, Color); 6] = [
+    ("black", Color::BLACK),
+    ("white", Color::WHITE),
+    ("red", Color::RED),
+    ("green", Color::GREEN),
+    ("blue", Color::BLUE),
+    ("yellow", Color::YELLOW)
+];
+
+/// Writes `color`, whose channels should be at most 255 give or take rounding.
+fn color_text(color: Color) -> String {
+    let color = Color::new(color.x.min(255.0), color.y.min(255.0), color.z.min(255.0));
+    NAMED.iter()
+        .find(|(_, named)| (named.x, named.y, named.z) == (color.x, color.y, color.z))
+        .map_or_else(|| format!("rgb({},{},{})", color.x, color.y, color.z), |(name, _)| name.to_string())
+}
+
+fn max_channel(color: Color) -> Float {
+    color.x.max(color.y).max(color.z)
+}
+
+/// The color and strength words for a light of `light`, its color as bright as it goes.
+fn light_text(light: Color) -> (String, Float) {
+    let strength = max_channel(light) / 255.0;
+    let color = if strength > 0.0 { light.scale(1.0 / strength) } else { Color::BLACK };
+    (color_text(color), strength)
+}
+
+/// The `<luminance>` word for an object whose color was written as `color`, and an
+/// `emit` option if its light isn't a multiple of that color.
+fn lum_text(color: Color, lum: Color) -> (String, Option<String>) {
+    if max_channel(lum) <= 0.0 {
+        return ("0".to_string(), None);
+    }
+    let channels = [(color.x, lum.x), (color.y, lum.y), (color.z, lum.z)];
+    let factor = max_channel(lum) / max_channel(color);
+    let proportional = channels.iter()
+        .all(|&(c, l)| (c * factor - l).abs() <= 1e-9 * l.abs().max(1.0));
+    if proportional {
+        (factor.to_string(), None)
+    } else {
+        let factor = max_channel(lum) / 255.0;
+        (factor.to_string(), Some(format!("emit={}", color_text(lum.scale(1.0 / factor)))))
+    }
+}
+
+fn glass_options(glass: &Glass, options: &mut Vec<String>) {
+    match glass.cauchy {
+        Some(cauchy) => options.push(format!("cauchy={},{}", cauchy.a, cauchy.b)),
+        None if glass.ior != Glass::default().ior => options.push(format!("ior={}", glass.ior)),
+        None => ()
+    }
+    if let Some(sigma) = glass.absorption {
+        // Over one unit, strong absorption leaves less than a color can say; a shorter depth keeps it in range.
+        let depth = (10.0 / max_channel(sigma)).min(1.0);
+        let fade = |sigma: Float| 255.0 * (-sigma * depth).exp();
+        options.push(format!("absorb={}", color_text(Color::new(fade(sigma.x), fade(sigma.y), fade(sigma.z)))));
+        if depth != 1.0 {
+            options.push(format!("absorb_depth={}", depth));
+        }
+    }
+    if let Some(medium) = glass.medium {
+        options.push(format!("medium={},{},{}", medium.scattering, medium.absorption, medium.g));
+    }
+    if let Some(tint) = glass.tint {
+        options.push(format!("tint={}", color_text(tint)));
+    }
+}
+
+/// The material word(s) of an object line, adding its options to `options`.
+fn material_text(material: &Material, options: &mut Vec<String>) -> String {
+    match material {
+        Material::Mirror(roughness) => {
+            if *roughness > 0.0 {
+                options.push(format!("roughness={}", roughness));
+            }
+            "mirror".to_string()
+        }
+        Material::Coated(coat, base) => {
+            let text = material_text(base, options);
+            options.push(format!("coat={},{}", coat.strength, coat.roughness));
+            text
+        }
+        Material::Principled(principled) => {
+            let default = Principled::default();
+            let dials = [
+                ("metallic", principled.metallic, default.metallic),
+                ("roughness", principled.roughness, default.roughness),
+                ("specular", principled.specular, default.specular),
+                ("transmission", principled.transmission, default.transmission),
+                ("ior", principled.ior, default.ior)
+            ];
+            for &(key, value, default) in &dials {
+                if value != default {
+                    options.push(format!("{}={}", key, value));
+                }
+            }
+            "principled".to_string()
+        }
+        Material::Thin(thin) => {
+            if thin.ior != ThinGlass::default().ior {
+                options.push(format!("ior={}", thin.ior));
+            }
+            if let Some(thickness) = thin.film {
+                options.push(format!("film={}", thickness));
+            }
+            if let Some(tint) = thin.tint {
+                options.push(format!("tint={}", color_text(tint)));
+            }
+            "thin".to_string()
+        }
+        Material::Catcher(reflection) => {
+            if *reflection > 0.0 {
+                options.push(format!("reflect={}", reflection));
+            }
+            "catcher".to_string()
+        }
+        Material::Translucent(clearness, glass) => {
+            let before = options.len();
+            glass_options(glass, options);
+            match clearness {
+                c if *c == 0.0 && options.len() == before => "opaque".to_string(),
+                c if *c == 1.0 => "glass".to_string(),
+                c => format!("translucent {}", c)
+            }
+        }
+    }
+}
+
+/// How an object with `color` and `material` looks, written as on its scene line: the
+/// color, the material and the material's options.
+pub(crate) fn material_name(color: Color, material: &Material) -> String {
+    let mut options = vec![];
+    let material = material_text(material, &mut options);
+    let mut name = format!("{} {}", color_text(color), material);
+    options.iter().for_each(|option| { name.push(' '); name.push_str(option); });
+    name
+}
+
+/// The shape an object was given as, and the transforms placing it, outermost first.
+fn placed(mut shape: &dyn Shape) -> (&dyn Shape, Vec<&Transform>) {
+    let mut transforms = vec![];
+    while let Some(placed) = shape.as_transformed() {
+        transforms.push(&placed.transform);
+        shape = &*placed.shape;
+    }
+    (shape, transforms)
+}
+
+/// The mesh an object is, wherever it is placed.
+fn mesh_of(obj: &Object) -> Option<&Mesh> {
+    placed(&*obj.shape).0.as_mesh()
+}
+
+/// Adds the options for the parts of `transform` that do anything.
+fn transform_options(transform: &Transform, options: &mut Vec<String>) {
+    let (t, r, s) = (transform.translate, transform.rotate, transform.scale);
+    if (t.x, t.y, t.z) != (0.0, 0.0, 0.0) {
+        options.push(format!("translate={},{},{}", t.x, t.y, t.z));
+    }
+    if (r.x, r.y, r.z) != (0.0, 0.0, 0.0) {
+        options.push(format!("rotate={},{},{}", r.x, r.y, r.z));
+    }
+    if s.x == s.y && s.y == s.z {
+        if s.x != 1.0 {
+            options.push(format!("scale={}", s.x));
+        }
+    } else {
+        options.push(format!("scale={},{},{}", s.x, s.y, s.z));
+    }
+}
+
+/// Writes `config` as a scene, naming each mesh's file with `mesh_file` and every other
+/// file, the images of heightfields and cutouts and the profiles of lights, with
+/// `asset_file`. Objects that have no scene syntax, like meshes without a file, are left
+/// as comments.
+fn write_scene(
+    config: &Config, out: &mut impl Write, mesh_file: impl Fn(&Mesh) -> Option<String>, asset_file: impl Fn(&Path) -> String
+) -> fmt::Result {
+    let (pos, dir) = (config.pov.pos, config.pov.dir);
+    writeln!(out, "{} {} {}", pos.x, pos.y, pos.z)?;
+    writeln!(out, "{} {} {}", dir.x, dir.y, dir.z)?;
+    writeln!(out, "{} {}", config.width, config.height)?;
+    writeln!(out, "{}", config.fov)?;
+    writeln!(out, "{} {}", config.max_depth, config.num_tries)?;
+    // What was the jitter angle; `filter` has taken its place.
+    writeln!(out, "0")?;
+
+    // Colors are written unscaled where they can be; brighter ones share a `<col_scale>`.
+    let col_scale = config.objects.iter().map(|obj| max_channel(obj.color)).fold(255.0, Float::max) / 255.0;
+    writeln!(out, "{} 1", col_scale)?;
+
+    if let Some(lens) = config.lens {
+        writeln!(out, "lens {} {} {} {}", lens.radius, lens.focus, lens.blades, lens.rotation)?;
+    }
+    for key in &config.focus_keys {
+        writeln!(out, "focus_key {} {} {}", key.frame, key.focal_length, key.focus)?;
+    }
+    if config.filter != Filter::default() {
+        writeln!(out, "filter {}", config.filter)?;
+    }
+    if config.sampler != Sampler::default() {
+        writeln!(out, "sampler {}", config.sampler)?;
+    }
+    if !config.upright() {
+        writeln!(out, "up {} {} {} {}", config.up.x, config.up.y, config.up.z, config.roll)?;
+    }
+    if config.spectral {
+        writeln!(out, "spectral")?;
+    }
+    if config.caustic_split != 1 {
+        writeln!(out, "caustic_split {}", config.caustic_split)?;
+    }
+    if let Some(cache) = &config.irradiance_cache {
+        writeln!(out, "irradiance_cache {} {} {} {}", cache.accuracy, cache.rays, cache.min_spacing, cache.max_spacing)?;
+    }
+    if let Some(guide) = &config.path_guide {
+        writeln!(out, "path_guiding {}", guide.passes)?;
+    }
+    if let Some(fog) = config.fog {
+        writeln!(out, "fog {} {} {}", fog.scattering, fog.absorption, fog.g)?;
+    }
+    if let Some(sky) = config.sky {
+        let sun = sky.sun;
+        writeln!(out, "sky {} {} {} {} {}", sun.x, sun.y, sun.z, sky.turbidity, sky.strength)?;
+    }
+    for light in &config.lights {
+        match *light {
+            Light::Directional { dir, radius, irradiance } => {
+                let (color, strength) = light_text(irradiance);
+                writeln!(out, "sun {} {} {} {} {} {}", color, strength, dir.x, dir.y, dir.z, 2.0 * radius)?;
+            }
+            Light::Spot { pos, dir, inner, outer, intensity, ref profile } => {
+                let (color, intensity) = light_text(intensity);
+                write!(
+                    out, "spot {} {} {} {} {} {} {} {} {} {}",
+                    color, intensity, pos.x, pos.y, pos.z, dir.x, dir.y, dir.z, inner, outer
+                )?;
+                match profile {
+                    Some(profile) => writeln!(out, " ies={}", asset_file(&profile.file))?,
+                    None => writeln!(out)?
+                }
+            }
+        }
+    }
+    if config.accel != Accel::default() {
+        writeln!(out, "accel {}", config.accel)?;
+    }
+
+    for (index, Object { shape, color, lum, material, backface, cutout, visibility, .. }) in config.objects.iter().enumerate() {
+        let (shape, mut transforms) = placed(&**shape);
+        let shape = match (shape.params(), shape.as_mesh(), shape.as_heightfield()) {
+            (Some(("sdf", params)), _, _) => Sdf::from_params(&params).map(|sdf| format!("sdf {}", sdf)),
+            (Some((name, params)), _, _) if name != "triangle" => {
+                let params: Vec<_> = params.iter().map(Float::to_string).collect();
+                Some(format!("{} {}", name, params.join(" ")))
+            }
+            (_, Some(mesh), _) => mesh_file(mesh).map(|file| {
+                let offset = mesh.offset;
+                format!("mesh {} {} {} {} {}", file, offset.x, offset.y, offset.z, mesh.scale)
+            }),
+            (_, _, Some(field)) => {
+                let source = match &field.source {
+                    Source::Image(file) => asset_file(file),
+                    Source::Noise { seed, cells } => format!("noise {} {}", seed, cells)
+                };
+                let (o, s) = (field.origin, field.size);
+                Some(format!("heightfield {} {} {} {} {} {} {}", source, o.x, o.y, o.z, s.x, s.y, s.z))
+            }
+            _ => None
+        };
+        let shape = match shape {
+            Some(shape) => shape,
+            None => {
+                writeln!(out, "// object {} has no scene syntax", index)?;
+                continue;
+            }
+        };
+
+        let color = color.scale(1.0 / col_scale);
+        let (lum, emit) = lum_text(color, *lum);
+        let mut options: Vec<String> = emit.into_iter().collect();
+        let material = material_text(material, &mut options);
+        if let Some(cutout) = cutout {
+            options.push(format!("cutout={}", asset_file(&cutout.texture.file)));
+            if cutout.threshold != Cutout::DEFAULT_THRESHOLD {
+                options.push(format!("cutout_threshold={}", cutout.threshold));
+            }
+        }
+        if *backface != Backface::Show {
+            options.push(format!("backface={}", backface));
+        }
+        let flags = [
+            ("camera_visible", visibility.camera),
+            ("casts_shadows", visibility.shadows),
+            ("visible_in_reflections", visibility.reflections)
+        ];
+        options.extend(flags.iter().filter(|(_, seen)| !seen).map(|(key, _)| format!("{}=false", key)));
+        // The innermost transform goes on the object's line and any others around it
+        // become groups, which lose the names they had.
+        if let Some(transform) = transforms.pop() {
+            transform_options(transform, &mut options);
+        }
+        for transform in &transforms {
+            let mut group = vec![];
+            transform_options(transform, &mut group);
+            writeln!(out, "group placed {}", group.join(" "))?;
+        }
+        write!(out, "{} {} {} {}", color_text(color), lum, material, shape)?;
+        options.iter().try_for_each(|option| write!(out, " {}", option))?;
+        writeln!(out)?;
+        for _ in &transforms {
+            writeln!(out, "end")?;
+        }
+    }
+    Ok(())
+}
+
+impl fmt::Display for Config {
+    /// The scene in the format `parse_config` reads, with meshes, images and profiles
+    /// named by their files.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_scene(self, f, |mesh| mesh.file.as_ref().map(|file| file.display().to_string()), |file| file.display().to_string())
+    }
+}
+
+/// `file` as named from the folder `dir`, both absolute, climbing out of `dir` as far as
+/// they differ. Paths on different drives have nothing in common, and stay absolute.
+fn relative_to(file: &Path, dir: &Path) -> PathBuf {
+    let file: Vec<_> = file.components().collect();
+    let dir: Vec<_> = dir.components().collect();
+    let common = file.iter().zip(&dir).take_while(|(a, b)| a == b).count();
+    if common == 0 {
+        return file.iter().collect();
+    }
+    dir[common..].iter().map(|_| Component::ParentDir)
+        .chain(file[common..].iter().cloned())
+        .collect()
+}
+
+/// Writes `config` as a scene file at `path`. Meshes without a file of their own, like
+/// those of imported glTF scenes, are saved beside it as `<name>.<object>.obj`.
+pub fn export_scene(config: &Config, path: &Path) -> ConfigResult<()> {
+    let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    let stem = path.file_stem().map_or("scene".into(), |stem| stem.to_string_lossy());
+    let out_dir = dir.canonicalize().map_err(ConfigError::IOError)?;
+
+    // Objects sharing a mesh share its file.
+    let mut written = HashMap::new();
+    for (index, obj) in config.objects.iter().enumerate() {
+        if let Some(mesh) = mesh_of(obj).filter(|mesh| mesh.file.is_none()) {
+            if let Entry::Vacant(entry) = written.entry(Arc::as_ptr(&mesh.data)) {
+                let name = format!("{}.{}.obj", stem, index);
+                mesh.data.save_obj(&dir.join(&name))?;
+                entry.insert(name);
+            }
+        }
+    }
+
+    // Files of parsed meshes and heightfield images are named from the new scene's folder.
+    let relative = |file: &Path| match file.canonicalize() {
+        Ok(file) => relative_to(&file, &out_dir).display().to_string(),
+        Err(_) => file.display().to_string()
+    };
+    let mut text = String::new();
+    write_scene(config, &mut text, |mesh| match &mesh.file {
+        Some(file) => Some(relative(file)),
+        None => written.get(&Arc::as_ptr(&mesh.data)).cloned()
+    }, relative).unwrap();
+    fs::write(path, text).map_err(ConfigError::IOError)
+}
+
+/// `config` as scene text naming meshes, images and profiles by the full paths of their
+/// files, to be read from any folder on a machine that has them there, or `None` if a
+/// mesh has no file.
+pub fn scene_text(config: &Config) -> Option<String> {
+    let no_file = config.objects.iter()
+        .any(|obj| mesh_of(obj).is_some_and(|mesh| mesh.file.is_none()));
+    if no_file {
+        return None;
+    }
+    let mut text = String::new();
+    let full = |file: &Path| file.canonicalize().unwrap_or_else(|_| file.to_path_buf()).display().to_string();
+    write_scene(config, &mut text, |mesh| mesh.file.as_deref().map(full), full).unwrap();
+    Some(text)
+}
@@ -0,0 +1,256 @@
+use std::collections::HashMap;
+use std::iter::Peekable;
+use std::str::CharIndices;
+
+pub type Variables = HashMap<String, f64>;
+
+/// Why an expression failed, `offset` bytes into the text handed to the parser.
+#[derive(Debug)]
+pub struct ExprError {
+    pub offset: usize,
+    pub expected: String,
+    pub found: String
+}
+
+pub type ExprResult<Ret> = Result<Ret, ExprError>;
+
+/*
+    expr   := term (('+' | '-') term)*
+    term   := factor (('*' | '/' | '%') factor)*
+    factor := unary ('^' factor)?
+    unary  := '-' unary | atom
+    atom   := number | name | name '(' expr (',' expr)* ')' | '(' expr ')'
+*/
+struct Parser<'a> {
+    src: &'a str,
+    chars: Peekable<CharIndices<'a>>,
+    vars: &'a Variables
+}
+
+impl<'a> Parser<'a> {
+    fn offset(&mut self) -> usize {
+        self.chars.peek().map_or(self.src.len(), |(i, _)| *i)
+    }
+
+    fn fail<T>(&mut self, offset: usize, expected: &str, found: &str) -> ExprResult<T> {
+        Err(ExprError { offset, expected: expected.to_string(), found: found.to_string() })
+    }
+
+    fn fail_here<T>(&mut self, expected: &str) -> ExprResult<T> {
+        let offset = self.offset();
+        let found = match self.chars.peek() {
+            Some((_, c)) => format!("{:?}", c),
+            None => "end of expression".to_string()
+        };
+        self.fail(offset, expected, &found)
+    }
+
+    fn skip_space(&mut self) {
+        while self.chars.peek().is_some_and(|(_, c)| c.is_whitespace()) {
+            self.chars.next();
+        }
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        self.skip_space();
+        if self.chars.peek().map(|(_, next)| *next) == Some(c) {
+            self.chars.next();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn take_while(&mut self, pred: impl Fn(char) -> bool) -> String {
+        let mut out = String::new();
+        while let Some(&(_, c)) = self.chars.peek() {
+            if !pred(c) {
+                break;
+            }
+            out.push(c);
+            self.chars.next();
+        }
+        out
+    }
+
+    fn expr(&mut self) -> ExprResult<f64> {
+        let mut value = self.term()?;
+        loop {
+            if self.eat('+') {
+                value += self.term()?;
+            } else if self.eat('-') {
+                value -= self.term()?;
+            } else {
+                return Ok(value);
+            }
+        }
+    }
+
+    fn term(&mut self) -> ExprResult<f64> {
+        let mut value = self.factor()?;
+        loop {
+            if self.eat('*') {
+                value *= self.factor()?;
+            } else if self.eat('/') {
+                value /= self.factor()?;
+            } else if self.eat('%') {
+                value %= self.factor()?;
+            } else {
+                return Ok(value);
+            }
+        }
+    }
+
+    fn factor(&mut self) -> ExprResult<f64> {
+        let base = self.unary()?;
+        if self.eat('^') {
+            Ok(base.powf(self.factor()?))
+        } else {
+            Ok(base)
+        }
+    }
+
+    fn unary(&mut self) -> ExprResult<f64> {
+        if self.eat('-') {
+            Ok(-self.unary()?)
+        } else {
+            self.atom()
+        }
+    }
+
+    fn atom(&mut self) -> ExprResult<f64> {
+        if self.eat('(') {
+            let value = self.expr()?;
+            return if self.eat(')') { Ok(value) } else { self.fail_here("')'") };
+        }
+
+        self.skip_space();
+        let start = self.offset();
+        match self.chars.peek().map(|(_, c)| *c) {
+            Some(c) if c.is_ascii_digit() || c == '.' => {
+                let num = self.take_while(|c| c.is_ascii_digit() || c == '.');
+                match num.parse() {
+                    Ok(value) => Ok(value),
+                    Err(_) => self.fail(start, "a number", &format!("{:?}", num))
+                }
+            }
+            Some(c) if c.is_alphabetic() || c == '_' => {
+                let name = self.take_while(|c| c.is_alphanumeric() || c == '_');
+                if self.eat('(') {
+                    let mut args = vec![self.expr()?];
+                    while self.eat(',') {
+                        args.push(self.expr()?);
+                    }
+                    if !self.eat(')') {
+                        return self.fail_here("')'");
+                    }
+                    self.call(start, &name, &args)
+                } else if let Some(value) = self.vars.get(&name) {
+                    Ok(*value)
+                } else if name == "pi" {
+                    Ok(std::f64::consts::PI)
+                } else {
+                    self.fail(start, "a variable defined by an earlier `let`", &format!("{:?}", name))
+                }
+            }
+            _ => self.fail_here("a number, name or '('")
+        }
+    }
+
+    fn call(&mut self, start: usize, name: &str, args: &[f64]) -> ExprResult<f64> {
+        match (name, args) {
+            ("sqrt", [x]) => Ok(x.sqrt()),
+            ("abs", [x]) => Ok(x.abs()),
+            ("sin", [x]) => Ok(x.sin()),
+            ("cos", [x]) => Ok(x.cos()),
+            ("tan", [x]) => Ok(x.tan()),
+            ("rad", [x]) => Ok(x.to_radians()),
+            ("min", [x, y]) => Ok(x.min(*y)),
+            ("max", [x, y]) => Ok(x.max(*y)),
+            _ => self.fail(
+                start,
+                "sqrt/1, abs/1, sin/1, cos/1, tan/1, rad/1, min/2 or max/2",
+                &format!("{}/{}", name, args.len())
+            )
+        }
+    }
+}
+
+pub fn eval(src: &str, vars: &Variables) -> ExprResult<f64> {
+    let mut parser = Parser { src, chars: src.char_indices().peekable(), vars };
+    let value = parser.expr()?;
+    parser.skip_space();
+    if parser.chars.peek().is_none() {
+        Ok(value)
+    } else {
+        parser.fail_here("an operator or end of expression")
+    }
+}
+
+fn shift(offset: usize) -> impl Fn(ExprError) -> ExprError {
+    move |err| ExprError { offset: err.offset + offset, ..err }
+}
+
+/// Replaces every `${expr}` in `line` with the value of `expr`.
+pub fn substitute(line: &str, vars: &Variables) -> ExprResult<String> {
+    let mut out = String::new();
+    let mut pos = 0;
+    while let Some(start) = line[pos..].find("${").map(|start| pos + start) {
+        out.push_str(&line[pos..start]);
+        let inner = start + 2;
+        let end = line[inner..].find('}').map(|end| inner + end).ok_or_else(|| ExprError {
+            offset: line.len(),
+            expected: "'}'".to_string(),
+            found: "end of line".to_string()
+        })?;
+        let value = eval(&line[inner..end], vars).map_err(shift(inner))?;
+        out.push_str(&value.to_string());
+        pos = end + 1;
+    }
+    out.push_str(&line[pos..]);
+    Ok(out)
+}
+
+/// The names in the expression `src`, whether of variables or functions.
+pub fn names(src: &str) -> impl Iterator<Item = &str> {
+    src.split(|c: char| !(c.is_alphanumeric() || c == '_'))
+        .filter(|word| word.starts_with(|c: char| c.is_alphabetic() || c == '_'))
+}
+
+/// The names in every `${expr}` of `line`, as `substitute` would see them.
+pub fn substituted_names(line: &str) -> Vec<&str> {
+    let mut found = vec![];
+    let mut rest = line;
+    while let Some(start) = rest.find("${") {
+        let inner = &rest[start + 2..];
+        let end = inner.find('}').unwrap_or(inner.len());
+        found.extend(names(&inner[..end]));
+        rest = &inner[end..];
+    }
+    found
+}
+
+/// Parses the `name = expr` part of a `let` line.
+pub fn parse_let(def: &str, vars: &Variables) -> ExprResult<(String, f64)> {
+    let (name, value) = def.split_once('=').ok_or_else(|| ExprError {
+        offset: def.len(),
+        expected: "'=' after the variable name".to_string(),
+        found: "end of line".to_string()
+    })?;
+
+    let trimmed = name.trim();
+    let valid_name = trimmed.starts_with(|c: char| c.is_alphabetic() || c == '_')
+        && trimmed.chars().all(|c| c.is_alphanumeric() || c == '_');
+    if !valid_name {
+        return Err(ExprError {
+            offset: name.len() - name.trim_start().len(),
+            expected: "a variable name".to_string(),
+            found: format!("{:?}", trimmed)
+        });
+    }
+
+    let value_offset = name.len() + 1;
+    let value = substitute(value, vars).map_err(shift(value_offset))?;
+    let value = eval(&value, vars).map_err(shift(value_offset))?;
+    Ok((trimmed.to_string(), value))
+}
@@ -0,0 +1,256 @@
+use std::cell::RefCell;
+use std::ffi::{c_char, c_int, CStr, CString};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::path::Path;
+use std::{ptr, slice};
+use std::sync::OnceLock;
+
+use crate::config::{parse_config_file, Config};
+use crate::filter::Filter;
+use crate::linalg::{consts::PI, Float, Vector3};
+use crate::mesh::Accel;
+use crate::profile::Profiler;
+use crate::sampler::Sampler;
+use crate::shapes::{Ray, Sphere};
+use crate::trace::{make_image, Backface, Color, Glass, Material, Object, Visibility};
+
+// A C interface for embedding the tracer, declared in `include/graphics.h`. Scenes are
+// built up through an opaque `RtScene` pointer or loaded from a scene file, then rendered
+// into a buffer the caller owns. Functions that can fail return 0 on success and -1
+// otherwise, leaving the reason for `rt_last_error`.
+
+/// A matte surface, for `rt_scene_add_sphere`.
+pub const RT_DIFFUSE: u32 = 0;
+/// A perfect mirror.
+pub const RT_MIRROR: u32 = 1;
+/// Clear glass.
+pub const RT_GLASS: u32 = 2;
+
+/// A scene being put together for rendering.
+pub struct RtScene(Config);
+
+thread_local! {
+    static LAST_ERROR: RefCell<CString> = RefCell::new(CString::default());
+}
+
+/// Keeps `message` for `rt_last_error` and gives the code for a failure.
+fn fail(message: impl Into<String>) -> c_int {
+    // A NUL, as from a path, would end the message early.
+    let message = CString::new(message.into().replace('\0', " ")).unwrap_or_default();
+    LAST_ERROR.with(|last| *last.borrow_mut() = message);
+    -1
+}
+
+/// Runs `body`, failing with `failed` if it panics instead, as unwinding into C is
+/// undefined.
+fn guard<T>(failed: T, body: impl FnOnce() -> T) -> T {
+    catch_unwind(AssertUnwindSafe(body)).unwrap_or_else(|panic| {
+        let reason = panic.downcast_ref::<&str>().map(|reason| reason.to_string())
+            .or_else(|| panic.downcast_ref::<String>().cloned());
+        fail(match reason {
+            Some(reason) => format!("panicked: {}", reason),
+            None => "panicked".to_string()
+        });
+        failed
+    })
+}
+
+/// Why the last failing call on this thread failed, valid until the next one fails.
+#[no_mangle]
+pub extern "C" fn rt_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| last.borrow().as_ptr())
+}
+
+/// An empty `width` by `height` scene, seen from the origin along +y with a 90 degree
+/// field of view, 16 samples per pixel and 8 bounces; null if either size is 0.
+#[no_mangle]
+pub extern "C" fn rt_scene_new(width: u32, height: u32) -> *mut RtScene {
+    if width == 0 || height == 0 {
+        fail("the image needs a width and a height");
+        return ptr::null_mut();
+    }
+    Box::into_raw(Box::new(RtScene(Config {
+        objects: vec![],
+        pov: Ray::new(Vector3::new(0.0, 0.0, 0.0), Vector3::new(0.0, 1.0, 0.0)),
+        width,
+        height,
+        fov: PI / 4.0,
+        lens: None,
+        focus_keys: vec![],
+        up: Vector3::new(0.0, 0.0, 1.0),
+        roll: 0.0,
+        max_depth: 8,
+        num_tries: 16,
+        filter: Filter::default(),
+        sampler: Sampler::default(),
+        spectral: false,
+        caustic_split: 1,
+        irradiance_cache: None,
+        path_guide: None,
+        fog: None,
+        sky: None,
+        lights: vec![],
+        light_tree: Default::default(),
+        glowing: Default::default(),
+        accel: Accel::default(),
+        warnings: vec![]
+    })))
+}
+
+/// Loads the scene file at `path`, or gives null if it can't be read or parsed.
+///
+/// # Safety
+/// `path` must be a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn rt_scene_load(path: *const c_char) -> *mut RtScene {
+    let path = match CStr::from_ptr(path).to_str() {
+        Ok(path) => path,
+        Err(_) => {
+            fail("the path isn't UTF-8");
+            return ptr::null_mut();
+        }
+    };
+    guard(ptr::null_mut(), || match parse_config_file(Path::new(path)) {
+        Ok(config) => Box::into_raw(Box::new(RtScene(config))),
+        Err(err) => {
+            fail(format!("{:#}", err));
+            ptr::null_mut()
+        }
+    })
+}
+
+/// Frees a scene from `rt_scene_new` or `rt_scene_load`; null is ignored.
+///
+/// # Safety
+/// `scene` must not be used again.
+#[no_mangle]
+pub unsafe extern "C" fn rt_scene_free(scene: *mut RtScene) {
+    if !scene.is_null() {
+        drop(Box::from_raw(scene));
+    }
+}
+
+/// # Safety
+/// `scene` must be a live scene.
+#[no_mangle]
+pub unsafe extern "C" fn rt_scene_width(scene: *const RtScene) -> u32 {
+    (*scene).0.width
+}
+
+/// # Safety
+/// `scene` must be a live scene.
+#[no_mangle]
+pub unsafe extern "C" fn rt_scene_height(scene: *const RtScene) -> u32 {
+    (*scene).0.height
+}
+
+/// Puts the camera at `(x, y, z)` looking along `(dx, dy, dz)`, taking in `fov_degrees`
+/// from the left of the image to the right.
+///
+/// # Safety
+/// `scene` must be a live scene.
+#[no_mangle]
+pub unsafe extern "C" fn rt_scene_set_camera(
+    scene: *mut RtScene,
+    x: f64, y: f64, z: f64,
+    dx: f64, dy: f64, dz: f64,
+    fov_degrees: f64
+) -> c_int {
+    let dir = Vector3::new(dx as Float, dy as Float, dz as Float);
+    if dir.size() == 0.0 {
+        return fail("the view direction can't be zero");
+    }
+    let fov = (fov_degrees as Float).to_radians() / 2.0;
+    if !(fov > 0.0 && fov < PI / 2.0) {
+        return fail("the field of view must be between 0 and 180 degrees");
+    }
+    let config = &mut (*scene).0;
+    config.pov = Ray::new(Vector3::new(x as Float, y as Float, z as Float), dir);
+    config.fov = fov;
+    0
+}
+
+/// Sets the samples taken per pixel and the bounces paths are followed for.
+///
+/// # Safety
+/// `scene` must be a live scene.
+#[no_mangle]
+pub unsafe extern "C" fn rt_scene_set_quality(scene: *mut RtScene, samples: u16, max_depth: u16) -> c_int {
+    if samples == 0 {
+        return fail("at least one sample per pixel is needed");
+    }
+    let config = &mut (*scene).0;
+    config.num_tries = samples;
+    config.max_depth = max_depth;
+    0
+}
+
+/// Adds a sphere of `radius` at `(x, y, z)` whose surface, one of `RT_DIFFUSE`, `RT_MIRROR`
+/// or `RT_GLASS`, reflects `(r, g, b)` out of 1 and glows with that color times `emission`.
+///
+/// # Safety
+/// `scene` must be a live scene.
+#[no_mangle]
+pub unsafe extern "C" fn rt_scene_add_sphere(
+    scene: *mut RtScene,
+    x: f64, y: f64, z: f64,
+    radius: f64,
+    r: f64, g: f64, b: f64,
+    emission: f64,
+    material: u32
+) -> c_int {
+    if ![x, y, z].iter().all(|coord| coord.is_finite()) {
+        return fail("a sphere's center must be finite");
+    }
+    if !(radius > 0.0 && radius.is_finite()) {
+        return fail("a sphere's radius must be positive");
+    }
+    // Written so that NaN fails too.
+    if ![r, g, b].iter().all(|&channel| channel >= 0.0 && channel.is_finite()) {
+        return fail("a sphere's color can't be negative or infinite");
+    }
+    if !(emission >= 0.0 && emission.is_finite()) {
+        return fail("emission can't be negative or infinite");
+    }
+    let material = match material {
+        RT_DIFFUSE => Material::Translucent(0.0, Glass::default()),
+        RT_MIRROR => Material::Mirror(0.0),
+        RT_GLASS => Material::Translucent(1.0, Glass::default()),
+        _ => return fail(format!("unknown material {}", material))
+    };
+    let color = Color::new(r as Float, g as Float, b as Float).scale(255.0);
+    let config = &mut (*scene).0;
+    config.objects.push(Object {
+        shape: Box::new(Sphere { center: Vector3::new(x as Float, y as Float, z as Float), radius: radius as Float }),
+        color,
+        lum: color.scale(emission as Float),
+        material,
+        backface: Backface::Show,
+        cutout: None,
+        visibility: Visibility::default(),
+        // Not from a file; lines only name objects in messages.
+        line: config.objects.len() + 1
+    });
+    // Found again, with the new sphere, when next needed.
+    config.glowing = OnceLock::new();
+    0
+}
+
+/// Renders `scene` into `pixels` as 8-bit RGB, row by row from the top, which must hold
+/// `len` bytes, at least three for every pixel.
+///
+/// # Safety
+/// `scene` must be a live scene and `pixels` must point to `len` writable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn rt_render(scene: *const RtScene, pixels: *mut u8, len: usize) -> c_int {
+    let config = &(*scene).0;
+    let needed = config.width as usize * config.height as usize * 3;
+    if len < needed {
+        return fail(format!("{} bytes are too few for a {}x{} image", len, config.width, config.height));
+    }
+    guard(-1, || {
+        let image = make_image(config, &Profiler::new(false)).to_rgb8(1.0);
+        slice::from_raw_parts_mut(pixels, needed).copy_from_slice(image.as_raw());
+        0
+    })
+}
@@ -0,0 +1,584 @@
+use std::ops::Range;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+
+use image::{ImageBuffer, Rgb, RgbImage};
+use rayon::prelude::*;
+
+use crate::filter::Filter;
+use crate::linalg::Float;
+use crate::trace::Color;
+
+/// An image being rendered: the weighted sum of every sample taken at each pixel, kept
+/// with the sum of their weights and how many they were, in one row-major buffer.
+#[derive(Debug, Clone)]
+pub struct Film {
+    width: u32,
+    height: u32,
+    sums: Vec<Color>,
+    weights: Vec<Float>,
+    samples: Vec<u32>,
+    /// Light splatted straight onto each pixel, weighed against nothing; empty until the
+    /// first splat.
+    splats: Vec<Color>
+}
+
+/// One row of a `Film`, for filling rows in parallel.
+pub struct FilmRow<'a> {
+    pub y: u32,
+    sums: &'a mut [Color],
+    weights: &'a mut [Float],
+    samples: &'a mut [u32]
+}
+
+impl FilmRow<'_> {
+    pub fn add_sample(&mut self, x: u32, color: Color) {
+        self.add_weighted_sample(x, color, 1.0);
+    }
+
+    /// Adds a sample counting `weight` times as much as one from `add_sample`.
+    pub fn add_weighted_sample(&mut self, x: u32, color: Color, weight: Float) {
+        self.sums[x as usize] += color.scale(weight);
+        self.weights[x as usize] += weight;
+        self.samples[x as usize] += 1;
+    }
+}
+
+/// A rectangle of pixels, columns `x` by rows `y`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Region {
+    pub x: Range<u32>,
+    pub y: Range<u32>
+}
+
+impl Region {
+    /// Every pixel of a `width` by `height` image.
+    pub fn full(width: u32, height: u32) -> Region {
+        Region { x: 0..width, y: 0..height }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.x.is_empty() || self.y.is_empty()
+    }
+
+    pub fn contains(&self, x: u32, y: u32) -> bool {
+        self.x.contains(&x) && self.y.contains(&y)
+    }
+
+    /// The smallest region holding both.
+    pub fn union(&self, other: &Region) -> Region {
+        if self.is_empty() {
+            return other.clone();
+        }
+        if other.is_empty() {
+            return self.clone();
+        }
+        Region {
+            x: self.x.start.min(other.x.start)..self.x.end.max(other.x.end),
+            y: self.y.start.min(other.y.start)..self.y.end.max(other.y.end)
+        }
+    }
+
+    pub fn pixels(&self) -> u64 {
+        if self.is_empty() { 0 } else { self.x.len() as u64 * self.y.len() as u64 }
+    }
+}
+
+/// The pixels whose centers lie within `filter`'s radius of `(x, y)`, measured in pixels
+/// from the top left corner, with the weight it gives each; only those in `bounds`.
+fn footprint(bounds: &Region, x: Float, y: Float, filter: Filter) -> impl Iterator<Item = (u32, u32, Float)> {
+    let radius = filter.radius();
+    let span = |at: Float, range: &Range<u32>| {
+        let low = (at - 0.5 - radius).ceil().max(range.start as Float);
+        let high = (at - 0.5 + radius).floor().min(range.end as Float - 1.0);
+        if low <= high { low as u32..high as u32 + 1 } else { 0..0 }
+    };
+    let (columns, rows) = (span(x, &bounds.x), span(y, &bounds.y));
+    rows.flat_map(move |row| columns.clone().map(move |column| (column, row)))
+        .map(move |(column, row)| {
+            (column, row, filter.weight(column as Float + 0.5 - x, row as Float + 0.5 - y))
+        })
+        .filter(|&(_, _, weight)| weight != 0.0)
+}
+
+/// The pixel `(x, y)` falls in, measured in pixels from the top left corner, if it's in
+/// `bounds`.
+fn pixel_at(bounds: &Region, x: Float, y: Float) -> Option<(u32, u32)> {
+    if x < 0.0 || y < 0.0 {
+        return None;
+    }
+    let (column, row) = (x as u32, y as u32);
+    bounds.contains(column, row).then_some((column, row))
+}
+
+/// The weighted mean of a pixel's samples times how many there were, so that a pixel
+/// of equally weighted samples is their plain sum.
+fn resolve(sum: Color, weight: Float, samples: u32) -> Color {
+    if weight > 0.0 {
+        sum.scale(samples as Float / weight)
+    } else {
+        Color::BLACK
+    }
+}
+
+/// Bytes needed to render a `width` by `height` image while holding `buffers` films,
+/// with the 8-bit image it is saved as.
+pub fn frame_bytes(width: u32, height: u32, buffers: usize) -> usize {
+    let pixels = width as usize * height as usize;
+    pixels * (buffers * (std::mem::size_of::<Color>() + std::mem::size_of::<Float>() + std::mem::size_of::<u32>()) + 3)
+}
+
+impl Film {
+    /// A black film with no samples yet.
+    pub fn new(width: u32, height: u32) -> Film {
+        let pixels = width as usize * height as usize;
+        Film {
+            width,
+            height,
+            sums: vec![Color::BLACK; pixels],
+            weights: vec![0.0; pixels],
+            samples: vec![0; pixels],
+            splats: vec![]
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    fn index(&self, x: u32, y: u32) -> usize {
+        y as usize * self.width as usize + x as usize
+    }
+
+    fn splat(&self, index: usize) -> Color {
+        self.splats.get(index).copied().unwrap_or(Color::BLACK)
+    }
+
+    /// The sum of the samples at `(x, y)`, as if they had all been weighted equally, and
+    /// the light splatted there.
+    pub fn get(&self, x: u32, y: u32) -> Color {
+        let index = self.index(x, y);
+        resolve(self.sums[index], self.weights[index], self.samples[index]) + self.splat(index)
+    }
+
+    pub fn samples(&self, x: u32, y: u32) -> u32 {
+        self.samples[self.index(x, y)]
+    }
+
+    /// The pixel sums, as from `get`, row by row from the top.
+    pub fn pixels(&self) -> Vec<Color> {
+        (0..self.sums.len())
+            .map(|index| resolve(self.sums[index], self.weights[index], self.samples[index]) + self.splat(index))
+            .collect()
+    }
+
+    pub fn add_sample(&mut self, x: u32, y: u32, color: Color) {
+        self.add_samples(x, y, color, 1);
+    }
+
+    /// Adds a sample counting `weight` times as much as one from `add_sample`.
+    pub fn add_weighted_sample(&mut self, x: u32, y: u32, color: Color, weight: Float) {
+        let index = self.index(x, y);
+        self.sums[index] += color.scale(weight);
+        self.weights[index] += weight;
+        self.samples[index] += 1;
+    }
+
+    /// Adds `count` equally weighted samples at once, given their sum.
+    pub fn add_samples(&mut self, x: u32, y: u32, sum: Color, count: u32) {
+        let index = self.index(x, y);
+        self.sums[index] += sum;
+        self.weights[index] += count as Float;
+        self.samples[index] += count;
+    }
+
+    /// Adds a sample taken at `(x, y)`, measured in pixels from the top left corner, to
+    /// every pixel `filter` spreads it over, weighted by how far it is from each center.
+    /// It is only counted as a sample by the pixel it falls in, so every pixel still
+    /// resolves to the mean of its neighborhood times the samples it took itself.
+    pub fn add_filtered_sample(&mut self, x: Float, y: Float, color: Color, filter: Filter) {
+        let region = self.region();
+        if let Some((column, row)) = pixel_at(&region, x, y) {
+            let index = self.index(column, row);
+            self.samples[index] += 1;
+        }
+        for (column, row, weight) in footprint(&region, x, y, filter) {
+            let index = self.index(column, row);
+            self.sums[index] += color.scale(weight);
+            self.weights[index] += weight;
+        }
+    }
+
+    /// Adds light to the pixel `(x, y)` falls in, if any, without counting it as a sample:
+    /// for paths traced from lights, which land wherever they land and are divided by how
+    /// many samples every pixel took along with the rest.
+    pub fn add_splat(&mut self, x: Float, y: Float, color: Color) {
+        if let Some((column, row)) = pixel_at(&self.region(), x, y) {
+            let index = self.index(column, row);
+            self.splats_mut()[index] += color;
+        }
+    }
+
+    fn splats_mut(&mut self) -> &mut [Color] {
+        if self.splats.is_empty() {
+            self.splats = vec![Color::BLACK; self.sums.len()];
+        }
+        &mut self.splats
+    }
+
+    /// Every pixel of the film.
+    pub fn region(&self) -> Region {
+        Region::full(self.width, self.height)
+    }
+
+    /// Forgets every sample taken, and all light splatted, at `(x, y)`.
+    pub fn clear(&mut self, x: u32, y: u32) {
+        let index = self.index(x, y);
+        self.sums[index] = Color::BLACK;
+        self.weights[index] = 0.0;
+        self.samples[index] = 0;
+        if let Some(splat) = self.splats.get_mut(index) {
+            *splat = Color::BLACK;
+        }
+    }
+
+    /// Forgets every sample taken in `region`.
+    pub fn clear_region(&mut self, region: &Region) {
+        for y in region.y.clone() {
+            for x in region.x.clone() {
+                self.clear(x, y);
+            }
+        }
+    }
+
+    /// The fewest samples any pixel has.
+    pub fn min_samples(&self) -> u32 {
+        self.samples.iter().copied().min().unwrap_or(0)
+    }
+
+    /// A copy with every pixel scaled as if it had taken `samples` samples, so pixels that
+    /// took different numbers of them can be saved together. Splatted light is kept as it
+    /// is, having been spread over every sample of the image rather than the pixel's.
+    pub fn rescaled(&self, samples: u32) -> Film {
+        let mut film = Film::new(self.width, self.height);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let index = self.index(x, y);
+                let taken = self.samples[index];
+                if taken > 0 {
+                    let sum = resolve(self.sums[index], self.weights[index], taken);
+                    film.add_samples(x, y, sum.scale(samples as Float / taken as Float), samples);
+                }
+            }
+        }
+        film.splats = self.splats.clone();
+        film
+    }
+
+    /// A `width` by `height` copy, each pixel taken from the one it falls in here, so a
+    /// film rendered small can be shown in blocks at full size.
+    pub fn enlarged(&self, width: u32, height: u32) -> Film {
+        let mut film = Film::new(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                let from = self.index(
+                    (x as u64 * self.width as u64 / width as u64) as u32,
+                    (y as u64 * self.height as u64 / height as u64) as u32
+                );
+                let to = film.index(x, y);
+                film.sums[to] = self.sums[from];
+                film.weights[to] = self.weights[from];
+                film.samples[to] = self.samples[from];
+                if !self.splats.is_empty() {
+                    film.splats_mut()[to] = self.splats[from];
+                }
+            }
+        }
+        film
+    }
+
+    /// Adds every sample of `other`, which must be the same size, to this film.
+    pub fn add_film(&mut self, other: &Film) {
+        assert_eq!((self.width, self.height), (other.width, other.height), "films differ in size");
+        for (sum, other) in self.sums.iter_mut().zip(&other.sums) {
+            *sum += *other;
+        }
+        for (weight, other) in self.weights.iter_mut().zip(&other.weights) {
+            *weight += other;
+        }
+        for (samples, other) in self.samples.iter_mut().zip(&other.samples) {
+            *samples += other;
+        }
+        if !other.splats.is_empty() {
+            for (splat, other) in self.splats_mut().iter_mut().zip(&other.splats) {
+                *splat += *other;
+            }
+        }
+    }
+
+    /// Adds every sample of `other` to the pixels it covers when its top left corner is
+    /// at `(x, y)`; it must fit inside this film.
+    pub fn add_film_at(&mut self, other: &Film, x: u32, y: u32) {
+        assert!(x + other.width <= self.width && y + other.height <= self.height, "film doesn't fit");
+        for row in 0..other.height {
+            for column in 0..other.width {
+                let (from, to) = (other.index(column, row), self.index(x + column, y + row));
+                self.sums[to] += other.sums[from];
+                self.weights[to] += other.weights[from];
+                self.samples[to] += other.samples[from];
+                if !other.splats.is_empty() {
+                    self.splats_mut()[to] += other.splats[from];
+                }
+            }
+        }
+    }
+
+    /// A blank tile for adding samples taken within `region` to on one thread, holding
+    /// every pixel `filter` could spread them onto, to be merged back with `merge_tile`.
+    pub fn tile(&self, region: &Region, filter: Filter) -> FilmTile {
+        let margin = (filter.radius() + 0.5).ceil() as u32;
+        let grow = |range: &Range<u32>, end: u32| range.start.saturating_sub(margin)..(range.end + margin).min(end);
+        let region = Region { x: grow(&region.x, self.width), y: grow(&region.y, self.height) };
+        let film = Film::new(region.x.len() as u32, region.y.len() as u32);
+        FilmTile { region, film }
+    }
+
+    /// Adds every sample of a tile made by `tile`.
+    pub fn merge_tile(&mut self, tile: &FilmTile) {
+        self.add_film_at(&tile.film, tile.region.x.start, tile.region.y.start);
+    }
+
+    /// How noisy each pixel still is, row by row: the standard error of its mean sample,
+    /// relative to how bright that is (anything dimmer than `floor` counting as `floor`).
+    /// It is judged from how far the mean of the samples also in `half`, which took some
+    /// of them, is from the mean of the rest, averaged over the pixels around it, since
+    /// one pixel's two means say little. Pixels without samples on both sides are
+    /// infinitely noisy.
+    pub fn noise(&self, half: &Film, floor: Float) -> Vec<Float> {
+        assert_eq!((self.width, self.height), (half.width, half.height), "films differ in size");
+        let squared: Vec<Float> = (0..self.sums.len()).map(|index| {
+            let (taken, halved) = (self.samples[index], half.samples[index]);
+            if halved == 0 || halved >= taken {
+                return Float::INFINITY;
+            }
+            let (taken, halved) = (taken as Float, halved as Float);
+            let mean = resolve(self.sums[index], self.weights[index], 1);
+            let half_mean = resolve(half.sums[index], half.weights[index], 1);
+            let rest_mean = (mean.scale(taken) - half_mean.scale(halved)).scale(1.0 / (taken - halved));
+            let gap = half_mean - rest_mean;
+            // The two means differ by sqrt(1/halved + 1/rest) standard deviations of a
+            // sample, against the full mean's sqrt(1/taken).
+            let error = gap.dot(gap) / 3.0 / (taken * (1.0 / halved + 1.0 / (taken - halved)));
+            let brightness = ((mean.x + mean.y + mean.z) / 3.0).max(floor);
+            error / (brightness * brightness)
+        }).collect();
+
+        let (width, height) = (self.width as i64, self.height as i64);
+        let mut noise = Vec::with_capacity(squared.len());
+        for y in 0..height {
+            for x in 0..width {
+                let (mut sum, mut count) = (0.0, 0.0);
+                for ny in (y - 1).max(0)..=(y + 1).min(height - 1) {
+                    for nx in (x - 1).max(0)..=(x + 1).min(width - 1) {
+                        sum += squared[(ny * width + nx) as usize];
+                        count += 1.0;
+                    }
+                }
+                noise.push((sum / count).sqrt());
+            }
+        }
+        noise
+    }
+
+    /// The rows, to be filled in parallel.
+    pub fn par_rows_mut(&mut self) -> impl IndexedParallelIterator<Item = FilmRow<'_>> {
+        let width = (self.width as usize).max(1);
+        self.sums.par_chunks_mut(width)
+            .zip(self.weights.par_chunks_mut(width))
+            .zip(self.samples.par_chunks_mut(width))
+            .enumerate()
+            .map(|(y, ((sums, weights), samples))| FilmRow { y: y as u32, sums, weights, samples })
+    }
+
+    /// Quantizes the pixel sums, multiplied by `scale`, to 8 bits per channel.
+    pub fn to_rgb8(&self, scale: Float) -> RgbImage {
+        self.to_display(scale, &Clip)
+    }
+
+    /// Like `to_rgb8`, but bringing the pixels into range with `tonemap` first.
+    pub fn to_display(&self, scale: Float, tonemap: &impl Tonemap) -> RgbImage {
+        ImageBuffer::from_fn(self.width, self.height, |x, y| {
+            let curr = tonemap.map(self.get(x, y).scale(scale));
+            Rgb([curr.x as u8, curr.y as u8, curr.z as u8])
+        })
+    }
+}
+
+/// Part of a `Film` that one thread adds samples to, filtered onto its pixels, without
+/// having to share the film with the others.
+#[derive(Debug, Clone)]
+pub struct FilmTile {
+    /// The film's pixels the tile covers.
+    region: Region,
+    film: Film
+}
+
+impl FilmTile {
+    pub fn region(&self) -> &Region {
+        &self.region
+    }
+
+    /// Adds a sample taken at `(x, y)`, measured in pixels from the film's top left
+    /// corner, as `Film::add_filtered_sample` does, to the pixels the tile covers.
+    pub fn add_filtered_sample(&mut self, x: Float, y: Float, color: Color, filter: Filter) {
+        let (left, top) = (self.region.x.start as Float, self.region.y.start as Float);
+        self.film.add_filtered_sample(x - left, y - top, color, filter);
+    }
+
+    /// Adds light to the pixel `(x, y)` falls in, as `Film::add_splat` does, if the tile
+    /// covers it.
+    pub fn add_splat(&mut self, x: Float, y: Float, color: Color) {
+        let (left, top) = (self.region.x.start as Float, self.region.y.start as Float);
+        self.film.add_splat(x - left, y - top, color);
+    }
+}
+
+/// A film any number of threads can add samples to at once, for paths that land anywhere
+/// on the image rather than in the pixels a thread was given.
+#[derive(Debug)]
+pub struct SharedFilm {
+    width: u32,
+    height: u32,
+    /// Each channel's bits as an `f64`, to be added to atomically.
+    sums: Vec<[AtomicU64; 3]>,
+    weights: Vec<AtomicU64>,
+    samples: Vec<AtomicU32>,
+    splats: Vec<[AtomicU64; 3]>
+}
+
+/// Adds `value` to the `f64` whose bits `cell` holds.
+// Widening is a no-op without the `f32` feature.
+#[allow(clippy::unnecessary_cast)]
+fn add_float(cell: &AtomicU64, value: Float) {
+    let _ = cell.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |bits| {
+        Some((f64::from_bits(bits) + value as f64).to_bits())
+    });
+}
+
+#[allow(clippy::unnecessary_cast)]
+fn load_float(cell: &AtomicU64) -> Float {
+    f64::from_bits(cell.load(Ordering::Relaxed)) as Float
+}
+
+fn add_color(cells: &[AtomicU64; 3], color: Color) {
+    add_float(&cells[0], color.x);
+    add_float(&cells[1], color.y);
+    add_float(&cells[2], color.z);
+}
+
+fn load_color(cells: &[AtomicU64; 3]) -> Color {
+    Color::new(load_float(&cells[0]), load_float(&cells[1]), load_float(&cells[2]))
+}
+
+impl SharedFilm {
+    /// A black film with no samples yet.
+    pub fn new(width: u32, height: u32) -> SharedFilm {
+        let pixels = width as usize * height as usize;
+        let zeros = || (0..pixels).map(|_| AtomicU64::new(0.0f64.to_bits()));
+        let colors = || (0..pixels).map(|_| [0; 3].map(|_| AtomicU64::new(0.0f64.to_bits())));
+        SharedFilm {
+            width,
+            height,
+            sums: colors().collect(),
+            weights: zeros().collect(),
+            samples: (0..pixels).map(|_| AtomicU32::new(0)).collect(),
+            splats: colors().collect()
+        }
+    }
+
+    fn index(&self, x: u32, y: u32) -> usize {
+        y as usize * self.width as usize + x as usize
+    }
+
+    pub fn add_weighted_sample(&self, x: u32, y: u32, color: Color, weight: Float) {
+        let index = self.index(x, y);
+        add_color(&self.sums[index], color.scale(weight));
+        add_float(&self.weights[index], weight);
+        self.samples[index].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Adds a sample as `Film::add_filtered_sample` does.
+    pub fn add_filtered_sample(&self, x: Float, y: Float, color: Color, filter: Filter) {
+        let region = Region::full(self.width, self.height);
+        if let Some((column, row)) = pixel_at(&region, x, y) {
+            self.samples[self.index(column, row)].fetch_add(1, Ordering::Relaxed);
+        }
+        for (column, row, weight) in footprint(&region, x, y, filter) {
+            let index = self.index(column, row);
+            add_color(&self.sums[index], color.scale(weight));
+            add_float(&self.weights[index], weight);
+        }
+    }
+
+    /// Adds light as `Film::add_splat` does.
+    pub fn add_splat(&self, x: Float, y: Float, color: Color) {
+        if let Some((column, row)) = pixel_at(&Region::full(self.width, self.height), x, y) {
+            add_color(&self.splats[self.index(column, row)], color);
+        }
+    }
+
+    /// Everything added, once no thread is adding any more.
+    pub fn into_film(self) -> Film {
+        let mut film = Film::new(self.width, self.height);
+        film.sums = self.sums.iter().map(load_color).collect();
+        film.weights = self.weights.iter().map(load_float).collect();
+        film.samples = self.samples.into_iter().map(AtomicU32::into_inner).collect();
+        if self.splats.iter().flatten().any(|cell| load_float(cell) != 0.0) {
+            film.splats = self.splats.iter().map(load_color).collect();
+        }
+        film
+    }
+}
+
+/// How pixel values, where 255 is white, are brought into what a display can show; any
+/// function of a color is one.
+pub trait Tonemap {
+    fn map(&self, color: Color) -> Color;
+}
+
+impl<F: Fn(Color) -> Color> Tonemap for F {
+    fn map(&self, color: Color) -> Color {
+        self(color)
+    }
+}
+
+/// Clips whatever is brighter than white.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Clip;
+
+impl Tonemap for Clip {
+    fn map(&self, color: Color) -> Color {
+        let clip = |value: Float| value.clamp(0.0, 255.0);
+        Color::new(clip(color.x), clip(color.y), clip(color.z))
+    }
+}
+
+/// Reinhard's operator, channel by channel: highlights roll off toward white, which they
+/// reach at `white` times as bright as white.
+#[derive(Debug, Clone, Copy)]
+pub struct Reinhard {
+    pub white: Float
+}
+
+impl Tonemap for Reinhard {
+    fn map(&self, color: Color) -> Color {
+        let map = |value: Float| {
+            let value = value.max(0.0) / 255.0;
+            (value * (1.0 + value / (self.white * self.white)) / (1.0 + value)).min(1.0) * 255.0
+        };
+        Color::new(map(color.x), map(color.y), map(color.z))
+    }
+}
@@ -0,0 +1,99 @@
+use std::fmt;
+use std::str::FromStr;
+
+use rand::Rng;
+
+use crate::linalg::Float;
+use crate::random;
+
+/// How the samples spread around a pixel are weighted into it. Wider filters blur
+/// slightly but alias less; all are measured in pixels, so they look the same at any
+/// resolution.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Filter {
+    /// Every sample inside the pixel counts the same.
+    #[default]
+    Box,
+    /// Weights falling off linearly to a pixel from the center.
+    Tent,
+    /// A Gaussian with a standard deviation of half a pixel, cut off at three of them.
+    Gaussian,
+    /// Mitchell and Netravali's cubic with B = C = 1/3, whose negative lobes keep edges
+    /// sharp.
+    Mitchell
+}
+
+impl FromStr for Filter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "box" => Ok(Filter::Box),
+            "tent" => Ok(Filter::Tent),
+            "gaussian" => Ok(Filter::Gaussian),
+            "mitchell" => Ok(Filter::Mitchell),
+            _ => Err(format!("Expected box, tent, gaussian or mitchell but got {:?}", s))
+        }
+    }
+}
+
+impl fmt::Display for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Filter::Box => "box",
+            Filter::Tent => "tent",
+            Filter::Gaussian => "gaussian",
+            Filter::Mitchell => "mitchell"
+        })
+    }
+}
+
+impl Filter {
+    /// How far from a pixel's center, along either axis, samples still count toward it.
+    pub fn radius(&self) -> Float {
+        match self {
+            Filter::Box => 0.5,
+            Filter::Tent => 1.0,
+            Filter::Gaussian => 1.5,
+            Filter::Mitchell => 2.0
+        }
+    }
+
+    /// Weight of a sample `dx` and `dy` pixels from the center.
+    pub fn weight(&self, dx: Float, dy: Float) -> Float {
+        self.weight_1d(dx) * self.weight_1d(dy)
+    }
+
+    fn weight_1d(&self, d: Float) -> Float {
+        let x = d.abs();
+        if x > self.radius() {
+            return 0.0;
+        }
+        match self {
+            Filter::Box => 1.0,
+            Filter::Tent => 1.0 - x,
+            Filter::Gaussian => {
+                // Shifted down to reach zero at the radius rather than stop short of it.
+                let gaussian = |x: Float| (-2.0 * x * x).exp();
+                (gaussian(x) - gaussian(self.radius())).max(0.0)
+            }
+            Filter::Mitchell => {
+                const B: Float = 1.0 / 3.0;
+                const C: Float = 1.0 / 3.0;
+                let cubic = if x < 1.0 {
+                    (12.0 - 9.0 * B - 6.0 * C) * x.powi(3) + (-18.0 + 12.0 * B + 6.0 * C) * x.powi(2) + (6.0 - 2.0 * B)
+                } else {
+                    (-B - 6.0 * C) * x.powi(3) + (6.0 * B + 30.0 * C) * x.powi(2) + (-12.0 * B - 48.0 * C) * x + (8.0 * B + 24.0 * C)
+                };
+                cubic / 6.0
+            }
+        }
+    }
+
+    /// A point evenly spread over the filter, as an offset in pixels from the center.
+    pub fn offset(&self, rng: &mut impl Rng) -> (Float, Float) {
+        let radius = self.radius();
+        let (u, v) = random::gen_pair(rng);
+        ((2.0 * u - 1.0) * radius, (2.0 * v - 1.0) * radius)
+    }
+}
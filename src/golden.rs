@@ -0,0 +1,108 @@
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use image::RgbImage;
+
+use crate::config::{parse_config_file, ConfigError, ConfigResult};
+use crate::linalg::Float;
+use crate::profile::Profiler;
+use crate::trace::make_image_with;
+
+// Golden images: small scenes kept with a reference render each, `name.png` beside
+// `name.config`, that new renders are checked against to catch changes to what the
+// tracer draws. Renders are seeded, so the same build draws the same image every time and
+// only the last bits of floating point arithmetic differ between machines.
+
+/// Every golden render starts from this seed.
+pub const SEED: u64 = 0x5eed;
+
+/// How far a render may stray from its reference, as the root mean square difference of
+/// its channels out of 255, before it no longer matches.
+pub const THRESHOLD: Float = 1.0;
+
+/// How a fresh render of a golden scene compares with its reference.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Comparison {
+    /// There is no reference to compare with yet.
+    Missing,
+    /// The reference is another size, as `(width, height)`, from the render's.
+    Resized { reference: (u32, u32), render: (u32, u32) },
+    /// The root mean square difference of every channel of every pixel.
+    Rmse(Float)
+}
+
+impl Comparison {
+    /// Whether the render is close enough to the reference to count as the same.
+    pub fn matches(&self) -> bool {
+        matches!(*self, Comparison::Rmse(rmse) if rmse <= THRESHOLD)
+    }
+}
+
+impl fmt::Display for Comparison {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Comparison::Missing => write!(f, "no reference image"),
+            Comparison::Resized { reference: (rw, rh), render: (w, h) } =>
+                write!(f, "rendered at {}x{} but the reference is {}x{}", w, h, rw, rh),
+            Comparison::Rmse(rmse) if *rmse <= THRESHOLD => write!(f, "RMSE {:.3}, within {}", rmse, THRESHOLD),
+            Comparison::Rmse(rmse) => write!(f, "RMSE {:.3}, over {}", rmse, THRESHOLD)
+        }
+    }
+}
+
+/// The golden scenes in `dir`, in name order.
+pub fn scenes(dir: &Path) -> ConfigResult<Vec<PathBuf>> {
+    let mut scenes = vec![];
+    for entry in fs::read_dir(dir).map_err(ConfigError::IOError)? {
+        let path = entry.map_err(ConfigError::IOError)?.path();
+        if path.extension().is_some_and(|ext| ext == "config") {
+            scenes.push(path);
+        }
+    }
+    scenes.sort();
+    Ok(scenes)
+}
+
+/// Where the reference image for `scene` is kept.
+pub fn reference_path(scene: &Path) -> PathBuf {
+    scene.with_extension("png")
+}
+
+/// Renders `scene` the way its reference was, from `SEED`.
+pub fn render(scene: &Path) -> ConfigResult<RgbImage> {
+    let config = parse_config_file(scene)?;
+    Ok(make_image_with(&config, &Profiler::new(false), Some(SEED), |_| ()).to_rgb8(1.0))
+}
+
+/// The root mean square difference between the channels of `a` and `b`, out of 255, or
+/// `None` if they aren't the same size.
+pub fn rmse(a: &RgbImage, b: &RgbImage) -> Option<Float> {
+    if a.dimensions() != b.dimensions() {
+        return None;
+    }
+    let sum: Float = a.as_raw().iter().zip(b.as_raw())
+        .map(|(&a, &b)| (a as Float - b as Float).powi(2))
+        .sum();
+    Some((sum / a.as_raw().len().max(1) as Float).sqrt())
+}
+
+/// Renders `scene` and compares it with its reference. With `update`, a render that
+/// doesn't match replaces the reference, which is compared with as it was before.
+pub fn check(scene: &Path, update: bool) -> ConfigResult<Comparison> {
+    let image = render(scene)?;
+    let path = reference_path(scene);
+    let comparison = if path.exists() {
+        let reference = image::open(&path).map_err(|err| ConfigError::InvalidAsset(path.clone(), err.to_string()))?.to_rgb8();
+        match rmse(&reference, &image) {
+            Some(rmse) => Comparison::Rmse(rmse),
+            None => Comparison::Resized { reference: reference.dimensions(), render: image.dimensions() }
+        }
+    } else {
+        Comparison::Missing
+    };
+    if update && !comparison.matches() {
+        image.save(&path).map_err(|err| ConfigError::ImageError(path, err))?;
+    }
+    Ok(comparison)
+}
@@ -0,0 +1,451 @@
+use std::fmt;
+use std::sync::OnceLock;
+
+use bytemuck::{Pod, Zeroable};
+use wgpu::util::DeviceExt;
+
+use crate::bvh::{Bvh, Node};
+use crate::config::Config;
+use crate::film::Film;
+use crate::filter::Filter;
+use crate::linalg::{Aabb, Float, Vector3};
+use crate::sampler::Sampler;
+use crate::shapes::Triangle;
+use crate::trace::{Backface, Color, Material, Object, Visibility};
+
+/// Pixels each workgroup of the shader covers along x and y.
+const WORKGROUP: u32 = 8;
+/// Marks leaves in `GpuNode::b`; see `gpu.wgsl`.
+const LEAF: u32 = 0x8000_0000;
+
+/// Why a scene couldn't be rendered on the GPU.
+#[derive(Debug)]
+pub enum GpuError {
+    /// There is no adapter able to run compute shaders, or it wouldn't give a device.
+    NoDevice(String),
+    /// The scene uses something the shader doesn't implement, and what.
+    Unsupported(String)
+}
+
+impl fmt::Display for GpuError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GpuError::NoDevice(reason) => write!(f, "No GPU available: {}", reason),
+            GpuError::Unsupported(what) => write!(f, "The GPU renderer does not support {}", what)
+        }
+    }
+}
+
+// The structs below mirror those of `gpu.wgsl`, field for field.
+
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Zeroable)]
+struct GpuParams {
+    pos: [f32; 3],
+    theta: f32,
+    phi: f32,
+    fov: f32,
+    width: u32,
+    height: u32,
+    max_depth: u32,
+    sample: u32,
+    spheres: u32,
+    planes: u32,
+    triangles: u32,
+    nodes: u32,
+    pad: [u32; 2]
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Zeroable)]
+struct GpuMaterial {
+    color: [f32; 3],
+    clearness: f32,
+    lum: [f32; 3],
+    ior: f32,
+    absorption: [f32; 3],
+    kind: u32,
+    roughness: f32,
+    coat_strength: f32,
+    coat_roughness: f32,
+    pad: f32
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Zeroable)]
+struct GpuSphere {
+    center: [f32; 3],
+    radius: f32,
+    material: u32,
+    pad: [u32; 3]
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Zeroable)]
+struct GpuPlane {
+    point: [f32; 3],
+    material: u32,
+    norm: [f32; 3],
+    pad: f32
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Zeroable)]
+struct GpuTriangle {
+    v1: [f32; 3],
+    material: u32,
+    edge1: [f32; 3],
+    pad0: f32,
+    edge2: [f32; 3],
+    pad1: f32,
+    /// Vertex normals; all the face normal for flat triangles.
+    n1: [f32; 3],
+    pad2: f32,
+    n2: [f32; 3],
+    pad3: f32,
+    n3: [f32; 3],
+    pad4: f32
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Zeroable)]
+struct GpuNode {
+    min: [f32; 3],
+    a: u32,
+    max: [f32; 3],
+    b: u32
+}
+
+// With the `f32` feature these casts change nothing.
+#[allow(clippy::unnecessary_cast)]
+fn single(x: Float) -> f32 {
+    x as f32
+}
+
+fn vector(v: Vector3) -> [f32; 3] {
+    [single(v.x), single(v.y), single(v.z)]
+}
+
+/// The scene as the shader reads it, with the triangles of every mesh in world space
+/// under one hierarchy.
+#[derive(Default)]
+struct Scene {
+    materials: Vec<GpuMaterial>,
+    spheres: Vec<GpuSphere>,
+    planes: Vec<GpuPlane>,
+    triangles: Vec<GpuTriangle>,
+    nodes: Vec<GpuNode>
+}
+
+fn material(obj: &Object) -> Result<GpuMaterial, GpuError> {
+    let unsupported = |what: &str| GpuError::Unsupported(format!("{} (object on line {})", what, obj.line));
+    let (coat, base) = match &obj.material {
+        Material::Coated(coat, base) => (Some(coat), &**base),
+        material => (None, material)
+    };
+    let mut gpu = GpuMaterial {
+        color: vector(obj.color),
+        lum: vector(obj.lum),
+        coat_strength: coat.map_or(0.0, |coat| single(coat.strength)),
+        coat_roughness: coat.map_or(0.0, |coat| single(coat.roughness)),
+        ..GpuMaterial::zeroed()
+    };
+    match base {
+        Material::Mirror(roughness) => gpu.roughness = single(*roughness),
+        Material::Translucent(clearness, glass) => {
+            if glass.cauchy.is_some() {
+                return Err(unsupported("dispersion"));
+            }
+            if glass.medium.is_some() {
+                return Err(unsupported("scattering media"));
+            }
+            if glass.tint.is_some() {
+                return Err(unsupported("tinted glass"));
+            }
+            gpu.kind = 1;
+            gpu.clearness = single(*clearness);
+            gpu.ior = single(glass.ior);
+            gpu.absorption = glass.absorption.map_or([0.0; 3], vector);
+        }
+        Material::Thin(_) => return Err(unsupported("thin glass")),
+        Material::Principled(_) => return Err(unsupported("principled materials")),
+        Material::Catcher(_) => return Err(unsupported("shadow catchers")),
+        Material::Coated(..) => return Err(unsupported("more than one coat"))
+    }
+    if obj.backface != Backface::Show {
+        return Err(unsupported("culled or flipped back faces"));
+    }
+    if obj.cutout.is_some() {
+        return Err(unsupported("cutouts"));
+    }
+    if obj.visibility != Visibility::default() {
+        return Err(unsupported("visibility flags"));
+    }
+    Ok(gpu)
+}
+
+fn triangle(triangle: &Triangle, material: u32) -> GpuTriangle {
+    let [v1, v2, v3] = triangle.vertices();
+    let [n1, n2, n3] = triangle.normals().unwrap_or([triangle.face_normal(); 3]);
+    GpuTriangle {
+        v1: vector(v1),
+        material,
+        edge1: vector(v2 - v1),
+        edge2: vector(v3 - v1),
+        n1: vector(n1),
+        n2: vector(n2),
+        n3: vector(n3),
+        ..GpuTriangle::zeroed()
+    }
+}
+
+impl Scene {
+    fn new(config: &Config) -> Result<Scene, GpuError> {
+        if config.spectral {
+            return Err(GpuError::Unsupported("spectral rendering".to_string()));
+        }
+        if config.irradiance_cache.is_some() {
+            return Err(GpuError::Unsupported("irradiance caching".to_string()));
+        }
+        if config.path_guide.is_some() {
+            return Err(GpuError::Unsupported("path guiding".to_string()));
+        }
+        if config.fog.is_some() {
+            return Err(GpuError::Unsupported("fog".to_string()));
+        }
+        if config.sky.is_some() {
+            return Err(GpuError::Unsupported("a sky".to_string()));
+        }
+        if config.lens.is_some() {
+            return Err(GpuError::Unsupported("a lens".to_string()));
+        }
+        if config.filter != Filter::Box {
+            return Err(GpuError::Unsupported("a filter other than box".to_string()));
+        }
+        if config.sampler != Sampler::Random {
+            return Err(GpuError::Unsupported("a sampler other than random".to_string()));
+        }
+        if !config.upright() {
+            return Err(GpuError::Unsupported("a tilted or rolled camera".to_string()));
+        }
+        if !config.lights.is_empty() {
+            return Err(GpuError::Unsupported("lights".to_string()));
+        }
+
+        let mut scene = Scene::default();
+        let mut triangles = vec![];
+        for (index, obj) in config.objects.iter().enumerate() {
+            let material = index as u32;
+            scene.materials.push(self::material(obj)?);
+            if let Some(mesh) = obj.shape.as_mesh() {
+                let to_world = |v: Vector3| v.scale(mesh.scale) + mesh.offset;
+                triangles.extend(mesh.data.triangles().map(|t| {
+                    let [v1, v2, v3] = t.vertices().map(to_world);
+                    // Offsetting and uniformly scaling leave the normals as they are.
+                    let triangle = match t.normals() {
+                        Some(normals) => Triangle::with_normals(v1, v2, v3, normals),
+                        None => Triangle::new(v1, v2, v3)
+                    };
+                    (triangle, material)
+                }));
+                continue;
+            }
+            match obj.shape.params() {
+                Some(("sphere", p)) => scene.spheres.push(GpuSphere {
+                    center: vector(Vector3::new(p[0], p[1], p[2])),
+                    radius: single(p[3]),
+                    material,
+                    pad: [0; 3]
+                }),
+                Some(("plane", p)) => scene.planes.push(GpuPlane {
+                    point: vector(Vector3::new(p[0], p[1], p[2])),
+                    material,
+                    norm: vector(Vector3::new(p[3], p[4], p[5])),
+                    pad: 0.0
+                }),
+                Some(("triangle", p)) => {
+                    let corner = |i: usize| Vector3::new(p[3 * i], p[3 * i + 1], p[3 * i + 2]);
+                    triangles.push((Triangle::new(corner(0), corner(1), corner(2)), material));
+                }
+                _ => return Err(GpuError::Unsupported(format!("the shape on line {}", obj.line)))
+            }
+        }
+
+        let boxes: Vec<_> = triangles.iter().map(|(triangle, _)| {
+            let [v1, v2, v3] = triangle.vertices();
+            Aabb::new(v1, v2).expand(v3)
+        }).collect();
+        let bvh = Bvh::build(&boxes);
+        // Leaves index straight into the triangles, so those are stored in the tree's order.
+        scene.triangles = bvh.order().iter().map(|&i| triangle(&triangles[i].0, triangles[i].1)).collect();
+        scene.nodes = bvh.nodes().iter().map(|node| {
+            let bounds = node.bounds();
+            let (a, b) = match *node {
+                Node::Inner { left, right, .. } => (left as u32, right as u32),
+                Node::Leaf { start, end, .. } => (start as u32, (end - start) as u32 | LEAF)
+            };
+            GpuNode {
+                min: bounds.min.map(single),
+                a,
+                max: bounds.max.map(single),
+                b
+            }
+        }).collect();
+        Ok(scene)
+    }
+}
+
+/// The device and compiled shader, set up once and shared by every render.
+struct Context {
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    pipeline: wgpu::ComputePipeline
+}
+
+impl Context {
+    fn new() -> Result<Context, GpuError> {
+        let instance = wgpu::Instance::default();
+        let adapter = pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions {
+            power_preference: wgpu::PowerPreference::HighPerformance,
+            ..Default::default()
+        })).map_err(|err| GpuError::NoDevice(err.to_string()))?;
+        if !adapter.get_downlevel_capabilities().flags.contains(wgpu::DownlevelFlags::COMPUTE_SHADERS) {
+            return Err(GpuError::NoDevice(format!("{} can't run compute shaders", adapter.get_info().name)));
+        }
+        let (device, queue) = pollster::block_on(adapter.request_device(&wgpu::DeviceDescriptor {
+            label: None,
+            required_features: wgpu::Features::empty(),
+            // Big meshes need all the storage the adapter allows.
+            required_limits: adapter.limits(),
+            memory_hints: wgpu::MemoryHints::Performance,
+            trace: wgpu::Trace::Off
+        })).map_err(|err| GpuError::NoDevice(err.to_string()))?;
+
+        let module = device.create_shader_module(wgpu::include_wgsl!("gpu.wgsl"));
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: None,
+            layout: None,
+            module: &module,
+            entry_point: Some("main"),
+            compilation_options: Default::default(),
+            cache: None
+        });
+        Ok(Context { device, queue, pipeline })
+    }
+
+    /// The shared context, made on first use. A failure is remembered, so a machine
+    /// without a GPU is only probed once.
+    fn get() -> Result<&'static Context, GpuError> {
+        static CONTEXT: OnceLock<Result<Context, String>> = OnceLock::new();
+        CONTEXT.get_or_init(|| Context::new().map_err(|err| err.to_string()))
+            .as_ref()
+            .map_err(|reason| GpuError::NoDevice(reason.clone()))
+    }
+
+    /// A storage buffer holding `items`, or one zeroed `T` if there are none, since
+    /// bindings can't be empty.
+    fn storage<T: Pod>(&self, items: &[T]) -> wgpu::Buffer {
+        let zero = [T::zeroed()];
+        let items = if items.is_empty() { &zero[..] } else { items };
+        self.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: None,
+            contents: bytemuck::cast_slice(items),
+            usage: wgpu::BufferUsages::STORAGE
+        })
+    }
+}
+
+/// Renders `config` like `trace::make_image`, as sums of `num_tries` samples per pixel,
+/// but with each sample of the whole image traced in one compute dispatch. Scenes using
+/// anything the shader lacks, and machines without a usable GPU, give an error instead.
+pub fn make_image(config: &Config) -> Result<Film, GpuError> {
+    let scene = Scene::new(config)?;
+    let context = Context::get()?;
+    let device = &context.device;
+
+    let pixels = config.width as usize * config.height as usize;
+    let image_size = (pixels * std::mem::size_of::<[f32; 4]>()) as wgpu::BufferAddress;
+    let image = device.create_buffer(&wgpu::BufferDescriptor {
+        label: None,
+        size: image_size,
+        usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+        mapped_at_creation: false
+    });
+    let readback = device.create_buffer(&wgpu::BufferDescriptor {
+        label: None,
+        size: image_size,
+        usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false
+    });
+    let mut params = GpuParams {
+        pos: vector(config.pov.pos),
+        theta: single(config.pov.dir.theta),
+        phi: single(config.pov.dir.phi),
+        fov: single(config.fov),
+        width: config.width,
+        height: config.height,
+        max_depth: config.max_depth as u32,
+        sample: 0,
+        spheres: scene.spheres.len() as u32,
+        planes: scene.planes.len() as u32,
+        triangles: scene.triangles.len() as u32,
+        nodes: scene.nodes.len() as u32,
+        pad: [0; 2]
+    };
+    let params_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+        label: None,
+        size: std::mem::size_of::<GpuParams>() as wgpu::BufferAddress,
+        usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false
+    });
+
+    let storage = [
+        context.storage(&scene.materials),
+        context.storage(&scene.spheres),
+        context.storage(&scene.planes),
+        context.storage(&scene.triangles),
+        context.storage(&scene.nodes)
+    ];
+    // Bindings in the order `gpu.wgsl` declares them.
+    let buffers: Vec<&wgpu::Buffer> = std::iter::once(&params_buffer)
+        .chain(&storage)
+        .chain(std::iter::once(&image))
+        .collect();
+    let entries: Vec<_> = buffers.iter().enumerate()
+        .map(|(binding, buffer)| wgpu::BindGroupEntry { binding: binding as u32, resource: buffer.as_entire_binding() })
+        .collect();
+    let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: None,
+        layout: &context.pipeline.get_bind_group_layout(0),
+        entries: &entries
+    });
+
+    // One submission per sample keeps each well under the time drivers allow a dispatch.
+    for sample in 0..config.num_tries {
+        params.sample = sample as u32;
+        context.queue.write_buffer(&params_buffer, 0, bytemuck::bytes_of(&params));
+        let mut encoder = device.create_command_encoder(&Default::default());
+        {
+            let mut pass = encoder.begin_compute_pass(&Default::default());
+            pass.set_pipeline(&context.pipeline);
+            pass.set_bind_group(0, &bind_group, &[]);
+            pass.dispatch_workgroups(config.width.div_ceil(WORKGROUP), config.height.div_ceil(WORKGROUP), 1);
+        }
+        if sample + 1 == config.num_tries {
+            encoder.copy_buffer_to_buffer(&image, 0, &readback, 0, image_size);
+        }
+        context.queue.submit([encoder.finish()]);
+    }
+
+    let slice = readback.slice(..);
+    slice.map_async(wgpu::MapMode::Read, |_| ());
+    device.poll(wgpu::PollType::Wait).map_err(|err| GpuError::NoDevice(err.to_string()))?;
+    let data = slice.get_mapped_range();
+    let values: &[[f32; 4]] = bytemuck::cast_slice(&data);
+    let mut film = Film::new(config.width, config.height);
+    for (index, p) in values.iter().enumerate() {
+        let (x, y) = (index as u32 % config.width, index as u32 / config.width);
+        film.add_samples(x, y, Color::new(p[0] as Float, p[1] as Float, p[2] as Float), config.num_tries as u32);
+    }
+    Ok(film)
+}
@@ -0,0 +1,334 @@
+// The path tracer of `trace.rs` as a compute shader, for the scenes `gpu.rs` accepts.
+// Each dispatch adds one sample to every pixel of `image`.
+
+const EPS: f32 = 0.0001;
+const PI: f32 = 3.14159265358979;
+const FAR: f32 = 3.4e38;
+/// Set in `Node.b` for leaves, whose triangles are `triangles[a..a + (b & !LEAF)]`.
+const LEAF: u32 = 0x80000000u;
+const STACK_SIZE: u32 = 64u;
+
+const MIRROR: u32 = 0u;
+const TRANSLUCENT: u32 = 1u;
+
+struct Params {
+    pos: vec3<f32>,
+    theta: f32,
+    phi: f32,
+    fov: f32,
+    width: u32,
+    height: u32,
+    max_depth: u32,
+    sample: u32,
+    spheres: u32,
+    planes: u32,
+    triangles: u32,
+    nodes: u32,
+    pad0: u32,
+    pad1: u32,
+}
+
+struct Material {
+    color: vec3<f32>,
+    clearness: f32,
+    lum: vec3<f32>,
+    ior: f32,
+    absorption: vec3<f32>,
+    kind: u32,
+    roughness: f32,
+    coat_strength: f32,
+    coat_roughness: f32,
+    pad: f32,
+}
+
+struct Sphere {
+    center: vec3<f32>,
+    radius: f32,
+    material: u32,
+    pad0: u32,
+    pad1: u32,
+    pad2: u32,
+}
+
+struct Plane {
+    point: vec3<f32>,
+    material: u32,
+    norm: vec3<f32>,
+    pad: f32,
+}
+
+struct Triangle {
+    v1: vec3<f32>,
+    material: u32,
+    edge1: vec3<f32>,
+    pad0: f32,
+    edge2: vec3<f32>,
+    pad1: f32,
+    n1: vec3<f32>,
+    pad2: f32,
+    n2: vec3<f32>,
+    pad3: f32,
+    n3: vec3<f32>,
+    pad4: f32,
+}
+
+/// Inner nodes have children `a` and `b`; see `LEAF` for leaves.
+struct Node {
+    min: vec3<f32>,
+    a: u32,
+    max: vec3<f32>,
+    b: u32,
+}
+
+struct Hit {
+    t: f32,
+    normal: vec3<f32>,
+    material: u32,
+}
+
+@group(0) @binding(0) var<uniform> params: Params;
+@group(0) @binding(1) var<storage, read> materials: array<Material>;
+@group(0) @binding(2) var<storage, read> spheres: array<Sphere>;
+@group(0) @binding(3) var<storage, read> planes: array<Plane>;
+@group(0) @binding(4) var<storage, read> triangles: array<Triangle>;
+@group(0) @binding(5) var<storage, read> nodes: array<Node>;
+@group(0) @binding(6) var<storage, read_write> image: array<vec4<f32>>;
+
+var<private> seed: u32;
+
+fn pcg(v: u32) -> u32 {
+    let state = v * 747796405u + 2891336453u;
+    let word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return (word >> 22u) ^ word;
+}
+
+fn random() -> f32 {
+    seed = pcg(seed);
+    return f32(seed >> 8u) / 16777216.0;
+}
+
+fn sphere_t(sphere: Sphere, pos: vec3<f32>, dir: vec3<f32>) -> f32 {
+    let offset = pos - sphere.center;
+    let b = 2.0 * dot(dir, offset);
+    let c = dot(offset, offset) - sphere.radius * sphere.radius;
+    let disc = b * b - 4.0 * c;
+    if disc < 0.0 {
+        return -1.0;
+    }
+    let t1 = -b + sqrt(disc);
+    let t2 = -b - sqrt(disc);
+    if t2 > EPS {
+        return t2 / 2.0;
+    } else if t1 > EPS {
+        return t1 / 2.0;
+    }
+    return -1.0;
+}
+
+fn plane_t(plane: Plane, pos: vec3<f32>, dir: vec3<f32>) -> f32 {
+    let t = dot(plane.norm, plane.point - pos) / dot(plane.norm, dir);
+    return select(-1.0, t, t > EPS);
+}
+
+/// Möller–Trumbore, as in `simd.rs`: the distance, negative for a miss, and the
+/// barycentric coordinates of the hit.
+fn triangle_t(triangle: Triangle, pos: vec3<f32>, dir: vec3<f32>) -> vec3<f32> {
+    let p = cross(dir, triangle.edge2);
+    let det = dot(triangle.edge1, p);
+    if det == 0.0 {
+        return vec3<f32>(-1.0, 0.0, 0.0);
+    }
+    let inv_det = 1.0 / det;
+    let s = pos - triangle.v1;
+    let u = dot(s, p) * inv_det;
+    let q = cross(s, triangle.edge1);
+    let v = dot(dir, q) * inv_det;
+    let t = dot(triangle.edge2, q) * inv_det;
+    return vec3<f32>(select(-1.0, t, u >= 0.0 && v >= 0.0 && u + v <= 1.0 && t > EPS), u, v);
+}
+
+/// Distance at which the ray enters `node`'s box, or `FAR` if it misses it or enters past `t_max`.
+fn entry(node: Node, pos: vec3<f32>, inv_dir: vec3<f32>, t_max: f32) -> f32 {
+    let t1 = (node.min - pos) * inv_dir;
+    let t2 = (node.max - pos) * inv_dir;
+    let near = max(max(max(min(t1.x, t2.x), min(t1.y, t2.y)), min(t1.z, t2.z)), 0.0);
+    let far = min(min(min(max(t1.x, t2.x), max(t1.y, t2.y)), max(t1.z, t2.z)), t_max);
+    return select(FAR, near, near <= far);
+}
+
+/// The nearest hit along the ray, with `t` negative if there is none.
+fn closest(pos: vec3<f32>, dir: vec3<f32>) -> Hit {
+    var hit = Hit(FAR, vec3<f32>(0.0), 0u);
+    for (var i = 0u; i < params.spheres; i++) {
+        let t = sphere_t(spheres[i], pos, dir);
+        if t > 0.0 && t < hit.t {
+            let sphere = spheres[i];
+            hit = Hit(t, (pos + dir * t - sphere.center) / sphere.radius, sphere.material);
+        }
+    }
+    for (var i = 0u; i < params.planes; i++) {
+        let t = plane_t(planes[i], pos, dir);
+        if t > 0.0 && t < hit.t {
+            hit = Hit(t, planes[i].norm, planes[i].material);
+        }
+    }
+
+    // Zero components would divide into NaNs at the slabs.
+    let safe_dir = select(dir, vec3<f32>(1e-30), abs(dir) < vec3<f32>(1e-30));
+    let inv_dir = 1.0 / safe_dir;
+    var stack: array<u32, STACK_SIZE>;
+    var top = 0u;
+    if params.nodes > 0u {
+        stack[0] = 0u;
+        top = 1u;
+    }
+    while top > 0u {
+        top--;
+        let node = nodes[stack[top]];
+        if entry(node, pos, inv_dir, hit.t) == FAR {
+            continue;
+        }
+        if (node.b & LEAF) != 0u {
+            for (var i = node.a; i < node.a + (node.b & ~LEAF); i++) {
+                let tuv = triangle_t(triangles[i], pos, dir);
+                if tuv.x > 0.0 && tuv.x < hit.t {
+                    let triangle = triangles[i];
+                    let blend = triangle.n1 * (1.0 - tuv.y - tuv.z) + triangle.n2 * tuv.y + triangle.n3 * tuv.z;
+                    hit = Hit(tuv.x, normalize(blend), triangle.material);
+                }
+            }
+        } else if top + 2u <= STACK_SIZE {
+            // Visit the nearer child first so the farther one is more often culled.
+            let near_a = entry(nodes[node.a], pos, inv_dir, hit.t) <= entry(nodes[node.b], pos, inv_dir, hit.t);
+            stack[top] = select(node.a, node.b, near_a);
+            stack[top + 1u] = select(node.b, node.a, near_a);
+            top += 2u;
+        }
+    }
+
+    if hit.t == FAR {
+        hit.t = -1.0;
+    }
+    return hit;
+}
+
+/// Rotates `local`, given in a frame whose z axis is `n`, into world space.
+fn to_world(n: vec3<f32>, local: vec3<f32>) -> vec3<f32> {
+    var v2: vec3<f32>;
+    if abs(n.x) > abs(n.y) {
+        v2 = vec3<f32>(-n.z, 0.0, n.x) / sqrt(n.x * n.x + n.z * n.z);
+    } else {
+        v2 = vec3<f32>(0.0, n.z, -n.y) / sqrt(n.y * n.y + n.z * n.z);
+    }
+    let v3 = cross(n, v2);
+    return v2 * local.x + v3 * local.y + n * local.z;
+}
+
+fn rand_hemi() -> vec3<f32> {
+    let u1 = random();
+    let r = sqrt(1.0 - u1 * u1);
+    let phi = 2.0 * PI * random();
+    return vec3<f32>(r * cos(phi), r * sin(phi), u1);
+}
+
+fn rand_ggx(alpha: f32) -> vec3<f32> {
+    let u1 = random();
+    let theta = atan(alpha * sqrt(u1 / (1.0 - u1)));
+    let phi = 2.0 * PI * random();
+    return vec3<f32>(sin(theta) * cos(phi), sin(theta) * sin(phi), cos(theta));
+}
+
+/// `dir` mirrored about a normal drawn with the given `roughness`, or zero if it went into the surface.
+fn reflect_rough(dir: vec3<f32>, facing: vec3<f32>, roughness: f32) -> vec3<f32> {
+    var normal = facing;
+    if roughness > 0.0 {
+        normal = to_world(facing, rand_ggx(roughness * roughness));
+    }
+    let new_dir = dir - normal * (2.0 * dot(dir, normal));
+    return select(vec3<f32>(0.0), new_dir, dot(new_dir, facing) > 0.0);
+}
+
+fn refract_glass(dir: vec3<f32>, outward: vec3<f32>, ior: f32) -> vec3<f32> {
+    let r0 = pow((1.0 - ior) / (1.0 + ior), 2.0);
+    var n = outward;
+    var refr = 1.0 / ior;
+    if dot(outward, dir) > 0.0 { // we're inside the medium
+        n = -outward;
+        refr = ior;
+    }
+    let cost1 = -dot(n, dir);
+    let cost2 = 1.0 - refr * refr * (1.0 - cost1 * cost1);
+    let r_prob = r0 + (1.0 - r0) * pow(max(1.0 - cost1, 0.0), 5.0);
+    if cost2 > 0.0 && random() > r_prob {
+        return normalize(dir * refr + n * (refr * cost1 - sqrt(cost2)));
+    }
+    return normalize(dir + n * (cost1 * 2.0));
+}
+
+fn primary_dir(x: u32, y: u32) -> vec3<f32> {
+    let width = f32(params.width);
+    let height = f32(params.height);
+    // As in `trace::camera_ray`: a random point of the pixel, on the image plane one
+    // unit ahead.
+    let xf = f32(x) + random();
+    let yf = f32(y) + random();
+    let half_width = tan(params.fov);
+    let half_height = half_width * (height / width);
+    let forward = vec3<f32>(cos(params.theta) * sin(params.phi), sin(params.theta) * sin(params.phi), cos(params.phi));
+    var right = cross(forward, vec3<f32>(0.0, 0.0, 1.0));
+    if length(right) > 1e-6 {
+        right = normalize(right);
+    } else {
+        right = vec3<f32>(0.0, sign(forward.z), 0.0);
+    }
+    let up = cross(right, forward);
+    return normalize(forward
+        + right * (((2.0 * xf - width) / width) * half_width)
+        + up * (((height - 2.0 * yf) / height) * half_height));
+}
+
+@compute @workgroup_size(8, 8)
+fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+    if id.x >= params.width || id.y >= params.height {
+        return;
+    }
+    let index = id.y * params.width + id.x;
+    seed = pcg(index ^ pcg(params.sample));
+
+    var pos = params.pos;
+    var dir = primary_dir(id.x, id.y);
+    var throughput = vec3<f32>(1.0);
+    var radiance = vec3<f32>(0.0);
+    for (var depth = 0u; depth < params.max_depth; depth++) {
+        let hit = closest(pos, dir);
+        if hit.t < 0.0 {
+            break;
+        }
+        let material = materials[hit.material];
+        let n = hit.normal;
+        // Coming from inside an absorbing medium, the whole way here was spent in it.
+        if material.kind == TRANSLUCENT && dot(dir, n) > 0.0 {
+            throughput *= exp(-material.absorption * hit.t);
+        }
+        radiance += throughput * material.lum;
+
+        pos = pos + dir * hit.t;
+        let facing = select(-n, n, dot(dir, n) < 0.0);
+        let fresnel = 0.04 + 0.96 * pow(max(1.0 + dot(facing, dir), 0.0), 5.0);
+        if material.coat_strength > 0.0 && random() < material.coat_strength * fresnel {
+            dir = reflect_rough(dir, facing, material.coat_roughness);
+        } else if material.kind == MIRROR {
+            dir = reflect_rough(dir, facing, material.roughness);
+            throughput *= material.color / 255.0;
+        } else if random() < material.clearness {
+            dir = refract_glass(dir, n, material.ior);
+        } else {
+            dir = to_world(facing, rand_hemi());
+            throughput *= material.color / 255.0 * dot(dir, facing) / 0.9;
+        }
+        if all(dir == vec3<f32>(0.0)) {
+            break;
+        }
+    }
+    image[index] += vec4<f32>(radiance, 0.0);
+}
@@ -0,0 +1,435 @@
+use std::cell::RefCell;
+use std::sync::RwLock;
+
+use rand::Rng;
+use rayon::prelude::*;
+
+use crate::config::Config;
+use crate::film::Region;
+use crate::linalg::{consts::PI, Float, Vector3};
+use crate::random::{self, LocalRng};
+use crate::trace::{sample_pixel, to_world, Color};
+
+/// Samples a region of space may see in a pass of one path a pixel before it's split in
+/// two; passes of more paths let regions see more, by the square root of how many more.
+const LEAF_SAMPLES: Float = 1000.0;
+/// How many times space may be split, for samples that pile up in one spot.
+const MAX_SPATIAL_DEPTH: usize = 32;
+/// Share of a region's light a square of directions may hold before it's split in four.
+const SPLIT_ENERGY: Float = 0.01;
+/// How many times a square of directions may be split.
+const MAX_DIRECTIONAL_DEPTH: usize = 16;
+/// Chance of drawing a bounce from the guide rather than from the surface's own spread.
+const GUIDED: Float = 0.5;
+/// How many rows a training pass traces at once before adding what they saw to the guide,
+/// in order, so that it learns the same however many threads there are.
+const BAND: usize = 8;
+/// About how many of the places the first pass sees to find the box around them from.
+const PLACES: usize = 4096;
+
+thread_local! {
+    /// What the row this thread is training on has seen so far, if it's training.
+    static ROW: RefCell<Option<Vec<Sample>>> = const { RefCell::new(None) };
+}
+
+/// Light arriving at a point from one direction, as one path measured it.
+#[derive(Debug, Clone, Copy)]
+struct Sample {
+    pos: Vector3,
+    /// The direction, mapped onto the unit square by `to_square`.
+    dir: [Float; 2],
+    /// How bright the light was, divided by how likely the path was to look that way.
+    value: Float
+}
+
+/// Where light comes from around the scene, learned by tracing a few passes before a
+/// render, for diffuse bounces to be drawn toward: after Müller, Gross and Novák's SD-tree,
+/// space is split until each region holds a fair share of what training saw, and each
+/// region keeps a quadtree over directions that's finer where more light came from.
+///
+/// Each training pass traces twice as many paths a pixel as the one before, drawing
+/// bounces from what that one learned, into a tree refined from it. Half of all guided
+/// bounces are still drawn the usual way, so light the guide missed is found all the same.
+#[derive(Debug)]
+pub struct PathGuide {
+    /// How many passes to train with, which together trace `2^passes - 1` paths a pixel.
+    pub passes: u32,
+    tree: RwLock<Option<SdTree>>
+}
+
+impl PathGuide {
+    pub fn new(passes: u32) -> Self {
+        PathGuide { passes, tree: RwLock::new(None) }
+    }
+
+    /// Whether the guide has been trained, which it only is once.
+    pub fn is_trained(&self) -> bool {
+        self.tree.read().unwrap().is_some()
+    }
+
+    /// Trains the guide on the paths through every pixel, unless it already has been. It
+    /// learns from the whole image even when only a tile of it is rendered, since the tile
+    /// that trains it is the only one it learns from. With a `seed`, rows draw from it as
+    /// `trace::make_image_with`'s do, so the guide learns the same every time however the
+    /// rows are shared between threads. After each band of rows `band_done` is told how
+    /// many paths it traced, and training stops, leaving the guide untrained, if it returns
+    /// `false`.
+    pub(crate) fn train(&self, config: &Config, seed: Option<u64>, band_done: &mut dyn FnMut(u64) -> bool) {
+        if self.is_trained() {
+            return;
+        }
+        let region = Region::full(config.width, config.height);
+        let rows: Vec<u32> = region.y.clone().collect();
+        let mut learning = SdTree::new();
+        for pass in 0..self.passes {
+            let paths = 1u32 << pass.min(16);
+            for band in rows.chunks(BAND) {
+                let seen: Vec<Vec<Sample>> = band.par_iter().map(|&y| {
+                    if let Some(seed) = seed {
+                        random::reseed_row(seed ^ (pass as u64 + 1).wrapping_mul(0xbf58_476d_1ce4_e5b9), y);
+                    }
+                    ROW.with(|row| *row.borrow_mut() = Some(vec![]));
+                    for x in region.x.clone() {
+                        for _ in 0..paths {
+                            sample_pixel(config, x, y, &mut LocalRng);
+                        }
+                    }
+                    ROW.with(|row| row.borrow_mut().take().unwrap_or_default())
+                }).collect();
+                seen.into_iter().flatten().for_each(|sample| learning.record(sample));
+                if !band_done(band.len() as u64 * region.x.len() as u64 * paths as u64) {
+                    *self.tree.write().unwrap() = None;
+                    return;
+                }
+            }
+            let next = learning.refined(LEAF_SAMPLES * (2.0 * paths as Float).sqrt());
+            *self.tree.write().unwrap() = Some(learning);
+            learning = next;
+        }
+    }
+
+    /// How many paths training traces.
+    pub(crate) fn paths(&self, config: &Config) -> u64 {
+        Region::full(config.width, config.height).pixels() * (0..self.passes).map(|pass| 1u64 << pass.min(16)).sum::<u64>()
+    }
+
+    /// A direction for a diffuse bounce at `pos` off a surface facing `facing`, with the
+    /// density it was drawn with over the sphere, or `None` if the guide knows nothing
+    /// of there and the bounce should be drawn the usual way.
+    pub(crate) fn sample(&self, pos: Vector3, facing: Vector3, rng: &mut impl Rng) -> Option<(Vector3, Float)> {
+        let tree = self.tree.read().unwrap();
+        let directions = tree.as_ref()?.lookup(pos);
+        if directions.total() <= 0.0 {
+            return None;
+        }
+        let dir = if rng.gen::<Float>() < GUIDED {
+            from_square(directions.sample(rng))
+        } else {
+            to_world(facing, Vector3::rand_hemi2())
+        };
+        let bsdf = if dir.dot(facing) > 0.0 { 1.0 / (2.0 * PI) } else { 0.0 };
+        let guide = directions.pdf(to_square(dir)) / (4.0 * PI);
+        Some((dir, GUIDED * guide + (1.0 - GUIDED) * bsdf))
+    }
+}
+
+/// Notes, if this thread is training, that `incoming` light arrived at `pos` from `dir`,
+/// which the path was drawn toward with density `pdf`.
+pub(crate) fn record(pos: Vector3, dir: Vector3, incoming: Color, pdf: Float) {
+    ROW.with(|row| {
+        if let Some(row) = row.borrow_mut().as_mut() {
+            let value = (incoming.x + incoming.y + incoming.z) / 3.0 / pdf;
+            if value.is_finite() && value >= 0.0 {
+                row.push(Sample { pos, dir: to_square(dir), value });
+            }
+        }
+    })
+}
+
+/// `dir` on the unit square, by height and by angle around, so that equal areas of the
+/// square are equal solid angles of the sphere.
+fn to_square(dir: Vector3) -> [Float; 2] {
+    let around = dir.y.atan2(dir.x) / (2.0 * PI);
+    [((dir.z + 1.0) / 2.0).clamp(0.0, 1.0), around - around.floor()]
+}
+
+/// The direction `to_square` puts at `point`.
+fn from_square(point: [Float; 2]) -> Vector3 {
+    let z = 2.0 * point[0] - 1.0;
+    let r = (1.0 - z * z).max(0.0).sqrt();
+    let phi = 2.0 * PI * point[1];
+    Vector3::new(r * phi.cos(), r * phi.sin(), z)
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    /// A region of space, with how many samples it saw and where their light came from.
+    Leaf { samples: usize, directions: Quadtree },
+    /// Everything below `split` on `axis` is in the first child, everything above in the second.
+    Inner { axis: usize, split: Float, children: [usize; 2] }
+}
+
+/// Space, split where training saw the most, with a quadtree of directions at each leaf.
+#[derive(Debug)]
+struct SdTree {
+    nodes: Vec<Node>,
+    /// The box the first split divides: around most of what the first pass saw.
+    bounds: Box3,
+    /// Until space is split, every `stride`th place seen, of `seen` so far, to find the
+    /// box from.
+    places: Vec<Vector3>,
+    stride: usize,
+    seen: usize
+}
+
+impl SdTree {
+    fn new() -> Self {
+        let root = Node::Leaf { samples: 0, directions: Quadtree::new() };
+        SdTree { nodes: vec![root], bounds: ([0.0; 3], [0.0; 3]), places: vec![], stride: 1, seen: 0 }
+    }
+
+    fn record(&mut self, sample: Sample) {
+        if self.nodes.len() == 1 {
+            if self.seen.is_multiple_of(self.stride) {
+                self.places.push(sample.pos);
+                // Keeping every other place is enough to find the box from, and keeps
+                // the list short however much is seen.
+                if self.places.len() >= 2 * PLACES {
+                    self.places = self.places.iter().copied().step_by(2).collect();
+                    self.stride *= 2;
+                }
+            }
+            self.seen += 1;
+        }
+        let index = self.leaf(sample.pos);
+        if let Node::Leaf { samples, directions } = &mut self.nodes[index] {
+            *samples += 1;
+            directions.record(sample.dir, sample.value);
+        }
+    }
+
+    /// A tree with this one's structure refined for another pass and nothing seen yet:
+    /// regions that saw more than `limit` samples split, and their directions split where
+    /// they saw the most light.
+    fn refined(&self, limit: Float) -> Self {
+        let bounds = if self.nodes.len() == 1 { around(&self.places) } else { self.bounds };
+        let mut tree = SdTree { nodes: vec![], bounds, places: vec![], stride: 1, seen: 0 };
+        tree.refine(self, 0, bounds, limit, 0);
+        tree
+    }
+
+    /// Adds a refined copy of `old`'s node `index`, which covers `bounds`, splitting
+    /// leaves that saw more than `limit` samples. Returns the copy's index.
+    fn refine(&mut self, old: &SdTree, index: usize, bounds: Box3, limit: Float, depth: usize) -> usize {
+        match &old.nodes[index] {
+            Node::Leaf { samples, directions } =>
+                self.split(directions.refined(), *samples as Float / limit, bounds, depth),
+            &Node::Inner { axis, split, children } => {
+                let new = self.nodes.len();
+                self.nodes.push(Node::Inner { axis, split, children: [0, 0] });
+                let (low, high) = halves(bounds, axis, split);
+                let low = self.refine(old, children[0], low, limit, depth + 1);
+                let high = self.refine(old, children[1], high, limit, depth + 1);
+                self.nodes[new] = Node::Inner { axis, split, children: [low, high] };
+                new
+            }
+        }
+    }
+
+    /// Adds a leaf with `directions` for `bounds`, split in half across its longest side
+    /// for as long as the region saw more than `crowding` times the samples it may.
+    /// Returns its index.
+    fn split(&mut self, directions: Quadtree, crowding: Float, bounds: Box3, depth: usize) -> usize {
+        let index = self.nodes.len();
+        let (min, max) = bounds;
+        let axis = (0..3).max_by(|&a, &b| (max[a] - min[a]).total_cmp(&(max[b] - min[b]))).unwrap();
+        // A region nothing was seen in has no sides to split.
+        if crowding <= 1.0 || depth >= MAX_SPATIAL_DEPTH || max[axis] <= min[axis] {
+            self.nodes.push(Node::Leaf { samples: 0, directions });
+            return index;
+        }
+        self.nodes.push(Node::Inner { axis, split: 0.0, children: [0, 0] });
+        let split = (min[axis] + max[axis]) / 2.0;
+        let (low, high) = halves(bounds, axis, split);
+        let low = self.split(directions.clone(), crowding / 2.0, low, depth + 1);
+        let high = self.split(directions, crowding / 2.0, high, depth + 1);
+        self.nodes[index] = Node::Inner { axis, split, children: [low, high] };
+        index
+    }
+
+    /// The index of the leaf for the region around `pos`.
+    fn leaf(&self, pos: Vector3) -> usize {
+        let mut index = 0;
+        loop {
+            match &self.nodes[index] {
+                Node::Leaf { .. } => return index,
+                Node::Inner { axis, split, children } => index = children[(coord(pos, *axis) >= *split) as usize]
+            }
+        }
+    }
+
+    /// Where light arrives from in the region around `pos`.
+    fn lookup(&self, pos: Vector3) -> &Quadtree {
+        match &self.nodes[self.leaf(pos)] {
+            Node::Leaf { directions, .. } => directions,
+            Node::Inner { .. } => unreachable!("`leaf` finds leaves")
+        }
+    }
+}
+
+/// A box, as its least and greatest corners.
+type Box3 = ([Float; 3], [Float; 3]);
+
+/// The box around all but the farthest few of `places` each way, which are often
+/// surfaces near the horizon whose room would leave nothing for the rest.
+fn around(places: &[Vector3]) -> Box3 {
+    let (mut min, mut max) = ([0.0; 3], [0.0; 3]);
+    if places.is_empty() {
+        return (min, max);
+    }
+    for axis in 0..3 {
+        let mut coords: Vec<Float> = places.iter().map(|&pos| coord(pos, axis)).collect();
+        coords.sort_by(Float::total_cmp);
+        let outliers = coords.len() / 100;
+        min[axis] = coords[outliers];
+        max[axis] = coords[coords.len() - 1 - outliers];
+    }
+    (min, max)
+}
+
+/// The parts of `bounds` below and above `split` on `axis`.
+fn halves((min, max): Box3, axis: usize, split: Float) -> (Box3, Box3) {
+    let (mut low_max, mut high_min) = (max, min);
+    low_max[axis] = split;
+    high_min[axis] = split;
+    ((min, low_max), (high_min, max))
+}
+
+fn coord(pos: Vector3, axis: usize) -> Float {
+    [pos.x, pos.y, pos.z][axis]
+}
+
+/// A square of directions, as the light that arrived from each of its quarters, left to
+/// right and then bottom to top, and the squares those are split into, if they are.
+#[derive(Debug, Clone)]
+struct Quad {
+    energy: [Float; 4],
+    /// Indices of the quarters' own nodes, or 0 for quarters that aren't split.
+    children: [usize; 4]
+}
+
+impl Quad {
+    fn new() -> Self {
+        Quad { energy: [0.0; 4], children: [0; 4] }
+    }
+}
+
+/// How light arriving at a region was spread over directions on the unit square.
+#[derive(Debug, Clone)]
+struct Quadtree {
+    nodes: Vec<Quad>
+}
+
+/// Which quarter of its square `point` is in, and where it is within that quarter.
+fn quarter([x, y]: [Float; 2]) -> (usize, [Float; 2]) {
+    let (right, top) = (x >= 0.5, y >= 0.5);
+    (right as usize + 2 * top as usize, [2.0 * x - right as u8 as Float, 2.0 * y - top as u8 as Float])
+}
+
+impl Quadtree {
+    fn new() -> Self {
+        Quadtree { nodes: vec![Quad::new()] }
+    }
+
+    /// All the light that arrived.
+    fn total(&self) -> Float {
+        self.nodes[0].energy.iter().sum()
+    }
+
+    /// Adds light of `value` arriving from `point`.
+    fn record(&mut self, mut point: [Float; 2], value: Float) {
+        let mut index = 0;
+        loop {
+            let (which, within) = quarter(point);
+            let node = &mut self.nodes[index];
+            node.energy[which] += value;
+            match node.children[which] {
+                0 => return,
+                child => (index, point) = (child, within)
+            }
+        }
+    }
+
+    /// A tree with nothing arrived yet, split into quarters wherever this one's hold more
+    /// than their share of its light, and nowhere else.
+    fn refined(&self) -> Self {
+        let mut tree = Quadtree { nodes: vec![] };
+        let total = self.total();
+        tree.refine(Some((self, 0)), total, total, 0);
+        tree
+    }
+
+    /// Adds a node for a square that saw `energy` of the `total` light: refined from the
+    /// given node of an old tree, or fresh, with the light spread evenly over it, if there
+    /// isn't one. Returns its index.
+    fn refine(&mut self, old: Option<(&Quadtree, usize)>, energy: Float, total: Float, depth: usize) -> usize {
+        let new = self.nodes.len();
+        self.nodes.push(Quad::new());
+        for which in 0..4 {
+            let (energy, child) = match old {
+                Some((tree, index)) => (tree.nodes[index].energy[which], tree.nodes[index].children[which]),
+                None => (energy / 4.0, 0)
+            };
+            if energy > SPLIT_ENERGY * total && depth < MAX_DIRECTIONAL_DEPTH {
+                let child = match (old, child) {
+                    (Some((tree, _)), child) if child != 0 => self.refine(Some((tree, child)), energy, total, depth + 1),
+                    _ => self.refine(None, energy, total, depth + 1)
+                };
+                self.nodes[new].children[which] = child;
+            }
+        }
+        new
+    }
+
+    /// A point of the square, drawn in proportion to the light arriving from there.
+    fn sample(&self, rng: &mut impl Rng) -> [Float; 2] {
+        let (mut index, mut origin, mut size) = (0, [0.0, 0.0], 1.0);
+        loop {
+            let node = &self.nodes[index];
+            let mut pick = rng.gen::<Float>() * node.energy.iter().sum::<Float>();
+            // Rounding can leave a little over; it goes to the last quarter with any light.
+            let mut which = (0..4).rev().find(|&which| node.energy[which] > 0.0).unwrap_or(0);
+            for (candidate, &energy) in node.energy.iter().enumerate() {
+                if pick < energy {
+                    which = candidate;
+                    break;
+                }
+                pick -= energy;
+            }
+            size /= 2.0;
+            origin[0] += (which % 2) as Float * size;
+            origin[1] += (which / 2) as Float * size;
+            match node.children[which] {
+                0 => return [origin[0] + rng.gen::<Float>() * size, origin[1] + rng.gen::<Float>() * size],
+                child => index = child
+            }
+        }
+    }
+
+    /// The density `sample` draws `point` with over the square.
+    fn pdf(&self, mut point: [Float; 2]) -> Float {
+        let (mut index, mut density) = (0, 1.0);
+        loop {
+            let node = &self.nodes[index];
+            let (which, within) = quarter(point);
+            if node.energy[which] <= 0.0 {
+                return 0.0;
+            }
+            density *= 4.0 * node.energy[which] / node.energy.iter().sum::<Float>();
+            match node.children[which] {
+                0 => return density,
+                child => (index, point) = (child, within)
+            }
+        }
+    }
+}
@@ -0,0 +1,226 @@
+use std::mem::size_of;
+use std::path::{Path, PathBuf};
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::config::{ConfigError, ConfigResult};
+use crate::linalg::{Aabb, Float, Vector3};
+use crate::shapes::{Hit, Ray, Shape, Triangle};
+
+/// Layers of noise summed into noise terrain, each with cells half the size and half
+/// the height of the one before.
+const OCTAVES: u32 = 5;
+
+/// Where a heightfield's heights came from.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Source {
+    /// A grayscale image, seen from above with its top to the north (+y), white highest.
+    Image(PathBuf),
+    /// Hills of noise drawn from `seed`, sampled `cells` times across each way.
+    Noise { seed: u64, cells: u32 }
+}
+
+/// Terrain over a box: a grid of heights, each cell split into two triangles, traced by
+/// stepping through the cells under the ray instead of building a tree over them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Heightfield {
+    pub source: Source,
+    /// The corner of the box with the smallest coordinates, under the first height.
+    pub origin: Vector3,
+    /// How far the box reaches from `origin` along each axis: the heights run from 0 to
+    /// `size.z`.
+    pub size: Vector3,
+    columns: usize,
+    rows: usize,
+    /// Heights from 0 to 1, row after row from the south, each from west to east.
+    heights: Vec<Float>,
+    /// Unit normals at the heights, blended across cells for smooth shading.
+    normals: Vec<Vector3>
+}
+
+impl Heightfield {
+    /// Terrain of `columns` by `rows` heights from 0 to 1, at least 2 each way, given
+    /// row after row from the south.
+    pub fn new(source: Source, origin: Vector3, size: Vector3, columns: usize, rows: usize, heights: Vec<Float>) -> Heightfield {
+        let mut field = Heightfield { source, origin, size, columns, rows, heights, normals: vec![] };
+        field.normals = (0..rows)
+            .flat_map(|j| (0..columns).map(move |i| (i, j)))
+            .map(|(i, j)| field.normal(i, j))
+            .collect();
+        field
+    }
+
+    /// Terrain from the grayscale image at `path`, one height per pixel.
+    pub fn load(path: &Path, origin: Vector3, size: Vector3) -> ConfigResult<Heightfield> {
+        let image = image::open(path)
+            .map_err(|err| ConfigError::InvalidAsset(path.to_path_buf(), err.to_string()))?
+            .to_luma16();
+        let (columns, rows) = (image.width() as usize, image.height() as usize);
+        if columns < 2 || rows < 2 {
+            return Err(ConfigError::InvalidAsset(path.to_path_buf(), "a heightfield needs at least 2×2 pixels".to_string()));
+        }
+        // The image's first row is the north edge.
+        let heights = (0..rows).rev()
+            .flat_map(|j| (0..columns).map(move |i| (i, j)))
+            .map(|(i, j)| image.get_pixel(i as u32, j as u32)[0] as Float / u16::MAX as Float)
+            .collect();
+        Ok(Heightfield::new(Source::Image(path.to_path_buf()), origin, size, columns, rows, heights))
+    }
+
+    /// Rolling terrain of value noise from `seed`, `cells` cells across each way.
+    pub fn noise(seed: u64, cells: u32, origin: Vector3, size: Vector3) -> Heightfield {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let samples = cells as usize + 1;
+        let mut heights = vec![0.0; samples * samples];
+        for octave in 0..OCTAVES {
+            let lattice = 2usize << octave;
+            let values: Vec<Float> = (0..(lattice + 1) * (lattice + 1)).map(|_| rng.gen()).collect();
+            let amplitude = (0.5 as Float).powi(octave as i32);
+            for (index, height) in heights.iter_mut().enumerate() {
+                let at = |k: usize| k as Float / cells as Float * lattice as Float;
+                let (x, y) = (at(index % samples), at(index / samples));
+                let (i, j) = ((x as usize).min(lattice - 1), (y as usize).min(lattice - 1));
+                // Smoothstep hides the lattice's straight lines.
+                let ease = |f: Float| f * f * (3.0 - 2.0 * f);
+                let (fx, fy) = (ease(x - i as Float), ease(y - j as Float));
+                let v = |i: usize, j: usize| values[j * (lattice + 1) + i];
+                let south = v(i, j) + (v(i + 1, j) - v(i, j)) * fx;
+                let north = v(i, j + 1) + (v(i + 1, j + 1) - v(i, j + 1)) * fx;
+                *height += (south + (north - south) * fy) * amplitude;
+            }
+        }
+        // Stretched to fill the box, lowest point to highest.
+        let (low, high) = heights.iter().fold((Float::INFINITY, Float::NEG_INFINITY), |(low, high), &h| (low.min(h), high.max(h)));
+        let range = if high > low { high - low } else { 1.0 };
+        heights.iter_mut().for_each(|h| *h = (*h - low) / range);
+        Heightfield::new(Source::Noise { seed, cells }, origin, size, samples, samples, heights)
+    }
+
+    /// How many heights there are west to east and south to north.
+    pub fn samples(&self) -> (usize, usize) {
+        (self.columns, self.rows)
+    }
+
+    /// The heights from 0 to 1, in the order `new` takes them.
+    pub fn heights(&self) -> &[Float] {
+        &self.heights
+    }
+
+    /// Bytes the heights and their normals take up.
+    pub fn memory(&self) -> usize {
+        self.heights.len() * size_of::<Float>() + self.normals.len() * size_of::<Vector3>()
+    }
+
+    fn height(&self, i: usize, j: usize) -> Float {
+        self.heights[j * self.columns + i]
+    }
+
+    /// Where height `(i, j)` is in the world.
+    fn point(&self, i: usize, j: usize) -> Vector3 {
+        let x = i as Float / (self.columns - 1) as Float * self.size.x;
+        let y = j as Float / (self.rows - 1) as Float * self.size.y;
+        self.origin + Vector3::new(x, y, self.height(i, j) * self.size.z)
+    }
+
+    /// The normal at height `(i, j)`, from the slope to its neighbors on either side.
+    fn normal(&self, i: usize, j: usize) -> Vector3 {
+        let (west, east) = (i.saturating_sub(1), (i + 1).min(self.columns - 1));
+        let (south, north) = (j.saturating_sub(1), (j + 1).min(self.rows - 1));
+        let dx = (self.point(east, j) - self.point(west, j)).scale(1.0 / (east - west) as Float);
+        let dy = (self.point(i, north) - self.point(i, south)).scale(1.0 / (north - south) as Float);
+        dx.cross(dy).normalize()
+    }
+
+    /// The two triangles of cell `(i, j)`, between heights `i` and `i + 1` across and
+    /// `j` and `j + 1` up.
+    fn triangles(&self, i: usize, j: usize) -> [Triangle; 2] {
+        let corner = |i: usize, j: usize| (self.point(i, j), self.normals[j * self.columns + i]);
+        let (a, b, c, d) = (corner(i, j), corner(i + 1, j), corner(i, j + 1), corner(i + 1, j + 1));
+        [
+            Triangle::with_normals(a.0, b.0, d.0, [a.1, b.1, d.1]),
+            Triangle::with_normals(a.0, d.0, c.0, [a.1, d.1, c.1])
+        ]
+    }
+}
+
+impl Shape for Heightfield {
+    fn intersect(&self, ray: Ray, t_max: Float) -> Option<Hit> {
+        // In grid space every cell is 1 across and heights run from 0 to 1; stretching
+        // the ray there leaves distances along it as they were.
+        let (last_i, last_j) = ((self.columns - 1) as Float, (self.rows - 1) as Float);
+        let stretch = Vector3::new(last_i / self.size.x, last_j / self.size.y, 1.0 / self.size.z);
+        let grid = |v: Vector3| Vector3::new(v.x * stretch.x, v.y * stretch.y, v.z * stretch.z);
+        let (pos, dir) = (grid(ray.pos - self.origin), grid(ray.dir));
+
+        let (mut enter, mut leave) = (0.0, t_max);
+        for (p, d, high) in [(pos.x, dir.x, last_i), (pos.y, dir.y, last_j), (pos.z, dir.z, 1.0)] {
+            if d == 0.0 {
+                if p < 0.0 || p > high {
+                    return None;
+                }
+                continue;
+            }
+            let (a, b) = (-p / d, (high - p) / d);
+            enter = Float::max(enter, a.min(b));
+            leave = Float::min(leave, a.max(b));
+        }
+        if enter > leave {
+            return None;
+        }
+
+        // 2D DDA: cell after cell under the ray, nearest first, so the first hit is the hit.
+        let start = pos + dir.scale(enter);
+        let cell = |p: Float, last: usize| (p.floor().max(0.0) as usize).min(last - 1);
+        let (mut i, mut j) = (cell(start.x, self.columns - 1), cell(start.y, self.rows - 1));
+        let crossing = |p: Float, d: Float, k: usize| match d {
+            d if d > 0.0 => (k as Float + 1.0 - p) / d,
+            d if d < 0.0 => (k as Float - p) / d,
+            _ => Float::INFINITY
+        };
+        let (mut next_x, mut next_y) = (crossing(pos.x, dir.x, i), crossing(pos.y, dir.y, j));
+        let (step_x, step_y) = ((1.0 / dir.x).abs(), (1.0 / dir.y).abs());
+        loop {
+            let exit = next_x.min(next_y).min(leave);
+            // Cells the ray passes wholly above or below can't be hit.
+            let (z1, z2) = (pos.z + dir.z * enter, pos.z + dir.z * exit);
+            let corners = [self.height(i, j), self.height(i + 1, j), self.height(i, j + 1), self.height(i + 1, j + 1)];
+            let low = corners.iter().cloned().fold(Float::INFINITY, Float::min);
+            let high = corners.iter().cloned().fold(Float::NEG_INFINITY, Float::max);
+            if z1.min(z2) <= high && z1.max(z2) >= low {
+                let hit = self.triangles(i, j).iter()
+                    .filter_map(|triangle| triangle.intersect_uv(ray).map(|(t, u, v)| (t, triangle.normal_at(u, v))))
+                    .filter(|&(t, _)| t < t_max)
+                    .min_by(|a, b| a.0.total_cmp(&b.0));
+                if let Some((t, normal)) = hit {
+                    let p = ray.get_point(t) - self.origin;
+                    return Some(Hit::new(ray, t, normal, (p.x / self.size.x, p.y / self.size.y)));
+                }
+            }
+            if exit >= leave {
+                return None;
+            }
+            if next_x < next_y {
+                if dir.x > 0.0 && i + 2 < self.columns { i += 1 } else if dir.x < 0.0 && i > 0 { i -= 1 } else { return None }
+                enter = next_x;
+                next_x += step_x;
+            } else {
+                if dir.y > 0.0 && j + 2 < self.rows { j += 1 } else if dir.y < 0.0 && j > 0 { j -= 1 } else { return None }
+                enter = next_y;
+                next_y += step_y;
+            }
+        }
+    }
+
+    fn area(&self) -> Option<Float> {
+        let cells = (0..self.rows - 1).flat_map(|j| (0..self.columns - 1).map(move |i| (i, j)));
+        Some(cells.flat_map(|(i, j)| self.triangles(i, j)).map(|triangle| triangle.area()).sum())
+    }
+
+    fn as_heightfield(&self) -> Option<&Heightfield> {
+        Some(self)
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        Some(Aabb::new(self.origin, self.origin + self.size))
+    }
+}
@@ -0,0 +1,132 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::config::{ConfigError, ConfigResult};
+use crate::linalg::Float;
+
+/// How a light fixture's brightness varies with direction, measured and written to an IES
+/// (LM-63) photometric file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Ies {
+    /// The file the measurements came from.
+    pub file: PathBuf,
+    /// Degrees from straight down the fixture's axis, rising.
+    vertical: Vec<Float>,
+    /// Degrees around the axis, rising from 0, the last of them telling how much of the
+    /// way around was measured and how the rest mirrors it.
+    horizontal: Vec<Float>,
+    /// The brightness along each vertical angle, for one horizontal angle after another,
+    /// as fractions of the brightest.
+    values: Vec<Float>
+}
+
+impl Ies {
+    /// Reads the type C profile in the file at `path`, the kind nearly every fixture is
+    /// measured as.
+    pub fn load(path: &Path) -> ConfigResult<Ies> {
+        let invalid = |message| ConfigError::InvalidAsset(path.to_path_buf(), message);
+        let bytes = fs::read(path).map_err(|err| invalid(err.to_string()))?;
+        let text = std::str::from_utf8(&bytes).map_err(|_| invalid("expected a text IES file".to_string()))?;
+        Ies::parse(path, text).map_err(invalid)
+    }
+
+    fn parse(path: &Path, text: &str) -> Result<Ies, String> {
+        // Keywords and the header come before the tilt line; the numbers after it run on
+        // regardless of where lines break.
+        let mut lines = text.lines();
+        let tilt = lines.by_ref()
+            .find_map(|line| line.trim().strip_prefix("TILT="))
+            .ok_or("expected a `TILT=` line")?;
+        if tilt.trim() != "NONE" {
+            return Err("only profiles with `TILT=NONE` are supported".to_string());
+        }
+        let mut numbers = lines.flat_map(|line| line.split(|c: char| c.is_whitespace() || c == ','))
+            .filter(|word| !word.is_empty())
+            .map(|word| word.parse::<Float>().map_err(|_| format!("expected a number, found {:?}", word)));
+        let mut next = |what: &str| numbers.next().unwrap_or_else(|| Err(format!("the file ends before the {}", what)));
+
+        let _lamps = next("number of lamps")?;
+        let _lumens = next("lumens per lamp")?;
+        let multiplier = next("candela multiplier")?;
+        let vertical_count = next("number of vertical angles")? as usize;
+        let horizontal_count = next("number of horizontal angles")? as usize;
+        if next("photometric type")? != 1.0 {
+            return Err("only type C photometry is supported".to_string());
+        }
+        // Units, the luminous opening's size, the ballast factor, a number kept for future
+        // use and the input watts don't change the shape of the light.
+        for what in ["units type", "width", "length", "height", "ballast factor", "future use", "input watts"] {
+            next(what)?;
+        }
+        if vertical_count < 2 || horizontal_count < 1 {
+            return Err("expected at least 2 vertical angles and 1 horizontal angle".to_string());
+        }
+        let vertical = (0..vertical_count).map(|_| next("vertical angles")).collect::<Result<Vec<_>, _>>()?;
+        let horizontal = (0..horizontal_count).map(|_| next("horizontal angles")).collect::<Result<Vec<_>, _>>()?;
+        let mut values = (0..vertical_count * horizontal_count)
+            .map(|_| next("candela values").map(|value| value * multiplier))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let rising = |angles: &[Float]| angles.windows(2).all(|pair| pair[0] < pair[1]);
+        if !rising(&vertical) || !rising(&horizontal) || horizontal[0] != 0.0 {
+            return Err("expected angles rising from 0 around the axis".to_string());
+        }
+        let brightest = values.iter().cloned().fold(0.0, Float::max);
+        if brightest <= 0.0 {
+            return Err("the fixture gives off no light".to_string());
+        }
+        values.iter_mut().for_each(|value| *value /= brightest);
+        Ok(Ies { file: path.to_path_buf(), vertical, horizontal, values })
+    }
+
+    /// A profile measured at `vertical` and `horizontal` angles, at least 2 and 1 of them
+    /// and each rising, with `values` laid out as `at` reads them.
+    pub(crate) fn new(file: PathBuf, vertical: Vec<Float>, horizontal: Vec<Float>, values: Vec<Float>) -> Ies {
+        Ies { file, vertical, horizontal, values }
+    }
+
+    /// The vertical and horizontal angles the profile was measured at.
+    pub fn angles(&self) -> (&[Float], &[Float]) {
+        (&self.vertical, &self.horizontal)
+    }
+
+    /// The brightness along each vertical angle, for one horizontal angle after another.
+    pub fn values(&self) -> &[Float] {
+        &self.values
+    }
+
+    /// The brightness `gamma` degrees off straight down the axis and `azimuth` degrees
+    /// around it, as a fraction of the brightest.
+    pub fn at(&self, gamma: Float, azimuth: Float) -> Float {
+        let (first, last) = (self.vertical[0], self.vertical[self.vertical.len() - 1]);
+        if gamma < first || gamma > last {
+            return 0.0;
+        }
+        // A profile measured part of the way around is mirrored for the rest.
+        let mut azimuth = azimuth.rem_euclid(360.0);
+        let around = self.horizontal[self.horizontal.len() - 1];
+        if around <= 180.0 && azimuth > 180.0 {
+            azimuth = 360.0 - azimuth;
+        }
+        if around <= 90.0 && azimuth > 90.0 {
+            azimuth = 180.0 - azimuth;
+        }
+
+        let (v, fv) = bracket(&self.vertical, gamma);
+        let (h, fh) = bracket(&self.horizontal, azimuth);
+        let column = |h: usize| {
+            let at = |v: usize| self.values[h * self.vertical.len() + v];
+            at(v) + (at((v + 1).min(self.vertical.len() - 1)) - at(v)) * fv
+        };
+        column(h) + (column((h + 1).min(self.horizontal.len() - 1)) - column(h)) * fh
+    }
+}
+
+/// The index in rising `angles` at or before `angle`, and how far it is toward the next.
+fn bracket(angles: &[Float], angle: Float) -> (usize, Float) {
+    let i = angles.partition_point(|&a| a <= angle).saturating_sub(1).min(angles.len() - 1);
+    match angles.get(i + 1) {
+        Some(&next) => (i, ((angle - angles[i]) / (next - angles[i])).clamp(0.0, 1.0)),
+        None => (i, 0.0)
+    }
+}
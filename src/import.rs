@@ -0,0 +1,237 @@
+use crate::linalg::consts::PI;
+use std::path::Path;
+use std::sync::Arc;
+
+use gltf::camera::Projection;
+use gltf::khr_lights_punctual::Kind;
+use gltf::material::AlphaMode;
+
+use crate::assets::Assets;
+use crate::config::{Config, ConfigError, ConfigResult};
+use crate::filter::Filter;
+use crate::light::Light;
+use crate::linalg::{Float, Vector3};
+use crate::mesh::{Accel, Face, Mesh, MeshData, Vertices};
+use crate::sampler::Sampler;
+use crate::shapes::{Ray, Sphere};
+use crate::trace::{Backface, Color, Glass, Material, Object, Principled, Visibility};
+
+/// Image width for imported scenes; the height follows the camera's aspect ratio.
+const WIDTH: u32 = 640;
+/// Radius of the small emissive spheres that stand in for point lights.
+const LIGHT_RADIUS: Float = 0.05;
+
+/// A column-major 4×4 transform, as glTF stores them.
+type Matrix = [[Float; 4]; 4];
+
+fn multiply(a: &Matrix, b: &Matrix) -> Matrix {
+    let mut product = [[0.0; 4]; 4];
+    for (col, out) in product.iter_mut().enumerate() {
+        for (row, value) in out.iter_mut().enumerate() {
+            *value = (0..4).map(|k| a[k][row] * b[col][k]).sum();
+        }
+    }
+    product
+}
+
+/// Applies `m` to `(x, y, z, w)` and converts from glTF's y-up axes to the tracer's z-up ones.
+fn transform(m: &Matrix, [x, y, z]: [Float; 3], w: Float) -> Vector3 {
+    let apply = |row: usize| m[0][row] * x + m[1][row] * y + m[2][row] * z + m[3][row] * w;
+    Vector3::new(apply(0), -apply(2), apply(1))
+}
+
+fn to_color([r, g, b]: [f32; 3]) -> Color {
+    Color::new(r as Float, g as Float, b as Float).scale(255.0)
+}
+
+fn material(material: &gltf::Material) -> (Color, Color, Material, Backface) {
+    let pbr = material.pbr_metallic_roughness();
+    let [r, g, b, alpha] = pbr.base_color_factor();
+    let color = to_color([r, g, b]);
+    let lum = to_color(material.emissive_factor());
+
+    let transmission = material.transmission().map_or(0.0, |t| t.transmission_factor() as Float);
+    let see_through = match material.alpha_mode() {
+        AlphaMode::Blend => 1.0 - alpha as Float,
+        _ => 0.0
+    };
+    let default = Principled::default();
+    let principled = Principled {
+        metallic: pbr.metallic_factor() as Float,
+        roughness: pbr.roughness_factor() as Float,
+        // glTF's factor of 1 is the usual shine of glass and plastic.
+        specular: material.specular().map_or(default.specular, |s| s.specular_factor() as Float / 2.0),
+        transmission: transmission.max(see_through),
+        ior: material.ior().map_or(default.ior, |ior| ior as Float)
+    };
+
+    let double_sided = material.double_sided();
+    let material = Material::Principled(principled);
+    // Single-sided surfaces can't be seen from behind, unless light has to leave through
+    // their backs.
+    let backface = match material {
+        Material::Principled(Principled { transmission, .. }) if transmission > 0.0 => Backface::Show,
+        _ if double_sided => Backface::Show,
+        _ => Backface::Cull
+    };
+    (color, lum, material, backface)
+}
+
+/// Everything collected while walking the node tree.
+struct Scene {
+    objects: Vec<Object>,
+    lights: Vec<Light>,
+    camera: Option<(Ray, Float, Option<Float>)>
+}
+
+fn visit(node: &gltf::Node, parent: &Matrix, buffers: &[gltf::buffer::Data], scene: &mut Scene) -> Result<(), String> {
+    let local = node.transform().matrix().map(|col| col.map(|value| value as Float));
+    let world = multiply(parent, &local);
+
+    if let Some(mesh) = node.mesh() {
+        for primitive in mesh.primitives() {
+            if primitive.mode() != gltf::mesh::Mode::Triangles {
+                continue;
+            }
+            let reader = primitive.reader(|buffer| buffers.get(buffer.index()).map(|data| &data.0[..]));
+            let coords = |v: Vector3| [v.x, v.y, v.z];
+            let positions: Vec<[Float; 3]> = match reader.read_positions() {
+                Some(positions) => positions
+                    .map(|p| coords(transform(&world, p.map(|value| value as Float), 1.0)))
+                    .collect(),
+                None => continue
+            };
+            // Normals move with the rotation alone, which is exact unless the scale is uneven.
+            let normals: Vec<[Float; 3]> = reader.read_normals()
+                .map(|normals| normals.map(|n| coords(transform(&world, n.map(|value| value as Float), 0.0))).collect())
+                .unwrap_or_default();
+            // glTF's texture coordinates run down from the top of the image, OBJ's up from the bottom.
+            let uvs: Vec<(Float, Float)> = reader.read_tex_coords(0)
+                .map(|uvs| uvs.into_f32().map(|[u, v]| (u as Float, 1.0 - v as Float)).collect())
+                .unwrap_or_default();
+            let vertices = Vertices { positions, normals, uvs };
+            let indices: Vec<u32> = match reader.read_indices() {
+                Some(indices) => indices.into_u32().collect(),
+                None => (0..vertices.positions.len() as u32).collect()
+            };
+
+            let mut faces = vec![];
+            for corners in indices.chunks_exact(3) {
+                let corners = [corners[0], corners[1], corners[2]];
+                // Normals and texture coordinates are only used if every corner has them.
+                let within = |len: usize| Some(corners).filter(|corners| corners.iter().all(|&i| (i as usize) < len));
+                let positions = within(vertices.positions.len())
+                    .ok_or_else(|| format!("mesh {} refers to a missing vertex", mesh.index()))?;
+                let face = Face { positions, normals: within(vertices.normals.len()), uvs: within(vertices.uvs.len()) };
+                vertices.push_face(&mut faces, face);
+            }
+
+            let (color, lum, material, backface) = material(&primitive.material());
+            let data = Arc::new(MeshData::new(Arc::new(vertices), faces));
+            let shape = Box::new(Mesh { data, file: None, offset: Vector3::new(0.0, 0.0, 0.0), scale: 1.0 });
+            scene.objects.push(Object { shape, color, lum, material, backface, cutout: None, visibility: Visibility::default(), line: 0 });
+        }
+    }
+
+    if let (Some(camera), None) = (node.camera(), &scene.camera) {
+        if let Projection::Perspective(perspective) = camera.projection() {
+            let pos = transform(&world, [0.0; 3], 1.0);
+            let dir = transform(&world, [0.0, 0.0, -1.0], 0.0);
+            let aspect = perspective.aspect_ratio().map(|aspect| aspect as Float);
+            scene.camera = Some((Ray::new(pos, dir), perspective.yfov() as Float, aspect));
+        }
+    }
+
+    // Point lights become small glowing spheres, as bright as a light of the same
+    // intensity (in candela); spot and directional (in lux) lights shine along the node's -z.
+    if let Some(light) = node.light() {
+        let color = to_color(light.color()).scale(light.intensity() as Float);
+        let pos = transform(&world, [0.0; 3], 1.0);
+        let forward = transform(&world, [0.0, 0.0, -1.0], 0.0);
+        match light.kind() {
+            Kind::Directional if forward.size() > 0.0 => {
+                scene.lights.push(Light::directional(-forward, 0.0, color));
+            }
+            Kind::Spot { inner_cone_angle, outer_cone_angle } if forward.size() > 0.0 => {
+                let (inner, outer) = (inner_cone_angle as Float, outer_cone_angle as Float);
+                scene.lights.push(Light::spot(pos, forward, inner.min(outer), outer, color));
+            }
+            Kind::Point => {
+                scene.objects.push(Object {
+                    shape: Box::new(Sphere { center: pos, radius: LIGHT_RADIUS }),
+                    color: Color::BLACK,
+                    lum: color.scale(1.0 / (PI * LIGHT_RADIUS * LIGHT_RADIUS)),
+                    material: Material::Translucent(0.0, Glass::default()),
+                    backface: Backface::Show,
+                    cutout: None,
+                    visibility: Visibility::default(),
+                    line: 0
+                });
+            }
+            _ => ()
+        }
+    }
+
+    for child in node.children() {
+        visit(&child, &world, buffers, scene)?;
+    }
+    Ok(())
+}
+
+/// Converts the default scene of a `.gltf` or `.glb` file into a `Config`: meshes with
+/// their transforms baked in, PBR materials mapped onto the nearest tracer material,
+/// the first perspective camera, and punctual lights. The external buffers it reads
+/// are noted in `assets`, so a cached import knows to look at them.
+pub fn load_gltf(path: &Path, assets: &Assets) -> ConfigResult<Config> {
+    let fail = |message: String| ConfigError::InvalidAsset(path.to_path_buf(), message);
+    let (document, buffers, _) = gltf::import(path).map_err(|err| fail(err.to_string()))?;
+    let dir = path.parent().unwrap_or(Path::new("."));
+    for buffer in document.buffers() {
+        if let gltf::buffer::Source::Uri(uri) = buffer.source() {
+            if !uri.starts_with("data:") {
+                assets.note(&dir.join(uri));
+            }
+        }
+    }
+    let gltf_scene = document.default_scene()
+        .or_else(|| document.scenes().next())
+        .ok_or_else(|| fail("no scenes".to_string()))?;
+
+    let mut scene = Scene { objects: vec![], lights: vec![], camera: None };
+    let identity = [[1.0, 0.0, 0.0, 0.0], [0.0, 1.0, 0.0, 0.0], [0.0, 0.0, 1.0, 0.0], [0.0, 0.0, 0.0, 1.0]];
+    for node in gltf_scene.nodes() {
+        visit(&node, &identity, &buffers, &mut scene).map_err(fail)?;
+    }
+
+    let (pov, yfov, aspect) = scene.camera.ok_or_else(|| fail("no perspective camera".to_string()))?;
+    let aspect = aspect.unwrap_or(4.0 / 3.0);
+    let height = ((WIDTH as Float / aspect).round() as u32).max(1);
+    // The tracer's field of view is half the horizontal angle.
+    let fov = ((yfov / 2.0).tan() * aspect).atan();
+    Ok(Config {
+        objects: scene.objects,
+        pov,
+        width: WIDTH,
+        height,
+        fov,
+        lens: None,
+        focus_keys: vec![],
+        up: Vector3::new(0.0, 0.0, 1.0),
+        roll: 0.0,
+        max_depth: 8,
+        num_tries: 16,
+        filter: Filter::default(),
+        sampler: Sampler::default(),
+        spectral: false,
+        caustic_split: 1,
+        irradiance_cache: None,
+        path_guide: None,
+        fog: None,
+        sky: None,
+        lights: scene.lights,
+        light_tree: Default::default(),
+        glowing: Default::default(),
+        accel: Accel::default(),
+        warnings: vec![]
+    })
+}
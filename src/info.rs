@@ -0,0 +1,86 @@
+use std::collections::{BTreeMap, HashSet};
+use std::sync::Arc;
+
+use crate::config::Config;
+use crate::film::frame_bytes;
+use crate::light::Light;
+use crate::linalg::Aabb;
+
+/// What a scene holds, worked out without rendering it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SceneInfo {
+    /// How many objects there are of each shape, by the shape's name.
+    pub shapes: BTreeMap<&'static str, usize>,
+    /// Objects that give off light of their own.
+    pub glowing: usize,
+    pub directional_lights: usize,
+    pub spot_lights: usize,
+    /// Whether a sky, with its sun, lights the scene.
+    pub sky: bool,
+    /// Corners of the box holding every object with bounds, if there are any.
+    pub bounds: Option<Aabb>,
+    /// Objects reaching without end, like planes, or whose bounds aren't known.
+    pub unbounded: usize,
+    /// Triangles in all the meshes, counting a mesh used twice twice.
+    pub triangles: usize,
+    /// Bytes the meshes and heightfields take up, counting a mesh shared between objects once.
+    pub mesh_bytes: usize,
+    /// Bytes the film and saved image take up while rendering.
+    pub frame_bytes: usize
+}
+
+impl SceneInfo {
+    /// Every light: lights of their own, glowing objects and the sky.
+    pub fn lights(&self) -> usize {
+        self.directional_lights + self.spot_lights + self.glowing + self.sky as usize
+    }
+}
+
+/// Counts up what `config` holds.
+pub fn scene_info(config: &Config) -> SceneInfo {
+    let mut shapes = BTreeMap::new();
+    let mut bounds: Option<Aabb> = None;
+    let (mut unbounded, mut triangles, mut mesh_bytes) = (0, 0, 0);
+    let mut meshes_seen = HashSet::new();
+    for object in &config.objects {
+        // Shapes are counted as what they are before being placed.
+        let mut shape = &*object.shape;
+        while let Some(placed) = shape.as_transformed() {
+            shape = &*placed.shape;
+        }
+        let name = match (shape.params(), shape.as_mesh(), shape.as_heightfield()) {
+            (Some((name, _)), _, _) => name,
+            (None, _, Some(field)) => {
+                mesh_bytes += field.memory();
+                "heightfield"
+            }
+            (None, Some(mesh), _) => {
+                triangles += mesh.data.len();
+                if meshes_seen.insert(Arc::as_ptr(&mesh.data)) {
+                    mesh_bytes += mesh.data.memory();
+                }
+                "mesh"
+            }
+            (None, None, None) => "other"
+        };
+        *shapes.entry(name).or_insert(0) += 1;
+
+        match object.shape.bounds() {
+            Some(shape_bounds) => bounds = Some(bounds.map_or(shape_bounds, |all| all.union(&shape_bounds))),
+            None => unbounded += 1
+        }
+    }
+
+    SceneInfo {
+        shapes,
+        glowing: config.glowing().len(),
+        directional_lights: config.lights.iter().filter(|light| matches!(light, Light::Directional { .. })).count(),
+        spot_lights: config.lights.iter().filter(|light| matches!(light, Light::Spot { .. })).count(),
+        sky: config.sky.is_some(),
+        bounds,
+        unbounded,
+        triangles,
+        mesh_bytes,
+        frame_bytes: frame_bytes(config.width, config.height, 1)
+    }
+}
@@ -0,0 +1,287 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+use rand::Rng;
+use rayon::prelude::*;
+
+use crate::config::Config;
+use crate::film::Region;
+use crate::linalg::{consts::PI, Float, Vector3};
+use crate::random::{self, LocalRng};
+use crate::trace::{sample_pixel, Color};
+
+/// How many rows the prepass measures at once before sharing what they found, so that
+/// the records kept don't depend on how many threads there are.
+const BAND: usize = 16;
+
+thread_local! {
+    /// Records measured for the row this thread is rendering, which only that row sees.
+    static ROW: RefCell<Vec<Record>> = const { RefCell::new(vec![]) };
+}
+
+/// Irradiance measured at points of diffuse surfaces and interpolated between them, after
+/// Ward and Heckbert, for the light that reaches surfaces seen from the camera by way of
+/// other surfaces. Since that light changes slowly over most surfaces, far fewer points
+/// need measuring than there are pixels, and the records outlast a render for the next
+/// pass over the same scene.
+///
+/// A prepass (`fill`) measures records for what the camera sees before a render, a band
+/// of rows at a time. Rendering then only reads the shared records: gaps between them get
+/// records of their own that last for the row, so seeded renders repeat exactly however
+/// the rows are shared between threads.
+#[derive(Debug)]
+pub struct IrradianceCache {
+    /// How far a record's measurement is trusted, about 0.1 to 0.3: smaller is slower and
+    /// more accurate.
+    pub accuracy: Float,
+    /// About how many rays measure each record.
+    pub rays: u32,
+    /// The least and most a record's area of influence can be scaled by, whatever its
+    /// surroundings: the least keeps records in corners from crowding, the most keeps
+    /// open areas from being measured only once.
+    pub min_spacing: Float,
+    pub max_spacing: Float,
+    store: RwLock<Store>
+}
+
+/// Where the cache keeps its records: each listed in every cell its area of influence
+/// touches, of whichever of a series of grids, each with cells half the size of the last,
+/// has cells just big enough to hold it.
+#[derive(Debug, Default)]
+struct Store {
+    records: Vec<Record>,
+    cells: HashMap<Cell, Vec<usize>>
+}
+
+/// A cell of one of the store's grids: its level, then where it is.
+type Cell = (u32, i64, i64, i64);
+
+/// The irradiance at one point, and how it changes around there.
+#[derive(Debug, Clone, Copy)]
+struct Record {
+    pos: Vector3,
+    normal: Vector3,
+    irradiance: Color,
+    /// Harmonic mean distance to the surfaces around, within the spacing limits.
+    radius: Float,
+    /// How each channel changes as the surface turns, and as it moves.
+    rotation: [Vector3; 3],
+    translation: [Vector3; 3]
+}
+
+impl Record {
+    /// How much the record counts toward the irradiance at `pos` facing `normal`, or
+    /// `None` if not at all.
+    fn weight(&self, pos: Vector3, normal: Vector3, accuracy: Float) -> Option<Float> {
+        let offset = pos - self.pos;
+        let reach = accuracy * self.radius;
+        if offset.dot(offset) >= reach * reach {
+            return None;
+        }
+        // Records from in front of the point see light it doesn't.
+        if offset.dot(normal + self.normal) / 2.0 < -0.05 * self.radius {
+            return None;
+        }
+        let error = offset.size() / self.radius + (1.0 - normal.dot(self.normal)).max(0.0).sqrt();
+        if error >= accuracy {
+            return None;
+        }
+        Some(1.0 / error.max(1e-9))
+    }
+
+    /// The irradiance extrapolated to `pos` facing `normal` along the gradients.
+    fn at(&self, pos: Vector3, normal: Vector3) -> Color {
+        let turn = self.normal.cross(normal);
+        let offset = pos - self.pos;
+        self.irradiance + along(&self.rotation, turn) + along(&self.translation, offset)
+    }
+}
+
+/// The change given by the per-channel `gradient` for a step of `step`.
+fn along(gradient: &[Vector3; 3], step: Vector3) -> Color {
+    Color::new(gradient[0].dot(step), gradient[1].dot(step), gradient[2].dot(step))
+}
+
+/// Adds `amount` of each channel of `dir` to `gradient`.
+fn add(gradient: &mut [Vector3; 3], dir: Vector3, amount: Color) {
+    gradient[0] += dir.scale(amount.x);
+    gradient[1] += dir.scale(amount.y);
+    gradient[2] += dir.scale(amount.z);
+}
+
+impl IrradianceCache {
+    pub fn new(accuracy: Float, rays: u32, min_spacing: Float, max_spacing: Float) -> Self {
+        IrradianceCache { accuracy, rays, min_spacing, max_spacing, store: RwLock::default() }
+    }
+
+    /// How many records have been measured so far.
+    pub fn len(&self) -> usize {
+        self.store.read().unwrap().records.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The irradiance arriving at `pos` on a surface facing `normal`: interpolated from
+    /// the records near enough, or measured there with `trace` and recorded for the row if
+    /// there are none. `trace` gives the light arriving along a direction from `pos`, and
+    /// how far away the surface it comes from is.
+    pub(crate) fn irradiance(&self, pos: Vector3, normal: Vector3, trace: impl Fn(Vector3) -> (Color, Float)) -> Color {
+        if let Some(irradiance) = self.interpolate(pos, normal) {
+            return irradiance;
+        }
+        let record = self.measure(pos, normal, trace);
+        ROW.with(|row| row.borrow_mut().push(record));
+        record.irradiance
+    }
+
+    /// Measures records wherever the paths through `region`'s pixels first bounce off a
+    /// diffuse surface away from those already kept, with one sample a pixel. With a
+    /// `seed`, rows draw from it as `trace::make_image_with`'s do, so the same records
+    /// are kept every time. After each band of rows `band_done` is told how many paths it
+    /// traced, and filling stops, keeping the records measured so far, if it returns `false`.
+    pub(crate) fn fill(&self, config: &Config, region: &Region, seed: Option<u64>, band_done: &mut dyn FnMut(u64) -> bool) {
+        let rows: Vec<u32> = region.y.clone().collect();
+        for band in rows.chunks(BAND) {
+            let measured: Vec<Vec<Record>> = band.par_iter().map(|&y| {
+                if let Some(seed) = seed {
+                    random::reseed_row(seed, y);
+                }
+                start_row();
+                for x in region.x.clone() {
+                    sample_pixel(config, x, y, &mut LocalRng);
+                }
+                ROW.with(|row| row.take())
+            }).collect();
+            measured.into_iter().flatten().for_each(|record| self.insert(record));
+            if !band_done(band.len() as u64 * region.x.len() as u64) {
+                break;
+            }
+        }
+        start_row();
+    }
+
+    /// How many grids finer than the coarsest the store keeps, enough for records of the
+    /// least spacing.
+    fn levels(&self) -> u32 {
+        (self.max_spacing / self.min_spacing).log2().floor() as u32
+    }
+
+    /// The finest grid whose cells are as big as the reach of a record of `radius`.
+    fn level(&self, radius: Float) -> u32 {
+        ((self.max_spacing / radius).log2().floor().max(0.0) as u32).min(self.levels())
+    }
+
+    fn cell(&self, level: u32, pos: Vector3) -> Cell {
+        let size = self.accuracy * self.max_spacing / (1u64 << level) as Float;
+        (level, (pos.x / size).floor() as i64, (pos.y / size).floor() as i64, (pos.z / size).floor() as i64)
+    }
+
+    fn interpolate(&self, pos: Vector3, normal: Vector3) -> Option<Color> {
+        let store = self.store.read().unwrap();
+        let mut total = Color::BLACK;
+        let mut weights = 0.0;
+        let mut gather = |record: &Record| {
+            if let Some(weight) = record.weight(pos, normal, self.accuracy) {
+                total += record.at(pos, normal).scale(weight);
+                weights += weight;
+            }
+        };
+        for level in 0..=self.levels() {
+            if let Some(cell) = store.cells.get(&self.cell(level, pos)) {
+                cell.iter().for_each(|&index| gather(&store.records[index]));
+            }
+        }
+        ROW.with(|row| row.borrow().iter().for_each(&mut gather));
+        if weights == 0.0 {
+            return None;
+        }
+        let irradiance = total.scale(1.0 / weights);
+        Some(Color::new(irradiance.x.max(0.0), irradiance.y.max(0.0), irradiance.z.max(0.0)))
+    }
+
+    /// Measures the irradiance at `pos` with a stratified, cosine-weighted sweep of the
+    /// hemisphere around `normal`, and from the same rays its gradients.
+    fn measure(&self, pos: Vector3, normal: Vector3, trace: impl Fn(Vector3) -> (Color, Float)) -> Record {
+        // About π times as many strata around as up, for strata roughly square.
+        let m = ((self.rays as Float / PI).sqrt().round() as usize).max(2);
+        let n = (self.rays as usize / m).max(3);
+        let (u, v) = normal.ons();
+        let around = |phi: Float| u.scale(phi.cos()) + v.scale(phi.sin());
+        let mut rng = LocalRng;
+
+        // Light and distance for each stratum, up from the horizon by rings.
+        let mut samples = vec![(Color::BLACK, 0.0); m * n];
+        let mut rotation = [Vector3::new(0.0, 0.0, 0.0); 3];
+        let mut irradiance = Color::BLACK;
+        let mut inverse_distances = 0.0;
+        for k in 0..n {
+            let phi = 2.0 * PI * (k as Float + rng.gen::<Float>()) / n as Float;
+            let mut tangents = Color::BLACK;
+            for j in 0..m {
+                let sin_theta = ((j as Float + rng.gen::<Float>()) / m as Float).sqrt();
+                let cos_theta = (1.0 - sin_theta * sin_theta).max(0.0).sqrt();
+                let dir = around(phi).scale(sin_theta) + normal.scale(cos_theta);
+                let (light, distance) = trace(dir);
+                samples[j * n + k] = (light, distance);
+                irradiance += light;
+                inverse_distances += 1.0 / distance;
+                tangents += light.scale(-sin_theta / cos_theta.max(1e-6));
+            }
+            add(&mut rotation, around(phi + PI / 2.0), tangents);
+        }
+        let per_sample = PI / (m * n) as Float;
+        let irradiance = irradiance.scale(per_sample);
+        rotation.iter_mut().for_each(|gradient| *gradient = gradient.scale(per_sample));
+
+        // How the light changes moving across the surface, from the differences between
+        // neighboring strata and how near the surfaces they see are.
+        let mut translation = [Vector3::new(0.0, 0.0, 0.0); 3];
+        let ring = |j: usize| (j as Float / m as Float).sqrt();
+        for k in 0..n {
+            let phi = 2.0 * PI * k as Float / n as Float;
+            let mut outward = Color::BLACK;
+            for j in 1..m {
+                let sin_theta = ring(j);
+                let cos_sq = 1.0 - sin_theta * sin_theta;
+                let ((light, distance), (below, below_distance)) = (samples[j * n + k], samples[(j - 1) * n + k]);
+                outward += (light - below).scale(sin_theta * cos_sq / distance.min(below_distance));
+            }
+            let mut sideways = Color::BLACK;
+            for j in 0..m {
+                let (light, distance) = samples[j * n + k];
+                let (before, before_distance) = samples[j * n + (k + n - 1) % n];
+                sideways += (light - before).scale((ring(j + 1) - ring(j)) / distance.min(before_distance));
+            }
+            add(&mut translation, around(phi + PI / n as Float), outward.scale(2.0 * PI / n as Float));
+            add(&mut translation, around(phi + PI / 2.0), sideways);
+        }
+
+        let radius = if inverse_distances > 0.0 { (m * n) as Float / inverse_distances } else { Float::INFINITY };
+        let radius = radius.clamp(self.min_spacing, self.max_spacing);
+        Record { pos, normal, irradiance, radius, rotation, translation }
+    }
+
+    fn insert(&self, record: Record) {
+        let reach = Vector3::new(1.0, 1.0, 1.0).scale(self.accuracy * record.radius);
+        let level = self.level(record.radius);
+        let (low, high) = (self.cell(level, record.pos - reach), self.cell(level, record.pos + reach));
+        let mut store = self.store.write().unwrap();
+        let index = store.records.len();
+        store.records.push(record);
+        for x in low.1..=high.1 {
+            for y in low.2..=high.2 {
+                for z in low.3..=high.3 {
+                    store.cells.entry((level, x, y, z)).or_default().push(index);
+                }
+            }
+        }
+    }
+}
+
+/// Forgets the records measured for the last row this thread rendered.
+pub(crate) fn start_row() {
+    ROW.with(|row| row.borrow_mut().clear());
+}
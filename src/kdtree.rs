@@ -0,0 +1,213 @@
+use crate::bvh::to_array;
+use crate::linalg::{Aabb, Float, Vector3};
+use crate::shapes::Ray;
+use crate::stats;
+
+/// Primitives per leaf below which a node isn't split.
+const LEAF_SIZE: usize = 4;
+/// Candidate split planes tried per axis.
+const BINS: usize = 16;
+/// Cost of stepping through an inner node, relative to intersecting one primitive.
+const TRAVERSAL_COST: Float = 1.0;
+
+#[derive(Debug)]
+enum Node {
+    /// Primitives `items[start..end]`.
+    Leaf { start: usize, end: usize },
+    /// Everything below `split` on `axis` is in `left`, everything above in `right`.
+    Inner { axis: usize, split: Float, left: usize, right: usize }
+}
+
+/// A kd-tree over primitives known only by their boxes. Unlike a BVH it splits space
+/// rather than primitives, so a primitive crossing a split plane is listed on both sides;
+/// in return, traversal can stop at the first leaf with a hit.
+#[derive(Debug)]
+pub struct KdTree {
+    bounds: Aabb,
+    nodes: Vec<Node>,
+    /// Primitive indices, grouped so that every leaf owns a contiguous run.
+    items: Vec<usize>
+}
+
+impl KdTree {
+    /// The box holding every primitive, or `None` if there are none.
+    pub fn bounds(&self) -> Option<Aabb> {
+        (!self.bounds.is_empty()).then_some(self.bounds)
+    }
+
+    /// Bytes the tree takes up, not counting the primitives.
+    pub fn memory(&self) -> usize {
+        std::mem::size_of_val(&self.nodes[..]) + std::mem::size_of_val(&self.items[..])
+    }
+
+    /// Builds the tree by splitting each node at the plane with the lowest surface area
+    /// heuristic cost, among evenly spaced candidates. `boxes` holds each primitive's box.
+    pub fn build(boxes: &[Aabb]) -> KdTree {
+        let bounds = boxes.iter().fold(Aabb::empty(), |acc, b| acc.union(b));
+        let mut tree = KdTree { bounds, nodes: vec![], items: vec![] };
+        if !boxes.is_empty() {
+            // The usual depth limit, past which duplicated primitives cost more than splitting saves.
+            let max_depth = 8 + (1.3 * (boxes.len() as Float).log2()) as usize;
+            tree.split(boxes, (0..boxes.len()).collect(), bounds, max_depth);
+        }
+        tree
+    }
+
+    /// Adds the node for `items`, which lie within `bounds`, and returns its index.
+    fn split(&mut self, boxes: &[Aabb], items: Vec<usize>, bounds: Aabb, depth: usize) -> usize {
+        let index = self.nodes.len();
+        let plane = if items.len() <= LEAF_SIZE || depth == 0 { None } else { best_plane(boxes, &items, &bounds) };
+        let (axis, split) = match plane {
+            Some(plane) => plane,
+            None => {
+                let start = self.items.len();
+                self.items.extend(items);
+                self.nodes.push(Node::Leaf { start, end: self.items.len() });
+                return index;
+            }
+        };
+
+        let (mut left_bounds, mut right_bounds) = (bounds, bounds);
+        left_bounds.max[axis] = split;
+        right_bounds.min[axis] = split;
+        let (left_items, right_items) = partition(boxes, &items, &bounds, axis, split);
+        drop(items);
+
+        // Reserve this node's slot; its children land after it.
+        self.nodes.push(Node::Leaf { start: 0, end: 0 });
+        let left = self.split(boxes, left_items, left_bounds, depth - 1);
+        let right = self.split(boxes, right_items, right_bounds, depth - 1);
+        self.nodes[index] = Node::Inner { axis, split, left, right };
+        index
+    }
+
+    /// The closest primitive along `ray` and its distance, where `hit` intersects a single one.
+    pub fn closest(&self, ray: Ray, hit: impl Fn(usize) -> Option<Float>) -> Option<(usize, Float)> {
+        let origin = to_array(ray.pos);
+        let dir = to_array(ray.dir);
+        let inv_dir = dir.map(|d| 1.0 / d);
+        let (t_min, t_max) = self.nodes.first().and(self.bounds.span(&origin, &inv_dir, Float::INFINITY))?;
+
+        let mut best: Option<(usize, Float)> = None;
+        let mut visited = 0;
+        // Nodes still to visit, farthest first, with the part of the ray inside each.
+        let mut stack = vec![(0, t_min, t_max)];
+        while let Some((mut index, t_min, mut t_max)) = stack.pop() {
+            if best.is_some_and(|(_, best_t)| best_t < t_min) {
+                break;
+            }
+            loop {
+                visited += 1;
+                match self.nodes[index] {
+                    Node::Inner { axis, split, left, right } => {
+                        let t = (split - origin[axis]) * inv_dir[axis];
+                        let left_first = origin[axis] < split || (origin[axis] == split && dir[axis] <= 0.0);
+                        let (near, far) = if left_first { (left, right) } else { (right, left) };
+                        if t > t_max || t <= 0.0 {
+                            index = near;
+                        } else if t < t_min {
+                            index = far;
+                        } else {
+                            stack.push((far, t, t_max));
+                            index = near;
+                            t_max = t;
+                        }
+                    }
+                    Node::Leaf { start, end } => {
+                        for &i in &self.items[start..end] {
+                            match (hit(i), best) {
+                                (Some(t), Some((_, best_t))) if t >= best_t => (),
+                                (Some(t), _) => best = Some((i, t)),
+                                (None, _) => ()
+                            }
+                        }
+                        // Nothing in a later node can be closer than a hit inside this one.
+                        if best.is_some_and(|(_, best_t)| best_t <= t_max) {
+                            stats::record(|counters| counters.nodes += visited);
+                            return best;
+                        }
+                        break;
+                    }
+                }
+            }
+        }
+        stats::record(|counters| counters.nodes += visited);
+        best
+    }
+
+    /// Calls `visit` on every primitive in a leaf that, grown by `eps`, contains `point`;
+    /// a primitive may be visited more than once.
+    pub fn containing(&self, point: Vector3, eps: Float, mut visit: impl FnMut(usize)) {
+        let point = to_array(point);
+        if self.nodes.is_empty() || !self.bounds.contains(&point, eps) {
+            return;
+        }
+        let mut stack = vec![0];
+        while let Some(index) = stack.pop() {
+            match self.nodes[index] {
+                Node::Leaf { start, end } => self.items[start..end].iter().for_each(|&i| visit(i)),
+                Node::Inner { axis, split, left, right } => {
+                    if point[axis] - eps <= split {
+                        stack.push(left);
+                    }
+                    if point[axis] + eps >= split {
+                        stack.push(right);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// The primitives of `items` on each side of `split`, judging each by the part of its box
+/// inside `bounds`. Primitives lying in the plane itself go left.
+fn partition(boxes: &[Aabb], items: &[usize], bounds: &Aabb, axis: usize, split: Float) -> (Vec<usize>, Vec<usize>) {
+    let (mut left, mut right) = (vec![], vec![]);
+    for &i in items {
+        let min = boxes[i].min[axis].max(bounds.min[axis]);
+        let max = boxes[i].max[axis].min(bounds.max[axis]);
+        if min < split || (min == split && max == split) {
+            left.push(i);
+        }
+        if max > split {
+            right.push(i);
+        }
+    }
+    (left, right)
+}
+
+/// The axis and position of the cheapest split of `items`, if any beats leaving them in a leaf.
+fn best_plane(boxes: &[Aabb], items: &[usize], bounds: &Aabb) -> Option<(usize, Float)> {
+    let area = bounds.surface_area();
+    let leaf_cost = items.len() as Float;
+    let mut best: Option<(Float, usize, Float)> = None;
+    for axis in 0..3 {
+        let (lo, hi) = (bounds.min[axis], bounds.max[axis]);
+        if hi - lo <= 0.0 {
+            continue;
+        }
+        // How many clipped boxes start and end in each bin, so every candidate's counts
+        // come from running sums.
+        let bin = |x: Float| (((x - lo) / (hi - lo) * BINS as Float) as usize).min(BINS - 1);
+        let (mut starts, mut ends) = ([0usize; BINS], [0usize; BINS]);
+        for &i in items {
+            starts[bin(boxes[i].min[axis].max(lo))] += 1;
+            ends[bin(boxes[i].max[axis].min(hi))] += 1;
+        }
+        let (mut left_count, mut right_count) = (0, items.len());
+        for k in 1..BINS {
+            left_count += starts[k - 1];
+            right_count -= ends[k - 1];
+            let split = lo + (hi - lo) * k as Float / BINS as Float;
+            let (mut left, mut right) = (*bounds, *bounds);
+            left.max[axis] = split;
+            right.min[axis] = split;
+            let cost = TRAVERSAL_COST
+                + (left.surface_area() * left_count as Float + right.surface_area() * right_count as Float) / area;
+            if cost < leaf_cost && best.is_none_or(|(best_cost, _, _)| cost < best_cost) {
+                best = Some((cost, axis, split));
+            }
+        }
+    }
+    best.map(|(_, axis, split)| (axis, split))
+}
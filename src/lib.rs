@@ -0,0 +1,63 @@
+pub mod ao;
+pub mod aov;
+pub mod assets;
+pub mod batch;
+pub mod bench;
+pub mod bloom;
+pub mod bvh;
+pub mod cache;
+pub mod camera;
+pub mod config;
+pub mod cryptomatte;
+pub mod dataset;
+pub mod debug;
+pub mod diff;
+pub mod dither;
+pub mod error;
+pub mod export;
+pub mod expr;
+pub mod ffi;
+pub mod film;
+pub mod filter;
+#[cfg(feature = "gpu")]
+pub mod gpu;
+pub mod guiding;
+pub mod golden;
+pub mod heightfield;
+pub mod ies;
+pub mod import;
+pub mod irradiance;
+pub mod info;
+pub mod kdtree;
+pub mod light;
+pub mod linalg;
+pub mod mesh;
+pub mod microfacet;
+pub mod mtl;
+pub mod output;
+pub mod preview;
+pub mod probe;
+pub mod profile;
+pub mod progress;
+pub mod random;
+pub mod remote;
+pub mod sampler;
+pub mod sdf;
+pub mod sensor;
+pub mod shapes;
+pub mod simd;
+pub mod sky;
+pub mod spectrum;
+pub mod stats;
+pub mod texture;
+pub mod trace;
+pub mod transform;
+pub mod turntable;
+#[cfg(target_arch = "wasm32")]
+pub mod wasm;
+pub mod watch;
+
+extern crate image;
+extern crate rand;
+extern crate rayon;
+extern crate itertools;
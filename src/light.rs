@@ -0,0 +1,426 @@
+use std::sync::Arc;
+
+use rand::Rng;
+
+use crate::ies::Ies;
+use crate::linalg::{consts::PI, Aabb, Float, Vector3};
+use crate::random::{self, LocalRng};
+use crate::shapes::{Ray, EPS};
+use crate::trace::{to_world, Backface, Color, Object};
+
+/// Light reaching a point from a `Light`, along a direction drawn by `Light::sample`.
+#[derive(Debug, Clone, Copy)]
+pub struct LightSample {
+    /// Toward the light.
+    pub dir: Vector3,
+    /// How far away the light is, for the shadow ray.
+    pub distance: Float,
+    /// Light a surface square-on to `dir` gets, divided by the density `dir` was drawn with.
+    pub irradiance: Color,
+    /// That density, per unit of solid angle: infinite for points and lights infinitely
+    /// small, which only drawing them can find.
+    pub pdf: Float
+}
+
+/// A light that isn't an object. Rays can't count on finding it by chance, so every
+/// diffuse bounce samples it directly.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Light {
+    /// A light so far away its rays all but run parallel, like the sun: toward `dir`,
+    /// spread over a disc `radius` radians across, and giving a surface square-on to it
+    /// `irradiance`.
+    Directional { dir: Vector3, radius: Float, irradiance: Color },
+    /// A point at `pos` shining along `dir` with `intensity` (light per unit solid angle):
+    /// fully out to `inner` radians off `dir`, fading to nothing at `outer`, and shaped by
+    /// a fixture's measured `profile` with `dir` as straight down, if it has one.
+    Spot { pos: Vector3, dir: Vector3, inner: Float, outer: Float, intensity: Color, profile: Option<Arc<Ies>> }
+}
+
+/// `1 - cos(radius)`, without the cancellation of working out the cosine first.
+fn cone(radius: Float) -> Float {
+    2.0 * (radius / 2.0).sin().powi(2)
+}
+
+/// Solid angle of a disc in the sky `radius` radians across.
+pub fn solid_angle(radius: Float) -> Float {
+    2.0 * PI * cone(radius)
+}
+
+/// Anything giving off light that diffuse bounces can aim at, rather than count on
+/// finding by chance: the scene's lights, and glowing objects as `AreaLight`s.
+pub trait Emitter {
+    /// A direction from `pos` toward the emitter, drawn with `u`, two numbers from 0 to 1,
+    /// and the light arriving along it; `None` if no light could be drawn.
+    fn sample_li(&self, pos: Vector3, u: (Float, Float)) -> Option<LightSample>;
+
+    /// The density, per unit of solid angle, with which `sample_li` draws `dir` from
+    /// `pos`: none for lights that are only ever reached by drawing them, like points.
+    fn pdf_li(&self, pos: Vector3, dir: Vector3) -> Float;
+
+    /// The light given off in all, averaged over the channels, for telling bright
+    /// emitters from dim ones.
+    fn power(&self) -> Float;
+}
+
+impl Light {
+    pub fn directional(dir: Vector3, radius: Float, irradiance: Color) -> Light {
+        Light::Directional { dir: dir.normalize(), radius, irradiance }
+    }
+
+    pub fn spot(pos: Vector3, dir: Vector3, inner: Float, outer: Float, intensity: Color) -> Light {
+        Light::Spot { pos, dir: dir.normalize(), inner, outer, intensity, profile: None }
+    }
+
+    /// The light shaped by `profile`, if it is a spot light.
+    pub fn with_profile(self, profile: Arc<Ies>) -> Light {
+        match self {
+            Light::Spot { pos, dir, inner, outer, intensity, .. } => {
+                Light::Spot { pos, dir, inner, outer, intensity, profile: Some(profile) }
+            }
+            light => light
+        }
+    }
+
+    /// A direction from `pos` toward the light and the light arriving along it.
+    pub fn sample(&self, pos: Vector3) -> LightSample {
+        // A point takes no numbers to aim at.
+        let u = match self {
+            Light::Directional { .. } => random::gen_pair(&mut LocalRng),
+            Light::Spot { .. } => (0.0, 0.0)
+        };
+        self.sample_at(pos, u)
+    }
+
+    /// `sample`, with `u` drawing the direction toward a light that isn't a point.
+    fn sample_at(&self, pos: Vector3, (u, v): (Float, Float)) -> LightSample {
+        match *self {
+            Light::Directional { dir, radius, irradiance } => {
+                let cos_theta = 1.0 - u * cone(radius);
+                let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+                let phi = 2.0 * PI * v;
+                let local = Vector3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta);
+                LightSample { dir: to_world(dir, local), distance: Float::INFINITY, irradiance, pdf: 1.0 / solid_angle(radius) }
+            }
+            Light::Spot { pos: light_pos, dir, inner, outer, intensity, ref profile } => {
+                let offset = light_pos - pos;
+                let distance = offset.size();
+                if distance == 0.0 {
+                    return LightSample { dir, distance, irradiance: Color::BLACK, pdf: Float::INFINITY };
+                }
+                let toward = offset.scale(1.0 / distance);
+                let mut falloff = spot_falloff(-toward.dot(dir), inner, outer);
+                if let Some(profile) = profile {
+                    let (across, along) = dir.ons();
+                    let out = -toward;
+                    let gamma = out.dot(dir).clamp(-1.0, 1.0).acos().to_degrees();
+                    let azimuth = out.dot(along).atan2(out.dot(across)).to_degrees();
+                    falloff *= profile.at(gamma, azimuth);
+                }
+                LightSample { dir: toward, distance, irradiance: intensity.scale(falloff / (distance * distance)), pdf: Float::INFINITY }
+            }
+        }
+    }
+
+    /// Radiance a ray leaving the scene along `ray_dir` sees of the light.
+    pub fn radiance(&self, ray_dir: Vector3) -> Color {
+        match *self {
+            Light::Directional { dir, radius, irradiance } => {
+                let cone = cone(radius);
+                if cone > 0.0 && 1.0 - ray_dir.dot(dir) <= cone {
+                    irradiance.scale(1.0 / solid_angle(radius))
+                } else {
+                    Color::BLACK
+                }
+            }
+            // A point can't be found by chance.
+            Light::Spot { .. } => Color::BLACK
+        }
+    }
+}
+
+impl Emitter for Light {
+    fn sample_li(&self, pos: Vector3, u: (Float, Float)) -> Option<LightSample> {
+        Some(self.sample_at(pos, u))
+    }
+
+    fn pdf_li(&self, _pos: Vector3, ray_dir: Vector3) -> Float {
+        match *self {
+            Light::Directional { dir, radius, .. } => {
+                let cone = cone(radius);
+                if cone > 0.0 && 1.0 - ray_dir.dot(dir) <= cone { 1.0 / solid_angle(radius) } else { 0.0 }
+            }
+            Light::Spot { .. } => 0.0
+        }
+    }
+
+    /// For a directional light, which lights the whole scene, what falls on each unit of
+    /// area square-on to it.
+    fn power(&self) -> Float {
+        match *self {
+            Light::Directional { irradiance, .. } => (irradiance.x + irradiance.y + irradiance.z) / 3.0,
+            Light::Spot { outer, intensity, .. } => (intensity.x + intensity.y + intensity.z) / 3.0 * 2.0 * PI * cone(outer)
+        }
+    }
+}
+
+/// A glowing object whose shape can be drawn points on, as a light.
+#[derive(Clone, Copy)]
+pub struct AreaLight<'a> {
+    pub object: &'a Object
+}
+
+impl<'a> AreaLight<'a> {
+    /// `object` as a light, if it glows and points can be drawn on it. Objects with a
+    /// cutout or a culled back aren't, since points drawn on them could be where there is
+    /// nothing to see.
+    pub fn of(object: &'a Object) -> Option<Self> {
+        let lum = object.lum;
+        let glows = lum.x > 0.0 || lum.y > 0.0 || lum.z > 0.0;
+        let whole = object.cutout.is_none() && object.backface != Backface::Cull;
+        (glows && whole && object.shape.sample_surface((0.5, 0.5)).is_some()).then_some(AreaLight { object })
+    }
+
+    /// The density, per unit of solid angle, with which `sample_li` draws `dir` from a
+    /// point `distance` away from where it meets the surface, whose normal is `normal`.
+    pub fn pdf_at(&self, dir: Vector3, distance: Float, normal: Vector3) -> Float {
+        // Points are spread evenly, so any of them tells the density per unit of area.
+        let Some(sample) = self.object.shape.sample_surface((0.5, 0.5)) else {
+            return 0.0;
+        };
+        let pdf = sample.pdf * distance * distance / normal.dot(dir).abs();
+        if pdf.is_finite() { pdf } else { 0.0 }
+    }
+}
+
+/// How much of the light found along a direction one way of drawing it, with density
+/// `pdf`, keeps when another draws it with density `other`: Veach's power heuristic.
+pub fn power_heuristic(pdf: Float, other: Float) -> Float {
+    if pdf.is_infinite() {
+        return 1.0;
+    }
+    let (pdf, other) = (pdf * pdf, other * other);
+    if pdf + other > 0.0 { pdf / (pdf + other) } else { 0.0 }
+}
+
+impl Emitter for AreaLight<'_> {
+    fn sample_li(&self, pos: Vector3, u: (Float, Float)) -> Option<LightSample> {
+        let sample = self.object.shape.sample_surface(u)?;
+        let offset = sample.point - pos;
+        let distance = offset.size();
+        if distance == 0.0 {
+            return None;
+        }
+        let dir = offset.scale(1.0 / distance);
+        // From per unit of area to per unit of solid angle.
+        let pdf = sample.pdf * distance * distance / sample.normal.dot(dir).abs();
+        if !pdf.is_finite() {
+            return None;
+        }
+        // Stopping short of the surface keeps shadow rays from finding the light itself.
+        Some(LightSample { dir, distance: distance - EPS, irradiance: self.object.lum.scale(1.0 / pdf), pdf })
+    }
+
+    fn pdf_li(&self, pos: Vector3, dir: Vector3) -> Float {
+        match self.object.shape.intersect(Ray { pos, dir }, Float::INFINITY) {
+            Some(hit) => self.pdf_at(dir, hit.t, hit.normal),
+            None => 0.0
+        }
+    }
+
+    /// As a scene's `W` and `lm` count it, `π` times the luminance and the area: what a
+    /// surface glowing the same every way gives off to one side.
+    fn power(&self) -> Float {
+        let lum = self.object.lum;
+        (lum.x + lum.y + lum.z) / 3.0 * PI * self.object.shape.area().unwrap_or(0.0)
+    }
+}
+
+/// How much of a spot light's intensity goes `acos(cos_angle)` off its axis: the square
+/// of how far the angle is from `outer` to `inner`, as glTF has it.
+fn spot_falloff(cos_angle: Float, inner: Float, outer: Float) -> Float {
+    let (cos_inner, cos_outer) = (inner.cos(), outer.cos());
+    if cos_angle >= cos_inner {
+        1.0
+    } else if cos_angle <= cos_outer {
+        0.0
+    } else {
+        ((cos_angle - cos_outer) / (cos_inner - cos_outer)).powi(2)
+    }
+}
+
+/// Spot and point lights it takes before diffuse bounces sample one drawn from a
+/// `LightTree` rather than every one of them.
+pub const TREE_LIGHTS: usize = 8;
+
+/// What a group of point-like lights could shine on: a box around where they are, a cone
+/// around the ways they face, and how much light they give off in all.
+#[derive(Debug, Clone, Copy)]
+struct LightBounds {
+    bounds: Aabb,
+    /// The axis of a cone of directions every light shines fully along some of, `spread`
+    /// radians around.
+    axis: Vector3,
+    spread: Float,
+    /// How far past that cone, in radians, the lights fade out over at most.
+    fade: Float,
+    power: Float
+}
+
+impl LightBounds {
+    /// The bounds of a spot light, or `None` for lights that aren't in one place.
+    fn of(light: &Light) -> Option<Self> {
+        match *light {
+            Light::Spot { pos, dir, inner, outer, .. } => {
+                let power = light.power();
+                let bounds = Aabb::point(pos);
+                Some(LightBounds { bounds, axis: dir, spread: inner, fade: outer - inner, power })
+            }
+            Light::Directional { .. } => None
+        }
+    }
+
+    fn union(&self, other: &LightBounds) -> Self {
+        let (axis, spread) = union_cones((self.axis, self.spread), (other.axis, other.spread));
+        LightBounds {
+            bounds: self.bounds.union(&other.bounds),
+            axis,
+            spread,
+            fade: self.fade.max(other.fade),
+            power: self.power + other.power
+        }
+    }
+
+    /// A guess, never zero where any of the lights could reach, at how much light they
+    /// give a surface at `pos` facing `facing`, after Conty and Kulla's many-light
+    /// hierarchy: each angle is widened by as much as the box could turn it.
+    fn importance(&self, pos: Vector3, facing: Vector3) -> Float {
+        let center = self.bounds.centroid();
+        let radius = (self.bounds.upper() - center).size();
+        let offset = pos - center;
+        let distance = offset.size();
+        let out = if distance > 0.0 { offset.scale(1.0 / distance) } else { self.axis };
+        // How far the box could turn the way to the lights: all the way around from inside.
+        let turn = if distance > radius { (radius / distance).asin() } else { PI };
+        let off_axis = angle(self.axis, out);
+        // How far past the cone the surface is, at the least, and how faded that leaves
+        // the lights, as spot lights fade.
+        let past = (off_axis - self.spread - turn).max(0.0);
+        if past > self.fade {
+            return 0.0;
+        }
+        let faded = if past > 0.0 { ((self.fade - past) / self.fade).powi(2) } else { 1.0 };
+        let incident = (angle(facing, -out) - turn).max(0.0);
+        if incident >= PI / 2.0 {
+            return 0.0;
+        }
+        // Near the box the distance means little: the lights could be anywhere in it.
+        self.power * faded * incident.cos() / (distance * distance).max(radius * radius / 4.0).max(1e-12)
+    }
+}
+
+/// The angle between unit vectors `a` and `b`.
+fn angle(a: Vector3, b: Vector3) -> Float {
+    a.dot(b).clamp(-1.0, 1.0).acos()
+}
+
+/// The narrowest cone around two cones of directions, each as its axis and half-angle.
+fn union_cones((a, spread_a): (Vector3, Float), (b, spread_b): (Vector3, Float)) -> (Vector3, Float) {
+    let between = angle(a, b);
+    if (between + spread_b).min(PI) <= spread_a {
+        return (a, spread_a);
+    }
+    if (between + spread_a).min(PI) <= spread_b {
+        return (b, spread_b);
+    }
+    let spread = (spread_a + between + spread_b) / 2.0;
+    let turn_axis = a.cross(b);
+    if spread >= PI || turn_axis.dot(turn_axis) == 0.0 {
+        return (a, PI);
+    }
+    // Turn `a` toward `b` until the cone's edge meets the far side of `b`'s.
+    let turn = spread - spread_a;
+    let k = turn_axis.normalize();
+    let axis = a.scale(turn.cos()) + k.cross(a).scale(turn.sin()) + k.scale(k.dot(a) * (1.0 - turn.cos()));
+    (axis.normalize(), spread)
+}
+
+#[derive(Debug)]
+enum LightNode {
+    /// One light, by its index in the scene's lights.
+    Leaf { bounds: LightBounds, light: usize },
+    Inner { bounds: LightBounds, children: [usize; 2] }
+}
+
+impl LightNode {
+    fn bounds(&self) -> &LightBounds {
+        match self {
+            LightNode::Leaf { bounds, .. } | LightNode::Inner { bounds, .. } => bounds
+        }
+    }
+}
+
+/// A bounding volume hierarchy over a scene's spot and point lights, so that a diffuse
+/// bounce can draw one likely to light it up in time that grows with the logarithm of
+/// how many there are, instead of sending a shadow ray toward every one.
+#[derive(Debug)]
+pub struct LightTree {
+    nodes: Vec<LightNode>
+}
+
+impl LightTree {
+    /// The tree over `lights`' spot and point lights, or `None` if there are fewer than
+    /// `TREE_LIGHTS` of them, which are cheaper to sample every one of.
+    pub fn new(lights: &[Light]) -> Option<Self> {
+        let mut leaves: Vec<(usize, LightBounds)> = lights.iter().enumerate()
+            .filter_map(|(index, light)| LightBounds::of(light).map(|bounds| (index, bounds)))
+            .collect();
+        if leaves.len() < TREE_LIGHTS {
+            return None;
+        }
+        let mut tree = LightTree { nodes: vec![] };
+        tree.build(&mut leaves);
+        Some(tree)
+    }
+
+    /// Adds a node for `leaves`, split at the middle light along the longest side of the
+    /// box around them. Returns its index.
+    fn build(&mut self, leaves: &mut [(usize, LightBounds)]) -> usize {
+        let index = self.nodes.len();
+        if let [(light, bounds)] = *leaves {
+            self.nodes.push(LightNode::Leaf { bounds, light });
+            return index;
+        }
+        let bounds = leaves[1..].iter().fold(leaves[0].1, |all, (_, bounds)| all.union(bounds));
+        let extent = |axis: usize| bounds.bounds.max[axis] - bounds.bounds.min[axis];
+        let axis = (0..3).max_by(|&a, &b| extent(a).total_cmp(&extent(b))).unwrap();
+        leaves.sort_by(|(_, a), (_, b)| a.bounds.min[axis].total_cmp(&b.bounds.min[axis]));
+        self.nodes.push(LightNode::Inner { bounds, children: [0, 0] });
+        let (low, high) = leaves.split_at_mut(leaves.len() / 2);
+        let children = [self.build(low), self.build(high)];
+        self.nodes[index] = LightNode::Inner { bounds, children };
+        index
+    }
+
+    /// The index of a light drawn for a surface at `pos` facing `facing`, in proportion to
+    /// how much it's guessed to light it, and the chance it was drawn with; `None` if
+    /// none of the lights could.
+    pub fn sample(&self, pos: Vector3, facing: Vector3, rng: &mut impl Rng) -> Option<(usize, Float)> {
+        let (mut index, mut chance) = (0, 1.0);
+        loop {
+            match &self.nodes[index] {
+                LightNode::Leaf { bounds, light } => {
+                    return (bounds.importance(pos, facing) > 0.0).then_some((*light, chance));
+                }
+                LightNode::Inner { children, .. } => {
+                    let [low, high] = children.map(|child| self.nodes[child].bounds().importance(pos, facing));
+                    if low + high <= 0.0 {
+                        return None;
+                    }
+                    let pick_low = rng.gen::<Float>() * (low + high) < low;
+                    chance *= if pick_low { low } else { high } / (low + high);
+                    index = children[!pick_low as usize];
+                }
+            }
+        }
+    }
+}
@@ -1,26 +1,42 @@
-use std::ops::{Add, Sub, Mul, Div};
-use rand::Rng;
+use std::ops::{Add, AddAssign, Sub, SubAssign, Mul, MulAssign, Div, Neg};
 
-#[derive(Debug, Copy, Clone)]
+use crate::random::{self, LocalRng};
+use crate::shapes::Ray;
+
+/// The precision all geometry and color math runs at: `f64`, or `f32` with the `f32`
+/// feature, which halves the memory of big meshes and doubles the SIMD width.
+#[cfg(not(feature = "f32"))]
+pub type Float = f64;
+#[cfg(feature = "f32")]
+pub type Float = f32;
+
+#[cfg(not(feature = "f32"))]
+pub use std::f64::consts;
+#[cfg(feature = "f32")]
+pub use std::f32::consts;
+
+use consts::PI;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub struct Vector3 {
-    pub x: f64, pub y: f64, pub z: f64,
-    pub rho: f64, pub theta: f64, pub phi: f64
+    pub x: Float, pub y: Float, pub z: Float,
+    pub rho: Float, pub theta: Float, pub phi: Float
 }
 
 impl Vector3 {
 
-    pub fn new(x: f64, y: f64, z: f64) -> Self {
+    pub fn new(x: Float, y: Float, z: Float) -> Self {
         Self::new_xyz(x, y, z)
     }
 
-    pub fn new_xyz(x: f64, y: f64, z: f64) -> Self {
+    pub fn new_xyz(x: Float, y: Float, z: Float) -> Self {
         let rho = (x.powi(2) + y.powi(2) + z.powi(2)).sqrt();
         let theta = y.atan2(x);
         let phi = if rho == 0.0 { 0.0 } else { (z / rho).acos() };
         Self { x, y, z, rho, theta, phi }
     }
 
-    pub fn new_sph(rho: f64, theta: f64, phi: f64) -> Self {
+    pub fn new_sph(rho: Float, theta: Float, phi: Float) -> Self {
         let x = rho * theta.cos() * phi.sin();
         let y = rho * theta.sin() * phi.sin();
         let z = rho * phi.cos();
@@ -28,12 +44,10 @@ impl Vector3 {
     }
 
     pub fn rand_hemi() -> Self {
-        let mut rng = rand::thread_rng();
-        let u1 = rng.gen::<f64>();
-        let u2 = rng.gen::<f64>();
+        let (u1, u2) = random::gen_pair(&mut LocalRng);
         
         let r = u1.sqrt();
-        let theta = 2.0 * std::f64::consts::PI * u2;
+        let theta = 2.0 * PI * u2;
     
         let x = r * theta.cos();
         let y = r * theta.sin();
@@ -42,16 +56,23 @@ impl Vector3 {
     }
 
     pub fn rand_hemi2() -> Self {
-        let mut rng = rand::thread_rng();
-        let u1 = rng.gen::<f64>();
-        let u2 = rng.gen::<f64>();
+        let (u1, u2) = random::gen_pair(&mut LocalRng);
 
         let r = (1.0 - u1.powi(2)).sqrt();
-        let phi = 2.0 * std::f64::consts::PI * u2;
+        let phi = 2.0 * PI * u2;
         Vector3::new(r * phi.cos(), r * phi.sin(), u1)
     }
 
-    pub fn dot(&self, other: Self) -> f64 {
+    /// A microfacet normal around +z drawn from the GGX distribution with width `alpha`.
+    pub fn rand_ggx(alpha: Float) -> Self {
+        let (u1, u2) = random::gen_pair(&mut LocalRng);
+
+        let theta = (alpha * (u1 / (1.0 - u1)).sqrt()).atan();
+        let phi = 2.0 * PI * u2;
+        Vector3::new(theta.sin() * phi.cos(), theta.sin() * phi.sin(), theta.cos())
+    }
+
+    pub fn dot(&self, other: Self) -> Float {
         (self.x * other.x) + (self.y * other.y) + (self.z * other.z)
     }
 
@@ -63,16 +84,48 @@ impl Vector3 {
         )
     }
 
-    pub fn scale(&self, scale: f64) -> Self {
+    pub fn scale(&self, scale: Float) -> Self {
         Self::new(
             self.x * scale, self.y * scale, self.z * scale
         )
     }
 
-    pub fn size(&self) -> f64 {
+    pub fn size(&self) -> Float {
         self.rho
     }
 
+    /// The squared length, without the square root.
+    pub fn length_squared(&self) -> Float {
+        self.dot(*self)
+    }
+
+    /// The point `t` of the way from this one to `other`.
+    pub fn lerp(&self, other: Self, t: Float) -> Self {
+        *self + (other - *self).scale(t)
+    }
+
+    pub fn min_component(&self) -> Float {
+        self.x.min(self.y).min(self.z)
+    }
+
+    pub fn max_component(&self) -> Float {
+        self.x.max(self.y).max(self.z)
+    }
+
+    /// This direction mirrored off a surface with unit normal `normal`.
+    pub fn reflect(&self, normal: Self) -> Self {
+        *self - normal.scale(2.0 * self.dot(normal))
+    }
+
+    /// This unit direction bent by Snell's law through a surface with unit normal `normal`
+    /// facing against it, where `eta` is the index of refraction it leaves over the one it
+    /// enters; `None` if all of it reflects instead.
+    pub fn refract(&self, normal: Self, eta: Float) -> Option<Self> {
+        let cos_i = -normal.dot(*self);
+        let cos_t2 = 1.0 - eta * eta * (1.0 - cos_i * cos_i);
+        (cos_t2 > 0.0).then(|| self.scale(eta) + normal.scale(eta * cos_i - cos_t2.sqrt()))
+    }
+
     pub fn normalize(&self) -> Self {
         if self.rho == 1.0 {
             *self
@@ -83,11 +136,11 @@ impl Vector3 {
         }
     }
 
-    pub fn shift(&self, dx: f64, dy: f64, dz: f64) -> Self {
+    pub fn shift(&self, dx: Float, dy: Float, dz: Float) -> Self {
         Self::new(self.x + dx, self.y + dy, self.z + dz)
     }
 
-    pub fn turn(&self, dtheta: f64, dphi: f64) -> Self {
+    pub fn turn(&self, dtheta: Float, dphi: Float) -> Self {
         Self::new_sph(self.rho, self.theta + dtheta, self.phi + dphi)
     }
 
@@ -137,4 +190,149 @@ impl Div for Vector3 {
     fn div(self, other: Self) -> Self {
         Self::new(self.x / other.x, self.y / other.y, self.z / other.z)
     }
-}
\ No newline at end of file
+}
+
+impl Neg for Vector3 {
+    type Output = Self;
+
+    fn neg(self) -> Self {
+        Self::new(-self.x, -self.y, -self.z)
+    }
+}
+
+impl Mul<Float> for Vector3 {
+    type Output = Self;
+
+    fn mul(self, scale: Float) -> Self {
+        self.scale(scale)
+    }
+}
+
+impl Div<Float> for Vector3 {
+    type Output = Self;
+
+    fn div(self, scale: Float) -> Self {
+        self.scale(1.0 / scale)
+    }
+}
+
+impl AddAssign for Vector3 {
+    fn add_assign(&mut self, other: Self) {
+        *self = *self + other;
+    }
+}
+
+impl SubAssign for Vector3 {
+    fn sub_assign(&mut self, other: Self) {
+        *self = *self - other;
+    }
+}
+
+impl MulAssign<Float> for Vector3 {
+    fn mul_assign(&mut self, scale: Float) {
+        *self = self.scale(scale);
+    }
+}
+
+/// An axis-aligned box, kept as plain arrays so ray tests skip `Vector3`'s bookkeeping.
+/// The empty box has every `min` above its `max`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Aabb {
+    pub min: [Float; 3],
+    pub max: [Float; 3]
+}
+
+impl Aabb {
+    /// The box with opposite corners `a` and `b`, in either order.
+    pub fn new(a: Vector3, b: Vector3) -> Self {
+        Aabb::point(a).expand(b)
+    }
+
+    /// The box holding just `point`.
+    pub fn point(point: Vector3) -> Self {
+        let corner = [point.x, point.y, point.z];
+        Aabb { min: corner, max: corner }
+    }
+
+    /// The box holding nothing, which any other is the union of itself with.
+    pub fn empty() -> Self {
+        Aabb { min: [Float::INFINITY; 3], max: [Float::NEG_INFINITY; 3] }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        (0..3).any(|axis| self.min[axis] > self.max[axis])
+    }
+
+    pub fn lower(&self) -> Vector3 {
+        Vector3::new(self.min[0], self.min[1], self.min[2])
+    }
+
+    pub fn upper(&self) -> Vector3 {
+        Vector3::new(self.max[0], self.max[1], self.max[2])
+    }
+
+    /// The smallest box holding both.
+    pub fn union(&self, other: &Aabb) -> Self {
+        let mut union = *self;
+        for axis in 0..3 {
+            union.min[axis] = union.min[axis].min(other.min[axis]);
+            union.max[axis] = union.max[axis].max(other.max[axis]);
+        }
+        union
+    }
+
+    /// The smallest box holding this one and `point`.
+    pub fn expand(&self, point: Vector3) -> Self {
+        self.union(&Aabb::point(point))
+    }
+
+    /// The middle of the box along `axis`.
+    pub fn center(&self, axis: usize) -> Float {
+        (self.min[axis] + self.max[axis]) / 2.0
+    }
+
+    pub fn centroid(&self) -> Vector3 {
+        Vector3::new(self.center(0), self.center(1), self.center(2))
+    }
+
+    /// The area of the box's six sides, which is what a random ray's chance of meeting it
+    /// goes with; none for an empty box.
+    pub fn surface_area(&self) -> Float {
+        let [dx, dy, dz] = [0, 1, 2].map(|axis| (self.max[axis] - self.min[axis]).max(0.0));
+        2.0 * (dx * dy + dy * dz + dz * dx)
+    }
+
+    /// The eight corners, `min` first and `max` last.
+    pub fn corners(&self) -> [Vector3; 8] {
+        [0, 1, 2, 3, 4, 5, 6, 7].map(|i: usize| Vector3::new(
+            if i & 1 == 0 { self.min[0] } else { self.max[0] },
+            if i & 2 == 0 { self.min[1] } else { self.max[1] },
+            if i & 4 == 0 { self.min[2] } else { self.max[2] }
+        ))
+    }
+
+    /// Distances along `ray` at which it enters and leaves the box, clipped to `0..t_max`.
+    pub fn intersect(&self, ray: Ray, t_max: Float) -> Option<(Float, Float)> {
+        let origin = [ray.pos.x, ray.pos.y, ray.pos.z];
+        let inv_dir = [1.0 / ray.dir.x, 1.0 / ray.dir.y, 1.0 / ray.dir.z];
+        self.span(&origin, &inv_dir, t_max)
+    }
+
+    /// `intersect` for a ray from `origin` along a direction whose reciprocal is
+    /// `inv_dir`, worked out once for the many boxes a traversal tests.
+    pub fn span(&self, origin: &[Float; 3], inv_dir: &[Float; 3], t_max: Float) -> Option<(Float, Float)> {
+        let (mut near, mut far) = (0.0, t_max);
+        for axis in 0..3 {
+            let t1 = (self.min[axis] - origin[axis]) * inv_dir[axis];
+            let t2 = (self.max[axis] - origin[axis]) * inv_dir[axis];
+            near = t1.min(t2).max(near);
+            far = t1.max(t2).min(far);
+        }
+        if near <= far { Some((near, far)) } else { None }
+    }
+
+    /// Whether `point` is in the box grown by `eps` on every side.
+    pub fn contains(&self, point: &[Float; 3], eps: Float) -> bool {
+        (0..3).all(|axis| self.min[axis] - eps <= point[axis] && point[axis] <= self.max[axis] + eps)
+    }
+}
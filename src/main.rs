@@ -1,138 +1,1144 @@
-mod config;
-mod linalg;
-mod shapes;
-mod trace;
+use graphics::ao::{make_ao_image, Occlusion};
+use graphics::aov::motion_vectors;
+use graphics::assets::Assets;
+use graphics::batch::{read_manifest, render_batch};
+use graphics::bench::{bench_run, BenchRun};
+use graphics::bloom::{apply_bloom, Bloom};
+use graphics::camera::key_at;
+use graphics::debug::{make_debug_image, DebugMode};
+use graphics::diff::{diff, dirty_region, Change};
+use graphics::cache::load_cached;
+use graphics::cryptomatte::{cryptomatte, id_pass, save_cryptomatte, save_id_pass, Key};
+use graphics::info::scene_info;
+use graphics::linalg::{consts::PI, Float, Vector3};
+use graphics::output::{film_channels, save_image_with, write_exr, write_multipart_exr, write_pfm, Channels, Dither, Quantize, Rounding};
+use graphics::probe::suggest_spp;
+use graphics::profile::Profiler;
+use graphics::config::{Config, ConfigError, ConfigResult, Loader, parse_config_file, parse_config_in};
+use graphics::error::{Error, RenderError};
+use graphics::export::export_scene;
+use graphics::film::{frame_bytes, Film, Region};
+use graphics::golden;
+use graphics::expr::Variables;
+use graphics::preview::{serve_preview, Preview};
+use graphics::progress::Progress;
+use graphics::remote::{render_remote, serve};
+use graphics::stats::{PixelStats, Stat};
+use graphics::trace::{make_image_stats, make_image_with, make_tile, pick};
+use graphics::turntable::{default_pivot, orbit};
+use graphics::watch::FileWatcher;
 
-
-extern crate image;
-extern crate rand;
-extern crate rayon;
-extern crate itertools;
-
-use crate::linalg::Vector3;
-use crate::config::{Config, ConfigError, ConfigResult, parse_config_file};
-use crate::trace::make_image;
-
-use config::parse_config;
-use image::{ImageBuffer, Rgb};
 use std::io::Write;
-use std::path::PathBuf;
-use std::sync::atomic::AtomicUsize;
-use std::thread::sleep;
-use std::time::Duration;
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
 use structopt::StructOpt;
 
 static PREV_LEN: AtomicUsize = AtomicUsize::new(0);
 
+/// Writes `message` over the last one on the current line.
+fn show(message: &str) -> std::io::Result<()> {
+    let num_erase = PREV_LEN.swap(message.len(), Ordering::Relaxed);
+    print!("\r{}", vec![" "; num_erase].join(""));
+    print!("\r{}", message);
+    std::io::stdout().flush()
+}
+
 macro_rules! message {
-    ($($items:tt)*) => {{
-        use core::sync::atomic::Ordering;
-        let message = format!($($items)*);
-        let num_erase = PREV_LEN.swap(message.len(), Ordering::Relaxed);
-        print!("\r{}", vec![" "; num_erase].join(""));
-        print!("\r{}", message);
-        std::io::stdout().flush().map_err(ConfigError::IOError)?;
-    }}
+    ($($items:tt)*) => {
+        show(&format!($($items)*)).map_err(ConfigError::IOError)?
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Pixel {
+    x: u32,
+    y: u32
+}
+
+impl FromStr for Pixel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || format!("Expected pixel as X,Y but got {:?}", s);
+        let (x, y) = s.split_once(',').ok_or_else(err)?;
+        let x = x.trim().parse().map_err(|_| err())?;
+        let y = y.trim().parse().map_err(|_| err())?;
+        Ok(Pixel { x, y })
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Point(Vector3);
+
+impl FromStr for Point {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || format!("Expected point as X,Y,Z but got {:?}", s);
+        let coords: Vec<Float> = s.split(',')
+            .map(|coord| coord.trim().parse().map_err(|_| err()))
+            .collect::<Result<_, _>>()?;
+        match coords[..] {
+            [x, y, z] => Ok(Point(Vector3::new(x, y, z))),
+            _ => Err(err())
+        }
+    }
+}
+
+/// A quality level the scene's resolution, samples and depth are scaled to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Preset {
+    /// A quarter of the resolution, a sixteenth of the samples and half the depth.
+    Draft,
+    /// Half the resolution, a quarter of the samples and three quarters of the depth.
+    Medium,
+    /// The scene as written.
+    Final
+}
+
+impl FromStr for Preset {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "draft" => Ok(Preset::Draft),
+            "medium" => Ok(Preset::Medium),
+            "final" => Ok(Preset::Final),
+            _ => Err(format!("Expected draft, medium or final but got {:?}", s))
+        }
+    }
+}
+
+impl Preset {
+    /// How much the resolution, samples per pixel and depth are multiplied by.
+    fn factors(self) -> (Float, Float, Float) {
+        match self {
+            Preset::Draft => (0.25, 1.0 / 16.0, 0.5),
+            Preset::Medium => (0.5, 0.25, 0.75),
+            Preset::Final => (1.0, 1.0, 1.0)
+        }
+    }
 }
 
 #[derive(Debug, StructOpt)]
 #[structopt(name = "example", about = "An example of StructOpt usage.")]
 struct CliArgs {
-    #[structopt(parse(from_os_str))]
-    input: PathBuf,
+    #[structopt(parse(from_os_str), required_unless_one = &["bench", "serve"])]
+    input: Option<PathBuf>,
 
-    #[structopt(parse(from_os_str))]
-    output: PathBuf,
+    /// The image to write; an `.exr` one keeps the render's full range and holds any passes
+    /// asked for as further parts of a multi-part file
+    #[structopt(parse(from_os_str), required_unless_one = &["bench", "serve", "compare", "info"])]
+    output: Option<PathBuf>,
 
     #[structopt(short, long)]
-    real_time: bool
+    real_time: bool,
+
+    /// In real-time mode, stop and keep the image once it has rendered for this many seconds
+    /// since the scene last changed, checked after each pass
+    #[structopt(long)]
+    max_seconds: Option<f64>,
+
+    /// In real-time mode, stop and keep the image once it has this many samples per pixel
+    #[structopt(long)]
+    max_samples: Option<u64>,
+
+    /// In real-time mode, stop and keep the image once all but 5% of pixels have a
+    /// standard error under this fraction of their brightness, like 0.02
+    #[structopt(long)]
+    noise_threshold: Option<Float>,
+
+    /// In real-time mode, aim for each pass to take about this many seconds: passes take
+    /// fewer samples per pixel, and after each change the scene is sketched at a coarser
+    /// size that refines pass by pass if even one sample per pixel would take longer
+    #[structopt(long)]
+    frame_budget: Option<Float>,
+
+    /// Treat the input as a manifest of `<scene> <output>` lines and render them all,
+    /// saving under the output directory and loading shared meshes only once
+    #[structopt(long)]
+    batch: bool,
+
+    /// Write the scene back out as a scene file at the output path instead of rendering it;
+    /// meshes of imported scenes are saved beside it as OBJ files
+    #[structopt(long)]
+    export: bool,
+
+    /// Report the object seen through pixel X,Y of the output image and exit
+    #[structopt(long)]
+    pick: Option<Pixel>,
+
+    /// Render only the object seen through pixel X,Y
+    #[structopt(long)]
+    solo: Option<Pixel>,
+
+    /// Render everything except the object seen through pixel X,Y
+    #[structopt(long)]
+    hide: Option<Pixel>,
+
+    /// Report what the scene holds, its bounds, lights, camera and the memory it needs, and exit
+    #[structopt(long)]
+    info: bool,
+
+    /// Estimate the samples per pixel needed for this relative noise level (e.g. 0.02) and exit
+    #[structopt(long)]
+    suggest_spp: Option<Float>,
+
+    /// Reuse a binary copy of the parsed scene, kept next to it, while the scene file is unchanged
+    #[structopt(long)]
+    cache: bool,
+
+    /// Bits per channel of the output image
+    #[structopt(long, default_value = "8", possible_values = &["8", "16"])]
+    bit_depth: u8,
+
+    /// Dither the output image: none, ordered or blue-noise
+    #[structopt(long, default_value = "none")]
+    dither: Dither,
+
+    /// Add a glow around light brighter than THRESHOLD (255 is white), spread RADIUS pixels
+    /// and added back at STRENGTH, given as THRESHOLD,RADIUS,STRENGTH
+    #[structopt(long)]
+    bloom: Option<Bloom>,
+
+    /// Round undithered output to the truncate or nearest level
+    #[structopt(long, default_value = "truncate")]
+    rounding: Rounding,
+
+    /// Write per-stage and per-tile timings to this file as a Chrome trace
+    #[structopt(long, parse(from_os_str))]
+    profile: Option<PathBuf>,
+
+    /// Lower the resolution until the frame buffers fit in this many megabytes
+    #[structopt(long)]
+    max_memory: Option<usize>,
+
+    /// Render on the GPU (in builds with the `gpu` feature), falling back to the CPU
+    /// for scenes or machines it can't handle
+    #[structopt(long)]
+    gpu: bool,
+
+    /// Render a built-in reference scene this many times, report rays per second and
+    /// per-stage timings, and exit
+    #[structopt(long)]
+    bench: Option<usize>,
+
+    /// Render a grayscale ambient occlusion image instead, casting this many rays from
+    /// each point seen
+    #[structopt(long)]
+    ao: Option<u32>,
+
+    /// How far away an object can be and still occlude, for --ao; any distance by default
+    #[structopt(long, default_value = "inf")]
+    ao_distance: Float,
+
+    /// Render a false-color image of normals, depth, uv or bounces instead
+    #[structopt(long)]
+    debug_mode: Option<DebugMode>,
+
+    /// Scale the scene's resolution, samples and depth for a draft (1/4, 1/16 and 1/2 of
+    /// them), medium (1/2, 1/4 and 3/4) or final (as written) render; --width, --height,
+    /// --spp and --max-depth still win
+    #[structopt(long)]
+    preset: Option<Preset>,
+
+    /// Render at this width instead of the scene's; alone, the height keeps the aspect ratio
+    #[structopt(long)]
+    width: Option<u32>,
+
+    /// Render at this height instead of the scene's; alone, the width keeps the aspect ratio
+    #[structopt(long)]
+    height: Option<u32>,
+
+    /// Take this many samples per pixel instead of the scene's number
+    #[structopt(long)]
+    spp: Option<u16>,
+
+    /// Follow paths for at most this many bounces instead of the scene's number
+    #[structopt(long)]
+    max_depth: Option<u16>,
+
+    /// Also write a heatmap of the intersection tests, acceleration structure nodes or
+    /// bounces that went into each pixel (tests, nodes or bounces) beside the output,
+    /// with `_heatmap` added to its name, or as its `heatmap` part if it is an EXR file;
+    /// renders on the CPU
+    #[structopt(long)]
+    heatmap: Option<Stat>,
+
+    /// Also write 16-bit PNGs numbering the object and the material seen through each
+    /// pixel beside the output, with `_object_id` and `_material_id` added to its name, or
+    /// as its `object_id` and `material_id` parts if it is an EXR file
+    #[structopt(long)]
+    ids: bool,
+
+    /// Also write an OpenEXR file of Cryptomatte object and material layers beside the
+    /// output, with `_cryptomatte.exr` in place of its extension, or as a `cryptomatte` part
+    /// of the output if it is an EXR file itself, for picking out objects with antialiased
+    /// edges in a compositor
+    #[structopt(long)]
+    cryptomatte: bool,
+
+    /// Render this many frames of the camera circling once around --pivot, saved beside
+    /// the output with the frame number added to its name
+    #[structopt(long)]
+    turntable: Option<u32>,
+
+    /// With --turntable, also write a PFM beside each frame, with `_motion` added to its
+    /// name, of how far in pixels (right, down) the point each pixel sees has moved across
+    /// the image since the frame before, or as a `motion` part of each frame if the output
+    /// is an EXR file; the first frame follows on from the last
+    #[structopt(long, requires = "turntable")]
+    motion_vectors: bool,
+
+    /// Point the turntable circles, as X,Y,Z, about an axis along the scene's up direction;
+    /// by default whatever the center of the image sees
+    #[structopt(long)]
+    pivot: Option<Point>,
+
+    /// Wait on this port for renders sent by --workers and render their tiles, until stopped
+    #[structopt(long)]
+    serve: Option<u16>,
+
+    /// Split the image into tiles and render them on these workers (HOST:PORT,...) started
+    /// with --serve; meshes are read by each worker from the same paths, so they need the
+    /// scene's mesh files where this machine has them
+    #[structopt(long, use_delimiter = true)]
+    workers: Vec<String>,
+
+    /// Serve a web page on this port showing the image so far, refreshed as passes finish
+    /// in real-time mode and once a single render is done
+    #[structopt(long)]
+    http: Option<u16>,
+
+    /// Treat the input as a folder of golden scenes, render each and compare it with the
+    /// PNG of the same name beside it, failing if any differ
+    #[structopt(long)]
+    compare: bool,
+
+    /// With --compare, replace the references of scenes that no longer match them
+    #[structopt(long, requires = "compare")]
+    update: bool
+}
+
+/// Passes saved as parts of a multi-part EXR output alongside the render, rather than as
+/// files of their own beside it.
+#[derive(Default)]
+struct Passes {
+    /// Each part's name and channels.
+    parts: Vec<(String, Channels)>,
+    attributes: Vec<(String, String)>
+}
+
+fn is_exr(output: &Path) -> bool {
+    output.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("exr"))
+}
+
+/// Halves the resolution until the frame buffers fit under `max_memory` (in MB)
+/// and can actually be allocated, warning about every step down.
+fn fit_in_memory(config: &mut Config, max_memory: Option<usize>, buffers: usize) -> Result<(), RenderError> {
+    let cap = max_memory.map_or(usize::MAX, |mb| mb.saturating_mul(1 << 20));
+    let fits = |width: u32, height: u32| {
+        let bytes = frame_bytes(width, height, buffers);
+        bytes <= cap && Vec::<u8>::new().try_reserve_exact(bytes).is_ok()
+    };
+
+    let (width, height) = (config.width, config.height);
+    while !fits(config.width, config.height) {
+        if config.width <= 1 && config.height <= 1 {
+            return Err(RenderError::OutOfMemory);
+        }
+        config.width = (config.width / 2).max(1);
+        config.height = (config.height / 2).max(1);
+    }
+    if (width, height) != (config.width, config.height) {
+        println!(
+            "Warning: {}x{} needs {} MB of frame buffers; rendering at {}x{} instead",
+            width, height, frame_bytes(width, height, buffers) >> 20, config.width, config.height
+        );
+    }
+    Ok(())
+}
+
+impl CliArgs {
+    fn quantize(&self) -> Quantize {
+        Quantize { bits: self.bit_depth, rounding: self.rounding, dither: self.dither }
+    }
+
+    /// Saves a render, with any bloom added before quantizing.
+    fn save(&self, film: &Film, scale: Float, output: &Path) -> ConfigResult<()> {
+        self.save_with(film, scale, output, Passes::default())
+    }
+
+    /// Like `save`, except that an `.exr` output keeps the render's full range as floats
+    /// and takes `passes` as further parts after the render's `rgba` one.
+    fn save_with(&self, film: &Film, scale: Float, output: &Path, passes: Passes) -> ConfigResult<()> {
+        let bloomed = self.bloom.as_ref().map(|bloom| apply_bloom(film, scale, bloom));
+        let (film, scale) = match &bloomed {
+            Some(bloomed) => (bloomed, 1.0),
+            None => (film, scale)
+        };
+        if is_exr(output) && passes.parts.is_empty() {
+            write_exr(output, film.width(), film.height(), &film_channels(film, scale, ""), &passes.attributes)
+        } else if is_exr(output) {
+            let mut parts = vec![("rgba".to_string(), film_channels(film, scale, ""))];
+            parts.extend(passes.parts);
+            write_multipart_exr(output, film.width(), film.height(), &parts, &passes.attributes)
+        } else {
+            save_image_with(film, scale, output, &self.quantize())
+        }
+    }
+
+    /// Replaces the scene's resolution, samples and depth with any given on the command line,
+    /// after scaling them to the preset.
+    fn override_config(&self, config: &mut Config) {
+        if let Some(preset) = self.preset {
+            // Never all the way down to nothing.
+            let scale = |value: Float, factor: Float| (value * factor).round().max(value.min(1.0));
+            let (resolution, samples, depth) = preset.factors();
+            config.width = scale(config.width as Float, resolution) as u32;
+            config.height = scale(config.height as Float, resolution) as u32;
+            config.num_tries = scale(config.num_tries as Float, samples) as u16;
+            config.max_depth = scale(config.max_depth as Float, depth) as u16;
+        }
+        let aspect = config.height as Float / config.width as Float;
+        let (width, height) = match (self.width, self.height) {
+            (Some(width), Some(height)) => (width, height),
+            (Some(width), None) => (width, (width as Float * aspect).round() as u32),
+            (None, Some(height)) => ((height as Float / aspect).round() as u32, height),
+            (None, None) => (config.width, config.height)
+        };
+        let (width, height) = (width.max(1), height.max(1));
+        config.width = width;
+        config.height = height;
+        config.num_tries = self.spp.unwrap_or(config.num_tries);
+        config.max_depth = self.max_depth.unwrap_or(config.max_depth);
+    }
+}
+
+struct Selection {
+    solo: Option<Pixel>,
+    hide: Option<Pixel>
+}
+
+impl Selection {
+    fn apply(&self, config: &mut Config) {
+        let solo = self.solo.map(|px| pick(config, px.x, px.y));
+        let hide = self.hide.and_then(|px| pick(config, px.x, px.y));
+
+        let mut index = 0;
+        config.objects.retain(|_| {
+            let keep = solo.is_none_or(|solo| solo == Some(index)) && hide != Some(index);
+            index += 1;
+            keep
+        });
+    }
 }
 
-fn main() -> ConfigResult<()> {
+fn main() {
+    if let Err(err) = run() {
+        let mut message = format!("{:#}", err);
+        let mut source = std::error::Error::source(&err);
+        while let Some(cause) = source {
+            message += &format!(": {:#}", cause);
+            source = cause.source();
+        }
+        eprintln!("Error: {}", message);
+        if let Some(hint) = hint(&err) {
+            eprintln!("Hint: {}", hint);
+        }
+        std::process::exit(1);
+    }
+}
+
+/// What to try about `err`, where it's clear.
+fn hint(err: &Error) -> Option<&'static str> {
+    match err {
+        Error::Render(RenderError::OutOfMemory) => Some("free some memory, or raise --max-memory"),
+        Error::Render(RenderError::Workers(_)) =>
+            Some("check every worker was started with --serve and can be reached from here"),
+        Error::Render(RenderError::Listen(..)) => Some("pick another port, or stop whatever is using this one"),
+        Error::Render(RenderError::Golden(..)) =>
+            Some("if the new images are right, run again with --update to make them the references"),
+        Error::Render(RenderError::Cancelled) => None,
+        Error::Config(err) => match err.innermost() {
+            ConfigError::ImageError(..) => Some("name the output with the extension of a format to save in, like .png"),
+            ConfigError::NotEnoughLines(_) =>
+                Some("a scene starts with seven lines of camera and image settings, and every `group` needs an `end`"),
+            _ => None
+        }
+    }
+}
+
+fn run() -> Result<(), Error> {
     let cli_args = CliArgs::from_args();
+    let selection = Selection { solo: cli_args.solo, hide: cli_args.hide };
+    let profiler = Profiler::new(cli_args.profile.is_some());
+    let write_profile = || match &cli_args.profile {
+        Some(path) => profiler.write(path).map_err(|err| Error::from(ConfigError::IOError(err))),
+        None => Ok(())
+    };
 
-    if cli_args.real_time {
-        build_real_time(&cli_args.input, &cli_args.output)
+    if let Some(runs) = cli_args.bench {
+        run_bench(runs, &profiler)?;
+        return write_profile();
+    }
+
+    if let Some(port) = cli_args.serve {
+        let listener = TcpListener::bind(("0.0.0.0", port)).map_err(|err| RenderError::Listen(port, err))?;
+        println!("Waiting for renders on port {}", port);
+        return serve(&listener, &profiler, |message| println!("{}", message)).map_err(|err| RenderError::Listen(port, err).into());
+    }
+
+    if cli_args.compare {
+        return compare_golden(cli_args.input.as_deref().unwrap(), cli_args.update);
+    }
+
+    if cli_args.info {
+        return Ok(report_info(&cli_args, cli_args.input.as_deref().unwrap(), &selection)?);
+    }
+
+    // Both are required unless benchmarking or serving, and the output for reports.
+    let (input, output) = (cli_args.input.as_deref().unwrap(), cli_args.output.as_deref().unwrap());
+    let preview = match cli_args.http {
+        Some(port) => {
+            let listener = TcpListener::bind(("0.0.0.0", port)).map_err(|err| RenderError::Listen(port, err))?;
+            println!("Previewing at http://localhost:{}/", port);
+            let preview = Arc::new(Preview::new());
+            serve_preview(listener, preview.clone());
+            Some(preview)
+        }
+        None => None
+    };
+    if let Some(px) = cli_args.pick {
+        Ok(report_pick(&cli_args, input, px)?)
+    } else if cli_args.batch {
+        let jobs = read_manifest(input, output)?;
+        render_batch(&jobs, &profiler, |index, job| {
+            println!("[{}/{}] {} -> {}", index + 1, jobs.len(), job.scene.display(), job.output.display());
+        })?;
+        write_profile()
+    } else if cli_args.export {
+        let mut config = parse_config_file(input)?;
+        warn(&config);
+        cli_args.override_config(&mut config);
+        selection.apply(&mut config);
+        Ok(export_scene(&config, output)?)
+    } else if let Some(target) = cli_args.suggest_spp {
+        Ok(report_spp(&cli_args, input, &selection, target)?)
+    } else if let Some(rays) = cli_args.ao {
+        let occlusion = Occlusion { rays, distance: cli_args.ao_distance };
+        build_ao(&cli_args, input, output, &selection, &occlusion, &profiler)?;
+        write_profile()
+    } else if let Some(mode) = cli_args.debug_mode {
+        build_debug(&cli_args, input, output, &selection, mode, &profiler)?;
+        write_profile()
+    } else if let Some(frames) = cli_args.turntable {
+        build_turntable(&cli_args, input, output, &selection, frames, &profiler)?;
+        write_profile()
+    } else if cli_args.real_time {
+        build_real_time(&cli_args, input, output, &selection, &profiler, preview.as_deref(), write_profile)
     } else {
-        build_once(&cli_args.input, &cli_args.output)
+        build_once(&cli_args, input, output, &selection, &profiler, preview.as_deref())?;
+        write_profile()
+    }
+}
+
+/// Prints what looked wrong in the scene, before rendering it anyway.
+fn warn(config: &Config) {
+    for warning in &config.warnings {
+        eprintln!("Warning: {}", warning);
+    }
+}
+
+fn report_pick(cli_args: &CliArgs, input: &Path, px: Pixel) -> ConfigResult<()> {
+    let raw = std::fs::read_to_string(input).map_err(ConfigError::IOError)?;
+    let mut config = parse_config_file(input)?;
+    cli_args.override_config(&mut config);
+    match pick(&config, px.x, px.y) {
+        Some(index) => {
+            let line = config.objects[index].line;
+            let text = raw.lines().nth(line - 1).unwrap_or("");
+            println!("Pixel ({}, {}) hits object on line {}: {}", px.x, px.y, line, text);
+        }
+        None => println!("Pixel ({}, {}) hits nothing", px.x, px.y)
+    }
+    Ok(())
+}
+
+fn report_info(cli_args: &CliArgs, input: &Path, selection: &Selection) -> ConfigResult<()> {
+    let mut config = parse_config_file(input)?;
+    warn(&config);
+    cli_args.override_config(&mut config);
+    selection.apply(&mut config);
+    let info = scene_info(&config);
+    let (pos, dir) = (config.pov.pos, config.pov.dir);
+
+    println!("Image: {}x{}, {} samples per pixel, {} bounces", config.width, config.height, config.num_tries, config.max_depth);
+    println!("Camera: at ({}, {}, {}) looking along ({:.3}, {:.3}, {:.3})", pos.x, pos.y, pos.z, dir.x, dir.y, dir.z);
+    println!("  {:.1} degree field of view, rolled {:.1} degrees", (2.0 * config.fov).to_degrees(), config.roll.to_degrees());
+    if let Some(lens) = &config.lens {
+        println!("  lens {} across, in focus at {}", 2.0 * lens.radius, lens.focus);
+    }
+    if let (Some(first), Some(last)) = (config.focus_keys.first(), config.focus_keys.last()) {
+        println!("  {} focus keys, from frame {} to {}", config.focus_keys.len(), first.frame, last.frame);
+    }
+    let shapes: Vec<String> = info.shapes.iter().map(|(name, count)| format!("{}: {}", name, count)).collect();
+    println!("Objects: {} ({})", config.objects.len(), if shapes.is_empty() { "none".to_string() } else { shapes.join(", ") });
+    if info.triangles > 0 {
+        println!("  {} triangles in meshes", info.triangles);
+    }
+    match info.bounds {
+        Some(bounds) => {
+            let (min, max) = (bounds.lower(), bounds.upper());
+            println!("Bounds: ({}, {}, {}) to ({}, {}, {})", min.x, min.y, min.z, max.x, max.y, max.z)
+        }
+        None => println!("Bounds: none")
+    }
+    if info.unbounded > 0 {
+        println!("  leaving out {} objects without bounds", info.unbounded);
+    }
+    println!(
+        "Lights: {} (glowing objects: {}, directional: {}, spot: {}{})",
+        info.lights(), info.glowing, info.directional_lights, info.spot_lights, if info.sky { ", sky: 1" } else { "" }
+    );
+    let mb = |bytes: usize| bytes as f64 / (1 << 20) as f64;
+    println!(
+        "Memory: about {:.1} MB ({:.1} MB of frame buffers, {:.1} MB of meshes and heightfields)",
+        mb(info.frame_bytes + info.mesh_bytes), mb(info.frame_bytes), mb(info.mesh_bytes)
+    );
+    Ok(())
+}
+
+fn report_spp(cli_args: &CliArgs, input: &Path, selection: &Selection, target: Float) -> ConfigResult<()> {
+    let mut config = parse_config_file(input)?;
+    cli_args.override_config(&mut config);
+    selection.apply(&mut config);
+    let suggestion = suggest_spp(&config, target);
+    println!(
+        "Current {} samples per pixel give about {:.1}% noise",
+        config.num_tries, suggestion.current_noise * 100.0
+    );
+    println!(
+        "{:.1}% noise needs about {} samples per pixel, projected to take {:.1?}",
+        target * 100.0, suggestion.spp, suggestion.render_time
+    );
+    Ok(())
+}
+
+/// Renders `config` on the GPU if asked to and able, and on the CPU otherwise, showing
+/// how far the CPU has got after `prefix` as rows finish.
+fn render(config: &Config, gpu: bool, profiler: &Profiler, prefix: &str) -> Film {
+    if gpu {
+        match gpu_image(config) {
+            Ok(image) => return image,
+            Err(reason) => println!("Warning: {}; rendering on the CPU instead", reason)
+        }
+    }
+    let samples = config.width as u64 * config.height as u64 * config.num_tries as u64;
+    let progress = Progress::new(samples);
+    make_image_with(config, profiler, None, |samples| {
+        if let Some(report) = progress.tick(samples) {
+            // Progress is only for show; a closed stdout shouldn't stop the render.
+            let _ = show(&format!("{}{}", prefix, report));
+        }
+    })
+}
+
+/// Renders `config` on the `workers`, showing how far they have got as tiles come back.
+fn render_on(config: &Config, workers: &[String]) -> Result<Film, RenderError> {
+    let samples = config.width as u64 * config.height as u64 * config.num_tries as u64;
+    let progress = Progress::new(samples);
+    render_remote(config, workers, |samples| {
+        if let Some(report) = progress.tick(samples) {
+            let _ = show(&report.to_string());
+        }
+    }).map_err(RenderError::Workers)
+}
+
+/// Renders only `region` of `config`, on the CPU, showing how far it has got after `prefix`.
+fn render_region(config: &Config, region: &Region, profiler: &Profiler, prefix: &str) -> Film {
+    let progress = Progress::new(region.pixels() * config.num_tries as u64);
+    make_tile(config, profiler, region, |samples| {
+        if let Some(report) = progress.tick(samples) {
+            let _ = show(&format!("{}{}", prefix, report));
+        }
+    })
+}
+
+/// Renders `config` on the CPU, counting the work done for every pixel.
+fn render_with_stats(config: &Config, profiler: &Profiler) -> (Film, PixelStats) {
+    let samples = config.width as u64 * config.height as u64 * config.num_tries as u64;
+    let progress = Progress::new(samples);
+    make_image_stats(config, profiler, |samples| {
+        if let Some(report) = progress.tick(samples) {
+            let _ = show(&report.to_string());
+        }
+    })
+}
+
+/// `output` with `suffix` added to its name, before any extension.
+fn suffixed_path(output: &Path, suffix: &str) -> PathBuf {
+    let stem = output.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match output.extension() {
+        Some(ext) => format!("{}{}.{}", stem, suffix, ext.to_string_lossy()),
+        None => format!("{}{}", stem, suffix)
+    };
+    output.with_file_name(name)
+}
+
+fn compare_golden(dir: &Path, update: bool) -> Result<(), Error> {
+    let scenes = golden::scenes(dir)?;
+    let mut failed = 0;
+    for scene in &scenes {
+        let comparison = golden::check(scene, update)?;
+        let name = scene.file_name().unwrap_or_default().to_string_lossy();
+        if comparison.matches() {
+            println!("ok      {}: {}", name, comparison);
+        } else if update {
+            println!("updated {}: {}", name, comparison);
+        } else {
+            println!("FAILED  {}: {}", name, comparison);
+            failed += 1;
+        }
+    }
+    if failed > 0 {
+        return Err(RenderError::Golden(failed, scenes.len()).into());
+    }
+    Ok(())
+}
+
+fn run_bench(runs: usize, profiler: &Profiler) -> ConfigResult<()> {
+    let mut results = Vec::with_capacity(runs);
+    for run in 1..=runs {
+        let result = bench_run(profiler)?;
+        println!(
+            "Run #{}: parse {:.1?} | render {:.1?} | quantize {:.1?} | {:.2}M rays/s",
+            run, result.parse, result.render, result.quantize, result.rays_per_sec() / 1e6
+        );
+        results.push(result);
+    }
+    let best = match results.iter().min_by_key(|result| result.total()) {
+        Some(best) => best,
+        None => return Ok(())
+    };
+
+    let mean = |stage: fn(&BenchRun) -> Duration| {
+        results.iter().map(stage).sum::<Duration>() / results.len() as u32
+    };
+    println!("{:>9} {:>10} {:>10}", "", "best", "mean");
+    for (name, stage) in [
+        ("parse", (|result: &BenchRun| result.parse) as fn(&BenchRun) -> Duration),
+        ("render", |result| result.render),
+        ("quantize", |result| result.quantize),
+        ("total", |result| result.total())
+    ] {
+        println!("{:>9} {:>10.1?} {:>10.1?}", name, stage(best), mean(stage));
     }
+    println!(
+        "{} rays and {} samples per run; best {:.2}M rays/s, {:.2}M samples/s",
+        best.rays, best.samples, best.rays_per_sec() / 1e6, best.samples_per_sec() / 1e6
+    );
+    Ok(())
 }
 
-fn build_once(input: &PathBuf, output: &PathBuf) -> ConfigResult<()> {
-    let config = parse_config_file(input)?;
-    let result = make_image(&config);
-    let img = ImageBuffer::from_fn(config.width, config.height, |x, y| {
-        let curr = result[y as usize][x as usize];
-        Rgb([curr.x as u8, curr.y as u8, curr.z as u8])
-    });
+#[cfg(feature = "gpu")]
+fn gpu_image(config: &Config) -> Result<Film, String> {
+    graphics::gpu::make_image(config).map_err(|err| err.to_string())
+}
+
+#[cfg(not(feature = "gpu"))]
+fn gpu_image(_config: &Config) -> Result<Film, String> {
+    Err("This build has no GPU renderer (see the `gpu` feature)".to_string())
+}
+
+fn build_once(
+    cli_args: &CliArgs,
+    input: &Path,
+    output: &Path,
+    selection: &Selection,
+    profiler: &Profiler,
+    preview: Option<&Preview>
+) -> Result<(), Error> {
+    let mut config = profiler.span("stage", || "parse".to_string(), || match cli_args.cache {
+        true => load_cached(input),
+        false => parse_config_file(input)
+    })?;
+    warn(&config);
+    cli_args.override_config(&mut config);
+    selection.apply(&mut config);
+    fit_in_memory(&mut config, cli_args.max_memory, 1)?;
+    if let Some(preview) = preview {
+        preview.set_status(format!("Rendering {}x{} at {} samples per pixel", config.width, config.height, config.num_tries));
+    }
+    let start = Instant::now();
+    let exr = is_exr(output);
+    let mut passes = Passes::default();
+    let result = match cli_args.heatmap {
+        Some(stat) => {
+            let (result, stats) = profiler.span("stage", || "render".to_string(), || render_with_stats(&config, profiler));
+            println!();
+            let (max, total) = stats.values(stat).fold((0, 0), |(max, total), value| (max.max(value), total + value));
+            let pixels = (config.width as u64 * config.height as u64).max(1);
+            let path = suffixed_path(output, "_heatmap");
+            let saved = if exr { format!("the heatmap part of {}", output.display()) } else { path.display().to_string() };
+            println!("{:?} per pixel: mean {:.1}, most {}; heatmap saved to {}", stat, total as Float / pixels as Float, max, saved);
+            if exr {
+                passes.parts.push(("heatmap".to_string(), film_channels(&stats.heatmap(stat), 1.0, "heatmap")));
+            } else {
+                save_image_with(&stats.heatmap(stat), 1.0, &path, &cli_args.quantize())?;
+            }
+            result
+        }
+        None if !cli_args.workers.is_empty() => {
+            let result = profiler.span("stage", || "render".to_string(), || render_on(&config, &cli_args.workers))?;
+            println!();
+            result
+        }
+        None => {
+            let result = profiler.span("stage", || "render".to_string(), || render(&config, cli_args.gpu, profiler, ""));
+            println!();
+            result
+        }
+    };
+    if let Some(preview) = preview {
+        preview.set_image(&result, 1.0);
+        preview.set_status(format!("Finished in {:.1?}", start.elapsed()));
+    }
+    if cli_args.ids {
+        profiler.span("stage", || "ids".to_string(), || -> ConfigResult<()> {
+            for (key, name) in [(Key::Object, "object_id"), (Key::Material, "material_id")] {
+                if exr {
+                    let ids = id_pass(&config, key).into_iter().map(|id| id as f32).collect();
+                    passes.parts.push((name.to_string(), vec![(format!("{}.Y", name), ids)]));
+                } else {
+                    save_id_pass(&config, key, &suffixed_path(output, &format!("_{}", name)))?;
+                }
+            }
+            Ok(())
+        })?;
+    }
+    if cli_args.cryptomatte {
+        let keys = [Key::Object, Key::Material];
+        profiler.span("stage", || "cryptomatte".to_string(), || -> ConfigResult<()> {
+            if exr {
+                let (channels, attributes) = cryptomatte(&config, &keys);
+                passes.parts.push(("cryptomatte".to_string(), channels));
+                passes.attributes.extend(attributes);
+                Ok(())
+            } else {
+                save_cryptomatte(&config, &keys, &suffixed_path(output, "_cryptomatte").with_extension("exr"))
+            }
+        })?;
+    }
+    Ok(profiler.span("stage", || "save".to_string(), || cli_args.save_with(&result, 1.0, output, passes))?)
+}
+
+fn build_ao(
+    cli_args: &CliArgs,
+    input: &Path,
+    output: &Path,
+    selection: &Selection,
+    occlusion: &Occlusion,
+    profiler: &Profiler
+) -> Result<(), Error> {
+    let mut config = parse_config_file(input)?;
+    warn(&config);
+    cli_args.override_config(&mut config);
+    selection.apply(&mut config);
+    fit_in_memory(&mut config, cli_args.max_memory, 1)?;
+    let result = profiler.span("stage", || "render".to_string(), || make_ao_image(&config, occlusion, profiler));
+    Ok(save_image_with(&result, 1.0, output, &cli_args.quantize())?)
+}
+
+fn build_debug(
+    cli_args: &CliArgs,
+    input: &Path,
+    output: &Path,
+    selection: &Selection,
+    mode: DebugMode,
+    profiler: &Profiler
+) -> Result<(), Error> {
+    let mut config = parse_config_file(input)?;
+    warn(&config);
+    cli_args.override_config(&mut config);
+    selection.apply(&mut config);
+    fit_in_memory(&mut config, cli_args.max_memory, 1)?;
+    let result = profiler.span("stage", || "render".to_string(), || make_debug_image(&config, mode, profiler));
+    Ok(save_image_with(&result, 1.0, output, &cli_args.quantize())?)
+}
+
+fn build_turntable(
+    cli_args: &CliArgs,
+    input: &Path,
+    output: &Path,
+    selection: &Selection,
+    frames: u32,
+    profiler: &Profiler
+) -> Result<(), Error> {
+    let mut config = parse_config_file(input)?;
+    warn(&config);
+    cli_args.override_config(&mut config);
+    selection.apply(&mut config);
+    fit_in_memory(&mut config, cli_args.max_memory, 1)?;
+    let pivot = cli_args.pivot.map_or_else(|| default_pivot(&config), |Point(pivot)| pivot);
+    let (start, up) = (config.pov, config.up);
+    let pov = |frame: u32| orbit(start, up, pivot, 2.0 * PI * frame as Float / frames as Float);
+    let (keys, start_fov) = (config.focus_keys.clone(), config.fov);
+    let fov = |frame: u32| key_at(&keys, frame).map_or(start_fov, |key| key.fov());
+    for frame in 0..frames {
+        config.pov = pov(frame);
+        config.focus_at(frame);
+        let prefix = format!("Frame {}/{} | ", frame + 1, frames);
+        let result = profiler.span("stage", || format!("frame {}", frame), || render(&config, cli_args.gpu, profiler, &prefix));
+        let path = suffixed_path(output, &format!("_{:04}", frame));
+        let mut passes = Passes::default();
+        if cli_args.motion_vectors {
+            let previous = (frame + frames - 1) % frames;
+            let motion = motion_vectors(&config, pov(previous), fov(previous));
+            if is_exr(output) {
+                // Narrowing to EXR's `f32` is a no-op with the `f32` feature.
+                #[allow(clippy::unnecessary_cast)]
+                let axis = |axis: usize| motion.chunks(3).map(|pixel| pixel[axis] as f32).collect();
+                passes.parts.push(("motion".to_string(), vec![("motion.X".to_string(), axis(0)), ("motion.Y".to_string(), axis(1))]));
+            } else {
+                write_pfm(&suffixed_path(&path, "_motion").with_extension("pfm"), config.width, config.height, 3, &motion)?;
+            }
+        }
+        cli_args.save_with(&result, 1.0, &path, passes)?;
+    }
+    println!();
+    Ok(())
+}
+
+/// Share of pixels `--noise-threshold` wants under it; the rest may stay noisy, as
+/// caustics and fireflies take far longer to settle than the image around them.
+const NOISE_SHARE: Float = 0.95;
 
-    img.save(output).map_err(ConfigError::ImageError)
+/// The noise `NOISE_SHARE` of the pixels are within.
+fn noise_share(mut noise: Vec<Float>) -> Float {
+    if noise.is_empty() {
+        return 0.0;
+    }
+    let index = ((noise.len() as Float * NOISE_SHARE).ceil() as usize).clamp(1, noise.len()) - 1;
+    *noise.select_nth_unstable_by(index, Float::total_cmp).1
+}
+
+/// The most times smaller each side of a sketch is than the image.
+const COARSEST: u32 = 16;
+
+/// How many times smaller each side of the image is first sketched after a change, for
+/// passes to take about `budget` seconds when a sample per pixel over the whole image
+/// last took `cost`: the coarsest size until that is known.
+fn first_step(budget: Option<Float>, cost: Option<Float>) -> u32 {
+    match (budget, cost) {
+        (None, _) => 1,
+        (Some(_), None) => COARSEST,
+        (Some(budget), Some(cost)) => {
+            let mut step = 1;
+            while step < COARSEST && cost / (step * step) as Float > budget {
+                step *= 2;
+            }
+            step
+        }
+    }
 }
 
-fn build_real_time(input: &PathBuf, output: &PathBuf) -> ConfigResult<()> {
-    fn get_config(input: &PathBuf, cached: Option<&str>) -> ConfigResult<Option<(String, Config)>> {
-        let load_raw = || std::fs::read_to_string(input).map_err(ConfigError::IOError);
+/// What `render` makes of `config` with each side `step` times smaller and `samples`
+/// samples per pixel, leaving `config` as it was.
+fn render_smaller(config: &mut Config, step: u32, samples: u16, render: impl FnOnce(&Config) -> Film) -> Film {
+    let (width, height, num_tries) = (config.width, config.height, config.num_tries);
+    config.width = width.div_ceil(step);
+    config.height = height.div_ceil(step);
+    config.num_tries = samples;
+    let film = render(config);
+    (config.width, config.height, config.num_tries) = (width, height, num_tries);
+    film
+}
+
+fn build_real_time(
+    cli_args: &CliArgs,
+    input: &Path,
+    output: &Path,
+    selection: &Selection,
+    profiler: &Profiler,
+    preview: Option<&Preview>,
+    write_profile: impl Fn() -> Result<(), Error>
+) -> Result<(), Error> {
+    fn get_config(input: &Path, cached: Option<&str>, watcher: &FileWatcher) -> ConfigResult<Option<(String, Config)>> {
+        let in_file = |err| ConfigError::InFile(input.to_path_buf(), Box::new(err));
+        let load_raw = || std::fs::read_to_string(input).map_err(|err| in_file(ConfigError::IOError(err)));
         let mut raw = load_raw()?;
         if cached == Some(&raw) {
             return Ok(None);
         }
 
         loop {
-            match parse_config(&raw) {
-                Ok(config) => return Ok(Some((raw, config))),
+            // Meshes are loaded afresh each time, in case they changed too.
+            let loader = Loader { dir: input.parent().unwrap_or(Path::new(".")), assets: &Assets::new() };
+            match parse_config_in(&raw, &Variables::new(), &loader) {
+                Ok(config) => {
+                    warn(&config);
+                    return Ok(Some((raw, config)));
+                }
                 Err(err) => {
-                    message!("Config Error: {:?}", err);
+                    message!("Config Error: {}", in_file(err));
                     raw = loop {
+                        watcher.wait();
                         let new_raw = load_raw()?;
                         if new_raw != raw {
                             break new_raw;
                         }
-                        sleep(Duration::from_secs(1));
                     };
                 }
             }
         }
     }
 
-    fn empty_result(config: &Config) -> Vec<Vec<Vector3>> {
-        vec![vec![Vector3::new(0.0, 0.0, 0.0); config.width as usize]; config.height as usize]
-    }
-
-    let (mut raw, mut config) = get_config(input, None)?.unwrap();
-    let mut result = empty_result(&config);
-    let start_time = std::time::Instant::now();
-    loop {
-        for it in 1.. {
-            message!("\rIter #{}; Time {:?}", it, start_time.elapsed());
-            std::io::stdout().flush().map_err(ConfigError::IOError)?;
-
-            {
-                let new = make_image(&config);
-                for x in 0..(config.width as usize) {
-                    for y in 0..(config.height as usize) {
-                        result[y][x] = result[y][x] + new[y][x];
+    let watcher = FileWatcher::new(input).map_err(ConfigError::IOError)?;
+    let (mut raw, mut config) = get_config(input, None, &watcher)?.unwrap();
+    cli_args.override_config(&mut config);
+    selection.apply(&mut config);
+    // With a noise threshold, every other pass is also kept apart, to tell how far the
+    // image still is from settling.
+    let buffers = 2 + cli_args.noise_threshold.is_some() as usize;
+    fit_in_memory(&mut config, cli_args.max_memory, buffers)?;
+    let mut result = Film::new(config.width, config.height);
+    let new_half = |config: &Config| cli_args.noise_threshold.map(|_| Film::new(config.width, config.height));
+    let mut half = new_half(&config);
+    // Pixels cleared by edits, to be rendered alone until they have as many samples as
+    // the rest had.
+    let mut dirty: Option<(Region, u32)> = None;
+    let mut start = Instant::now();
+    let budget = cli_args.frame_budget;
+    // Seconds a sample per pixel took over the whole image on the last pass, and how
+    // many times smaller each side of the image is rendered until the scene has been seen.
+    let mut cost: Option<Float> = None;
+    let mut step = first_step(budget, cost);
+    for it in 1.. {
+        // What the scene looks like at the moment, if this pass only sketched it.
+        let sketch = profiler.span("stage", || format!("render #{}", it), || {
+            let prefix = format!("Iter #{} | ", it);
+            match &dirty {
+                Some((region, _)) => {
+                    let tile = render_region(&config, region, profiler, &prefix);
+                    result.add_film_at(&tile, region.x.start, region.y.start);
+                    if let Some(half) = half.as_mut().filter(|_| it % 2 == 0) {
+                        half.add_film_at(&tile, region.x.start, region.y.start);
                     }
+                    None
+                }
+                None => {
+                    let area = (step * step) as Float;
+                    let samples = budget.zip(cost).map_or(config.num_tries, |(budget, cost)| {
+                        (budget * area / cost).clamp(1.0, config.num_tries as Float) as u16
+                    });
+                    let pass_start = Instant::now();
+                    let pass = render_smaller(&mut config, step, samples, |config| {
+                        render(config, cli_args.gpu, profiler, &prefix)
+                    });
+                    cost = Some(pass_start.elapsed().as_secs_f64() as Float * area / samples as Float);
+                    if step > 1 {
+                        step /= 2;
+                        let sketch = pass.rescaled(config.num_tries as u32);
+                        return Some(sketch.enlarged(config.width, config.height));
+                    }
+                    result.add_film(&pass);
+                    if let Some(half) = half.as_mut().filter(|_| it % 2 == 0) {
+                        half.add_film(&pass);
+                    }
+                    None
                 }
             }
+        });
+        if dirty.as_ref().is_some_and(|&(_, goal)| result.min_samples() >= goal) {
+            dirty = None;
+        }
 
-            {
-                let img = ImageBuffer::from_fn(config.width, config.height, |x, y| {
-                    let curr = result[y as usize][x as usize].scale(1.0 / (it as f64));
-                    Rgb([curr.x as u8, curr.y as u8, curr.z as u8])
-                });
-            
-                img.save(output).map_err(ConfigError::ImageError)?;
+        // Pixels reset by edits have fewer samples than the rest.
+        let sketched = sketch.is_some();
+        let image = sketch.unwrap_or_else(|| result.rescaled(config.num_tries as u32));
+        profiler.span("stage", || format!("save #{}", it), || cli_args.save(&image, 1.0, output))?;
+        write_profile()?;
+
+        let samples = result.min_samples() as u64;
+        if sketched {
+            // Nothing to stop on yet.
+            if let Some(preview) = preview {
+                preview.set_image(&image, 1.0);
+                preview.set_status(format!("Pass #{}: sketched at 1/{} size in {:.1?}", it, step * 2, start.elapsed()));
+            }
+        } else {
+            // The noise all but the noisiest pixels are under, once it can be told.
+            let noise = half.as_ref()
+                .map(|half| noise_share(result.noise(half, 1.0 / config.num_tries as Float)))
+                .filter(|noise| noise.is_finite());
+            let quality = noise.map_or(String::new(), |noise| format!(", {:.1}% noise", noise * 100.0));
+            if let Some(preview) = preview {
+                preview.set_image(&image, 1.0);
+                preview.set_status(format!("Pass #{}: {} samples per pixel in {:.1?}{}", it, samples, start.elapsed(), quality));
             }
+            let out_of_time = cli_args.max_seconds.is_some_and(|max| start.elapsed().as_secs_f64() >= max);
+            let settled = noise.is_some_and(|noise| cli_args.noise_threshold.is_some_and(|max| noise <= max));
+            if out_of_time || settled || cli_args.max_samples.is_some_and(|max| samples >= max) {
+                println!();
+                println!("Stopped after {} samples per pixel in {:.1?}", samples, start.elapsed());
+                if let Some(noise) = noise {
+                    println!("{:.0}% of pixels are within {:.2}% noise", NOISE_SHARE * 100.0, noise * 100.0);
+                }
+                return Ok(());
+            }
+        }
 
-            match get_config(input, Some(&raw))? {
-                None => (),
-                Some((new_raw, new_config)) => {
-                    raw = new_raw;
-                    config = new_config;
-                    result = empty_result(&config);
-                    break;
+        let reload = || match watcher.changed() {
+            true => get_config(input, Some(&raw), &watcher),
+            false => Ok(None)
+        };
+        if let Some((new_raw, mut new_config)) = profiler.span("stage", || "reload".to_string(), reload)? {
+            cli_args.override_config(&mut new_config);
+            selection.apply(&mut new_config);
+            fit_in_memory(&mut new_config, cli_args.max_memory, buffers)?;
+            match diff(&config, &new_config) {
+                Change::Nothing => (),
+                Change::Objects { old, new } => {
+                    match dirty_region(&config, &old, &new_config, &new) {
+                        Some(region) => {
+                            let goal = result.min_samples();
+                            result.clear_region(&region);
+                            if let Some(half) = half.as_mut() {
+                                half.clear_region(&region);
+                            }
+                            dirty = Some(match dirty.take() {
+                                Some((before, before_goal)) => (before.union(&region), before_goal.max(goal)),
+                                None => (region, goal)
+                            });
+                            if dirty.as_ref().is_some_and(|(region, goal)| region.is_empty() || *goal == 0) {
+                                dirty = None;
+                            }
+                        }
+                        None => {
+                            result = Film::new(new_config.width, new_config.height);
+                            half = new_half(&new_config);
+                            step = first_step(budget, cost);
+                            dirty = None;
+                        }
+                    }
+                    start = Instant::now();
+                }
+                Change::Everything => {
+                    result = Film::new(new_config.width, new_config.height);
+                    half = new_half(&new_config);
+                    step = first_step(budget, cost);
+                    dirty = None;
+                    start = Instant::now();
                 }
             }
+            raw = new_raw;
+            config = new_config;
         }
     }
+    Ok(())
 }
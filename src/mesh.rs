@@ -0,0 +1,807 @@
+use std::collections::HashMap;
+use std::convert::{TryFrom, TryInto};
+use std::fmt;
+use std::fs;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, OnceLock};
+
+use rayon::prelude::*;
+
+use crate::bvh::Bvh;
+use crate::config::{ConfigError, ConfigResult};
+use crate::kdtree::KdTree;
+use crate::linalg::{Aabb, Float, Vector3};
+use crate::mtl::{load_mtl, MtlMaterial};
+use crate::shapes::{Hit, Ray, Shape, SurfaceSample, Triangle, EPS};
+use crate::simd::{TrianglePacket, LANES};
+use crate::stats;
+
+/// Which structure speeds up tracing a mesh's triangles.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Accel {
+    #[default]
+    Bvh,
+    KdTree
+}
+
+impl FromStr for Accel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bvh" => Ok(Accel::Bvh),
+            "kdtree" => Ok(Accel::KdTree),
+            _ => Err(format!("Expected bvh or kdtree but got {:?}", s))
+        }
+    }
+}
+
+impl fmt::Display for Accel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Accel::Bvh => "bvh",
+            Accel::KdTree => "kdtree"
+        })
+    }
+}
+
+#[derive(Debug)]
+enum Tree {
+    /// A BVH, whose leaves' triangles are packed for testing all at once as they are
+    /// reached. Keeping the packets would cost more than the faces and vertices together.
+    Bvh(Bvh),
+    KdTree(KdTree)
+}
+
+/// The corners a model's faces share: where each is, and the normals and texture
+/// coordinates its faces give there. They are kept as bare coordinates, half the size
+/// of a `Vector3` and quicker to make, until a triangle is built from them.
+#[derive(Debug, Default, PartialEq)]
+pub struct Vertices {
+    pub positions: Vec<[Float; 3]>,
+    pub normals: Vec<[Float; 3]>,
+    pub uvs: Vec<(Float, Float)>
+}
+
+impl Vertices {
+    /// Adds a corner at `position`, giving its index.
+    fn add(&mut self, position: [Float; 3]) -> Result<u32, String> {
+        let index = u32::try_from(self.positions.len()).map_err(|_| "more vertices than can be numbered")?;
+        self.positions.push(position);
+        Ok(index)
+    }
+
+    /// Adds `face` to `faces` unless it has zero area, since then it has no normal and
+    /// can't be hit anyway. It is shaded flat instead if one of its normals is zero.
+    pub(crate) fn push_face(&self, faces: &mut Vec<Face>, face: Face) {
+        let [v1, v2, v3] = face.positions.map(|i| self.positions[i as usize]);
+        let normal = cross(sub(v2, v1), sub(v3, v1));
+        if dot(normal, normal) > 0.0 {
+            let normals = face.normals.filter(|normals| normals.iter().all(|&n| self.normals[n as usize] != [0.0; 3]));
+            faces.push(Face { normals, ..face });
+        }
+    }
+}
+
+/// One triangle of a model, as the indices of its corners' positions in its `Vertices`,
+/// and of their normals and texture coordinates if it has them.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Face {
+    pub positions: [u32; 3],
+    pub normals: Option<[u32; 3]>,
+    pub uvs: Option<[u32; 3]>
+}
+
+impl Face {
+    /// The corners of this face in `vertices`.
+    fn corners(&self, vertices: &Vertices) -> [Vector3; 3] {
+        self.positions.map(|i| point(vertices.positions[i as usize]))
+    }
+
+    /// The triangle this face makes of `vertices`.
+    pub fn triangle(&self, vertices: &Vertices) -> Triangle {
+        let [v1, v2, v3] = self.corners(vertices);
+        let triangle = match self.normals {
+            Some(normals) => Triangle::with_normals(v1, v2, v3, normals.map(|i| point(vertices.normals[i as usize]))),
+            None => Triangle::new(v1, v2, v3)
+        };
+        match self.uvs {
+            Some(uvs) => triangle.with_uvs(uvs.map(|i| vertices.uvs[i as usize])),
+            None => triangle
+        }
+    }
+}
+
+fn point([x, y, z]: [Float; 3]) -> Vector3 {
+    Vector3::new(x, y, z)
+}
+
+// Arithmetic on bare coordinates, for what is done to every face, where making a
+// `Vector3` of each step would cost more than the step.
+
+fn sub(a: [Float; 3], b: [Float; 3]) -> [Float; 3] {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+fn dot(a: [Float; 3], b: [Float; 3]) -> Float {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn cross(a: [Float; 3], b: [Float; 3]) -> [Float; 3] {
+    [a[1] * b[2] - a[2] * b[1], a[2] * b[0] - a[0] * b[2], a[0] * b[1] - a[1] * b[0]]
+}
+
+/// Triangles loaded from a model file, with the tree that makes them quick to trace.
+/// Shared between every object (and scene) that uses the same file. Only the faces are
+/// stored, each triangle being built from them when it is hit or drawn on.
+#[derive(Debug)]
+pub struct MeshData {
+    /// The corners of these faces, shared with the other parts of their model.
+    vertices: Arc<Vertices>,
+    faces: Vec<Face>,
+    tree: Tree,
+    /// The area of the triangles up to and including each, found when points are first
+    /// drawn on the mesh.
+    running_areas: OnceLock<Vec<Float>>
+}
+
+impl MeshData {
+    pub fn new(vertices: Arc<Vertices>, faces: Vec<Face>) -> Self {
+        MeshData::with_accel(vertices, faces, Accel::default())
+    }
+
+    pub fn with_accel(vertices: Arc<Vertices>, faces: Vec<Face>, accel: Accel) -> Self {
+        let boxes: Vec<_> = faces.par_iter().map(|face| {
+            let [a, b, c] = face.positions.map(|i| vertices.positions[i as usize]);
+            let corner = |pick: fn(Float, Float) -> Float| [0, 1, 2].map(|axis| pick(pick(a[axis], b[axis]), c[axis]));
+            Aabb { min: corner(Float::min), max: corner(Float::max) }
+        }).collect();
+        let tree = match accel {
+            Accel::Bvh => Tree::Bvh(Bvh::build(&boxes)),
+            Accel::KdTree => Tree::KdTree(KdTree::build(&boxes))
+        };
+        MeshData { vertices, faces, tree, running_areas: OnceLock::new() }
+    }
+
+    /// How many triangles there are.
+    pub fn len(&self) -> usize {
+        self.faces.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.faces.is_empty()
+    }
+
+    /// The corners these faces are made of, shared with the other parts of their model.
+    pub fn vertices(&self) -> &Arc<Vertices> {
+        &self.vertices
+    }
+
+    pub fn faces(&self) -> &[Face] {
+        &self.faces
+    }
+
+    /// Triangle `index`, built from its face.
+    pub fn triangle(&self, index: usize) -> Triangle {
+        self.faces[index].triangle(&self.vertices)
+    }
+
+    /// Every triangle in turn, each built as it is reached.
+    pub fn triangles(&self) -> impl Iterator<Item = Triangle> + '_ {
+        self.faces.iter().map(move |face| face.triangle(&self.vertices))
+    }
+
+    /// The box holding every triangle, or `None` if there aren't any.
+    pub fn bounds(&self) -> Option<Aabb> {
+        match &self.tree {
+            Tree::Bvh(bvh) => bvh.bounds(),
+            Tree::KdTree(tree) => tree.bounds()
+        }
+    }
+
+    /// Bytes the faces, their vertices and their tree take up, counting vertices shared
+    /// with the model's other parts in each.
+    pub fn memory(&self) -> usize {
+        let Vertices { positions, normals, uvs } = &*self.vertices;
+        let vertices = std::mem::size_of_val(&positions[..]) + std::mem::size_of_val(&normals[..]) + std::mem::size_of_val(&uvs[..]);
+        vertices + std::mem::size_of_val(&self.faces[..]) + match &self.tree {
+            Tree::Bvh(bvh) => bvh.memory(),
+            Tree::KdTree(tree) => tree.memory()
+        }
+    }
+
+    /// The area of every triangle up to and including each.
+    fn running_areas(&self) -> &[Float] {
+        self.running_areas.get_or_init(|| {
+            self.triangles()
+                .scan(0.0, |total, triangle| {
+                    *total += triangle.area();
+                    Some(*total)
+                })
+                .collect()
+        })
+    }
+
+    /// A point drawn evenly over the triangles' area for `u`, as `Shape::sample_surface`
+    /// draws one: `u.0` picks a triangle in proportion to its area and, stretched back
+    /// over the unit, the point on it with `u.1`.
+    fn sample_surface(&self, (u, v): (Float, Float)) -> Option<SurfaceSample> {
+        let areas = self.running_areas();
+        let total = *areas.last()?;
+        let target = u * total;
+        let index = areas.partition_point(|&area| area <= target).min(areas.len() - 1);
+        let before = if index == 0 { 0.0 } else { areas[index - 1] };
+        let u = ((target - before) / (areas[index] - before)).clamp(0.0, 1.0);
+        let sample = self.triangle(index).sample_surface((u, v))?;
+        Some(SurfaceSample { pdf: 1.0 / total, ..sample })
+    }
+
+    /// The nearest triangle `ray` hits and the distance to it.
+    fn closest(&self, ray: Ray) -> Option<(usize, Float)> {
+        match &self.tree {
+            Tree::Bvh(bvh) => bvh.closest_leaf(ray, |_, items| {
+                stats::record(|counters| counters.tests += items.len() as u64);
+                let mut corners = [[Vector3::new(0.0, 0.0, 0.0); 3]; LANES];
+                for (corners, &i) in corners.iter_mut().zip(items) {
+                    *corners = self.faces[i].corners(&self.vertices);
+                }
+                TrianglePacket::new(&corners[..items.len()]).closest(ray).map(|(lane, t)| (items[lane], t))
+            }),
+            Tree::KdTree(tree) => tree.closest(ray, |i| {
+                stats::record(|counters| counters.tests += 1);
+                let (t, u, v) = self.solve(i, ray);
+                // NaNs from a ray along the plane fail every test.
+                (u >= 0.0 && v >= 0.0 && u + v <= 1.0 && t > EPS).then_some(t)
+            })
+        }
+    }
+
+    /// What `Triangle::solve` finds for triangle `index` and `ray`, without building the
+    /// triangle.
+    fn solve(&self, index: usize, ray: Ray) -> (Float, Float, Float) {
+        let [v1, v2, v3] = self.faces[index].positions.map(|i| self.vertices.positions[i as usize]);
+        let (pos, dir) = ([ray.pos.x, ray.pos.y, ray.pos.z], [ray.dir.x, ray.dir.y, ray.dir.z]);
+        let (edge1, edge2) = (sub(v2, v1), sub(v3, v1));
+        let p = cross(dir, edge2);
+        let inv_det = 1.0 / dot(edge1, p);
+        let s = sub(pos, v1);
+        let q = cross(s, edge1);
+        (dot(edge2, q) * inv_det, dot(s, p) * inv_det, dot(dir, q) * inv_det)
+    }
+
+    /// Writes the triangles, with any vertex normals and texture coordinates, as a
+    /// Wavefront OBJ file. Vertices are numbered as the faces first use them, so a part
+    /// of a bigger model leaves out those only the other parts use.
+    pub fn save_obj(&self, path: &Path) -> ConfigResult<()> {
+        fn number(numbers: &mut HashMap<u32, usize>, index: u32) -> usize {
+            let next = numbers.len() + 1;
+            *numbers.entry(index).or_insert(next)
+        }
+        fn in_order(numbers: HashMap<u32, usize>) -> Vec<usize> {
+            let mut indices: Vec<_> = numbers.into_iter().collect();
+            indices.sort_by_key(|&(_, number)| number);
+            indices.into_iter().map(|(index, _)| index as usize).collect()
+        }
+
+        let (mut v, mut vt, mut vn) = (HashMap::new(), HashMap::new(), HashMap::new());
+        let mut faces = String::new();
+        for face in &self.faces {
+            faces += "f";
+            for corner in 0..3 {
+                faces += &format!(" {}", number(&mut v, face.positions[corner]));
+                faces += &match (face.uvs, face.normals) {
+                    (Some(uvs), Some(normals)) => format!("/{}/{}", number(&mut vt, uvs[corner]), number(&mut vn, normals[corner])),
+                    (Some(uvs), None) => format!("/{}", number(&mut vt, uvs[corner])),
+                    (None, Some(normals)) => format!("//{}", number(&mut vn, normals[corner])),
+                    (None, None) => String::new()
+                };
+            }
+            faces += "\n";
+        }
+
+        let mut text = String::new();
+        for i in in_order(v) {
+            let [x, y, z] = self.vertices.positions[i];
+            text += &format!("v {} {} {}\n", x, y, z);
+        }
+        for i in in_order(vt) {
+            let (u, v) = self.vertices.uvs[i];
+            text += &format!("vt {} {}\n", u, v);
+        }
+        for i in in_order(vn) {
+            let [x, y, z] = self.vertices.normals[i];
+            text += &format!("vn {} {} {}\n", x, y, z);
+        }
+        text += &faces;
+        fs::write(path, text).map_err(ConfigError::IOError)
+    }
+}
+
+/// Faces of a model that share a material, if its file gave them one.
+#[derive(Debug)]
+pub struct MeshPart {
+    pub material: Option<MtlMaterial>,
+    pub data: Arc<MeshData>
+}
+
+/// Everything loaded from one model file.
+#[derive(Debug)]
+pub struct Model {
+    pub parts: Vec<MeshPart>,
+    /// The MTL files its materials came from.
+    pub libraries: Vec<PathBuf>,
+    /// What in the file looked like a mistake, for the scenes using it to pass on.
+    pub warnings: Vec<String>
+}
+
+impl Model {
+    /// Loads a Wavefront OBJ (with the materials of any MTL files it names), PLY or STL
+    /// file, picked by its extension, fanning polygons out into triangles traced with `accel`.
+    pub fn load(path: &Path, accel: Accel) -> ConfigResult<Self> {
+        let bytes = map_file(path)?;
+        let extension = path.extension()
+            .and_then(|extension| extension.to_str())
+            .map(|extension| extension.to_ascii_lowercase());
+        let invalid = |message| ConfigError::InvalidAsset(path.to_path_buf(), message);
+        let (vertices, faces) = match extension.as_deref() {
+            Some("ply") => parse_ply(&bytes),
+            Some("stl") => parse_stl(&bytes),
+            _ => {
+                let text = std::str::from_utf8(&bytes).map_err(|_| invalid("expected a text OBJ file".to_string()))?;
+                return Model::from_obj(path, parse_obj(text).map_err(invalid)?, accel);
+            }
+        }.map_err(invalid)?;
+        let data = Arc::new(MeshData::with_accel(Arc::new(vertices), faces, accel));
+        Ok(Model { parts: vec![MeshPart { material: None, data }], libraries: vec![], warnings: vec![] })
+    }
+
+    fn from_obj(path: &Path, obj: ObjFile, accel: Accel) -> ConfigResult<Self> {
+        let dir = path.parent().unwrap_or(Path::new("."));
+        let mut materials = HashMap::new();
+        let mut libraries = vec![];
+        for library in &obj.libraries {
+            // Exporters often name a library that didn't travel with the model; its faces
+            // then keep the scene's material.
+            let library = dir.join(library);
+            if library.exists() {
+                materials.extend(load_mtl(&library)?);
+                libraries.push(library);
+            }
+        }
+        let groups: Vec<_> = obj.groups.into_iter().filter(|(_, faces)| !faces.is_empty()).collect();
+
+        let mut warnings = vec![];
+        let mut unused: Vec<&String> = materials.keys()
+            .filter(|name| !groups.iter().any(|(group, _)| group.as_ref() == Some(*name)))
+            .collect();
+        unused.sort();
+        warnings.extend(unused.into_iter().map(|name| format!("material `{}` is never used", name)));
+        // Without any libraries, every face keeps the scene's material anyway.
+        if !materials.is_empty() {
+            let missing = groups.iter().filter_map(|(name, _)| name.as_ref()).filter(|name| !materials.contains_key(*name));
+            warnings.extend(missing.map(|name| format!("material `{}` isn't defined", name)));
+        }
+
+        let vertices = Arc::new(obj.vertices);
+        let parts = groups.into_iter()
+            .map(|(name, faces)| MeshPart {
+                material: name.and_then(|name| materials.get(&name).cloned()),
+                data: Arc::new(MeshData::with_accel(vertices.clone(), faces, accel))
+            })
+            .collect();
+        Ok(Model { parts, libraries, warnings })
+    }
+}
+
+/// The bytes of the file at `path`, mapped into memory rather than read into it, so a model
+/// many gigabytes large isn't held twice while its faces are read: the pages are
+/// only read in as the parser reaches them, and can be dropped again once it's past.
+#[cfg(not(target_arch = "wasm32"))]
+fn map_file(path: &Path) -> ConfigResult<impl Deref<Target = [u8]>> {
+    let file = fs::File::open(path).map_err(ConfigError::IOError)?;
+    // Safety: the map is only read, and only while the model loads; a file changed on
+    // disk meanwhile garbles the model at worst, as it would being read half-written.
+    unsafe { memmap2::Mmap::map(&file) }.map_err(ConfigError::IOError)
+}
+
+/// The bytes of the file at `path`, where there is nothing to map files with.
+#[cfg(target_arch = "wasm32")]
+fn map_file(path: &Path) -> ConfigResult<impl Deref<Target = [u8]>> {
+    fs::read(path).map_err(ConfigError::IOError)
+}
+
+/// The faces of an OBJ file, grouped by the `usemtl` name in effect, the vertices they
+/// share, and the MTL files it names.
+struct ObjFile {
+    vertices: Vertices,
+    groups: Vec<(Option<String>, Vec<Face>)>,
+    libraries: Vec<String>
+}
+
+fn parse_obj(text: &str) -> Result<ObjFile, String> {
+    let mut obj = ObjFile { vertices: Vertices::default(), groups: vec![(None, vec![])], libraries: vec![] };
+    let vertices = &mut obj.vertices;
+    let mut group = 0;
+    for (num, line) in text.lines().enumerate() {
+        let fail = |what: &str| format!("line {}: {}", num + 1, what);
+        let mut words = line.split_whitespace();
+        match words.next() {
+            Some("v") => {
+                let coords: Vec<Float> = words.take(3)
+                    .map(|word| word.parse().map_err(|_| fail("expected a number in `v <x> <y> <z>`")))
+                    .collect::<Result<_, _>>()?;
+                match coords[..] {
+                    [x, y, z] => vertices.positions.push([x, y, z]),
+                    _ => return Err(fail("expected three coordinates in `v <x> <y> <z>`"))
+                }
+            }
+            Some("vn") => {
+                let coords: Vec<Float> = words.take(3)
+                    .map(|word| word.parse().map_err(|_| fail("expected a number in `vn <x> <y> <z>`")))
+                    .collect::<Result<_, _>>()?;
+                match coords[..] {
+                    [x, y, z] => vertices.normals.push([x, y, z]),
+                    _ => return Err(fail("expected three coordinates in `vn <x> <y> <z>`"))
+                }
+            }
+            Some("vt") => {
+                // A third coordinate, for 3D textures, is left out.
+                let coords: Vec<Float> = words.take(2)
+                    .map(|word| word.parse().map_err(|_| fail("expected a number in `vt <u> <v>`")))
+                    .collect::<Result<_, _>>()?;
+                match coords[..] {
+                    [u] => vertices.uvs.push((u, 0.0)),
+                    [u, v] => vertices.uvs.push((u, v)),
+                    _ => return Err(fail("expected a coordinate in `vt <u> <v>`"))
+                }
+            }
+            Some("f") => {
+                // Corners are `v`, `v/vt`, `v//vn` or `v/vt/vn`.
+                // An index counts from 1 at the first of a list, or back from -1 at the last.
+                fn lookup(len: usize, index: &str, what: &str, fail: &dyn Fn(&str) -> String) -> Result<u32, String> {
+                    let index: i64 = index.parse().map_err(|_| fail(&format!("expected a {} index in `f`", what)))?;
+                    let resolved = if index < 0 { len as i64 + index } else { index - 1 };
+                    u32::try_from(resolved).ok()
+                        .filter(|&i| (i as usize) < len)
+                        .ok_or_else(|| fail(&format!("{} {} does not exist", what, index)))
+                }
+                let corners = words.map(|word| {
+                    let mut indices = word.split('/');
+                    let vertex = lookup(vertices.positions.len(), indices.next().unwrap_or(""), "vertex", &fail)?;
+                    let uv = match indices.next().filter(|index| !index.is_empty()) {
+                        Some(index) => Some(lookup(vertices.uvs.len(), index, "texture coordinate", &fail)?),
+                        None => None
+                    };
+                    let normal = match indices.next().filter(|index| !index.is_empty()) {
+                        Some(index) => Some(lookup(vertices.normals.len(), index, "normal", &fail)?),
+                        None => None
+                    };
+                    Ok((vertex, uv, normal))
+                }).collect::<Result<Vec<_>, String>>()?;
+                if corners.len() < 3 {
+                    return Err(fail("expected at least three vertices in `f`"));
+                }
+                for i in 1..corners.len() - 1 {
+                    let [(v1, t1, n1), (v2, t2, n2), (v3, t3, n3)] = [corners[0], corners[i], corners[i + 1]];
+                    let normals = n1.zip(n2).zip(n3).map(|((n1, n2), n3)| [n1, n2, n3]);
+                    let uvs = t1.zip(t2).zip(t3).map(|((t1, t2), t3)| [t1, t2, t3]);
+                    vertices.push_face(&mut obj.groups[group].1, Face { positions: [v1, v2, v3], normals, uvs });
+                }
+            }
+            Some("usemtl") => {
+                let name = Some(words.collect::<Vec<_>>().join(" "));
+                group = match obj.groups.iter().position(|(group_name, _)| *group_name == name) {
+                    Some(index) => index,
+                    None => {
+                        obj.groups.push((name, vec![]));
+                        obj.groups.len() - 1
+                    }
+                };
+            }
+            Some("mtllib") => obj.libraries.extend(words.map(str::to_string)),
+            _ => ()
+        }
+    }
+    Ok(obj)
+}
+
+/// How a PLY file stores the values after its header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PlyFormat {
+    Ascii,
+    Binary { big_endian: bool }
+}
+
+/// A PLY property type, by its size in bytes and whether it is a float, signed or neither.
+#[derive(Debug, Clone, Copy)]
+struct PlyScalar {
+    size: usize,
+    float: bool,
+    signed: bool
+}
+
+impl PlyScalar {
+    fn parse(name: &str) -> Option<Self> {
+        let (size, float, signed) = match name {
+            "char" | "int8" => (1, false, true),
+            "uchar" | "uint8" => (1, false, false),
+            "short" | "int16" => (2, false, true),
+            "ushort" | "uint16" => (2, false, false),
+            "int" | "int32" => (4, false, true),
+            "uint" | "uint32" => (4, false, false),
+            "float" | "float32" => (4, true, true),
+            "double" | "float64" => (8, true, true),
+            _ => return None
+        };
+        Some(PlyScalar { size, float, signed })
+    }
+}
+
+#[derive(Debug)]
+enum PlyProperty {
+    Scalar(String, PlyScalar),
+    /// A list whose length comes first, as the first type, followed by items of the second.
+    List(String, PlyScalar, PlyScalar)
+}
+
+#[derive(Debug)]
+struct PlyElement {
+    name: String,
+    count: usize,
+    properties: Vec<PlyProperty>
+}
+
+/// Reads the values after a PLY header one at a time, whatever the format.
+struct PlyReader<'a> {
+    format: PlyFormat,
+    body: &'a [u8],
+    pos: usize
+}
+
+impl PlyReader<'_> {
+    fn read(&mut self, scalar: PlyScalar) -> Result<Float, String> {
+        match self.format {
+            PlyFormat::Ascii => {
+                let rest = &self.body[self.pos..];
+                let start = rest.iter().position(|b| !b.is_ascii_whitespace())
+                    .ok_or("the file ends before all its elements")?;
+                let len = rest[start..].iter().position(|b| b.is_ascii_whitespace()).unwrap_or(rest.len() - start);
+                self.pos += start + len;
+                std::str::from_utf8(&rest[start..start + len]).ok()
+                    .and_then(|word| word.parse().ok())
+                    .ok_or_else(|| "expected a number in the body".to_string())
+            }
+            PlyFormat::Binary { big_endian } => {
+                let bytes = self.body.get(self.pos..self.pos + scalar.size)
+                    .ok_or("the file ends before all its elements")?;
+                self.pos += scalar.size;
+                let mut buf = [0; 8];
+                if big_endian {
+                    buf[8 - scalar.size..].copy_from_slice(bytes);
+                    buf.reverse();
+                } else {
+                    buf[..scalar.size].copy_from_slice(bytes);
+                }
+                let raw = u64::from_le_bytes(buf);
+                // Shifting left then right sign-extends narrower integers.
+                let shift = 64 - 8 * scalar.size as u32;
+                Ok(match (scalar.float, scalar.signed, scalar.size) {
+                    (true, _, 4) => f32::from_bits(raw as u32) as Float,
+                    (true, _, _) => f64::from_bits(raw) as Float,
+                    (false, true, _) => (((raw << shift) as i64) >> shift) as Float,
+                    (false, false, _) => raw as Float
+                })
+            }
+        }
+    }
+}
+
+/// Parses the header of a PLY file into its format and elements, and returns them with
+/// the rest of the file.
+fn parse_ply_header(bytes: &[u8]) -> Result<(PlyFormat, Vec<PlyElement>, &[u8]), String> {
+    const END: &[u8] = b"end_header";
+    let end = bytes.windows(END.len()).position(|window| window == END)
+        .ok_or("expected an `end_header` line")?;
+    let header = std::str::from_utf8(&bytes[..end]).map_err(|_| "expected a text header")?;
+    // The body starts after the newline ending `end_header`.
+    let body_start = bytes[end..].iter().position(|&b| b == b'\n').map_or(bytes.len(), |i| end + i + 1);
+
+    let mut lines = header.lines();
+    if lines.next().map(str::trim) != Some("ply") {
+        return Err("expected `ply` on the first line".to_string());
+    }
+    let mut format = None;
+    let mut elements: Vec<PlyElement> = vec![];
+    for line in lines {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let scalar = |name: &str| PlyScalar::parse(name).ok_or_else(|| format!("unknown property type {:?}", name));
+        match words[..] {
+            ["format", "ascii", _] => format = Some(PlyFormat::Ascii),
+            ["format", "binary_little_endian", _] => format = Some(PlyFormat::Binary { big_endian: false }),
+            ["format", "binary_big_endian", _] => format = Some(PlyFormat::Binary { big_endian: true }),
+            ["format", ..] => return Err(format!("unknown format in {:?}", line)),
+            ["element", name, count] => elements.push(PlyElement {
+                name: name.to_string(),
+                count: count.parse().map_err(|_| format!("expected a count in {:?}", line))?,
+                properties: vec![]
+            }),
+            ["property", "list", count, item, name] => elements.last_mut()
+                .ok_or("expected an `element` before its properties")?
+                .properties.push(PlyProperty::List(name.to_string(), scalar(count)?, scalar(item)?)),
+            ["property", ty, name] => elements.last_mut()
+                .ok_or("expected an `element` before its properties")?
+                .properties.push(PlyProperty::Scalar(name.to_string(), scalar(ty)?)),
+            _ => ()
+        }
+    }
+    let format = format.ok_or("expected a `format` line")?;
+    Ok((format, elements, &bytes[body_start..]))
+}
+
+/// Reads the faces of an ASCII or binary PLY file, fanning polygons out into triangles,
+/// smooth-shaded if the vertices have `nx`, `ny` and `nz` normals.
+fn parse_ply(bytes: &[u8]) -> Result<(Vertices, Vec<Face>), String> {
+    let (format, elements, body) = parse_ply_header(bytes)?;
+    let mut reader = PlyReader { format, body, pos: 0 };
+    let has_normals = elements.iter()
+        .filter(|element| element.name == "vertex")
+        .flat_map(|element| &element.properties)
+        .any(|property| matches!(property, PlyProperty::Scalar(name, _) if name == "nx"));
+    let mut vertices = Vertices::default();
+    let mut faces = vec![];
+    for element in &elements {
+        for _ in 0..element.count {
+            let mut position = [0.0; 3];
+            let mut normal = [0.0; 3];
+            let mut corners = vec![];
+            for property in &element.properties {
+                match property {
+                    PlyProperty::Scalar(name, scalar) => {
+                        let value = reader.read(*scalar)?;
+                        match (element.name.as_str(), name.as_str()) {
+                            ("vertex", "x") => position[0] = value,
+                            ("vertex", "y") => position[1] = value,
+                            ("vertex", "z") => position[2] = value,
+                            ("vertex", "nx") => normal[0] = value,
+                            ("vertex", "ny") => normal[1] = value,
+                            ("vertex", "nz") => normal[2] = value,
+                            _ => ()
+                        }
+                    }
+                    PlyProperty::List(name, count, item) => {
+                        let count = reader.read(*count)? as usize;
+                        let items = (0..count).map(|_| reader.read(*item)).collect::<Result<Vec<_>, _>>()?;
+                        if element.name == "face" && (name == "vertex_indices" || name == "vertex_index") {
+                            corners = items;
+                        }
+                    }
+                }
+            }
+            match element.name.as_str() {
+                "vertex" => {
+                    vertices.positions.push(position);
+                    if has_normals {
+                        vertices.normals.push(normal);
+                    }
+                }
+                "face" => {
+                    let corners: Vec<u32> = corners.iter()
+                        .map(|&index| u32::try_from(index as usize).ok()
+                            .filter(|&i| (i as usize) < vertices.positions.len())
+                            .ok_or_else(|| format!("vertex {} does not exist", index)))
+                        .collect::<Result<_, _>>()?;
+                    for i in 1..corners.len().saturating_sub(1) {
+                        let positions = [corners[0], corners[i], corners[i + 1]];
+                        let normals = Some(positions).filter(|_| has_normals);
+                        vertices.push_face(&mut faces, Face { positions, normals, uvs: None });
+                    }
+                }
+                _ => ()
+            }
+        }
+    }
+    Ok((vertices, faces))
+}
+
+/// Reads the facets of an ASCII or binary STL file.
+fn parse_stl(bytes: &[u8]) -> Result<(Vertices, Vec<Face>), String> {
+    let mut vertices = Vertices::default();
+    let mut faces = vec![];
+    // Binary files may also start with `solid`, so their exact size is the better tell:
+    // an 80-byte header, a facet count, then 50 bytes per facet.
+    let count = bytes.get(80..84).map(|count| u32::from_le_bytes(count.try_into().unwrap()) as usize);
+    if count.is_some_and(|count| bytes.len() == 84 + 50 * count) {
+        for facet in bytes[84..].chunks_exact(50) {
+            // Each facet is a normal, three vertices and two attribute bytes; the normal is recomputed.
+            let vertex = |i: usize| {
+                let coord = |j: usize| {
+                    let start = 12 + 12 * i + 4 * j;
+                    f32::from_le_bytes(facet[start..start + 4].try_into().unwrap()) as Float
+                };
+                [coord(0), coord(1), coord(2)]
+            };
+            let positions = [vertices.add(vertex(0))?, vertices.add(vertex(1))?, vertices.add(vertex(2))?];
+            vertices.push_face(&mut faces, Face { positions, normals: None, uvs: None });
+        }
+        return Ok((vertices, faces));
+    }
+
+    let text = std::str::from_utf8(bytes).map_err(|_| "expected a binary STL file of the right size, or a text one")?;
+    let mut corners = vec![];
+    for (num, line) in text.lines().enumerate() {
+        let fail = |what: &str| format!("line {}: {}", num + 1, what);
+        let mut words = line.split_whitespace();
+        match words.next() {
+            Some("vertex") => {
+                let coords: Vec<Float> = words.take(3)
+                    .map(|word| word.parse().map_err(|_| fail("expected a number in `vertex <x> <y> <z>`")))
+                    .collect::<Result<_, _>>()?;
+                match coords[..] {
+                    [x, y, z] => corners.push(vertices.add([x, y, z])?),
+                    _ => return Err(fail("expected three coordinates in `vertex <x> <y> <z>`"))
+                }
+            }
+            Some("endloop") => {
+                if corners.len() < 3 {
+                    return Err(fail("expected at least three vertices in a facet"));
+                }
+                for i in 1..corners.len() - 1 {
+                    vertices.push_face(&mut faces, Face { positions: [corners[0], corners[i], corners[i + 1]], normals: None, uvs: None });
+                }
+                corners.clear();
+            }
+            _ => ()
+        }
+    }
+    Ok((vertices, faces))
+}
+
+/// A placed copy of a loaded mesh: moved to `offset` and uniformly scaled by `scale`.
+#[derive(Debug, Clone)]
+pub struct Mesh {
+    pub data: Arc<MeshData>,
+    /// The file holding exactly these triangles, if there is one.
+    pub file: Option<PathBuf>,
+    pub offset: Vector3,
+    pub scale: Float
+}
+
+impl Mesh {
+    fn to_local(&self, pos: Vector3) -> Vector3 {
+        (pos - self.offset).scale(1.0 / self.scale)
+    }
+}
+
+impl Shape for Mesh {
+    fn intersect(&self, ray: Ray, t_max: Float) -> Option<Hit> {
+        let local = Ray { pos: self.to_local(ray.pos), dir: ray.dir };
+        let (index, t) = self.data.closest(local).filter(|&(_, t)| t * self.scale < t_max)?;
+        // Only the nearest triangle's coordinates are needed, so the trees don't track them.
+        let (_, u, v) = self.data.solve(index, local);
+        let triangle = self.data.triangle(index);
+        Some(Hit::new(ray, t * self.scale, triangle.normal_at(u, v), triangle.uv_at(u, v)))
+    }
+
+    fn area(&self) -> Option<Float> {
+        let area = self.data.running_areas().last().copied().unwrap_or(0.0);
+        Some(area * self.scale * self.scale)
+    }
+
+    fn sample_surface(&self, u: (Float, Float)) -> Option<SurfaceSample> {
+        let sample = self.data.sample_surface(u)?;
+        let pdf = sample.pdf / (self.scale * self.scale);
+        pdf.is_finite().then(|| SurfaceSample { point: self.offset + sample.point.scale(self.scale), normal: sample.normal, pdf })
+    }
+
+    fn as_mesh(&self) -> Option<&Mesh> {
+        Some(self)
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        let bounds = self.data.bounds()?;
+        let (a, b) = (self.offset + bounds.lower().scale(self.scale), self.offset + bounds.upper().scale(self.scale));
+        Some(Aabb::new(a, b))
+    }
+}
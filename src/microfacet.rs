@@ -0,0 +1,74 @@
+use std::sync::OnceLock;
+
+use crate::linalg::{consts::PI, Float};
+use crate::trace::Color;
+
+/// Roughnesses, and cosines of the angle light leaves at, that the albedo table is worked
+/// out at, evenly spaced from 0 to 1.
+const TABLE_SIZE: usize = 32;
+/// Microfacet normals drawn across each side of the square of random numbers the table's
+/// albedos are averaged over.
+const STEPS: usize = 64;
+
+/// The share of light a white rough mirror of `roughness` sends back out along a direction
+/// at `cos` to its normal after a single bounce off its microfacets. Normals drawn as
+/// `Vector3::rand_ggx` draws them that would mirror the light into the surface lose it.
+pub fn albedo(roughness: Float, cos: Float) -> Float {
+    let table = albedo_table();
+    let at = |value: Float| {
+        let pos = value.clamp(0.0, 1.0) * (TABLE_SIZE - 1) as Float;
+        let i = (pos as usize).min(TABLE_SIZE - 2);
+        (i, pos - i as Float)
+    };
+    let ((i, s), (j, t)) = (at(roughness), at(cos));
+    let row = |i: usize| table[i * TABLE_SIZE + j] * (1.0 - t) + table[i * TABLE_SIZE + j + 1] * t;
+    row(i) * (1.0 - s) + row(i + 1) * s
+}
+
+/// What light a rough metal of `color`, out of 255, and `roughness` reflects along a
+/// direction at `cos` to its normal is scaled by, channel by channel, to make up for the
+/// light that bounces between its microfacets more than once before leaving, which a
+/// single bounce loses. This is `1 + F0 (1 - E) / E` for the metal's color `F0` and
+/// `albedo` `E`, after Turquin's "Practical multiple scattering compensation for
+/// microfacet models", so that a white metal reflects all the light reaching it however
+/// rough it is, and a colored one takes on more of its color the more it scatters.
+pub fn multiple_scattering(color: Color, roughness: Float, cos: Float) -> Color {
+    let albedo = albedo(roughness, cos);
+    if albedo <= 0.0 {
+        return Color::new(1.0, 1.0, 1.0);
+    }
+    let lost = (1.0 - albedo) / albedo / 255.0;
+    Color::new(1.0 + color.x * lost, 1.0 + color.y * lost, 1.0 + color.z * lost)
+}
+
+/// `albedo` at every roughness and cosine of the table, row by row of roughness, averaged
+/// over microfacet normals drawn evenly rather than at random.
+fn albedo_table() -> &'static [Float] {
+    static TABLE: OnceLock<Vec<Float>> = OnceLock::new();
+    TABLE.get_or_init(|| {
+        let step = |i: usize, steps: usize| (i as Float + 0.5) / steps as Float;
+        let mut table = Vec::with_capacity(TABLE_SIZE * TABLE_SIZE);
+        for i in 0..TABLE_SIZE {
+            let roughness = i as Float / (TABLE_SIZE - 1) as Float;
+            let alpha = roughness * roughness;
+            // The same normals as `Vector3::rand_ggx` draws for the same random numbers.
+            let normals: Vec<[Float; 3]> = (0..STEPS * STEPS).map(|k| {
+                let (u1, u2) = (step(k / STEPS, STEPS), step(k % STEPS, STEPS));
+                let theta = (alpha * (u1 / (1.0 - u1)).sqrt()).atan();
+                let phi = 2.0 * PI * u2;
+                [theta.sin() * phi.cos(), theta.sin() * phi.sin(), theta.cos()]
+            }).collect();
+            for j in 0..TABLE_SIZE {
+                // Light leaves along `(sin, 0, cos)` if it came in mirrored about `m`; a
+                // cosine of 0 is taken as just above, where there is still a direction.
+                let cos = (j as Float / (TABLE_SIZE - 1) as Float).max(1e-3);
+                let sin = (1.0 - cos * cos).sqrt();
+                let kept = normals.iter()
+                    .filter(|m| 2.0 * (sin * m[0] + cos * m[2]) * m[2] - cos > 0.0)
+                    .count();
+                table.push(kept as Float / normals.len() as Float);
+            }
+        }
+        table
+    })
+}
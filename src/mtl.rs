@@ -0,0 +1,127 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use crate::config::{ConfigError, ConfigResult};
+use crate::linalg::Float;
+use crate::trace::{Coat, Color, Glass, Material};
+
+/// What an MTL file gives a group of faces, before the scene's color and luminance scales.
+#[derive(Debug, Clone)]
+pub struct MtlMaterial {
+    pub color: Color,
+    pub lum: Color,
+    pub material: Material
+}
+
+/// The statements of one `newmtl` block that the tracer has a use for.
+struct Entry {
+    diffuse: [Float; 3],
+    specular: [Float; 3],
+    emission: [Float; 3],
+    /// The transmission filter, the color light takes on going through.
+    filter: Option<[Float; 3]>,
+    exponent: Float,
+    ior: Option<Float>,
+    dissolve: Float,
+    illum: u32
+}
+
+impl Default for Entry {
+    fn default() -> Self {
+        Entry {
+            diffuse: [0.8; 3],
+            specular: [0.0; 3],
+            emission: [0.0; 3],
+            filter: None,
+            exponent: 0.0,
+            ior: None,
+            dissolve: 1.0,
+            illum: 2
+        }
+    }
+}
+
+fn to_color([r, g, b]: [Float; 3]) -> Color {
+    Color::new(r, g, b).scale(255.0)
+}
+
+impl Entry {
+    fn to_material(&self) -> MtlMaterial {
+        let specular = self.specular.iter().cloned().fold(0.0, Float::max).min(1.0);
+        // The usual match between a Phong exponent and a microfacet roughness.
+        let roughness = (2.0 / (self.exponent.max(0.0) + 2.0)).sqrt();
+
+        // Illumination model 3 is a ray-traced mirror; the others are diffuse or glass,
+        // with any specular highlight becoming a coat.
+        if self.illum == 3 && specular > 0.0 {
+            return MtlMaterial {
+                color: to_color(self.specular),
+                lum: to_color(self.emission),
+                material: Material::Mirror(roughness)
+            };
+        }
+        let glass = Glass {
+            ior: self.ior.unwrap_or(Glass::default().ior),
+            tint: self.filter.map(to_color),
+            ..Glass::default()
+        };
+        let mut material = Material::Translucent((1.0 - self.dissolve).clamp(0.0, 1.0), glass);
+        if specular > 0.0 {
+            material = Material::Coated(Coat { strength: specular, roughness }, Box::new(material));
+        }
+        MtlMaterial { color: to_color(self.diffuse), lum: to_color(self.emission), material }
+    }
+}
+
+/// Loads the materials of an MTL file by name.
+pub fn load_mtl(path: &Path) -> ConfigResult<HashMap<String, MtlMaterial>> {
+    let text = fs::read_to_string(path).map_err(ConfigError::IOError)?;
+    parse_mtl(&text).map_err(|message| ConfigError::InvalidAsset(path.to_path_buf(), message))
+}
+
+fn parse_mtl(text: &str) -> Result<HashMap<String, MtlMaterial>, String> {
+    let mut entries: Vec<(String, Entry)> = vec![];
+    for (num, line) in text.lines().enumerate() {
+        let fail = |what: &str| format!("line {}: {}", num + 1, what);
+        let mut words = line.split_whitespace();
+        let keyword = match words.next() {
+            Some("newmtl") => {
+                entries.push((words.collect::<Vec<_>>().join(" "), Entry::default()));
+                continue;
+            }
+            // Texture maps and the rest have no counterpart here.
+            Some(keyword @ ("Kd" | "Ks" | "Ke" | "Tf" | "Ns" | "Ni" | "d" | "Tr" | "illum")) => keyword,
+            _ => continue
+        };
+        let entry = match entries.last_mut() {
+            Some((_, entry)) => entry,
+            None => return Err(fail("expected `newmtl <name>` before the material's statements"))
+        };
+        let nums: Vec<Float> = words
+            .map(|word| word.parse().map_err(|_| fail(&format!("expected numbers after `{}`", keyword))))
+            .collect::<Result<_, _>>()?;
+        let one = || match nums[..] {
+            [value] => Ok(value),
+            _ => Err(fail(&format!("expected one number after `{}`", keyword)))
+        };
+        // A single value stands for a gray.
+        let rgb = || match nums[..] {
+            [r, g, b] => Ok([r, g, b]),
+            [value] => Ok([value; 3]),
+            _ => Err(fail(&format!("expected `{} <r> <g> <b>`", keyword)))
+        };
+        match keyword {
+            "Kd" => entry.diffuse = rgb()?,
+            "Ks" => entry.specular = rgb()?,
+            "Ke" => entry.emission = rgb()?,
+            "Tf" => entry.filter = Some(rgb()?),
+            "Ns" => entry.exponent = one()?,
+            "Ni" => entry.ior = Some(one()?).filter(|&ior| ior > 0.0),
+            "d" => entry.dissolve = one()?,
+            "Tr" => entry.dissolve = 1.0 - one()?,
+            _ => entry.illum = one()? as u32
+        }
+    }
+    Ok(entries.into_iter().map(|(name, entry)| (name, entry.to_material())).collect())
+}
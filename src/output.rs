@@ -0,0 +1,258 @@
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+use image::{ImageBuffer, Rgb};
+
+use crate::config::{ConfigError, ConfigResult};
+use crate::dither;
+use crate::film::Film;
+use crate::linalg::Float;
+
+/// How values between two output levels are settled when there is no dithering.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rounding {
+    Truncate,
+    Nearest
+}
+
+/// Per-pixel threshold noise added before quantizing, to trade banding for fine grain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Dither {
+    None,
+    /// An 8×8 Bayer pattern.
+    Ordered,
+    /// A 64×64 void-and-cluster mask, whose grain is the least visible.
+    BlueNoise
+}
+
+impl FromStr for Rounding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "truncate" => Ok(Rounding::Truncate),
+            "nearest" => Ok(Rounding::Nearest),
+            _ => Err(format!("Expected truncate or nearest but got {:?}", s))
+        }
+    }
+}
+
+impl FromStr for Dither {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Dither::None),
+            "ordered" => Ok(Dither::Ordered),
+            "blue-noise" => Ok(Dither::BlueNoise),
+            _ => Err(format!("Expected none, ordered or blue-noise but got {:?}", s))
+        }
+    }
+}
+
+/// How float pixels become integers in the saved image.
+#[derive(Debug, Clone, Copy)]
+pub struct Quantize {
+    /// 8 or 16 bits per channel.
+    pub bits: u8,
+    pub rounding: Rounding,
+    pub dither: Dither
+}
+
+impl Default for Quantize {
+    fn default() -> Self {
+        Quantize { bits: 8, rounding: Rounding::Truncate, dither: Dither::None }
+    }
+}
+
+impl Quantize {
+    /// `value` (0 to 255 is the displayable range) as a level out of `max`, for the pixel at `(x, y)`.
+    fn level(&self, value: Float, max: Float, x: u32, y: u32, channel: u32) -> Float {
+        let value = value * max / 255.0;
+        // Offsetting each channel's tile keeps the grain from lining up into gray speckle.
+        let (x, y) = (x + 23 * channel, y + 41 * channel);
+        let level = match (self.dither, self.rounding) {
+            (Dither::Ordered, _) => (value + dither::bayer(x, y) as Float).floor(),
+            (Dither::BlueNoise, _) => (value + dither::blue_noise(x, y) as Float).floor(),
+            (Dither::None, Rounding::Truncate) => value.floor(),
+            (Dither::None, Rounding::Nearest) => value.round()
+        };
+        level.clamp(0.0, max)
+    }
+}
+
+pub fn save_image(film: &Film, scale: Float, path: &Path) -> ConfigResult<()> {
+    film.to_rgb8(scale).save(path).map_err(|err| ConfigError::ImageError(path.to_path_buf(), err))
+}
+
+/// Like `save_image`, but quantizing to the given bit depth, rounding and dithering.
+pub fn save_image_with(film: &Film, scale: Float, path: &Path, quantize: &Quantize) -> ConfigResult<()> {
+    let (width, height) = (film.width(), film.height());
+    let pixel = |x: u32, y: u32, max: Float| {
+        let curr = film.get(x, y).scale(scale);
+        let level = |value, channel| quantize.level(value, max, x, y, channel);
+        [level(curr.x, 0), level(curr.y, 1), level(curr.z, 2)]
+    };
+
+    match quantize.bits {
+        16 => ImageBuffer::from_fn(width, height, |x, y| {
+            let p = pixel(x, y, u16::MAX as Float);
+            Rgb([p[0] as u16, p[1] as u16, p[2] as u16])
+        }).save(path),
+        _ => ImageBuffer::from_fn(width, height, |x, y| {
+            let p = pixel(x, y, u8::MAX as Float);
+            Rgb([p[0] as u8, p[1] as u8, p[2] as u8])
+        }).save(path)
+    }.map_err(|err| ConfigError::ImageError(path.to_path_buf(), err))
+}
+
+/// Writes raw floats as a Portable Float Map, with one or three `channels` per pixel
+/// and rows given top to bottom.
+// Narrowing to the format's `f32` is a no-op with the `f32` feature.
+#[allow(clippy::unnecessary_cast)]
+pub fn write_pfm(path: &Path, width: u32, height: u32, channels: usize, data: &[Float]) -> ConfigResult<()> {
+    let magic = if channels == 1 { "Pf" } else { "PF" };
+    let mut bytes = format!("{}\n{} {}\n-1.0\n", magic, width, height).into_bytes();
+    // PFM stores the bottom row first.
+    for row in data.chunks(width as usize * channels).rev() {
+        for value in row {
+            bytes.extend_from_slice(&(*value as f32).to_le_bytes());
+        }
+    }
+    fs::write(path, bytes).map_err(ConfigError::IOError)
+}
+
+/// A named float channel for an EXR file, with its values row by row from the top.
+pub type Channel = (String, Vec<f32>);
+
+/// The channels of an EXR file or of one part of it.
+pub type Channels = Vec<Channel>;
+
+/// The `R`, `G` and `B` channels of `film` multiplied by `scale`, in linear values where
+/// 1 is white, named within `layer` unless it is empty.
+// Narrowing to EXR's `f32` is a no-op with the `f32` feature.
+#[allow(clippy::unnecessary_cast)]
+pub fn film_channels(film: &Film, scale: Float, layer: &str) -> Channels {
+    let pixels = film.pixels();
+    ["R", "G", "B"].iter().enumerate().map(|(channel, name)| {
+        let name = if layer.is_empty() { name.to_string() } else { format!("{}.{}", layer, name) };
+        let values = pixels.iter().map(|pixel| {
+            let value = [pixel.x, pixel.y, pixel.z][channel];
+            (value * scale / 255.0) as f32
+        }).collect();
+        (name, values)
+    }).collect()
+}
+
+/// Writes an uncompressed scanline OpenEXR file of 32-bit float `channels`, each a name
+/// and its values row by row from the top, with `attributes` added to its header as
+/// strings.
+pub fn write_exr(
+    path: &Path, width: u32, height: u32, channels: &[(String, Vec<f32>)], attributes: &[(String, String)]
+) -> ConfigResult<()> {
+    fs::write(path, exr_bytes(width, height, &[(None, channels)], attributes)).map_err(ConfigError::IOError)
+}
+
+/// Writes a multi-part OpenEXR file like `write_exr`, with a part for each of `parts`: a
+/// name and the channels in it. Every part's header gets `attributes`, since readers
+/// show what they find in the header of whichever part they are looking at.
+pub fn write_multipart_exr(
+    path: &Path, width: u32, height: u32, parts: &[(String, Channels)], attributes: &[(String, String)]
+) -> ConfigResult<()> {
+    let parts: Vec<_> = parts.iter().map(|(name, channels)| (Some(name.as_str()), channels.as_slice())).collect();
+    fs::write(path, exr_bytes(width, height, &parts, attributes)).map_err(ConfigError::IOError)
+}
+
+/// A whole EXR file. Named parts make it a multi-part one; otherwise there must be just
+/// the one part.
+fn exr_bytes(width: u32, height: u32, parts: &[(Option<&str>, &[Channel])], attributes: &[(String, String)]) -> Vec<u8> {
+    fn attribute(header: &mut Vec<u8>, name: &str, kind: &str, value: &[u8]) {
+        for text in [name, kind] {
+            header.extend_from_slice(text.as_bytes());
+            header.push(0);
+        }
+        header.extend_from_slice(&(value.len() as i32).to_le_bytes());
+        header.extend_from_slice(value);
+    }
+    let ints = |values: &[i32]| values.iter().flat_map(|value| value.to_le_bytes()).collect::<Vec<_>>();
+    let multipart = parts.iter().any(|(name, _)| name.is_some());
+
+    // Readers expect the channels sorted by name.
+    let parts: Vec<_> = parts.iter().map(|&(name, channels)| {
+        let mut channels: Vec<_> = channels.iter().collect();
+        channels.sort_by(|a, b| a.0.cmp(&b.0));
+        (name, channels)
+    }).collect();
+
+    let mut headers = vec![];
+    for (name, channels) in &parts {
+        let mut list = vec![];
+        for (name, _) in channels {
+            list.extend_from_slice(name.as_bytes());
+            list.push(0);
+            // FLOAT pixels, not perceptually linear, three reserved bytes, sampled everywhere.
+            list.extend(ints(&[2]));
+            list.extend_from_slice(&[0; 4]);
+            list.extend(ints(&[1, 1]));
+        }
+        list.push(0);
+        let window = ints(&[0, 0, width as i32 - 1, height as i32 - 1]);
+        attribute(&mut headers, "channels", "chlist", &list);
+        attribute(&mut headers, "compression", "compression", &[0]);
+        attribute(&mut headers, "dataWindow", "box2i", &window);
+        attribute(&mut headers, "displayWindow", "box2i", &window);
+        attribute(&mut headers, "lineOrder", "lineOrder", &[0]);
+        attribute(&mut headers, "pixelAspectRatio", "float", &1f32.to_le_bytes());
+        attribute(&mut headers, "screenWindowCenter", "v2f", &[0; 8]);
+        attribute(&mut headers, "screenWindowWidth", "float", &1f32.to_le_bytes());
+        if let Some(name) = name {
+            attribute(&mut headers, "name", "string", name.as_bytes());
+            attribute(&mut headers, "type", "string", b"scanlineimage");
+            attribute(&mut headers, "chunkCount", "int", &(height as i32).to_le_bytes());
+        }
+        for (name, value) in attributes {
+            attribute(&mut headers, name, "string", value.as_bytes());
+        }
+        headers.push(0);
+    }
+    // An empty header ends a multi-part file's list of them.
+    if multipart {
+        headers.push(0);
+    }
+
+    // Names past 31 bytes need the long names flag.
+    let long = parts.iter().flat_map(|(_, channels)| channels.iter().map(|(name, _)| name))
+        .chain(attributes.iter().map(|(name, _)| name))
+        .any(|name| name.len() > 31);
+    let mut bytes = vec![0x76, 0x2f, 0x31, 0x01];
+    let flags = 2u32 | if long { 0x400 } else { 0 } | if multipart { 0x1000 } else { 0 };
+    bytes.extend_from_slice(&flags.to_le_bytes());
+    bytes.extend(headers);
+
+    // An offset to every scanline of every part, then each one with its number and size
+    // ahead of it, and in a multi-part file the number of its part ahead of those.
+    let lead = if multipart { 12 } else { 8 };
+    let mut offset = bytes.len() + parts.len() * height as usize * 8;
+    for (_, channels) in &parts {
+        let line_size = channels.len() * width as usize * 4;
+        for _ in 0..height {
+            bytes.extend_from_slice(&(offset as u64).to_le_bytes());
+            offset += lead + line_size;
+        }
+    }
+    for (index, (_, channels)) in parts.iter().enumerate() {
+        let line_size = channels.len() * width as usize * 4;
+        for y in 0..height as usize {
+            if multipart {
+                bytes.extend(ints(&[index as i32]));
+            }
+            bytes.extend(ints(&[y as i32, line_size as i32]));
+            for (_, values) in channels {
+                let row = &values[y * width as usize..(y + 1) * width as usize];
+                row.iter().for_each(|value| bytes.extend_from_slice(&value.to_le_bytes()));
+            }
+        }
+    }
+    bytes
+}
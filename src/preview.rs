@@ -0,0 +1,120 @@
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use image::{DynamicImage, ImageOutputFormat};
+
+use crate::film::Film;
+use crate::linalg::Float;
+
+/// How often the page fetches the image and status again, in milliseconds.
+const REFRESH_MS: u32 = 1000;
+
+const PAGE: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>Render preview</title>
+<style>
+body { background: #222; color: #ddd; font-family: sans-serif; margin: 1em; }
+img { max-width: 100%; image-rendering: pixelated; }
+</style>
+</head>
+<body>
+<p id="status"></p>
+<img id="image" alt="Nothing rendered yet">
+<script>
+function refresh() {
+    fetch("/status").then(r => r.text()).then(text => document.getElementById("status").textContent = text);
+    document.getElementById("image").src = "/image.png?" + Date.now();
+}
+refresh();
+setInterval(refresh, REFRESH_MS);
+</script>
+</body>
+</html>
+"#;
+
+/// The latest look at a render, as served by `serve_preview`.
+#[derive(Default)]
+pub struct Preview {
+    /// The image as a PNG file, once there is one.
+    png: Mutex<Option<Arc<Vec<u8>>>>,
+    status: Mutex<String>
+}
+
+impl Preview {
+    pub fn new() -> Preview {
+        Preview::default()
+    }
+
+    /// Shows `film`, multiplied by `scale`, from now on.
+    pub fn set_image(&self, film: &Film, scale: Float) {
+        let mut png = vec![];
+        let image = DynamicImage::ImageRgb8(film.to_rgb8(scale));
+        // Writing to memory only fails for images PNG can't hold, which RGB8 ones aren't.
+        if image.write_to(&mut png, ImageOutputFormat::Png).is_ok() {
+            *self.png.lock().unwrap() = Some(Arc::new(png));
+        }
+    }
+
+    /// Shows `status` above the image from now on.
+    pub fn set_status(&self, status: impl Into<String>) {
+        *self.status.lock().unwrap() = status.into();
+    }
+}
+
+/// Answers web browsers connecting to `listener` with a page showing `preview` as it
+/// changes, from a thread of its own that lasts as long as the process.
+pub fn serve_preview(listener: TcpListener, preview: Arc<Preview>) {
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            // A browser that hangs or hangs up only loses its own request.
+            let _ = answer(stream, &preview);
+        }
+    });
+}
+
+fn answer(stream: TcpStream, preview: &Preview) -> io::Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    let mut input = BufReader::new(stream.try_clone()?);
+    let mut request = String::new();
+    input.read_line(&mut request)?;
+    // The headers say nothing we need.
+    let mut header = String::new();
+    while input.read_line(&mut header)? > 2 {
+        header.clear();
+    }
+
+    let path = match request.split_whitespace().collect::<Vec<_>>()[..] {
+        ["GET", target, _] => target.split('?').next().unwrap_or(target),
+        _ => return respond(stream, "405 Method Not Allowed", "text/plain", b"Only GET is supported\n")
+    };
+    match path {
+        "/" => respond(stream, "200 OK", "text/html; charset=utf-8", PAGE.replace("REFRESH_MS", &REFRESH_MS.to_string()).as_bytes()),
+        "/status" => {
+            let status = preview.status.lock().unwrap().clone();
+            respond(stream, "200 OK", "text/plain; charset=utf-8", status.as_bytes())
+        }
+        "/image.png" => {
+            let png = preview.png.lock().unwrap().clone();
+            match png {
+                Some(png) => respond(stream, "200 OK", "image/png", &png),
+                None => respond(stream, "404 Not Found", "text/plain", b"Nothing rendered yet\n")
+            }
+        }
+        _ => respond(stream, "404 Not Found", "text/plain", b"Not found\n")
+    }
+}
+
+fn respond(mut stream: TcpStream, status: &str, content_type: &str, body: &[u8]) -> io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nCache-Control: no-store\r\nConnection: close\r\n\r\n",
+        status, content_type, body.len()
+    )?;
+    stream.write_all(body)?;
+    stream.flush()
+}
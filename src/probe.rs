@@ -0,0 +1,60 @@
+use std::time::{Duration, Instant};
+
+use rayon::prelude::*;
+
+use crate::config::Config;
+use crate::linalg::Float;
+use crate::random::LocalRng;
+use crate::trace::sample_pixel;
+
+/// Probe every `PROBE_STRIDE`th pixel along each axis...
+const PROBE_STRIDE: u32 = 8;
+/// ...with this many samples each.
+const PROBE_SAMPLES: u32 = 32;
+
+/// How many samples per pixel a scene needs, as estimated by `suggest_spp`.
+#[derive(Debug, Clone, Copy)]
+pub struct SppSuggestion {
+    pub spp: u64,
+    /// Relative RMS error at the scene's current `num_tries`.
+    pub current_noise: Float,
+    /// Projected time to render the full image at `spp`.
+    pub render_time: Duration
+}
+
+/// Renders a sparse grid of pixels, measures how noisy their samples are, and
+/// extrapolates the samples per pixel needed for a relative RMS error of `target`.
+///
+/// The error of an `n`-sample mean falls as `1/√n`, so `n = variance / (mean² target²)`,
+/// with variance and mean² summed over the probed pixels so that dark pixels don't dominate.
+pub fn suggest_spp(config: &Config, target: Float) -> SppSuggestion {
+    let pixels: Vec<(u32, u32)> = (0..config.height).step_by(PROBE_STRIDE as usize)
+        .flat_map(|y| (0..config.width).step_by(PROBE_STRIDE as usize).map(move |x| (x, y)))
+        .collect();
+
+    let start = Instant::now();
+    let (variance, mean_sq) = pixels.par_iter().map(|&(x, y)| {
+        let mut rng = LocalRng;
+        let samples: Vec<Float> = (0..PROBE_SAMPLES).map(|_| {
+            let (color, _) = sample_pixel(config, x, y, &mut rng);
+            (color.x + color.y + color.z) / 3.0
+        }).collect();
+
+        let n = samples.len() as Float;
+        let mean = samples.iter().sum::<Float>() / n;
+        let variance = samples.iter().map(|s| (s - mean).powi(2)).sum::<Float>() / (n - 1.0);
+        (variance, mean * mean)
+    }).reduce(|| (0.0, 0.0), |(v1, m1), (v2, m2)| (v1 + v2, m1 + m2));
+    let elapsed = start.elapsed();
+
+    let relative_variance = if mean_sq > 0.0 { variance / mean_sq } else { 0.0 };
+    let spp = (relative_variance / (target * target)).ceil().max(1.0) as u64;
+
+    let per_sample = elapsed.as_secs_f64() / (pixels.len() as f64 * PROBE_SAMPLES as f64);
+    let total_samples = config.width as f64 * config.height as f64 * spp as f64;
+    SppSuggestion {
+        spp,
+        current_noise: (relative_variance / config.num_tries.max(1) as Float).sqrt(),
+        render_time: Duration::from_secs_f64(per_sample * total_samples)
+    }
+}
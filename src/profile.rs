@@ -0,0 +1,73 @@
+use std::fs;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::Instant;
+
+struct Event {
+    name: String,
+    cat: &'static str,
+    tid: usize,
+    start: f64,
+    dur: f64
+}
+
+/// Collects timed spans and writes them in Chrome's trace-event format, which
+/// about:tracing and Perfetto can load.
+pub struct Profiler {
+    enabled: bool,
+    /// When profiling started; the clock isn't touched when it's off, since some
+    /// platforms, like browsers, have none.
+    origin: Option<Instant>,
+    events: Mutex<Vec<Event>>
+}
+
+impl Profiler {
+    pub fn new(enabled: bool) -> Self {
+        Profiler { enabled, origin: enabled.then(Instant::now), events: Mutex::new(vec![]) }
+    }
+
+    /// Runs `f`, recording how long it took under `name` if profiling is enabled.
+    pub fn span<R>(&self, cat: &'static str, name: impl FnOnce() -> String, f: impl FnOnce() -> R) -> R {
+        let Some(origin) = self.origin else {
+            return f();
+        };
+
+        let start = origin.elapsed().as_secs_f64() * 1e6;
+        let result = f();
+        let dur = origin.elapsed().as_secs_f64() * 1e6 - start;
+
+        // Rayon workers get their own track; everything else shares the main one.
+        let tid = rayon::current_thread_index().map_or(0, |i| i + 1);
+        let event = Event { name: name(), cat, tid, start, dur };
+        self.events.lock().unwrap().push(event);
+        result
+    }
+
+    pub fn write(&self, path: &Path) -> std::io::Result<()> {
+        if !self.enabled {
+            return Ok(());
+        }
+
+        let events = self.events.lock().unwrap();
+        let mut tids: Vec<_> = events.iter().map(|event| event.tid).collect();
+        tids.sort_unstable();
+        tids.dedup();
+
+        let names = tids.iter().map(|&tid| {
+            let name = if tid == 0 { "main".to_string() } else { format!("worker {}", tid - 1) };
+            format!(
+                r#"{{"name":"thread_name","ph":"M","pid":1,"tid":{},"args":{{"name":"{}"}}}}"#,
+                tid, name
+            )
+        });
+        let spans = events.iter().map(|event| {
+            format!(
+                r#"{{"name":"{}","cat":"{}","ph":"X","pid":1,"tid":{},"ts":{:.3},"dur":{:.3}}}"#,
+                event.name.escape_default(), event.cat, event.tid, event.start, event.dur
+            )
+        });
+
+        let body = names.chain(spans).collect::<Vec<_>>().join(",\n");
+        fs::write(path, format!("{{\"traceEvents\":[\n{}\n]}}\n", body))
+    }
+}
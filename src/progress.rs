@@ -0,0 +1,88 @@
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Shortest time between two reports, so printing doesn't slow the render.
+const REPORT_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How far a render has got, counted in samples as its tiles finish. Shared between
+/// the threads rendering tiles.
+pub struct Progress {
+    total: u64,
+    done: AtomicU64,
+    start: Instant,
+    last_report: Mutex<Option<Instant>>
+}
+
+/// A snapshot of `Progress`, printed as percent done, time taken and left, and throughput.
+#[derive(Debug, Clone, Copy)]
+pub struct Report {
+    pub done: u64,
+    pub total: u64,
+    pub elapsed: Duration
+}
+
+impl Progress {
+    /// Starts the clock on a render of `total` samples.
+    pub fn new(total: u64) -> Progress {
+        Progress { total, done: AtomicU64::new(0), start: Instant::now(), last_report: Mutex::new(None) }
+    }
+
+    pub fn report(&self) -> Report {
+        Report { done: self.done.load(Ordering::Relaxed), total: self.total, elapsed: self.start.elapsed() }
+    }
+
+    /// Counts `samples` more as done, and gives a report if it's been a while since the
+    /// last one or the render just finished.
+    pub fn tick(&self, samples: u64) -> Option<Report> {
+        let done = self.done.fetch_add(samples, Ordering::Relaxed) + samples;
+        let mut last = self.last_report.lock().unwrap();
+        let now = Instant::now();
+        if done < self.total && last.is_some_and(|last| now - last < REPORT_INTERVAL) {
+            return None;
+        }
+        *last = Some(now);
+        Some(self.report())
+    }
+}
+
+impl Report {
+    pub fn fraction(&self) -> f64 {
+        if self.total == 0 { 1.0 } else { self.done as f64 / self.total as f64 }
+    }
+
+    pub fn samples_per_sec(&self) -> f64 {
+        self.done as f64 / self.elapsed.as_secs_f64().max(1e-9)
+    }
+
+    /// Time left at the rate so far, once there is a rate to go by.
+    pub fn remaining(&self) -> Option<Duration> {
+        if self.done == 0 {
+            return None;
+        }
+        let left = self.total.saturating_sub(self.done) as f64;
+        Some(Duration::from_secs_f64(left / self.samples_per_sec()))
+    }
+}
+
+/// Formats a count like `1.5M`.
+fn count(value: f64) -> String {
+    match value {
+        v if v >= 1e9 => format!("{:.2}G", v / 1e9),
+        v if v >= 1e6 => format!("{:.2}M", v / 1e6),
+        v if v >= 1e3 => format!("{:.1}k", v / 1e3),
+        v => format!("{:.0}", v)
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:5.1}% | {:.1?} elapsed", 100.0 * self.fraction(), self.elapsed)?;
+        match self.remaining() {
+            Some(remaining) => write!(f, " | {:.1?} left", remaining)?,
+            None => write!(f, " | ? left")?
+        }
+        write!(f, " | {} samples/s", count(self.samples_per_sec()))
+    }
+}
@@ -0,0 +1,126 @@
+use std::cell::{Cell, RefCell};
+
+use rand::rngs::StdRng;
+use rand::{Error, Rng, RngCore, SeedableRng};
+
+use crate::dither;
+use crate::linalg::Float;
+use crate::sampler;
+
+thread_local! {
+    static RNG: RefCell<StdRng> = RefCell::new(StdRng::from_entropy());
+    /// The sample the calling thread is drawing for, if the sampler shapes its draws.
+    static SAMPLE: Cell<Option<Sample>> = const { Cell::new(None) };
+}
+
+/// A sample whose draws aren't simply random, with how many numbers it has drawn.
+#[derive(Debug, Clone, Copy)]
+enum Sample {
+    /// Random draws, each shifted around by another layer of pixel `(x, y)`'s blue noise.
+    Dithered { x: u32, y: u32, draws: u32 },
+    /// Draws in pairs, each pair a point of a correlated multi-jittered pattern of its
+    /// own for sample `index` of `count`, picked by `scramble`.
+    Jittered { index: u32, count: u32, scramble: u32, draws: u32 }
+}
+
+const TWO_TO_64: f64 = 18_446_744_073_709_551_616.0;
+
+/// The calling thread's next draw, as a fraction of 2^64, from `raw` if its sample's
+/// draws are random.
+fn next_draw(raw: impl FnOnce() -> u64) -> u64 {
+    SAMPLE.with(|sample| match sample.get() {
+        None => raw(),
+        Some(Sample::Dithered { x, y, draws }) => {
+            sample.set(Some(Sample::Dithered { x, y, draws: draws + 1 }));
+            // Shifting every draw around by the same amount, wrapping, leaves it as
+            // uniform as it was.
+            raw().wrapping_add((dither::blue_noise_layer(x, y, draws) * TWO_TO_64) as u64)
+        }
+        Some(Sample::Jittered { index, count, scramble, draws }) => {
+            sample.set(Some(Sample::Jittered { index, count, scramble, draws: draws + 1 }));
+            let point = sampler::cmj(index, count, mix(scramble ^ (draws / 2 + 1).wrapping_mul(0x9e37_79b9)));
+            (point[(draws % 2) as usize] * TWO_TO_64) as u64
+        }
+    })
+}
+
+/// Murmur3's finalizer, to spread `key`'s bits over all of the result's.
+fn mix(mut key: u32) -> u32 {
+    key ^= key >> 16;
+    key = key.wrapping_mul(0x85eb_ca6b);
+    key ^= key >> 13;
+    key = key.wrapping_mul(0xc2b2_ae35);
+    key ^ key >> 16
+}
+
+/// The calling thread's generator, which everything the tracer draws comes from.
+/// Unlike `rand::thread_rng`, it can be reseeded, so a render can be repeated exactly.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LocalRng;
+
+impl RngCore for LocalRng {
+    fn next_u32(&mut self) -> u32 {
+        (next_draw(|| (RNG.with(|rng| rng.borrow_mut().next_u32()) as u64) << 32) >> 32) as u32
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        next_draw(|| RNG.with(|rng| rng.borrow_mut().next_u64()))
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        RNG.with(|rng| rng.borrow_mut().fill_bytes(dest))
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), Error> {
+        RNG.with(|rng| rng.borrow_mut().try_fill_bytes(dest))
+    }
+}
+
+/// Restarts the calling thread's generator from `seed`.
+pub fn reseed(seed: u64) {
+    RNG.with(|rng| *rng.borrow_mut() = StdRng::seed_from_u64(seed));
+}
+
+/// Restarts the calling thread's generator for row `y` of a render seeded from `seed`.
+pub fn reseed_row(seed: u64, y: u32) {
+    reseed(seed ^ (y as u64 + 1).wrapping_mul(0x9e37_79b9_7f4a_7c15));
+}
+
+/// Restarts the calling thread's generator for the part of row `y` from column `x` on,
+/// which from the first column is the whole row, as `reseed_row` has it.
+pub fn reseed_span(seed: u64, x: u32, y: u32) {
+    reseed_row(seed ^ (x as u64).wrapping_mul(0xbf58_476d_1ce4_e5b9), y);
+}
+
+/// Two numbers in `[0, 1)` meant to be used together, as a point of the unit square,
+/// which samplers that spread points evenly over the square keep together.
+pub fn gen_pair(rng: &mut impl Rng) -> (Float, Float) {
+    SAMPLE.with(|sample| {
+        if let Some(Sample::Jittered { index, count, scramble, draws }) = sample.get() {
+            sample.set(Some(Sample::Jittered { index, count, scramble, draws: draws + draws % 2 }));
+        }
+    });
+    (rng.gen(), rng.gen())
+}
+
+/// Starts the calling thread's generator on sample `index` of pixel `(x, y)` of a render
+/// drawing blue-noise dithered samples from `base`: the sample draws the same numbers as
+/// every other pixel's sample `index`, each shifted around by another layer of the
+/// pixel's blue noise.
+pub fn start_dithered(base: u64, x: u32, y: u32, index: u32) {
+    reseed(base ^ (index as u64 + 1).wrapping_mul(0xd1b5_4a32_d192_ed03));
+    SAMPLE.with(|sample| sample.set(Some(Sample::Dithered { x, y, draws: 0 })));
+}
+
+/// Starts the calling thread's generator on sample `index` of the `count` for pixel
+/// `(x, y)` of a render drawing correlated multi-jittered samples from `base`, scrambled
+/// for every pixel apart.
+pub fn start_jittered(base: u64, x: u32, y: u32, index: u32, count: u32) {
+    let scramble = mix(base as u32 ^ mix((base >> 32) as u32 ^ mix(x ^ mix(y))));
+    SAMPLE.with(|sample| sample.set(Some(Sample::Jittered { index, count, scramble, draws: 0 })));
+}
+
+/// Goes back to drawing plain random numbers on the calling thread.
+pub fn stop_sampling() {
+    SAMPLE.with(|sample| sample.set(None));
+}
@@ -0,0 +1,265 @@
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+use crate::assets::Assets;
+use crate::config::{parse_config_in, Config, Loader};
+use crate::export::scene_text;
+use crate::expr::Variables;
+use crate::film::{Film, Region};
+use crate::linalg::Float;
+use crate::profile::Profiler;
+use crate::trace::{make_tile, Color};
+
+// A coordinator opens one connection per worker and sends the magic, the version and
+// the scene as text, which the worker answers with a status. Then, as often as it likes,
+// it sends a tile as the start and end column and row and the worker answers with a
+// status and the tile's pixels, row by row, as their sums and sample counts. A status is
+// a 0 byte, or a 1 followed by what went wrong. Numbers are little-endian, and colors
+// are sent at double precision whatever either side was built with.
+
+const MAGIC: &[u8; 4] = b"RTWK";
+/// Bump whenever the messages above change, so mismatched builds refuse each other.
+const VERSION: u32 = 1;
+/// Width and height of the tiles an image is split into.
+const TILE_SIZE: u32 = 64;
+
+fn write_u32(out: &mut impl Write, value: u32) -> io::Result<()> {
+    out.write_all(&value.to_le_bytes())
+}
+
+fn write_str(out: &mut impl Write, value: &str) -> io::Result<()> {
+    out.write_all(&(value.len() as u64).to_le_bytes())?;
+    out.write_all(value.as_bytes())
+}
+
+// `Float` is already `f64` unless built with the `f32` feature.
+#[allow(clippy::unnecessary_cast)]
+fn write_float(out: &mut impl Write, value: Float) -> io::Result<()> {
+    out.write_all(&(value as f64).to_le_bytes())
+}
+
+fn read_bytes<const N: usize>(input: &mut impl Read) -> io::Result<[u8; N]> {
+    let mut bytes = [0; N];
+    input.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+fn read_u32(input: &mut impl Read) -> io::Result<u32> {
+    read_bytes(input).map(u32::from_le_bytes)
+}
+
+fn read_f64(input: &mut impl Read) -> io::Result<f64> {
+    read_bytes(input).map(f64::from_le_bytes)
+}
+
+fn read_str(input: &mut impl Read) -> io::Result<String> {
+    let len = u64::from_le_bytes(read_bytes(input)?);
+    let mut bytes = vec![];
+    input.take(len).read_to_end(&mut bytes)?;
+    if bytes.len() as u64 != len {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    String::from_utf8(bytes).map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "text isn't UTF-8"))
+}
+
+fn write_status(out: &mut impl Write, result: &Result<(), String>) -> io::Result<()> {
+    match result {
+        Ok(()) => out.write_all(&[0]),
+        Err(message) => {
+            out.write_all(&[1])?;
+            write_str(out, message)
+        }
+    }
+}
+
+fn read_status(input: &mut impl Read) -> io::Result<()> {
+    match read_bytes::<1>(input)? {
+        [0] => Ok(()),
+        _ => Err(io::Error::other(read_str(input)?))
+    }
+}
+
+/// Renders tiles for coordinators connecting to `listener`, one at a time, until the
+/// process is stopped. Meshes are loaded from the paths the coordinator's scene gives.
+/// `log` hears about every connection and what went wrong with it.
+pub fn serve(listener: &TcpListener, profiler: &Profiler, log: impl Fn(&str)) -> io::Result<()> {
+    for stream in listener.incoming() {
+        let stream = stream?;
+        let peer = stream.peer_addr().map_or("unknown".to_string(), |addr| addr.to_string());
+        log(&format!("Rendering for {}", peer));
+        match serve_one(stream, profiler) {
+            Ok(tiles) => log(&format!("Rendered {} tiles for {}", tiles, peer)),
+            Err(err) => log(&format!("Lost {}: {}", peer, err))
+        }
+    }
+    Ok(())
+}
+
+/// Answers one coordinator until it hangs up, giving how many tiles it rendered.
+fn serve_one(stream: TcpStream, profiler: &Profiler) -> io::Result<usize> {
+    let mut input = BufReader::new(stream.try_clone()?);
+    let mut out = BufWriter::new(stream);
+
+    let version = (read_bytes::<4>(&mut input)?, read_u32(&mut input)?);
+    if version != (*MAGIC, VERSION) {
+        write_status(&mut out, &Err("coordinator speaks another version".to_string()))?;
+        out.flush()?;
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "coordinator speaks another version"));
+    }
+    let raw = read_str(&mut input)?;
+    let loader = Loader { dir: Path::new("."), assets: &Assets::new() };
+    let config = match parse_config_in(&raw, &Variables::new(), &loader) {
+        Ok(config) => config,
+        Err(err) => {
+            write_status(&mut out, &Err(err.to_string()))?;
+            out.flush()?;
+            return Err(io::Error::new(io::ErrorKind::InvalidData, err.to_string()));
+        }
+    };
+    write_status(&mut out, &Ok(()))?;
+    out.flush()?;
+
+    let mut tiles = 0;
+    loop {
+        let x_start = match read_bytes::<4>(&mut input) {
+            Ok(bytes) => u32::from_le_bytes(bytes),
+            // Hanging up between tiles is how a coordinator says it's done.
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(tiles),
+            Err(err) => return Err(err)
+        };
+        let region = Region { x: x_start..read_u32(&mut input)?, y: read_u32(&mut input)?..read_u32(&mut input)? };
+        if region.x.end > config.width || region.y.end > config.height {
+            write_status(&mut out, &Err(format!("tile {:?} is outside the image", region)))?;
+            out.flush()?;
+            continue;
+        }
+        let tile = make_tile(&config, profiler, &region, |_| ());
+        write_status(&mut out, &Ok(()))?;
+        for y in 0..tile.height() {
+            for x in 0..tile.width() {
+                let sum = tile.get(x, y);
+                for channel in [sum.x, sum.y, sum.z] {
+                    write_float(&mut out, channel)?;
+                }
+                write_u32(&mut out, tile.samples(x, y))?;
+            }
+        }
+        out.flush()?;
+        tiles += 1;
+    }
+}
+
+/// A connection to one worker that has accepted the scene.
+struct Worker {
+    input: BufReader<TcpStream>,
+    out: BufWriter<TcpStream>
+}
+
+impl Worker {
+    fn connect(address: &str, scene: &str) -> io::Result<Worker> {
+        let stream = TcpStream::connect(address)?;
+        let mut worker = Worker { input: BufReader::new(stream.try_clone()?), out: BufWriter::new(stream) };
+        worker.out.write_all(MAGIC)?;
+        write_u32(&mut worker.out, VERSION)?;
+        write_str(&mut worker.out, scene)?;
+        worker.out.flush()?;
+        read_status(&mut worker.input)?;
+        Ok(worker)
+    }
+
+    fn render(&mut self, region: &Region) -> io::Result<Film> {
+        for value in [region.x.start, region.x.end, region.y.start, region.y.end] {
+            write_u32(&mut self.out, value)?;
+        }
+        self.out.flush()?;
+        read_status(&mut self.input)?;
+        let mut tile = Film::new(region.x.len() as u32, region.y.len() as u32);
+        for y in 0..tile.height() {
+            for x in 0..tile.width() {
+                let (r, g, b) = (read_f64(&mut self.input)?, read_f64(&mut self.input)?, read_f64(&mut self.input)?);
+                let samples = read_u32(&mut self.input)?;
+                tile.add_samples(x, y, Color::new(r as Float, g as Float, b as Float), samples);
+            }
+        }
+        Ok(tile)
+    }
+}
+
+/// Renders `config` on the workers listening at `addresses` (as `host:port`), split into
+/// tiles that go to whichever worker is free. A worker that fails has its tile handed to
+/// the others; only if all of them fail is the render given up. `progress` hears about
+/// the samples of every tile as it comes back.
+pub fn render_remote(config: &Config, addresses: &[String], progress: impl Fn(u64) + Sync) -> io::Result<Film> {
+    let scene = scene_text(config)
+        .ok_or_else(|| io::Error::other("meshes without a file of their own can't be sent to workers"))?;
+
+    let mut tiles = vec![];
+    for y in (0..config.height).step_by(TILE_SIZE as usize) {
+        for x in (0..config.width).step_by(TILE_SIZE as usize) {
+            let x_end = (x + TILE_SIZE).min(config.width);
+            let y_end = (y + TILE_SIZE).min(config.height);
+            tiles.push(Region { x: x..x_end, y: y..y_end });
+        }
+    }
+    // Taken from the back, so the image fills in from the top.
+    tiles.reverse();
+
+    let queue = Mutex::new(tiles);
+    let in_flight = AtomicUsize::new(0);
+    let film = Mutex::new(Film::new(config.width, config.height));
+    let errors = Mutex::new(vec![]);
+    thread::scope(|scope| {
+        for address in addresses {
+            let (queue, in_flight, film, errors, progress, scene) = (&queue, &in_flight, &film, &errors, &progress, &scene);
+            scope.spawn(move || {
+                let mut worker = match Worker::connect(address, scene) {
+                    Ok(worker) => worker,
+                    Err(err) => return errors.lock().unwrap().push(format!("{}: {}", address, err))
+                };
+                loop {
+                    let region = {
+                        let mut queue = queue.lock().unwrap();
+                        let region = queue.pop();
+                        if region.is_some() {
+                            in_flight.fetch_add(1, Ordering::SeqCst);
+                        }
+                        region
+                    };
+                    let region = match region {
+                        Some(region) => region,
+                        // A tile still out may yet come back for someone else to take.
+                        None if in_flight.load(Ordering::SeqCst) > 0 => {
+                            thread::sleep(Duration::from_millis(10));
+                            continue;
+                        }
+                        None => return
+                    };
+                    match worker.render(&region) {
+                        Ok(tile) => {
+                            film.lock().unwrap().add_film_at(&tile, region.x.start, region.y.start);
+                            in_flight.fetch_sub(1, Ordering::SeqCst);
+                            progress(region.pixels() * config.num_tries as u64);
+                        }
+                        Err(err) => {
+                            queue.lock().unwrap().push(region);
+                            in_flight.fetch_sub(1, Ordering::SeqCst);
+                            return errors.lock().unwrap().push(format!("{}: {}", address, err));
+                        }
+                    }
+                }
+            });
+        }
+    });
+
+    if !queue.into_inner().unwrap().is_empty() {
+        let errors = errors.into_inner().unwrap();
+        let reason = if errors.is_empty() { "no workers given".to_string() } else { errors.join("; ") };
+        return Err(io::Error::other(format!("every worker failed: {}", reason)));
+    }
+    Ok(film.into_inner().unwrap())
+}
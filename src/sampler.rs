@@ -0,0 +1,127 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::random;
+
+/// Where the numbers a pixel's samples draw come from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Sampler {
+    /// Independent random numbers for every pixel, whose noise clumps at low sample counts.
+    #[default]
+    Random,
+    /// The same numbers for every pixel, each pixel's shifted by a blue-noise mask, after
+    /// Georgiev and Fajardo: neighboring pixels' errors differ as much as they can, so
+    /// the noise left is fine-grained and even, and blurs away at a glance.
+    BlueNoise,
+    /// Every pair of numbers a pixel's samples draw together spread evenly over the unit
+    /// square, after Kensler's correlated multi-jittering, and shuffled apart from other
+    /// pairs and other pixels': the pixel's area, the lens and lights are each covered
+    /// evenly, for less noise at the same sample count.
+    Cmj
+}
+
+impl FromStr for Sampler {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "random" => Ok(Sampler::Random),
+            "bluenoise" => Ok(Sampler::BlueNoise),
+            "cmj" => Ok(Sampler::Cmj),
+            _ => Err(format!("Expected random, bluenoise or cmj but got {:?}", s))
+        }
+    }
+}
+
+impl fmt::Display for Sampler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Sampler::Random => "random",
+            Sampler::BlueNoise => "bluenoise",
+            Sampler::Cmj => "cmj"
+        })
+    }
+}
+
+impl Sampler {
+    /// Gets the calling thread's generator ready for sample `index` of the `count` for
+    /// pixel `(x, y)` of a render whose samples all draw from `base`.
+    pub(crate) fn start_sample(self, base: u64, x: u32, y: u32, index: u32, count: u32) {
+        match self {
+            Sampler::Random => (),
+            Sampler::BlueNoise => random::start_dithered(base, x, y, index),
+            Sampler::Cmj => random::start_jittered(base, x, y, index, count)
+        }
+    }
+
+    /// Leaves the calling thread's generator drawing as it usually does, after a row.
+    pub(crate) fn finish(self) {
+        if self != Sampler::Random {
+            random::stop_sampling();
+        }
+    }
+}
+
+/// Point `index` of `count` of Kensler's correlated multi-jittered pattern `pattern`:
+/// jittered in a grid's cells with one in every row and column, shuffled the same way
+/// in every row and column, so that the points spread evenly over the square and along
+/// each side of it.
+pub(crate) fn cmj(index: u32, count: u32, pattern: u32) -> [f64; 2] {
+    let m = ((count as f64).sqrt() as u32).max(1);
+    let n = count.div_ceil(m);
+    let s = permute(index, count, pattern.wrapping_mul(0x5163_3e2d));
+    let sx = permute(s % m, m, pattern.wrapping_mul(0x68bc_21eb));
+    let sy = permute(s / m, n, pattern.wrapping_mul(0x02e5_be93));
+    let jx = jitter(s, pattern.wrapping_mul(0x967a_889b));
+    let jy = jitter(s, pattern.wrapping_mul(0x368c_c8b7));
+    [(sx as f64 + (sy as f64 + jx) / n as f64) / m as f64, (s as f64 + jy) / count as f64]
+}
+
+/// Where `pattern` shuffles `index` to among `0..len`.
+fn permute(mut index: u32, len: u32, pattern: u32) -> u32 {
+    let mut mask = len - 1;
+    mask |= mask >> 1;
+    mask |= mask >> 2;
+    mask |= mask >> 4;
+    mask |= mask >> 8;
+    mask |= mask >> 16;
+    // Shuffling the next power of two up until `index` lands in range.
+    loop {
+        index ^= pattern;
+        index = index.wrapping_mul(0xe170_893d);
+        index ^= pattern >> 16;
+        index ^= (index & mask) >> 4;
+        index ^= pattern >> 8;
+        index = index.wrapping_mul(0x0929_eb3f);
+        index ^= pattern >> 23;
+        index ^= (index & mask) >> 1;
+        index = index.wrapping_mul(1 | pattern >> 27);
+        index = index.wrapping_mul(0x6935_fa69);
+        index ^= (index & mask) >> 11;
+        index = index.wrapping_mul(0x74dc_b303);
+        index ^= (index & mask) >> 2;
+        index = index.wrapping_mul(0x9e50_1cc3);
+        index ^= (index & mask) >> 2;
+        index = index.wrapping_mul(0xc860_a3df);
+        index &= mask;
+        index ^= index >> 5;
+        if index < len {
+            return index.wrapping_add(pattern) % len;
+        }
+    }
+}
+
+/// Where in its cell `pattern` puts point `index`, in `[0, 1)`.
+fn jitter(mut index: u32, pattern: u32) -> f64 {
+    index ^= pattern;
+    index ^= index >> 17;
+    index ^= index >> 10;
+    index = index.wrapping_mul(0xb365_34e5);
+    index ^= index >> 12;
+    index ^= index >> 21;
+    index = index.wrapping_mul(0x93fc_4795);
+    index ^= 0xdf6e_307f;
+    index ^= index >> 17;
+    index = index.wrapping_mul(1 | pattern >> 18);
+    index as f64 / 4_294_967_296.0
+}
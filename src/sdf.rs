@@ -0,0 +1,296 @@
+use std::fmt;
+
+use crate::linalg::{consts::PI, Aabb, Float, Vector3};
+use crate::shapes::{Hit, Ray, Shape, EPS};
+
+/// Steps a ray takes through a distance field before it is taken to have missed.
+const MAX_STEPS: usize = 512;
+/// How near the surface a step has to land to count as a hit.
+const HIT_DISTANCE: Float = 1e-4;
+/// How far a ray leaving the surface has to get from it before it can hit it again.
+const CLEAR_DISTANCE: Float = 2e-3;
+/// Segments per turn of a torus knot's curve, of which its tube is the distance field.
+const KNOT_SEGMENTS: u32 = 64;
+
+/// One of the shapes a distance field is blended from.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Part {
+    Sphere { center: Vector3, radius: Float },
+    /// A box `half` as big as it is along each axis, its edges rounded off by `radius`.
+    RoundedBox { center: Vector3, half: Vector3, radius: Float },
+    /// A ring around the z axis, `major` out to the middle of a tube `minor` thick.
+    Torus { center: Vector3, major: Float, minor: Float },
+    /// A tube `tube` thick along the (`p`, `q`) torus knot, reaching `radius` from the
+    /// z axis, held as the line through points along it.
+    Knot { center: Vector3, radius: Float, tube: Float, p: u32, q: u32, curve: Vec<Vector3> },
+    /// The Mandelbulb of `power`, worked out to `iterations`, scaled up `size` times so
+    /// that it reaches about `size` from its center.
+    Mandelbulb { center: Vector3, size: Float, power: Float, iterations: u32 },
+    /// A cube `size` out from its center to each face, with the middle of every face and
+    /// the cube cut out of it and each of the 20 cubes left, `iterations` times over.
+    Menger { center: Vector3, size: Float, iterations: u32 }
+}
+
+impl Part {
+    /// A (`p`, `q`) torus knot, winding `p` times around the z axis and `q` times
+    /// through the ring.
+    pub fn knot(center: Vector3, radius: Float, tube: Float, p: u32, q: u32) -> Part {
+        let segments = KNOT_SEGMENTS * p.max(q);
+        let curve = (0..=segments).map(|i| {
+            let phi = 2.0 * PI * i as Float / segments as Float;
+            let r = (q as Float * phi).cos() + 2.0;
+            Vector3::new(r * (p as Float * phi).cos(), r * (p as Float * phi).sin(), -(q as Float * phi).sin())
+                .scale(radius / 3.0)
+        }).collect();
+        Part::Knot { center, radius, tube, p, q, curve }
+    }
+
+    /// How far `point` is from the surface, negative inside.
+    fn distance(&self, point: Vector3) -> Float {
+        match self {
+            Part::Sphere { center, radius } => (point - *center).size() - radius,
+            Part::RoundedBox { center, half, radius } => {
+                let p = point - *center;
+                let q = Vector3::new(p.x.abs() - half.x, p.y.abs() - half.y, p.z.abs() - half.z);
+                let outside = Vector3::new(q.x.max(0.0), q.y.max(0.0), q.z.max(0.0)).size();
+                outside + q.x.max(q.y).max(q.z).min(0.0) - radius
+            }
+            Part::Torus { center, major, minor } => {
+                let p = point - *center;
+                let ring = (p.x.powi(2) + p.y.powi(2)).sqrt() - major;
+                (ring.powi(2) + p.z.powi(2)).sqrt() - minor
+            }
+            Part::Knot { center, radius, tube, curve, .. } => {
+                let p = point - *center;
+                // Far from the knot, the distance to a sphere around it is a quicker underestimate.
+                let around = p.size() - radius - tube;
+                if around > radius / 2.0 {
+                    return around;
+                }
+                let nearest = curve.windows(2).map(|segment| {
+                    let (a, b) = (segment[0], segment[1]);
+                    let along = ((p - a).dot(b - a) / (b - a).dot(b - a)).clamp(0.0, 1.0);
+                    (p - a - (b - a).scale(along)).size()
+                }).fold(Float::INFINITY, Float::min);
+                nearest - tube
+            }
+            Part::Mandelbulb { center, size, power, iterations } => {
+                let c = (point - *center).scale(1.0 / size);
+                // Points that get 2 away from the origin go on getting further for good.
+                let (mut z, mut dr, mut r) = (c, 1.0, c.size());
+                for _ in 0..*iterations {
+                    if r > 2.0 {
+                        break;
+                    }
+                    let theta = (z.z / r).acos() * power;
+                    let phi = z.y.atan2(z.x) * power;
+                    dr = r.powf(power - 1.0) * power * dr + 1.0;
+                    z = Vector3::new(theta.sin() * phi.cos(), theta.sin() * phi.sin(), theta.cos())
+                        .scale(r.powf(*power)) + c;
+                    r = z.size();
+                }
+                // How fast the point escapes gives how far it is from the points that don't.
+                if r <= 1.0 || dr <= 0.0 { 0.0 } else { 0.5 * r.ln() * r / dr * size }
+            }
+            Part::Menger { center, size, iterations } => {
+                let p = (point - *center).scale(1.0 / size);
+                let q = Vector3::new(p.x.abs() - 1.0, p.y.abs() - 1.0, p.z.abs() - 1.0);
+                let mut d = Vector3::new(q.x.max(0.0), q.y.max(0.0), q.z.max(0.0)).size() + q.x.max(q.y).max(q.z).min(0.0);
+                let mut s: Float = 1.0;
+                for _ in 0..*iterations {
+                    // Where the point falls in the tiling of crosses cut out at this scale.
+                    let r = |x: Float| (1.0 - 3.0 * ((x * s).rem_euclid(2.0) - 1.0).abs()).abs();
+                    let (rx, ry, rz) = (r(p.x), r(p.y), r(p.z));
+                    s *= 3.0;
+                    let cross = (rx.max(ry).min(ry.max(rz)).min(rz.max(rx)) - 1.0) / s;
+                    d = d.max(cross);
+                }
+                d * size
+            }
+        }
+    }
+
+    /// The middle and radius of a sphere holding the part.
+    fn bounds(&self) -> (Vector3, Float) {
+        match self {
+            Part::Sphere { center, radius } => (*center, *radius),
+            Part::RoundedBox { center, half, radius } => (*center, half.size() + radius),
+            Part::Torus { center, major, minor } => (*center, major + minor),
+            // The curve reaches a third of `radius` further than `radius` at most.
+            Part::Knot { center, radius, tube, .. } => (*center, radius + tube),
+            Part::Mandelbulb { center, size, .. } => (*center, 2.0 * size),
+            Part::Menger { center, size, .. } => (*center, size * (3.0 as Float).sqrt())
+        }
+    }
+
+    /// The number standing for the part in `params`, and the numbers that rebuild it.
+    fn params(&self) -> (Float, Vec<Float>) {
+        match self {
+            Part::Sphere { center: c, radius } => (0.0, vec![c.x, c.y, c.z, *radius]),
+            Part::RoundedBox { center: c, half: h, radius } => (1.0, vec![c.x, c.y, c.z, h.x, h.y, h.z, *radius]),
+            Part::Torus { center: c, major, minor } => (2.0, vec![c.x, c.y, c.z, *major, *minor]),
+            Part::Knot { center: c, radius, tube, p, q, .. } =>
+                (3.0, vec![c.x, c.y, c.z, *radius, *tube, *p as Float, *q as Float]),
+            Part::Mandelbulb { center: c, size, power, iterations } =>
+                (4.0, vec![c.x, c.y, c.z, *size, *power, *iterations as Float]),
+            Part::Menger { center: c, size, iterations } => (5.0, vec![c.x, c.y, c.z, *size, *iterations as Float])
+        }
+    }
+}
+
+impl fmt::Display for Part {
+    /// The part in the scene format.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Part::Sphere { .. } => "sphere",
+            Part::RoundedBox { .. } => "box",
+            Part::Torus { .. } => "torus",
+            Part::Knot { .. } => "knot",
+            Part::Mandelbulb { .. } => "mandelbulb",
+            Part::Menger { .. } => "menger"
+        };
+        let params: Vec<_> = self.params().1.iter().map(Float::to_string).collect();
+        write!(f, "{} {}", name, params.join(" "))
+    }
+}
+
+/// Smallest of `a` and `b`, rounded off where they are within `k` of each other.
+fn smooth_min(a: Float, b: Float, k: Float) -> Float {
+    if k <= 0.0 {
+        return a.min(b);
+    }
+    let h = (k - (a - b).abs()).max(0.0) / k;
+    a.min(b) - h * h * k / 4.0
+}
+
+/// A shape given by how far points are from it, traced by stepping along rays that far
+/// at a time, so that anything a distance can be worked out for can be drawn: the parts
+/// here, melted into each other where they are within `blend`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sdf {
+    pub parts: Vec<Part>,
+    pub blend: Float,
+    /// The middle and radius of a sphere holding every part, outside of which rays are
+    /// not stepped along.
+    bounds: (Vector3, Float)
+}
+
+impl Sdf {
+    /// Blends `parts`, of which there must be at least one.
+    pub fn new(parts: Vec<Part>, blend: Float) -> Sdf {
+        let boxes: Vec<_> = parts.iter().map(Part::bounds).collect();
+        let mut min = Vector3::new(Float::INFINITY, Float::INFINITY, Float::INFINITY);
+        let mut max = -min;
+        for &(center, radius) in &boxes {
+            min = Vector3::new(min.x.min(center.x - radius), min.y.min(center.y - radius), min.z.min(center.z - radius));
+            max = Vector3::new(max.x.max(center.x + radius), max.y.max(center.y + radius), max.z.max(center.z + radius));
+        }
+        let center = (min + max).scale(0.5);
+        // Blending only ever adds a quarter of `blend` to the shape.
+        let radius = boxes.iter().map(|&(c, r)| (c - center).size() + r).fold(0.0, Float::max) + blend / 4.0;
+        Sdf { parts, blend, bounds: (center, radius) }
+    }
+
+    /// Rebuilds a field from the numbers of its `params`.
+    pub fn from_params(params: &[Float]) -> Option<Sdf> {
+        let (&blend, mut rest) = params.split_first()?;
+        let mut parts = vec![];
+        while let Some((&kind, args)) = rest.split_first() {
+            let v = |i: usize| Vector3::new(args[i], args[i + 1], args[i + 2]);
+            let (part, len) = match (kind as u32, args.len()) {
+                (0, 4..) => (Part::Sphere { center: v(0), radius: args[3] }, 4),
+                (1, 7..) => (Part::RoundedBox { center: v(0), half: v(3), radius: args[6] }, 7),
+                (2, 5..) => (Part::Torus { center: v(0), major: args[3], minor: args[4] }, 5),
+                (3, 7..) => (Part::knot(v(0), args[3], args[4], args[5] as u32, args[6] as u32), 7),
+                (4, 6..) => (Part::Mandelbulb { center: v(0), size: args[3], power: args[4], iterations: args[5] as u32 }, 6),
+                (5, 5..) => (Part::Menger { center: v(0), size: args[3], iterations: args[4] as u32 }, 5),
+                _ => return None
+            };
+            parts.push(part);
+            rest = &args[len..];
+        }
+        (!parts.is_empty()).then(|| Sdf::new(parts, blend))
+    }
+
+    /// The middle and radius of a sphere holding the whole shape.
+    pub fn bounding_sphere(&self) -> (Vector3, Float) {
+        self.bounds
+    }
+
+    /// How far `point` is from the surface, or at least no farther, negative inside.
+    pub fn distance(&self, point: Vector3) -> Float {
+        self.parts.iter()
+            .map(|part| part.distance(point))
+            .reduce(|a, b| smooth_min(a, b, self.blend))
+            .unwrap_or(Float::INFINITY)
+    }
+
+    /// Which way the distance grows fastest at `point`, the normal on the surface.
+    fn gradient(&self, point: Vector3) -> Vector3 {
+        let h = HIT_DISTANCE;
+        let axis = |dx: Float, dy: Float, dz: Float| {
+            self.distance(point.shift(dx, dy, dz)) - self.distance(point.shift(-dx, -dy, -dz))
+        };
+        Vector3::new(axis(h, 0.0, 0.0), axis(0.0, h, 0.0), axis(0.0, 0.0, h))
+    }
+}
+
+impl fmt::Display for Sdf {
+    /// The field in the scene format, after `sdf`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.blend)?;
+        self.parts.iter().try_for_each(|part| write!(f, " {}", part))
+    }
+}
+
+impl Shape for Sdf {
+    fn intersect(&self, ray: Ray, t_max: Float) -> Option<Hit> {
+        // Only the stretch of the ray inside the bounding sphere is stepped along.
+        let (center, radius) = self.bounds;
+        let offset = ray.pos - center;
+        let b = ray.dir.dot(offset);
+        let disc = b * b - (offset.dot(offset) - radius * radius);
+        if disc < 0.0 {
+            return None;
+        }
+        let (enter, exit) = (-b - disc.sqrt(), -b + disc.sqrt());
+        let end = exit.min(t_max);
+        let mut t = enter.max(0.0);
+        // A ray leaving the surface starts on it, so it can only hit once it gets clear.
+        let mut clear = self.distance(ray.get_point(t)).abs() > CLEAR_DISTANCE;
+        for _ in 0..MAX_STEPS {
+            if t > end {
+                return None;
+            }
+            let distance = self.distance(ray.get_point(t)).abs();
+            if !clear {
+                clear = distance > CLEAR_DISTANCE;
+            } else if distance < HIT_DISTANCE {
+                if t <= EPS {
+                    return None;
+                }
+                let gradient = self.gradient(ray.get_point(t));
+                let normal = if gradient.size() > 0.0 { gradient.normalize() } else { -ray.dir };
+                return Some(Hit::new(ray, t, normal, (0.0, 0.0)));
+            }
+            t += distance.max(HIT_DISTANCE);
+        }
+        None
+    }
+
+    fn params(&self) -> Option<(&'static str, Vec<Float>)> {
+        let mut params = vec![self.blend];
+        for part in &self.parts {
+            let (kind, args) = part.params();
+            params.push(kind);
+            params.extend(args);
+        }
+        Some(("sdf", params))
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        let (center, radius) = self.bounds;
+        let reach = Vector3::new(radius, radius, radius);
+        Some(Aabb::new(center - reach, center + reach))
+    }
+}
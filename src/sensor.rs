@@ -0,0 +1,37 @@
+use crate::aov::{make_aovs, Aov};
+use crate::config::Config;
+use crate::film::Film;
+use crate::profile::Profiler;
+use crate::trace::{make_image, Color};
+
+/// Everything the renderer knows about one pixel once it is finished.
+#[derive(Debug, Clone, Copy)]
+pub struct SensorPixel {
+    pub x: u32,
+    pub y: u32,
+    /// The pixel as `make_image` returns it: linear, summed over `num_tries`
+    /// samples, and already converted to RGB in spectral mode.
+    pub color: Color,
+    pub aov: Aov
+}
+
+/// Renders `config` and hands every pixel to `sensor` in row-major order,
+/// before anything is scaled or quantized, so embedders can model their own
+/// sensors (event cameras, depth sensors, ...) on top of the normal render.
+/// The image is returned as well, ready for `save_image`.
+pub fn render_with_sensor(
+    config: &Config,
+    profiler: &Profiler,
+    mut sensor: impl FnMut(&SensorPixel)
+) -> Film {
+    let image = make_image(config, profiler);
+    let aovs = profiler.span("stage", || "aovs".to_string(), || make_aovs(config));
+
+    for (y, aovs) in aovs.iter().enumerate() {
+        for (x, &aov) in aovs.iter().enumerate() {
+            let (x, y) = (x as u32, y as u32);
+            sensor(&SensorPixel { x, y, color: image.get(x, y), aov });
+        }
+    }
+    image
+}
@@ -1,6 +1,9 @@
-use crate::linalg::Vector3;
+use crate::heightfield::Heightfield;
+use crate::linalg::{consts::PI, Aabb, Float, Vector3};
+use crate::mesh::Mesh;
+use crate::transform::Transformed;
 
-const EPS: f64 = 0.0001;
+pub(crate) const EPS: Float = 0.0001;
 
 #[derive(Debug, Copy, Clone)]
 pub struct Ray {
@@ -12,22 +15,100 @@ impl Ray {
         Ray { pos, dir: dir.normalize() }
     }
 
-    pub fn shift(&self, dx: f64, dy: f64, dz: f64) -> Self {
+    pub fn shift(&self, dx: Float, dy: Float, dz: Float) -> Self {
         Ray { pos: self.pos.shift(dx, dy, dz), dir: self.dir }
     }
     
-    pub fn turn(&self, dtheta: f64, dphi: f64) -> Self {
+    pub fn turn(&self, dtheta: Float, dphi: Float) -> Self {
         Ray { pos: self.pos, dir: self.dir.turn(dtheta, dphi) }
     }
 
-    pub fn get_point(&self, t: f64) -> Vector3 {
+    pub fn get_point(&self, t: Float) -> Vector3 {
         self.pos + self.dir.scale(t)
     }
 }
 
-pub trait Shape {
-    fn intersect(&self, ray: Ray) -> Option<f64>;
-    fn normal(&self, pos: Vector3) -> Vector3;
+/// Where a ray meets a shape.
+#[derive(Debug, Copy, Clone)]
+pub struct Hit {
+    /// Distance along the ray.
+    pub t: Float,
+    pub point: Vector3,
+    /// The outward unit normal, blended from vertex normals on smooth triangles.
+    pub normal: Vector3,
+    /// Coordinates on the surface: the texture coordinates of triangles that have them
+    /// and barycentric ones on the rest, longitude and latitude
+    /// (from 0 to 1) on spheres and ellipsoids, the angle around and share of the way
+    /// along on capsules, distances along two axes on planes, shares of the way across on
+    /// heightfields and none on distance fields.
+    pub uv: (Float, Float),
+    /// Whether the ray came from outside, against `normal`.
+    pub front_face: bool
+}
+
+/// A point drawn on a shape's surface by `Shape::sample_surface`.
+#[derive(Debug, Clone, Copy)]
+pub struct SurfaceSample {
+    pub point: Vector3,
+    /// The outward unit normal there.
+    pub normal: Vector3,
+    /// The density the point was drawn with, per unit of area.
+    pub pdf: Float
+}
+
+impl Hit {
+    pub fn new(ray: Ray, t: Float, normal: Vector3, uv: (Float, Float)) -> Hit {
+        Hit { t, point: ray.get_point(t), normal, uv, front_face: ray.dir.dot(normal) < 0.0 }
+    }
+}
+
+/// Something rays can hit. Shapes are shared between the threads rendering a scene, so
+/// they must be safe to send and share.
+pub trait Shape: Send + Sync {
+    /// The nearest place along `ray` where it meets the shape, if that's past `EPS` and
+    /// before `t_max`; nearer hits are all that callers want, so farther ones aren't worked out.
+    fn intersect(&self, ray: Ray, t_max: Float) -> Option<Hit>;
+
+    /// The shape's name and the numbers that rebuild it, for the scene cache.
+    /// Shapes that return `None` keep their scenes from being cached.
+    fn params(&self) -> Option<(&'static str, Vec<Float>)> {
+        None
+    }
+
+    /// How much surface the shape has, or `None` if it reaches without end or there's no
+    /// telling.
+    fn area(&self) -> Option<Float> {
+        None
+    }
+
+    /// A box holding the whole shape, or `None` if it reaches without end or there's no
+    /// telling.
+    fn bounds(&self) -> Option<Aabb> {
+        None
+    }
+
+    /// A point on the surface for `u`, two numbers from 0 to 1, spread evenly over its
+    /// area as `u` is over the unit square, so that glowing shapes can be aimed at; `None`
+    /// for shapes that can't be sampled that way, like planes, which reach without end.
+    /// Rectangles and other flat lights are meshes or triangles, which can be.
+    fn sample_surface(&self, _u: (Float, Float)) -> Option<SurfaceSample> {
+        None
+    }
+
+    /// The shape itself if it is a mesh, whose triangles can't be given as `params`.
+    fn as_mesh(&self) -> Option<&Mesh> {
+        None
+    }
+
+    /// The shape itself if it is a heightfield, whose heights aren't given as `params`.
+    fn as_heightfield(&self) -> Option<&Heightfield> {
+        None
+    }
+
+    /// The shape itself if it is another placed by a transform, which has no `params`.
+    fn as_transformed(&self) -> Option<&Transformed> {
+        None
+    }
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -36,7 +117,7 @@ pub struct Plane {
 }
 
 impl Plane {
-    fn new(point: Vector3, norm: Vector3) -> Plane {
+    pub fn new(point: Vector3, norm: Vector3) -> Plane {
         Plane {
             point,
             norm: norm.normalize()
@@ -45,27 +126,31 @@ impl Plane {
 }
 
 impl Shape for Plane {
-    fn intersect(&self, ray: Ray) -> Option<f64> {
+    fn intersect(&self, ray: Ray, t_max: Float) -> Option<Hit> {
         let t = self.norm.dot(self.point - ray.pos) / self.norm.dot(ray.dir);
-        if t > EPS {
-            Some(t)
+        if t > EPS && t < t_max {
+            let (axis_u, axis_v) = self.norm.ons();
+            let offset = ray.get_point(t) - self.point;
+            Some(Hit::new(ray, t, self.norm, (offset.dot(axis_u), offset.dot(axis_v))))
         } else {
             None
         }
     }
 
-    fn normal(&self, _pos: Vector3) -> Vector3 {
-        self.norm
+    fn params(&self) -> Option<(&'static str, Vec<Float>)> {
+        let (p, n) = (self.point, self.norm);
+        Some(("plane", vec![p.x, p.y, p.z, n.x, n.y, n.z]))
     }
 }
 
 #[derive(Debug, Copy, Clone)]
 pub struct Sphere {
-    pub center: Vector3, pub radius: f64
+    pub center: Vector3, pub radius: Float
 }
 
-impl Shape for Sphere {
-    fn intersect(&self, ray: Ray) -> Option<f64> {
+impl Sphere {
+    /// The nearer distance at which `ray` crosses the sphere, past `EPS`.
+    fn distance(&self, ray: Ray) -> Option<Float> {
         /*
             c=<cx, cy, cz>, r
             o=<ox, oy, oz>, d=<dx, dy, dz>
@@ -112,16 +197,204 @@ impl Shape for Sphere {
             }
         }
     }
+}
+
+impl Shape for Sphere {
+    fn intersect(&self, ray: Ray, t_max: Float) -> Option<Hit> {
+        self.distance(ray).filter(|&t| t < t_max).map(|t| {
+            let normal = (ray.get_point(t) - self.center).scale(1.0 / self.radius);
+            let u = normal.y.atan2(normal.x) / (2.0 * PI) + 0.5;
+            let v = normal.z.clamp(-1.0, 1.0).acos() / PI;
+            Hit::new(ray, t, normal, (u, v))
+        })
+    }
+
+    fn params(&self) -> Option<(&'static str, Vec<Float>)> {
+        let c = self.center;
+        Some(("sphere", vec![c.x, c.y, c.z, self.radius]))
+    }
+
+    fn area(&self) -> Option<Float> {
+        Some(4.0 * PI * self.radius * self.radius)
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        let reach = Vector3::new(self.radius, self.radius, self.radius);
+        Some(Aabb::new(self.center - reach, self.center + reach))
+    }
 
-    fn normal(&self, pos: Vector3) -> Vector3 {
-        (pos - self.center).scale(1.0 / self.radius)
+    fn sample_surface(&self, u: (Float, Float)) -> Option<SurfaceSample> {
+        let normal = unit_sphere(u);
+        let pdf = 1.0 / self.area()?;
+        pdf.is_finite().then(|| SurfaceSample { point: self.center + normal.scale(self.radius), normal, pdf })
     }
 }
 
+/// A point on the unit sphere for `u`, spread evenly over it as `u` is over the unit square.
+fn unit_sphere((u, v): (Float, Float)) -> Vector3 {
+    let z = 1.0 - 2.0 * u;
+    let r = (1.0 - z * z).max(0.0).sqrt();
+    let phi = 2.0 * PI * v;
+    Vector3::new(r * phi.cos(), r * phi.sin(), z)
+}
+
+/// The nearest of `ts` past `EPS` and before `t_max`.
+fn nearest(ts: &[Float], t_max: Float) -> Option<Float> {
+    ts.iter().cloned().filter(|&t| t > EPS && t < t_max).reduce(Float::min)
+}
+
+/// A sphere of `radius` swept from `a` to `b`: a cylinder with round ends.
 #[derive(Debug, Copy, Clone)]
+pub struct Capsule {
+    pub a: Vector3, pub b: Vector3, pub radius: Float
+}
+
+impl Capsule {
+    /// How far along from `a` to `b` the point of the segment nearest `point` is, from 0 to 1.
+    fn along(&self, point: Vector3) -> Float {
+        let axis = self.b - self.a;
+        let length2 = axis.dot(axis);
+        if length2 > 0.0 { ((point - self.a).dot(axis) / length2).clamp(0.0, 1.0) } else { 0.0 }
+    }
+}
+
+impl Shape for Capsule {
+    fn intersect(&self, ray: Ray, t_max: Float) -> Option<Hit> {
+        let (axis, offset) = (self.b - self.a, ray.pos - self.a);
+        let r2 = self.radius * self.radius;
+        let mut ts = [Float::NAN; 6];
+
+        // The side, where points are `radius` from the axis between the ends: with `y` how
+        // far along the axis a point is, times its length, |p - a|² |axis|² - y² = r² |axis|².
+        let (length2, dir_y, offset_y) = (axis.dot(axis), ray.dir.dot(axis), offset.dot(axis));
+        let a = length2 - dir_y * dir_y;
+        let b = length2 * ray.dir.dot(offset) - offset_y * dir_y;
+        let c = length2 * offset.dot(offset) - offset_y * offset_y - r2 * length2;
+        let disc = b * b - a * c;
+        if a > 0.0 && disc >= 0.0 {
+            for (i, &t) in [(-b - disc.sqrt()) / a, (-b + disc.sqrt()) / a].iter().enumerate() {
+                let y = offset_y + t * dir_y;
+                if y > 0.0 && y < length2 {
+                    ts[i] = t;
+                }
+            }
+        }
+
+        // The ends, halves of spheres around `a` and `b` facing away from each other.
+        for (k, &(center, past)) in [(self.a, 0.0), (self.b, length2)].iter().enumerate() {
+            let offset = ray.pos - center;
+            let b = ray.dir.dot(offset);
+            let disc = b * b - (offset.dot(offset) - r2);
+            if disc >= 0.0 {
+                for (i, &t) in [-b - disc.sqrt(), -b + disc.sqrt()].iter().enumerate() {
+                    let y = (ray.get_point(t) - self.a).dot(axis);
+                    if (k == 0 && y <= past) || (k == 1 && y >= past) {
+                        ts[2 + 2 * k + i] = t;
+                    }
+                }
+            }
+        }
+
+        let t = nearest(&ts, t_max)?;
+        let point = ray.get_point(t);
+        let along = self.along(point);
+        let normal = (point - self.a - axis.scale(along)).scale(1.0 / self.radius);
+        // Around the axis, then along it.
+        let (side1, side2) = if length2 > 0.0 { axis.normalize().ons() } else { (Vector3::new(1.0, 0.0, 0.0), Vector3::new(0.0, 1.0, 0.0)) };
+        let u = normal.dot(side2).atan2(normal.dot(side1)) / (2.0 * PI) + 0.5;
+        Some(Hit::new(ray, t, normal, (u, along)))
+    }
+
+    fn params(&self) -> Option<(&'static str, Vec<Float>)> {
+        let (a, b) = (self.a, self.b);
+        Some(("capsule", vec![a.x, a.y, a.z, b.x, b.y, b.z, self.radius]))
+    }
+
+    fn area(&self) -> Option<Float> {
+        Some(2.0 * PI * self.radius * ((self.b - self.a).size() + 2.0 * self.radius))
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        let reach = Vector3::new(self.radius, self.radius, self.radius);
+        let ends = Aabb::new(self.a, self.b);
+        Some(Aabb::new(ends.lower() - reach, ends.upper() + reach))
+    }
+
+    /// The side and the two ends, each drawn in proportion to its area; the ends together
+    /// make one sphere.
+    fn sample_surface(&self, (u, v): (Float, Float)) -> Option<SurfaceSample> {
+        let pdf = 1.0 / self.area()?;
+        if !pdf.is_finite() {
+            return None;
+        }
+        let axis = self.b - self.a;
+        let length = axis.size();
+        let side = length / (length + 2.0 * self.radius);
+        let (normal, base) = if u < side {
+            let (across, along) = axis.scale(1.0 / length).ons();
+            let phi = 2.0 * PI * v;
+            let normal = across.scale(phi.cos()) + along.scale(phi.sin());
+            (normal, self.a + axis.scale(u / side))
+        } else {
+            // Each end is the half of a sphere facing away from the other.
+            let normal = unit_sphere(((u - side) / (1.0 - side), v));
+            let away = if length > 0.0 { axis } else { Vector3::new(0.0, 0.0, 1.0) };
+            if normal.dot(away) > 0.0 { (normal, self.b) } else { (normal, self.a) }
+        };
+        Some(SurfaceSample { point: base + normal.scale(self.radius), normal, pdf })
+    }
+}
+
+/// A sphere stretched along the axes to reach `radii` from its center.
+#[derive(Debug, Copy, Clone)]
+pub struct Ellipsoid {
+    pub center: Vector3, pub radii: Vector3
+}
+
+impl Shape for Ellipsoid {
+    fn intersect(&self, ray: Ray, t_max: Float) -> Option<Hit> {
+        // Shrunk by the radii along each axis, the ellipsoid is the unit sphere.
+        let shrink = |v: Vector3| Vector3::new(v.x / self.radii.x, v.y / self.radii.y, v.z / self.radii.z);
+        let (pos, dir) = (shrink(ray.pos - self.center), shrink(ray.dir));
+        let (a, b, c) = (dir.dot(dir), pos.dot(dir), pos.dot(pos) - 1.0);
+        let disc = b * b - a * c;
+        if disc < 0.0 {
+            return None;
+        }
+        let t = nearest(&[(-b - disc.sqrt()) / a, (-b + disc.sqrt()) / a], t_max)?;
+        let unit = pos + dir.scale(t);
+        let normal = shrink(unit).normalize();
+        let u = unit.y.atan2(unit.x) / (2.0 * PI) + 0.5;
+        let v = unit.z.clamp(-1.0, 1.0).acos() / PI;
+        Some(Hit::new(ray, t, normal, (u, v)))
+    }
+
+    fn params(&self) -> Option<(&'static str, Vec<Float>)> {
+        let (c, r) = (self.center, self.radii);
+        Some(("ellipsoid", vec![c.x, c.y, c.z, r.x, r.y, r.z]))
+    }
+
+    /// Knud Thomsen's formula, within about 1% of the true area, which has no closed form.
+    fn area(&self) -> Option<Float> {
+        const P: Float = 1.6075;
+        let (a, b, c) = (self.radii.x.powf(P), self.radii.y.powf(P), self.radii.z.powf(P));
+        Some(4.0 * PI * ((a * b + a * c + b * c) / 3.0).powf(1.0 / P))
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        Some(Aabb::new(self.center - self.radii, self.center + self.radii))
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub struct Triangle {
     vertices: [Vector3; 3],
-    plane: Plane
+    /// The unit normal of the flat face, by the right-hand rule around the vertices.
+    norm: Vector3,
+    /// Unit normals at the vertices, blended across the face for smooth shading.
+    normals: Option<[Vector3; 3]>,
+    /// Texture coordinates at the vertices, blended across the face.
+    uvs: Option<[(Float, Float); 3]>
 }
 
 impl Triangle {
@@ -129,44 +402,128 @@ impl Triangle {
         let norm = (v2 - v1).cross(v3 - v1);
         Triangle  {
             vertices: [v1, v2, v3],
-            plane: Plane::new(v1, norm)
+            norm: norm.normalize(),
+            normals: None,
+            uvs: None
         }
     }
 
+    /// A triangle shaded as if its surface curved to meet `normals`, which must be nonzero,
+    /// at the vertices.
+    pub fn with_normals(v1: Vector3, v2: Vector3, v3: Vector3, normals: [Vector3; 3]) -> Triangle {
+        Triangle::with_unit_normals(v1, v2, v3, normals.map(|n| n.normalize()))
+    }
+
+    /// Like `with_normals`, for `normals` already of unit length, which are kept bit for bit.
+    pub(crate) fn with_unit_normals(v1: Vector3, v2: Vector3, v3: Vector3, normals: [Vector3; 3]) -> Triangle {
+        Triangle { normals: Some(normals), ..Triangle::new(v1, v2, v3) }
+    }
+
+    /// The same triangle with texture coordinates `uvs` at its vertices.
+    pub fn with_uvs(self, uvs: [(Float, Float); 3]) -> Triangle {
+        Triangle { uvs: Some(uvs), ..self }
+    }
+
     pub fn vertices(&self) -> [Vector3; 3] {
         self.vertices
     }
 
-    pub fn area(&self) -> f64 {
+    pub fn normals(&self) -> Option<[Vector3; 3]> {
+        self.normals
+    }
+
+    pub fn uvs(&self) -> Option<[(Float, Float); 3]> {
+        self.uvs
+    }
+
+    /// The normal of the flat face, whatever the vertex normals.
+    pub fn face_normal(&self) -> Vector3 {
+        self.norm
+    }
+
+    pub fn area(&self) -> Float {
+        let [v1, v2, v3] = self.vertices;
+        (v2 - v1).cross(v3 - v1).size() / 2.0
+    }
+
+    /// The distance along `ray` to the triangle's plane, and the barycentric coordinates
+    /// `(u, v)` of that point, the shares of `v2` and `v3` in it, whether or not it is
+    /// inside the triangle. All are infinite or NaN if the ray runs along the plane.
+    pub(crate) fn solve(&self, ray: Ray) -> (Float, Float, Float) {
         let [v1, v2, v3] = self.vertices;
+        let (edge1, edge2) = (v2 - v1, v3 - v1);
+        let p = ray.dir.cross(edge2);
+        let inv_det = 1.0 / edge1.dot(p);
+        let s = ray.pos - v1;
+        let q = s.cross(edge1);
+        (edge2.dot(q) * inv_det, s.dot(p) * inv_det, ray.dir.dot(q) * inv_det)
+    }
 
-        let l1 = (v2 - v1).size();
-        let l2 = (v3 - v1).size();
-        let l3 = (v3 - v2).size();
+    /// The distance to where `ray` hits the triangle, with the barycentric coordinates
+    /// of that point, by Möller–Trumbore.
+    pub fn intersect_uv(&self, ray: Ray) -> Option<(Float, Float, Float)> {
+        let (t, u, v) = self.solve(ray);
+        // NaNs from a ray along the plane fail every test.
+        if u >= 0.0 && v >= 0.0 && u + v <= 1.0 && t > EPS { Some((t, u, v)) } else { None }
+    }
 
-        let p = (l1 + l2 + l3) / 2.0;
-        let prod = p * (p - l1) * (p - l2) * (p - l3);
-        prod.sqrt()
+    /// The normal at barycentric coordinates `(u, v)`.
+    pub fn normal_at(&self, u: Float, v: Float) -> Vector3 {
+        match self.normals {
+            Some([n1, n2, n3]) => {
+                let blend = n1.scale(1.0 - u - v) + n2.scale(u) + n3.scale(v);
+                // Opposed vertex normals can cancel out.
+                if blend.size() > 0.0 { blend.normalize() } else { self.norm }
+            }
+            None => self.norm
+        }
+    }
+
+    /// The texture coordinates at barycentric coordinates `(u, v)`, which are those
+    /// coordinates themselves if the vertices have none.
+    pub fn uv_at(&self, u: Float, v: Float) -> (Float, Float) {
+        match self.uvs {
+            Some([(u1, v1), (u2, v2), (u3, v3)]) => (
+                u1 * (1.0 - u - v) + u2 * u + u3 * v,
+                v1 * (1.0 - u - v) + v2 * u + v3 * v
+            ),
+            None => (u, v)
+        }
     }
 }
 
 impl Shape for Triangle {
-    fn intersect(&self, ray: Ray) -> Option<f64> {
-        self.plane
-            .intersect(ray)
-            .filter(|t| {
-                let point = ray.get_point(*t);
-                let [v1, v2, v3] = self.vertices;
+    fn intersect(&self, ray: Ray, t_max: Float) -> Option<Hit> {
+        self.intersect_uv(ray).filter(|&(t, _, _)| t < t_max)
+            .map(|(t, u, v)| Hit::new(ray, t, self.normal_at(u, v), self.uv_at(u, v)))
+    }
 
-                let tri1 = Triangle::new(v1, v2, point);
-                let tri2 = Triangle::new(v1, v3, point);
-                let tri3 = Triangle::new(v2, v3, point);
+    fn params(&self) -> Option<(&'static str, Vec<Float>)> {
+        // Vertex normals only come from mesh files, whose triangles the cache stores itself.
+        let coords = self.vertices.iter().flat_map(|v| [v.x, v.y, v.z]).collect();
+        Some(("triangle", coords))
+    }
+
+    fn area(&self) -> Option<Float> {
+        Some(Triangle::area(self))
+    }
 
-                (tri1.area() + tri2.area() + tri3.area() - self.area()).abs() < EPS
-            })
+    fn bounds(&self) -> Option<Aabb> {
+        let [v1, v2, v3] = self.vertices;
+        Some(Aabb::new(v1, v2).expand(v3))
     }
 
-    fn normal(&self, pos: Vector3) -> Vector3 {
-        self.plane.normal(pos)
+    fn sample_surface(&self, (u, v): (Float, Float)) -> Option<SurfaceSample> {
+        let pdf = 1.0 / Triangle::area(self);
+        if !pdf.is_finite() {
+            return None;
+        }
+        // Folding the square's corner over would do too, but a square root keeps nearby
+        // `u`s nearby on the triangle.
+        let root = u.sqrt();
+        let (b2, b3) = (root * (1.0 - v), root * v);
+        let [v1, v2, v3] = self.vertices;
+        let point = v1 + (v2 - v1).scale(b2) + (v3 - v1).scale(b3);
+        Some(SurfaceSample { point, normal: self.norm, pdf })
     }
 }
\ No newline at end of file
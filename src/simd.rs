@@ -0,0 +1,95 @@
+use wide::{CmpGe, CmpGt, CmpLe, CmpNe};
+
+use crate::linalg::{Float, Vector3};
+use crate::shapes::{Ray, EPS};
+
+/// One number per lane; single precision fits twice as many in a register.
+#[cfg(not(feature = "f32"))]
+type Lanes = wide::f64x4;
+#[cfg(feature = "f32")]
+type Lanes = wide::f32x8;
+
+/// Lanes in a packet.
+#[cfg(not(feature = "f32"))]
+pub const LANES: usize = 4;
+#[cfg(feature = "f32")]
+pub const LANES: usize = 8;
+
+type LaneVec = [Lanes; 3];
+
+fn splat(v: Vector3) -> LaneVec {
+    [Lanes::splat(v.x), Lanes::splat(v.y), Lanes::splat(v.z)]
+}
+
+fn dot(a: &LaneVec, b: &LaneVec) -> Lanes {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn cross(a: &LaneVec, b: &LaneVec) -> LaneVec {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0]
+    ]
+}
+
+fn sub(a: &LaneVec, b: &LaneVec) -> LaneVec {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+/// Up to `LANES` triangles stored lane by lane, so one ray is tested against all of them
+/// at once. Unused lanes hold empty triangles that nothing hits.
+#[derive(Debug, Clone, Copy)]
+pub struct TrianglePacket {
+    v1: LaneVec,
+    edge1: LaneVec,
+    edge2: LaneVec
+}
+
+impl TrianglePacket {
+    /// Packs the triangles with these `corners`, of which there may be at most `LANES`.
+    pub fn new(triangles: &[[Vector3; 3]]) -> Self {
+        assert!(triangles.len() <= LANES, "a packet holds at most {} triangles", LANES);
+        let lanes = |f: &dyn Fn(&[Vector3; 3]) -> Vector3| {
+            let mut lanes = [[0.0; LANES]; 3];
+            for (lane, triangle) in triangles.iter().enumerate() {
+                let v = f(triangle);
+                lanes[0][lane] = v.x;
+                lanes[1][lane] = v.y;
+                lanes[2][lane] = v.z;
+            }
+            lanes.map(Lanes::from)
+        };
+        TrianglePacket {
+            v1: lanes(&|&[v1, _, _]| v1),
+            edge1: lanes(&|&[v1, v2, _]| v2 - v1),
+            edge2: lanes(&|&[v1, _, v3]| v3 - v1)
+        }
+    }
+
+    /// The lane of the nearest triangle `ray` hits, and the distance to it, by Möller–Trumbore.
+    pub fn closest(&self, ray: Ray) -> Option<(usize, Float)> {
+        let dir = splat(ray.dir);
+        let p = cross(&dir, &self.edge2);
+        let det = dot(&self.edge1, &p);
+        let inv_det = Lanes::ONE / det;
+        let s = sub(&splat(ray.pos), &self.v1);
+        let u = dot(&s, &p) * inv_det;
+        let q = cross(&s, &self.edge1);
+        let v = dot(&dir, &q) * inv_det;
+        let t = dot(&self.edge2, &q) * inv_det;
+
+        let zero = Lanes::ZERO;
+        let hit = det.cmp_ne(zero)
+            & u.cmp_ge(zero)
+            & v.cmp_ge(zero)
+            & (u + v).cmp_le(Lanes::ONE)
+            & t.cmp_gt(Lanes::splat(EPS));
+        let mask = hit.move_mask();
+        let t = t.to_array();
+        (0..LANES)
+            .filter(|lane| mask & (1 << lane) != 0)
+            .min_by(|&a, &b| t[a].total_cmp(&t[b]))
+            .map(|lane| (lane, t[lane]))
+    }
+}
@@ -0,0 +1,106 @@
+use crate::light::{self, Light};
+use crate::linalg::{consts::PI, Float, Vector3};
+use crate::trace::Color;
+
+/// Angular radius of the sun's disc, in radians.
+pub const SUN_RADIUS: Float = 0.00465;
+/// Luminance of the sun above the atmosphere, in kcd/m².
+const SUN_LUMINANCE: Float = 1.6e6;
+/// Wavelengths (in μm) the sun's color is worked out at, for red, green and blue.
+const SUN_WAVELENGTHS: [Float; 3] = [0.61, 0.55, 0.465];
+
+/// A clear daytime sky by the Preetham model: a gradient worked out from where the sun
+/// is and how hazy the air is, and the sun as a light. Up is +z.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sky {
+    /// Toward the sun.
+    pub sun: Vector3,
+    /// Haziness, from about 2 for very clear air to 10 for thick haze.
+    pub turbidity: Float,
+    /// Radiance of one kcd/m² of sky, as a fraction of white.
+    pub strength: Float,
+    /// Perez coefficients A to E for luminance and the two chromaticities.
+    perez: [[Float; 5]; 3],
+    /// Luminance and chromaticity at the zenith, divided by the Perez function there
+    /// so that they scale it directly.
+    zenith: [Float; 3],
+    sun_radiance: Color
+}
+
+impl Sky {
+    pub fn new(sun: Vector3, turbidity: Float, strength: Float) -> Sky {
+        let sun = sun.normalize();
+        let t = turbidity;
+        let perez = [
+            [0.1787 * t - 1.4630, -0.3554 * t + 0.4275, -0.0227 * t + 5.3251, 0.1206 * t - 2.5771, -0.0670 * t + 0.3703],
+            [-0.0193 * t - 0.2592, -0.0665 * t + 0.0008, -0.0004 * t + 0.2125, -0.0641 * t - 0.8989, -0.0033 * t + 0.0452],
+            [-0.0167 * t - 0.2608, -0.0950 * t + 0.0092, -0.0079 * t + 0.2102, -0.0441 * t - 1.6537, -0.0109 * t + 0.0529]
+        ];
+
+        // The fit only holds for a sun above the horizon.
+        let theta_s = sun.z.clamp(0.0, 1.0).acos();
+        let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta_s);
+        let luminance = (4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192;
+        let cubic = |coeffs: [[Float; 4]; 3]| {
+            let [a, b, c] = coeffs.map(|[k3, k2, k1, k0]| ((k3 * theta_s + k2) * theta_s + k1) * theta_s + k0);
+            a * t * t + b * t + c
+        };
+        let x = cubic([
+            [0.00166, -0.00375, 0.00209, 0.0],
+            [-0.02903, 0.06377, -0.03202, 0.00394],
+            [0.11693, -0.21196, 0.06052, 0.25886]
+        ]);
+        let y = cubic([
+            [0.00275, -0.00610, 0.00317, 0.0],
+            [-0.04214, 0.08970, -0.04153, 0.00516],
+            [0.15346, -0.26756, 0.06670, 0.26688]
+        ]);
+        let mut zenith = [luminance.max(0.0), x, y];
+        for (value, coeffs) in zenith.iter_mut().zip(&perez) {
+            *value /= perez_function(coeffs, 1.0, theta_s);
+        }
+
+        // Light from the sun is thinned by Rayleigh and aerosol scattering over the air
+        // mass it goes through, more at shorter wavelengths.
+        let sun_radiance = if sun.z > 0.0 {
+            let degrees = theta_s.to_degrees();
+            let air_mass = 1.0 / (sun.z + 0.15 * (93.885 - degrees).powf(-1.253));
+            let beta = 0.04608 * t - 0.04586;
+            let [r, g, b] = SUN_WAVELENGTHS.map(|lambda: Float| {
+                let rayleigh = -0.008735 * lambda.powf(-4.08);
+                let aerosol = -beta * lambda.powf(-1.3);
+                ((rayleigh + aerosol) * air_mass).exp()
+            });
+            Color::new(r, g, b).scale(SUN_LUMINANCE * 255.0 * strength)
+        } else {
+            Color::BLACK
+        };
+
+        Sky { sun, turbidity, strength, perez, zenith, sun_radiance }
+    }
+
+    /// Radiance of the sky, without the sun, coming from `dir`. Below the horizon it
+    /// stays as it is at the horizon.
+    pub fn radiance(&self, dir: Vector3) -> Color {
+        let cos_theta = dir.z.max(0.01);
+        let gamma = dir.dot(self.sun).clamp(-1.0, 1.0).acos();
+        let [luminance, x, y] = [0, 1, 2].map(|i| self.zenith[i] * perez_function(&self.perez[i], cos_theta, gamma));
+
+        // xyY to XYZ to linear sRGB.
+        let (big_x, big_z) = (x / y * luminance, (1.0 - x - y) / y * luminance);
+        let r = 3.2406 * big_x - 1.5372 * luminance - 0.4986 * big_z;
+        let g = -0.9689 * big_x + 1.8758 * luminance + 0.0415 * big_z;
+        let b = 0.0557 * big_x - 0.2040 * luminance + 1.0570 * big_z;
+        Color::new(r.max(0.0), g.max(0.0), b.max(0.0)).scale(255.0 * self.strength)
+    }
+
+    /// The sun, as a light whose disc is equally bright all over.
+    pub fn sun_light(&self) -> Light {
+        Light::directional(self.sun, SUN_RADIUS, self.sun_radiance.scale(light::solid_angle(SUN_RADIUS)))
+    }
+}
+
+/// The Perez sky distribution at zenith angle `acos(cos_theta)`, `gamma` from the sun.
+fn perez_function([a, b, c, d, e]: &[Float; 5], cos_theta: Float, gamma: Float) -> Float {
+    (1.0 + a * (b / cos_theta).exp()) * (1.0 + c * (d * gamma).exp() + e * gamma.cos().powi(2))
+}
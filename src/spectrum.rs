@@ -0,0 +1,105 @@
+use std::sync::OnceLock;
+
+use crate::linalg::Float;
+use crate::trace::Color;
+
+pub const LAMBDA_MIN: Float = 380.0;
+pub const LAMBDA_MAX: Float = 730.0;
+
+/// Picks a wavelength (in nm) uniformly from the visible range, given `u` in [0, 1).
+pub fn sample_wavelength(u: Float) -> Float {
+    LAMBDA_MIN + u * (LAMBDA_MAX - LAMBDA_MIN)
+}
+
+fn sigmoid(x: Float) -> Float {
+    1.0 / (1.0 + (-x).exp())
+}
+
+/// How much of an RGB color's red, green and blue channels shows up at `lambda`.
+/// The three smooth bands always sum to one, so white upsamples to a flat spectrum.
+fn basis(lambda: Float) -> (Float, Float, Float) {
+    let blue_green = sigmoid((lambda - 490.0) / 10.0);
+    let green_red = sigmoid((lambda - 585.0) / 10.0);
+    (green_red, blue_green - green_red, 1.0 - blue_green)
+}
+
+/// The value at `lambda` of a smooth spectrum standing in for `color`, copied
+/// into every channel so the RGB integrator can carry it unchanged.
+pub fn at_wavelength(color: Color, lambda: Float) -> Color {
+    let (r, g, b) = basis(lambda);
+    let value = color.x * r + color.y * g + color.z * b;
+    Color::new(value, value, value)
+}
+
+/*
+    CIE 1931 2-degree color matching functions, using the multi-lobe Gaussian fit from
+    Wyman, Sloan and Shirley, "Simple Analytic Approximations to the CIE XYZ Color
+    Matching Functions" (JCGT 2013).
+*/
+fn cie_xyz(lambda: Float) -> (Float, Float, Float) {
+    let lobe = |mu: Float, sigma1: Float, sigma2: Float| {
+        let t = (lambda - mu) / if lambda < mu { sigma1 } else { sigma2 };
+        (-0.5 * t * t).exp()
+    };
+
+    let x = 1.056 * lobe(599.8, 37.9, 31.0) + 0.362 * lobe(442.0, 16.0, 26.7) - 0.065 * lobe(501.1, 20.4, 26.2);
+    let y = 0.821 * lobe(568.8, 46.9, 40.5) + 0.286 * lobe(530.9, 16.3, 31.1);
+    let z = 1.217 * lobe(437.0, 11.8, 36.0) + 0.681 * lobe(459.0, 26.0, 13.8);
+    (x, y, z)
+}
+
+/// XYZ to linear sRGB (D65).
+fn xyz_to_rgb((x, y, z): (Float, Float, Float)) -> (Float, Float, Float) {
+    (
+         3.2406 * x - 1.5372 * y - 0.4986 * z,
+        -0.9689 * x + 1.8758 * y + 0.0415 * z,
+         0.0557 * x - 0.2040 * y + 1.0570 * z
+    )
+}
+
+/// RGB response to a flat unit spectrum, used to white-balance so that white stays white.
+fn white() -> (Float, Float, Float) {
+    static WHITE: OnceLock<(Float, Float, Float)> = OnceLock::new();
+    *WHITE.get_or_init(|| {
+        let steps = 1000;
+        let dl = (LAMBDA_MAX - LAMBDA_MIN) / steps as Float;
+        (0..steps)
+            .map(|i| xyz_to_rgb(cie_xyz(LAMBDA_MIN + (i as Float + 0.5) * dl)))
+            .fold((0.0, 0.0, 0.0), |(r, g, b), (dr, dg, db)| (r + dr * dl, g + dg * dl, b + db * dl))
+    })
+}
+
+/// Converts `radiance` carried at a uniformly sampled `lambda` into a one-sample RGB estimate.
+pub fn to_rgb(radiance: Float, lambda: Float) -> Color {
+    let (r, g, b) = xyz_to_rgb(cie_xyz(lambda));
+    let (wr, wg, wb) = white();
+    let scale = radiance * (LAMBDA_MAX - LAMBDA_MIN);
+    Color::new(r * scale / wr, g * scale / wg, b * scale / wb)
+}
+
+/// Linear sRGB of a black body glowing at `kelvin`, up to a constant factor.
+fn planck_rgb(kelvin: Float) -> (Float, Float, Float) {
+    // Planck's law, dropping constant factors; the second radiation constant is in nm·K.
+    let planck = |lambda: Float| 1.0 / (lambda.powi(5) * ((1.4388e7 / (lambda * kelvin)).exp() - 1.0));
+    let steps = 200;
+    let dl = (LAMBDA_MAX - LAMBDA_MIN) / steps as Float;
+    (0..steps)
+        .map(|i| LAMBDA_MIN + (i as Float + 0.5) * dl)
+        .map(|lambda| {
+            let (r, g, b) = xyz_to_rgb(cie_xyz(lambda));
+            let power = planck(lambda);
+            (r * power, g * power, b * power)
+        })
+        .fold((0.0, 0.0, 0.0), |(r, g, b), (dr, dg, db)| (r + dr, g + dg, b + db))
+}
+
+/// The color of a black body glowing at `kelvin`, with its brightest channel at 255.
+/// Daylight's 6504 K is white, as sRGB has it; cooler bodies are redder and hotter bluer.
+pub fn blackbody(kelvin: Float) -> Color {
+    let (r, g, b) = planck_rgb(kelvin);
+    let (wr, wg, wb) = planck_rgb(6504.0);
+    // Deep reds and blues fall outside what RGB can show; those channels are left dark.
+    let (r, g, b) = ((r / wr).max(0.0), (g / wg).max(0.0), (b / wb).max(0.0));
+    let brightest = r.max(g).max(b);
+    Color::new(r, g, b).scale(255.0 / brightest)
+}
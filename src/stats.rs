@@ -0,0 +1,86 @@
+use std::cell::Cell;
+use std::str::FromStr;
+
+use crate::debug::heat;
+use crate::film::Film;
+use crate::linalg::Float;
+
+/// Work done while tracing, counted per thread so the hot paths never contend.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Counters {
+    /// Intersection tests against objects, and against the triangles of meshes.
+    pub tests: u64,
+    /// Nodes of mesh acceleration structures visited.
+    pub nodes: u64,
+    /// Surfaces paths met.
+    pub bounces: u64
+}
+
+thread_local! {
+    static COUNTERS: Cell<Counters> = const { Cell::new(Counters { tests: 0, nodes: 0, bounces: 0 }) };
+}
+
+/// Adds to this thread's counters.
+pub(crate) fn record(update: impl FnOnce(&mut Counters)) {
+    COUNTERS.with(|counters| {
+        let mut value = counters.get();
+        update(&mut value);
+        counters.set(value);
+    });
+}
+
+/// This thread's counts since it last took them, starting again from zero.
+pub fn take() -> Counters {
+    COUNTERS.with(|counters| counters.replace(Counters::default()))
+}
+
+/// Which of the `Counters` a heatmap shows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stat {
+    Tests,
+    Nodes,
+    Bounces
+}
+
+impl FromStr for Stat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tests" => Ok(Stat::Tests),
+            "nodes" => Ok(Stat::Nodes),
+            "bounces" => Ok(Stat::Bounces),
+            _ => Err(format!("Expected tests, nodes or bounces but got {:?}", s))
+        }
+    }
+}
+
+/// The counts from rendering each pixel, for all of its samples together.
+#[derive(Debug, Clone)]
+pub struct PixelStats {
+    pub width: u32,
+    pub height: u32,
+    /// Row by row from the top.
+    pub counters: Vec<Counters>
+}
+
+impl PixelStats {
+    pub fn values(&self, stat: Stat) -> impl Iterator<Item = u64> + '_ {
+        self.counters.iter().map(move |counters| match stat {
+            Stat::Tests => counters.tests,
+            Stat::Nodes => counters.nodes,
+            Stat::Bounces => counters.bounces
+        })
+    }
+
+    /// `stat` at every pixel on a ramp from black for none to white for the most.
+    pub fn heatmap(&self, stat: Stat) -> Film {
+        let max = self.values(stat).max().unwrap_or(0).max(1) as Float;
+        let mut film = Film::new(self.width, self.height);
+        let width = self.width.max(1) as usize;
+        for (i, value) in self.values(stat).enumerate() {
+            film.add_sample((i % width) as u32, (i / width) as u32, heat(value as Float / max));
+        }
+        film
+    }
+}
@@ -0,0 +1,76 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::config::{ConfigError, ConfigResult};
+use crate::linalg::Float;
+
+/// Values from 0 to 1 read off an image at surface coordinates, repeating past 0 and 1
+/// both ways.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Texture {
+    /// The image file the values came from.
+    pub file: PathBuf,
+    width: usize,
+    height: usize,
+    /// Row after row from the top of the image.
+    values: Vec<Float>
+}
+
+impl Texture {
+    /// How opaque the image at `path` is: its alpha channel, or how bright it is if it
+    /// has none.
+    pub fn opacity(path: &Path) -> ConfigResult<Texture> {
+        let image = image::open(path).map_err(|err| ConfigError::InvalidAsset(path.to_path_buf(), err.to_string()))?;
+        let has_alpha = image.color().has_alpha();
+        let image = image.to_luma_alpha16();
+        if image.width() == 0 || image.height() == 0 {
+            return Err(ConfigError::InvalidAsset(path.to_path_buf(), "the image is empty".to_string()));
+        }
+        let values = image.pixels()
+            .map(|pixel| pixel[if has_alpha { 1 } else { 0 }] as Float / u16::MAX as Float)
+            .collect();
+        Ok(Texture { file: path.to_path_buf(), width: image.width() as usize, height: image.height() as usize, values })
+    }
+
+    /// A texture of `width` by `height` values, at least 1 each way, given row after row
+    /// from the top.
+    pub(crate) fn new(file: PathBuf, width: usize, height: usize, values: Vec<Float>) -> Texture {
+        Texture { file, width, height, values }
+    }
+
+    /// How many pixels across and down the image is.
+    pub fn size(&self) -> (usize, usize) {
+        (self.width, self.height)
+    }
+
+    /// The values row after row from the top, in the order `new` takes them.
+    pub fn values(&self) -> &[Float] {
+        &self.values
+    }
+
+    /// The value of the pixel at `(u, v)`, with `v` running up from the bottom of the image.
+    pub fn at(&self, (u, v): (Float, Float)) -> Float {
+        let x = (u.rem_euclid(1.0) * self.width as Float) as usize;
+        let y = ((1.0 - v.rem_euclid(1.0)) * self.height as Float) as usize;
+        // NaN coordinates land on the first pixel.
+        self.values[y.min(self.height - 1) * self.width + x.min(self.width - 1)]
+    }
+}
+
+/// Holes cut in an object's surface where a texture is less opaque than `threshold`,
+/// which rays and shadows pass through as if nothing were there.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cutout {
+    pub texture: Arc<Texture>,
+    pub threshold: Float
+}
+
+impl Cutout {
+    /// The opacity below which a surface is cut away unless a scene says otherwise.
+    pub const DEFAULT_THRESHOLD: Float = 0.5;
+
+    /// Whether the surface is there at coordinates `uv`.
+    pub fn keeps(&self, uv: (Float, Float)) -> bool {
+        self.texture.at(uv) >= self.threshold
+    }
+}
@@ -1,6 +1,25 @@
+use std::borrow::Cow;
+use std::cell::Cell;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use crate::camera;
 use crate::config::Config;
-use crate::shapes::{Shape, Ray};
-use crate::linalg::Vector3;
+use crate::error::RenderError;
+use crate::film::{Film, Region};
+use crate::guiding;
+use crate::irradiance;
+use crate::microfacet;
+use crate::light::{self, AreaLight, Emitter, Light, LightSample};
+use crate::profile::Profiler;
+use crate::random::{self, LocalRng};
+use crate::shapes::{Hit, Ray, Shape};
+use crate::linalg::{consts::PI, Float, Vector3};
+use crate::spectrum;
+use crate::stats::{self, Counters, PixelStats};
+use crate::texture::Cutout;
 
 use rand::Rng;
 use rayon::prelude::*;
@@ -15,6 +34,27 @@ impl Color {
     pub const BLUE: Color = Color { x: 0.0, y: 0.0, z: 255.0, rho: 0.0, theta: 0.0, phi: 0.0 };
     pub const YELLOW: Color = Color { x: 255.0, y: 255.0, z: 0.0, rho: 0.0, theta: 0.0, phi: 0.0 };
 
+    const PALETTE: [(&'static str, [u8; 3]); 16] = [
+        ("cyan", [0, 255, 255]),
+        ("magenta", [255, 0, 255]),
+        ("orange", [255, 165, 0]),
+        ("purple", [128, 0, 128]),
+        ("pink", [255, 192, 203]),
+        ("brown", [139, 69, 19]),
+        ("gray", [128, 128, 128]),
+        ("grey", [128, 128, 128]),
+        ("silver", [192, 192, 192]),
+        ("gold", [255, 215, 0]),
+        ("navy", [0, 0, 128]),
+        ("teal", [0, 128, 128]),
+        ("maroon", [128, 0, 0]),
+        ("olive", [128, 128, 0]),
+        ("lime", [50, 205, 50]),
+        ("ivory", [255, 255, 240]),
+    ];
+
+    /// Parses a named color, `rgb(r,g,b)` with channels in 0..=255, `#rrggbb`, or the color
+    /// of something glowing at a temperature in kelvin, like `3200K`.
     pub fn from_string(s: &str) -> Option<Color> {
         match s {
             "black" => Some(Color::BLACK),
@@ -23,135 +63,1102 @@ impl Color {
             "green" => Some(Color::GREEN),
             "blue" => Some(Color::BLUE),
             "yellow" => Some(Color::YELLOW),
+            _ => Self::from_palette(s)
+                .or_else(|| Self::from_rgb(s))
+                .or_else(|| Self::from_hex(s))
+                .or_else(|| Self::from_temperature(s))
+        }
+    }
+
+    fn from_palette(s: &str) -> Option<Color> {
+        Self::PALETTE.iter()
+            .find(|(name, _)| *name == s)
+            .map(|(_, [r, g, b])| Color::new(*r as Float, *g as Float, *b as Float))
+    }
+
+    fn from_rgb(s: &str) -> Option<Color> {
+        let channels = s.strip_prefix("rgb(")?.strip_suffix(')')?;
+        let channels: Vec<Float> = channels
+            .split(',')
+            .map(|channel| channel.trim().parse().ok())
+            .collect::<Option<_>>()?;
+        match channels[..] {
+            [r, g, b] if channels.iter().all(|c| (0.0..=255.0).contains(c)) => Some(Color::new(r, g, b)),
             _ => None
         }
     }
+
+    fn from_temperature(s: &str) -> Option<Color> {
+        let kelvin: Float = s.strip_suffix('K')?.parse().ok()?;
+        if !(kelvin > 0.0 && kelvin.is_finite()) {
+            return None;
+        }
+        Some(spectrum::blackbody(kelvin))
+    }
+
+    fn from_hex(s: &str) -> Option<Color> {
+        let hex = s.strip_prefix('#')?;
+        if hex.len() != 6 {
+            return None;
+        }
+        let channel = |i: usize| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok().map(Float::from);
+        Some(Color::new(channel(0)?, channel(2)?, channel(4)?))
+    }
 }
 
-#[derive(Clone, Copy, Debug)]
+/// Cauchy's equation for the refractive index, `n = a + b / λ²` with λ in micrometers.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Cauchy {
+    pub a: Float,
+    pub b: Float
+}
+
+impl Cauchy {
+    pub fn ior(&self, lambda: Float) -> Float {
+        let micros = lambda / 1000.0;
+        self.a + self.b / (micros * micros)
+    }
+}
+
+/// Wavelengths (in nm) standing in for the red, green and blue channels.
+const CHANNEL_WAVELENGTHS: [Float; 3] = [610.0, 550.0, 465.0];
+
+/// A homogeneous participating medium, like fog, haze or murky water.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Medium {
+    /// Scattering coefficient per unit distance.
+    pub scattering: Float,
+    /// Absorption coefficient per unit distance.
+    pub absorption: Float,
+    /// Henyey–Greenstein anisotropy, from -1 (back) through 0 (even) to 1 (forward).
+    pub g: Float
+}
+
+impl Medium {
+    /// Light reaching the start of `ray` if it scatters before travelling `t_max`;
+    /// `None` means it got through and the caller should carry on as if in a vacuum.
+    /// Sampling the distance to match the transmittance leaves only the albedo as weight.
+    fn scatter(&self, config: &Config, ray: Ray, t_max: Float, depth: u16, path: Path) -> Option<Color> {
+        let extinction = self.scattering + self.absorption;
+        if extinction <= 0.0 {
+            return None;
+        }
+        let distance = -(1.0 - LocalRng.gen::<Float>()).ln() / extinction;
+        if distance >= t_max {
+            return None;
+        }
+
+        let new_dir = to_world(ray.dir, self.sample_phase());
+        let path = Path { lights_sampled: false, bounce_pdf: None, kind: RayKind::Diffuse, ..path };
+        let new_ray = Ray::new(ray.get_point(distance), new_dir);
+        let incoming = get_color(config, new_ray, depth - 1, path);
+        Some(incoming.scale(self.scattering / extinction))
+    }
+
+    /// A direction around +z (the way the light was going) drawn from the phase function.
+    fn sample_phase(&self) -> Vector3 {
+        let mut rng = LocalRng;
+        let (u1, u2) = (rng.gen::<Float>(), rng.gen::<Float>());
+        let g = self.g;
+        let cos_theta = if g.abs() < 1e-3 {
+            1.0 - 2.0 * u1
+        } else {
+            let term = (1.0 - g * g) / (1.0 - g + 2.0 * g * u1);
+            (1.0 + g * g - term * term) / (2.0 * g)
+        };
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * PI * u2;
+        Vector3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta)
+    }
+}
+
+/// What happens to light passing through a translucent material.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Glass {
+    /// Refractive index, used at every wavelength unless `cauchy` is given.
+    pub ior: Float,
+    /// How the refractive index varies with wavelength.
+    pub cauchy: Option<Cauchy>,
+    /// Beer–Lambert absorption coefficient per unit distance, for each channel.
+    pub absorption: Option<Color>,
+    /// What fills the inside, scattering light on its way through.
+    pub medium: Option<Medium>,
+    /// The color white light takes on going in through the surface, for stained glass
+    /// whose color is in its surface rather than its depth.
+    pub tint: Option<Color>
+}
+
+impl Default for Glass {
+    fn default() -> Self {
+        Glass { ior: 1.5, cauchy: None, absorption: None, medium: None, tint: None }
+    }
+}
+
+impl Glass {
+    /// Fraction of light surviving `distance` units inside the material.
+    pub fn transmittance(&self, distance: Float) -> Option<Color> {
+        self.absorption.map(|sigma| Color::new(
+            (-sigma.x * distance).exp(),
+            (-sigma.y * distance).exp(),
+            (-sigma.z * distance).exp()
+        ))
+    }
+}
+
+/// A sheet of glass too thin to bend the light going through it, like a window pane or
+/// the skin of a soap bubble, with one surface standing in for both of its own.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ThinGlass {
+    pub ior: Float,
+    /// How thick the sheet is, in nm, for the colors of light interfering with itself
+    /// between its two faces; `None` for a pane too thick to show any.
+    pub film: Option<Float>,
+    /// The color white light takes on going through, for stained windows.
+    pub tint: Option<Color>
+}
+
+impl Default for ThinGlass {
+    fn default() -> Self {
+        ThinGlass { ior: 1.5, film: None, tint: None }
+    }
+}
+
+impl ThinGlass {
+    /// Fraction of light at wavelength `lambda` (in nm) reflected off the sheet when it
+    /// arrives at an angle whose cosine is `cos_i`; the rest goes straight through.
+    pub fn reflectance(&self, cos_i: Float, lambda: Float) -> Float {
+        let sin_t2 = (1.0 - cos_i * cos_i) / (self.ior * self.ior);
+        if sin_t2 >= 1.0 {
+            return 1.0;
+        }
+        let cos_t = (1.0 - sin_t2).sqrt();
+        // Fresnel amplitudes for each polarization at the first face; the second face
+        // gives the same with the sign turned.
+        let s = (cos_i - self.ior * cos_t) / (cos_i + self.ior * cos_t);
+        let p = (self.ior * cos_i - cos_t) / (self.ior * cos_i + cos_t);
+        let sheet = |r: Float| {
+            let r2 = r * r;
+            match self.film {
+                // Light bouncing back and forth inside adds up without interfering.
+                None => 2.0 * r2 / (1.0 + r2),
+                Some(thickness) => {
+                    let phase = (4.0 * PI * self.ior * thickness * cos_t / lambda).cos();
+                    2.0 * r2 * (1.0 - phase) / (1.0 + r2 * r2 - 2.0 * r2 * phase)
+                }
+            }
+        };
+        (sheet(s) + sheet(p)) / 2.0
+    }
+}
+
+/// One material standing for many, blended from a few dials the way modeling tools and
+/// glTF describe surfaces: a metal, a glass, or plastic with a shine, and anything between.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Principled {
+    /// How much of the surface is metal, reflecting in its own color, from 0 to 1.
+    pub metallic: Float,
+    /// How blurred reflections are, from 0 for sharp up to 1.
+    pub roughness: Float,
+    /// How strongly the part that isn't metal shines, from 0 to 1, with 0.5 as much as
+    /// glass or plastic.
+    pub specular: Float,
+    /// How much of the part that isn't metal lets light through, tinted by the color,
+    /// from 0 to 1. Light goes through sharply whatever the roughness.
+    pub transmission: Float,
+    pub ior: Float
+}
+
+impl Default for Principled {
+    fn default() -> Self {
+        Principled { metallic: 0.0, roughness: 0.5, specular: 0.5, transmission: 0.0, ior: 1.5 }
+    }
+}
+
+/// A clear varnish over another material, like car paint or lacquered wood.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Coat {
+    /// How much of the Fresnel reflection the coat actually gives, from 0 to 1.
+    pub strength: Float,
+    pub roughness: Float
+}
+
+#[derive(Clone, Debug, PartialEq)]
 pub enum Material {
-    Mirror,
-    Translucent(f64),
+    /// Roughness, from 0 for a perfect mirror up to 1 for brushed metal.
+    Mirror(Float),
+    /// How often light goes through rather than scattering diffusely, and how it does so.
+    Translucent(Float, Glass),
+    /// A sheet with nothing inside, reflecting some light and letting the rest straight through.
+    Thin(ThinGlass),
+    /// Metal, glass and shiny plastic in any blend.
+    Principled(Principled),
+    /// A coat layered on top of a base material.
+    Coated(Coat, Box<Material>),
+    /// A stand-in for the ground of a photograph the render is laid over: unseen itself, it
+    /// shows what is behind it darkened by the shadows falling on it, and reflects the
+    /// scene's objects by the given fraction. Light bouncing off objects still finds it a
+    /// diffuse surface of its color.
+    Catcher(Float)
+}
+
+impl Material {
+    /// The material under any coats.
+    pub fn base(&self) -> &Material {
+        match self {
+            Material::Coated(_, base) => base.base(),
+            material => material
+        }
+    }
+}
+
+/// What an object does with the back of its surface, the side its normals point away from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Backface {
+    /// Drawn like the front.
+    #[default]
+    Show,
+    /// Left out, so that rays reaching it from behind go on through.
+    Cull,
+    /// Taken as the front, the normals turned around, for surfaces wound the wrong way.
+    Flip
+}
+
+impl FromStr for Backface {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "show" => Ok(Backface::Show),
+            "cull" => Ok(Backface::Cull),
+            "flip" => Ok(Backface::Flip),
+            _ => Err(format!("Expected show, cull or flip but got {:?}", s))
+        }
+    }
+}
+
+impl fmt::Display for Backface {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Backface::Show => "show",
+            Backface::Cull => "cull",
+            Backface::Flip => "flip"
+        })
+    }
+}
+
+/// What a ray is looking for, which decides the objects it can find.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RayKind {
+    /// Straight from the camera, or on through surfaces without bouncing.
+    Camera,
+    /// Off a mirror-like surface or through glass.
+    Reflection,
+    /// Off a diffuse surface or a particle of fog.
+    Diffuse,
+    /// Toward a light, to learn whether anything is in the way.
+    Shadow
+}
+
+/// Which rays find an object; every kind of them unless the scene says otherwise.
+/// Diffuse bounces always do, so that an object unseen otherwise still lights the scene.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Visibility {
+    pub camera: bool,
+    pub shadows: bool,
+    pub reflections: bool
+}
+
+impl Default for Visibility {
+    fn default() -> Self {
+        Visibility { camera: true, shadows: true, reflections: true }
+    }
+}
+
+impl Visibility {
+    pub fn finds(&self, kind: RayKind) -> bool {
+        match kind {
+            RayKind::Camera => self.camera,
+            RayKind::Reflection => self.reflections,
+            RayKind::Diffuse => true,
+            RayKind::Shadow => self.shadows
+        }
+    }
 }
 
 pub struct Object {
     pub shape: Box<dyn Shape>, 
     pub color: Color, 
     pub lum: Color,
-    pub material: Material
+    pub material: Material,
+    pub backface: Backface,
+    pub cutout: Option<Cutout>,
+    pub visibility: Visibility,
+    pub line: usize
 }
-unsafe impl Sync for Object {}
 
-fn get_color(objects: &[Object], ray: Ray, depth: u16) -> Color {
+impl Object {
+    /// Where `ray` first meets the object before `t_max`, its back treated as `backface`
+    /// says and leaving out whatever `cutout` cuts away.
+    pub fn intersect(&self, ray: Ray, t_max: Float) -> Option<Hit> {
+        if self.backface == Backface::Show && self.cutout.is_none() {
+            return self.shape.intersect(ray, t_max);
+        }
+        // Past a surface that isn't there, the ray carries on from where it met it.
+        let mut start = 0.0;
+        loop {
+            let hit = self.shape.intersect(Ray { pos: ray.get_point(start), dir: ray.dir }, t_max - start)?;
+            let culled = self.backface == Backface::Cull && !hit.front_face;
+            let cut = self.cutout.as_ref().is_some_and(|cutout| !cutout.keeps(hit.uv));
+            if !culled && !cut {
+                let normal = if self.backface == Backface::Flip { -hit.normal } else { hit.normal };
+                return Some(Hit::new(ray, start + hit.t, normal, hit.uv));
+            }
+            start += hit.t;
+        }
+    }
+}
+
+/// The nearest of the `objects` that a ray of the given `kind` finds, and where.
+pub(crate) fn closest_hit(objects: &[Object], ray: Ray, kind: RayKind) -> Option<(usize, Hit)> {
+    RAYS.with(|rays| rays.set(rays.get() + 1));
+    stats::record(|counters| counters.tests += objects.len() as u64);
+    let mut best: Option<(usize, Hit)> = None;
+    for (i, obj) in objects.iter().enumerate() {
+        if !obj.visibility.finds(kind) {
+            continue;
+        }
+        let t_max = best.map_or(Float::INFINITY, |(_, hit)| hit.t);
+        if let Some(hit) = obj.intersect(ray, t_max) {
+            best = Some((i, hit));
+        }
+    }
+    best
+}
+
+/// What a path carries from bounce to bounce.
+#[derive(Clone, Copy, Debug)]
+struct Path {
+    /// The one wavelength being traced, in spectral mode.
+    lambda: Option<Float>,
+    /// Whether the path has bounced off a diffuse surface yet.
+    diffuse: bool,
+    /// How the path came by its latest ray.
+    kind: RayKind,
+    /// How many continuations to trace at the first glass hit after a diffuse bounce.
+    caustic_split: u16,
+    /// What fills the space between objects.
+    medium: Option<Medium>,
+    /// Whether the last bounce already gathered light from `config.lights` and the sky's
+    /// sun directly, so that finding them again would count them twice.
+    lights_sampled: bool,
+    /// The density the latest ray was drawn with, per unit of solid angle, if the bounce
+    /// it left also aimed at the glowing objects, so that finding one counts only as much
+    /// as the power heuristic weighs it against the aiming; 0 gives it no weight at all.
+    bounce_pdf: Option<Float>
+}
+
+/// Where a ray landed on an object, as far as its material is concerned.
+struct Surface {
+    pos: Vector3,
+    /// The outward normal.
+    n: Vector3,
+    /// The object's color, already at the path's wavelength in spectral mode.
+    color: Color
+}
+
+/// Radiance arriving along `ray`. When tracing a single wavelength (spectral mode),
+/// every channel of the result carries the radiance at that wavelength.
+fn get_color(config: &Config, ray: Ray, depth: u16, path: Path) -> Color {
+    let lambda = path.lambda;
     if depth == 0 {
         Color::BLACK
     } else {
-        let obj_ts = objects.iter()
-            .filter_map(|obj| obj.shape.intersect(ray).map(|t| (obj, t)))
-            .reduce(|(o1, t1), (o2, t2)| if t1 < t2 { (o1, t1) } else { (o2, t2) });
-        match obj_ts {
-            None => Color::BLACK,
-            Some((best_obj, best_t)) => {
-                let new_pos = ray.pos + ray.dir.scale(best_t);
-
-                let n = best_obj.shape.normal(new_pos);
-                let cost = ray.dir.dot(n);
-
-                let reflected = match &best_obj.material {
-                    Material::Mirror => {
-                        let new_dir = ray.dir - n.scale(2.0 * cost);
-                        let new_ray = Ray { pos: new_pos, dir: new_dir };
-                        let incoming = get_color(objects, new_ray, depth - 1);
-                        (incoming * best_obj.color).scale(1.0/255.0)
+        let obj_hit = closest_hit(&config.objects, ray, path.kind).map(|(i, hit)| (&config.objects[i], hit));
+
+        // Leaving a translucent object, the ray went through whatever fills it instead.
+        let medium = match obj_hit {
+            Some((obj, hit)) => match obj.material.base() {
+                Material::Translucent(_, glass) if !hit.front_face => glass.medium,
+                _ => path.medium
+            },
+            None => path.medium
+        };
+        let t_max = obj_hit.map_or(Float::INFINITY, |(_, hit)| hit.t);
+        if let Some(scattered) = medium.and_then(|medium| medium.scatter(config, ray, t_max, depth, path)) {
+            return scattered;
+        }
+
+        match obj_hit {
+            None => {
+                let mut radiance = config.sky.map_or(Color::BLACK, |sky| sky.radiance(ray.dir));
+                if !path.lights_sampled {
+                    for light in lights(config) {
+                        radiance += light.radiance(ray.dir);
+                    }
+                }
+                match lambda {
+                    Some(lambda) => spectrum::at_wavelength(radiance, lambda),
+                    None => radiance
+                }
+            }
+            Some((best_obj, hit)) => {
+                stats::record(|counters| counters.bounces += 1);
+                let (color, lum) = match lambda {
+                    Some(lambda) => (
+                        spectrum::at_wavelength(best_obj.color, lambda),
+                        spectrum::at_wavelength(best_obj.lum, lambda)
+                    ),
+                    None => (best_obj.color, best_obj.lum)
+                };
+
+                // Light the last bounce could also have aimed at is weighed against that.
+                let lum = match path.bounce_pdf.zip(AreaLight::of(best_obj)) {
+                    Some((pdf, light)) => lum.scale(light::power_heuristic(pdf, light.pdf_at(ray.dir, hit.t, hit.normal))),
+                    None => lum
+                };
+                let surface = Surface { pos: hit.point, n: hit.normal, color };
+                let emitted = scatter(config, &best_obj.material, ray, &surface, depth, path) + lum;
+
+                // Coming from inside an absorbing medium, the whole way here was spent in it.
+                match best_obj.material.base() {
+                    Material::Translucent(_, glass) if !hit.front_face => match glass.transmittance(hit.t) {
+                        Some(transmittance) => {
+                            let transmittance = match lambda {
+                                Some(lambda) => spectrum::at_wavelength(transmittance, lambda),
+                                None => transmittance
+                            };
+                            emitted * transmittance
+                        }
+                        None => emitted
                     },
-                    Material::Translucent(clearness) => {
-                        let rand: f64 = rand::random();
-                        if rand < *clearness { // Glass
-                            // let new_dir = ray.dir - n.scale(2.0 * cost);
-                            // let new_ray = Ray { pos: new_pos, dir: new_dir };
-                            // let incoming = get_color(objects, new_ray, depth - 1);
-                            // incoming * best_obj.color
-                            let refr: f64 = 1.5;
-                            let r0: f64 = (1.0 - refr) / (1.0 + refr);
-                            let r0 = r0 * r0;
-                            let (n, refr) =
-                                if n.dot(ray.dir) > 0.0 { // we're inside the medium
-                                    (n.scale(-1.0), refr)
-                                } else {
-                                    (n, 1.0 / refr)
-                                };
-                            let cost1: f64 = n.dot(ray.dir) * -1.0; // cosine of theta_1
-                            let cost2: f64 = 1.0 - refr.powi(2) * (1.0 - cost1.powi(2)); // cosine of theta_2
-                            let r_prob: f64 = r0 + (1.0 - r0) * (1.0 - cost1).powi(5); // Schlick-approximation
-                            let new_dir = 
-                                if cost2 > 0.0 && rand::thread_rng().gen::<f64>() > r_prob { // refraction direction
-                                    (ray.dir.scale(refr) + n.scale(refr * cost1 - cost2.sqrt())).normalize()
-                                } else { // reflection direction
-                                    (ray.dir + n.scale(cost1 * 2.0)).normalize()
-                                };
-                            let new_ray = Ray::new(new_pos, new_dir);
-
-                            let incoming = get_color(objects, new_ray, depth - 1);
-                            incoming.scale(1.15).scale(1.0 / 0.9)
-                        } else { // Opaque
-                            let n = if cost < 0.0 { n } else { n.scale(-1.0) };
-                            let cost = cost.abs();
-
-                            let (rot_x, rot_y) = n.ons();
-                            let sampled_dir = Vector3::rand_hemi2();
-                            let new_dir = Vector3::new(
-                                Vector3::new(rot_x.x, rot_y.x, n.x).dot(sampled_dir),
-                                Vector3::new(rot_x.y, rot_y.y, n.y).dot(sampled_dir),
-                                Vector3::new(rot_x.z, rot_y.z, n.z).dot(sampled_dir)
-                            );
-                            let new_ray = Ray::new(new_pos, new_dir);
-
-                            let incoming = get_color(objects, new_ray, depth - 1);
-                            let cost = new_dir.dot(n);
-                            (incoming * best_obj.color).scale(cost).scale(1.0/255.0).scale(1.0/0.9)
+                    _ => emitted
+                }
+            }
+        }
+    }
+}
+
+/// The fraction of each channel a `tint` lets through, at the path's wavelength in
+/// spectral mode.
+fn filter(tint: Option<Color>, lambda: Option<Float>) -> Color {
+    match (tint, lambda) {
+        (None, _) => Color::new(1.0, 1.0, 1.0),
+        (Some(tint), Some(lambda)) => spectrum::at_wavelength(tint, lambda).scale(1.0 / 255.0),
+        (Some(tint), None) => tint.scale(1.0 / 255.0)
+    }
+}
+
+/// Light that `material` sends back along `ray` from elsewhere in the scene.
+fn scatter(config: &Config, material: &Material, ray: Ray, surface: &Surface, depth: u16, path: Path) -> Color {
+    let path = Path { lights_sampled: false, bounce_pdf: None, ..path };
+    let Surface { pos: new_pos, n, color } = *surface;
+    let cost = ray.dir.dot(n);
+    let facing = if cost < 0.0 { n } else { -n };
+
+    match material {
+        Material::Mirror(roughness) => {
+            let incoming = reflect(config, ray, new_pos, facing, *roughness, depth, path);
+            let reflected = (incoming * color).scale(1.0/255.0);
+            if *roughness > 0.0 {
+                reflected * microfacet::multiple_scattering(color, *roughness, -ray.dir.dot(facing))
+            } else {
+                reflected
+            }
+        },
+        Material::Coated(coat, base) => coated(config, coat, base, ray, surface, depth, path),
+        Material::Principled(principled) => {
+            // One layer is picked in proportion to how much of the surface it makes up,
+            // which leaves each unweighted.
+            let rand: Float = LocalRng.gen();
+            let clear = principled.metallic + (1.0 - principled.metallic) * principled.transmission;
+            if rand < principled.metallic {
+                scatter(config, &Material::Mirror(principled.roughness), ray, surface, depth, path)
+            } else if rand < clear {
+                let glass = Glass { ior: principled.ior, tint: Some(color), ..Glass::default() };
+                scatter(config, &Material::Translucent(1.0, glass), ray, surface, depth, path)
+            } else {
+                // Specular 0.5 shines like the coat at full strength.
+                let coat = Coat { strength: (2.0 * principled.specular).min(1.0), roughness: principled.roughness };
+                coated(config, &coat, &Material::Translucent(0.0, Glass::default()), ray, surface, depth, path)
+            }
+        },
+        Material::Catcher(reflection) => {
+            if path.diffuse {
+                return scatter(config, &Material::Translucent(0.0, Glass::default()), ray, surface, depth, path);
+            }
+            if LocalRng.gen::<Float>() < *reflection {
+                let mirrored = Ray::new(new_pos, ray.dir.reflect(facing));
+                // The photograph already shows whatever isn't one of the scene's objects.
+                let object = closest_hit(&config.objects, mirrored, RayKind::Reflection).map(|(i, _)| &config.objects[i]);
+                if object.is_some_and(|object| !matches!(object.material.base(), Material::Catcher(_))) {
+                    return get_color(config, mirrored, depth - 1, Path { kind: RayKind::Reflection, ..path });
+                }
+            }
+            get_color(config, Ray::new(new_pos, ray.dir), depth - 1, path).scale(unshadowed(config, new_pos, facing))
+        },
+        Material::Thin(thin) => {
+            let cos_i = -facing.dot(ray.dir);
+            let reflectance = match path.lambda {
+                Some(lambda) => {
+                    let r = thin.reflectance(cos_i, lambda);
+                    Color::new(r, r, r)
+                }
+                None => {
+                    let [r, g, b] = CHANNEL_WAVELENGTHS.map(|lambda| thin.reflectance(cos_i, lambda));
+                    Color::new(r, g, b)
+                }
+            };
+            // One way is picked in proportion to how much light goes that way on average.
+            let chance = (reflectance.x + reflectance.y + reflectance.z) / 3.0;
+            if LocalRng.gen::<Float>() < chance {
+                let new_ray = Ray::new(new_pos, ray.dir.reflect(facing));
+                let path = Path { kind: RayKind::Reflection, ..path };
+                (get_color(config, new_ray, depth - 1, path) * reflectance).scale(1.0 / chance)
+            } else {
+                let through = Color::new(1.0 - reflectance.x, 1.0 - reflectance.y, 1.0 - reflectance.z) * filter(thin.tint, path.lambda);
+                let new_ray = Ray::new(new_pos, ray.dir);
+                (get_color(config, new_ray, depth - 1, path) * through).scale(1.0 / (1.0 - chance))
+            }
+        },
+        Material::Translucent(clearness, glass) => {
+            let rand: Float = LocalRng.gen();
+            if rand < *clearness { // Glass
+                let refract = |path: Path| {
+                    // Outside spectral mode, a dispersive glass picks one channel to carry on with.
+                    let (refr, channel) = match (glass.cauchy, path.lambda) {
+                        (None, _) => (glass.ior, None),
+                        (Some(cauchy), Some(lambda)) => (cauchy.ior(lambda), None),
+                        (Some(cauchy), None) => {
+                            let channel = LocalRng.gen_range(0..3);
+                            (cauchy.ior(CHANNEL_WAVELENGTHS[channel]), Some(channel))
                         }
+                    };
+                    let r0: Float = (1.0 - refr) / (1.0 + refr);
+                    let r0 = r0 * r0;
+                    let inside = n.dot(ray.dir) > 0.0;
+                    let (n, refr) =
+                        if inside { // we're inside the medium
+                            (-n, refr)
+                        } else {
+                            (n, 1.0 / refr)
+                        };
+                    let cost1: Float = -n.dot(ray.dir); // cosine of theta_1
+                    let r_prob: Float = r0 + (1.0 - r0) * (1.0 - cost1).powi(5); // Schlick-approximation
+                    let refracted = ray.dir.refract(n, refr).filter(|_| LocalRng.gen::<Float>() > r_prob);
+                    let (new_dir, tint) = match refracted {
+                        Some(dir) => {
+                            // Light is tinted once, on the way in.
+                            let tint = if inside { None } else { glass.tint };
+                            (dir.normalize(), tint)
+                        }
+                        None => (ray.dir.reflect(n).normalize(), None)
+                    };
+                    let new_ray = Ray::new(new_pos, new_dir);
+                    let path = Path { kind: RayKind::Reflection, ..path };
+
+                    let incoming = get_color(config, new_ray, depth - 1, path) * filter(tint, path.lambda);
+                    match channel {
+                        Some(0) => Color::new(3.0 * incoming.x, 0.0, 0.0),
+                        Some(1) => Color::new(0.0, 3.0 * incoming.y, 0.0),
+                        Some(_) => Color::new(0.0, 0.0, 3.0 * incoming.z),
+                        None => incoming
                     }
                 };
 
-                reflected + best_obj.lum
-                // best_obj.color
+                // Splitting once per path keeps the extra work bounded.
+                if path.diffuse && path.caustic_split > 1 {
+                    let split = Path { caustic_split: 1, ..path };
+                    (0..path.caustic_split)
+                        .map(|_| refract(split))
+                        .fold(Color::BLACK, |sum, color| sum + color)
+                        .scale(1.0 / path.caustic_split as Float)
+                } else {
+                    refract(path)
+                }
+            } else { // Opaque
+                // A trained guide draws bounces toward where light came from in training.
+                let guided = config.path_guide.as_ref().and_then(|guide| guide.sample(new_pos, facing, &mut LocalRng));
+                let new_dir = guided.map_or_else(|| to_world(facing, Vector3::rand_hemi2()), |(dir, _)| dir);
+                let direct = direct_light(config, new_pos, facing, color, path);
+                // The cache holds light already bounced once, so only the first diffuse
+                // bounce of a path can use it; records are in color, not at one wavelength.
+                let cached = config.irradiance_cache.is_some() && !path.diffuse && path.lambda.is_none();
+                // Bounces the guide draws, or the cache stands in for, leave the glowing
+                // objects to the shadow rays aimed at them; the rest draw from 1/2π.
+                let bounce_pdf = if guided.is_some() || cached { 0.0 } else { 1.0 / (2.0 * PI) };
+                // With no bounces left the bounce would find nothing, so neither may aiming.
+                let glow = if depth > 1 { glow_light(config, new_pos, facing, color, path, bounce_pdf) } else { None };
+                let path = Path {
+                    diffuse: true,
+                    kind: RayKind::Diffuse,
+                    lights_sampled: direct.is_some(),
+                    bounce_pdf: glow.map(|_| bounce_pdf),
+                    ..path
+                };
+                let direct = match (direct, glow) {
+                    (None, None) => None,
+                    (direct, glow) => Some(direct.unwrap_or(Color::BLACK) + glow.unwrap_or(Color::BLACK))
+                };
+                if let (Some(cache), true) = (&config.irradiance_cache, cached) {
+                    let irradiance = cache.irradiance(new_pos, facing, |dir| {
+                        let new_ray = Ray::new(new_pos, dir);
+                        let distance = closest_hit(&config.objects, new_ray, RayKind::Diffuse).map_or(Float::INFINITY, |(_, hit)| hit.t);
+                        (get_color(config, new_ray, depth - 1, path), distance)
+                    });
+                    // A diffuse bounce weights light by `color / 255 / 0.9` over 2π.
+                    return (irradiance * color).scale(1.0 / (255.0 * 0.9 * 2.0 * PI)) + direct.unwrap_or(Color::BLACK);
+                }
+                let cost = new_dir.dot(facing);
+                if guided.is_some() && cost <= 0.0 {
+                    return direct.unwrap_or(Color::BLACK);
+                }
+                let new_ray = Ray::new(new_pos, new_dir);
+                let incoming = get_color(config, new_ray, depth - 1, path);
+                // Bounces drawn the usual way have density 1/2π, which the weighting assumes.
+                let pdf = guided.map_or(1.0 / (2.0 * PI), |(_, pdf)| pdf);
+                guiding::record(new_pos, new_dir, incoming, pdf);
+                let weight = guided.map_or(1.0, |(_, pdf)| 1.0 / (2.0 * PI * pdf));
+                (incoming * color).scale(cost).scale(1.0/255.0).scale(1.0/0.9).scale(weight) + direct.unwrap_or(Color::BLACK)
             }
         }
     }
 }
 
-pub fn make_image(config: &Config) -> Vec<Vec<Vector3>> {
-    (0..config.height).into_par_iter().map(|y| {
-        (0..config.width).into_par_iter().map(|x| {
-            let mut rng = rand::thread_rng();
-            
-            let xf = x as f64;
-            let yf = (config.height - y - 1) as f64;
+/// Light that `base` under `coat` sends back along `ray`.
+fn coated(config: &Config, coat: &Coat, base: &Material, ray: Ray, surface: &Surface, depth: u16, path: Path) -> Color {
+    let n = surface.n;
+    let facing = if ray.dir.dot(n) < 0.0 { n } else { -n };
+    // The coat's Fresnel reflectance (Schlick, IOR 1.5) picks a layer; since the
+    // coat is white and the base gets whatever it lets through, neither needs weighting.
+    let fresnel = 0.04 + 0.96 * (1.0 + facing.dot(ray.dir)).powi(5);
+    if LocalRng.gen::<Float>() < coat.strength * fresnel {
+        reflect(config, ray, surface.pos, facing, coat.roughness, depth, path)
+    } else {
+        scatter(config, base, ray, surface, depth, path)
+    }
+}
+
+/// The scene's lights, with the sky's sun among them.
+fn lights(config: &Config) -> impl Iterator<Item = Cow<'_, Light>> {
+    config.lights.iter().map(Cow::Borrowed).chain(config.sky.map(|sky| Cow::Owned(sky.sun_light())))
+}
+
+/// Light from the scene's lights that a diffuse surface at `pos`, facing `facing`, sends
+/// on, from a shadow ray toward each; or, in scenes with a light tree, toward each light
+/// that isn't in it and one drawn from it. `None` if there are no lights to sample.
+fn direct_light(config: &Config, pos: Vector3, facing: Vector3, color: Color, path: Path) -> Option<Color> {
+    if config.lights.is_empty() && config.sky.is_none() {
+        return None;
+    }
+    let tree = config.light_tree();
+    let drawn = tree.and_then(|tree| tree.sample(pos, facing, &mut LocalRng))
+        .map(|(index, chance)| (Cow::Borrowed(&config.lights[index]), 1.0 / chance));
+    let outside = lights(config).filter(|light| tree.is_none() || matches!(**light, Light::Directional { .. }));
+    let mut total = Color::BLACK;
+    for (light, weight) in outside.map(|light| (light, 1.0)).chain(drawn) {
+        if let Some(arriving) = arriving(config, pos, facing, &light.sample(pos), path) {
+            // Weighted like a diffuse bounce, whose directions are drawn with density 1/2π,
+            // would weigh the same light.
+            total += (arriving * color).scale(weight / (2.0 * PI)).scale(1.0/255.0).scale(1.0/0.9);
+        }
+    }
+    Some(total)
+}
+
+/// Light from the glowing objects that a diffuse surface at `pos`, facing `facing`, sends
+/// on, as `direct_light` gathers it from the scene's lights: from a shadow ray toward a
+/// point drawn on each, weighed by the power heuristic against the bounce, which finds
+/// them drawing directions with density `bounce_pdf`. `None` if none can be aimed at.
+fn glow_light(config: &Config, pos: Vector3, facing: Vector3, color: Color, path: Path, bounce_pdf: Float) -> Option<Color> {
+    let mut lights = config.area_lights().peekable();
+    lights.peek()?;
+    let mut total = Color::BLACK;
+    for light in lights {
+        let Some(sample) = light.sample_li(pos, random::gen_pair(&mut LocalRng)) else {
+            continue;
+        };
+        if let Some(arriving) = arriving(config, pos, facing, &sample, path) {
+            let weight = light::power_heuristic(sample.pdf, bounce_pdf);
+            total += (arriving * color).scale(weight / (2.0 * PI)).scale(1.0/255.0).scale(1.0/0.9);
+        }
+    }
+    Some(total)
+}
+
+/// Light from `sample` reaching a surface at `pos` facing `facing`, times the cosine it
+/// arrives at, at the path's wavelength and thinned by its medium; `None` if it comes
+/// from behind or something is in the way.
+fn arriving(config: &Config, pos: Vector3, facing: Vector3, sample: &LightSample, path: Path) -> Option<Color> {
+    let cost = sample.dir.dot(facing);
+    if cost <= 0.0 {
+        return None;
+    }
+    if let Some((_, hit)) = closest_hit(&config.objects, Ray::new(pos, sample.dir), RayKind::Shadow) {
+        if hit.t < sample.distance {
+            return None;
+        }
+    }
+    let mut irradiance = match path.lambda {
+        Some(lambda) => spectrum::at_wavelength(sample.irradiance, lambda),
+        None => sample.irradiance
+    };
+    // The medium the surface is in thins the light on its way, and lets none through
+    // from infinitely far.
+    if let Some(medium) = path.medium {
+        irradiance = irradiance.scale((-(medium.scattering + medium.absorption) * sample.distance).exp());
+    }
+    Some(irradiance.scale(cost))
+}
+
+/// The fraction of the light from the scene's lights reaching `pos`, facing `facing`,
+/// that nothing blocks on the way: all of it where no light shines.
+fn unshadowed(config: &Config, pos: Vector3, facing: Vector3) -> Float {
+    let (mut lit, mut total) = (0.0, 0.0);
+    for light in lights(config) {
+        let sample = light.sample(pos);
+        let cost = sample.dir.dot(facing);
+        if cost <= 0.0 {
+            continue;
+        }
+        let irradiance = (sample.irradiance.x + sample.irradiance.y + sample.irradiance.z) * cost;
+        total += irradiance;
+        let blocked = closest_hit(&config.objects, Ray::new(pos, sample.dir), RayKind::Shadow).is_some_and(|(_, hit)| hit.t < sample.distance);
+        if !blocked {
+            lit += irradiance;
+        }
+    }
+    if total > 0.0 { lit / total } else { 1.0 }
+}
+
+/// Incoming light along `facing` mirrored about a normal drawn with the given `roughness`.
+fn reflect(config: &Config, ray: Ray, pos: Vector3, facing: Vector3, roughness: Float, depth: u16, path: Path) -> Color {
+    let normal = if roughness > 0.0 {
+        to_world(facing, Vector3::rand_ggx(roughness * roughness))
+    } else {
+        facing
+    };
+    let new_dir = ray.dir.reflect(normal);
+    if new_dir.dot(facing) <= 0.0 { // scattered into the surface
+        return Color::BLACK;
+    }
+    get_color(config, Ray { pos, dir: new_dir }, depth - 1, Path { kind: RayKind::Reflection, ..path })
+}
+
+/// Rotates `local`, given in a frame whose z axis is `n`, into world space.
+pub(crate) fn to_world(n: Vector3, local: Vector3) -> Vector3 {
+    let (rot_x, rot_y) = n.ons();
+    Vector3::new(
+        Vector3::new(rot_x.x, rot_y.x, n.x).dot(local),
+        Vector3::new(rot_x.y, rot_y.y, n.y).dot(local),
+        Vector3::new(rot_x.z, rot_y.z, n.z).dot(local)
+    )
+}
+
+/// The ray through the center of pixel `(x, y)`.
+pub(crate) fn primary_ray(config: &Config, x: u32, y: u32) -> Ray {
+    camera_ray(config, x as Float + 0.5, y as Float + 0.5)
+}
+
+/// The ray through the point `(x, y)` of the image, measured in pixels from its top left
+/// corner.
+pub(crate) fn camera_ray(config: &Config, x: Float, y: Float) -> Ray {
+    let widthf = config.width as Float;
+    let heightf = config.height as Float;
+
+    // The image plane one unit ahead, as wide as `fov` and as tall as the aspect ratio
+    // makes it, so resizing the image crops or extends it rather than squashing it.
+    let half_width = config.fov.tan();
+    let half_height = half_width * (heightf / widthf);
+
+    let dx = ((2.0 * x - widthf) / widthf) * half_width;
+    let dy = ((heightf - 2.0 * y) / heightf) * half_height;
+    let [right, up, forward] = camera::axes(config.pov.dir, config.up, config.roll);
+    Ray::new(config.pov.pos, forward + right.scale(dx) + up.scale(dy))
+}
+
+/// Where on the image, in pixels from its top left corner as for `camera_ray`, `point`
+/// shows up, or `None` if it is behind the camera.
+pub(crate) fn project(config: &Config, point: Vector3) -> Option<(Float, Float)> {
+    project_from(config, config.pov, config.fov, point)
+}
+
+/// Like `project`, for the camera at `pov` with half the field of view `fov` instead.
+pub(crate) fn project_from(config: &Config, pov: Ray, fov: Float, point: Vector3) -> Option<(Float, Float)> {
+    let widthf = config.width as Float;
+    let heightf = config.height as Float;
+    let half_width = fov.tan();
+    let half_height = half_width * (heightf / widthf);
+
+    let [right, up, forward] = camera::axes(pov.dir, config.up, config.roll);
+    let offset = point - pov.pos;
+    let depth = offset.dot(forward);
+    if depth <= 1e-9 {
+        return None;
+    }
+    let dx = offset.dot(right) / depth / half_width;
+    let dy = offset.dot(up) / depth / half_height;
+    Some(((dx + 1.0) * widthf / 2.0, (1.0 - dy) * heightf / 2.0))
+}
+
+/// A ray through a random point of `config.filter` around pixel `(x, y)`, with the weight
+/// the filter gives it there.
+pub(crate) fn filtered_ray(config: &Config, x: u32, y: u32, rng: &mut impl Rng) -> (Ray, Float) {
+    let (dx, dy) = config.filter.offset(rng);
+    let ray = camera_ray(config, x as Float + 0.5 + dx, y as Float + 0.5 + dy);
+    (ray, config.filter.weight(dx, dy))
+}
+
+/// Index into `config.objects` of the object seen through pixel `(x, y)`, if any.
+pub fn pick(config: &Config, x: u32, y: u32) -> Option<usize> {
+    if x >= config.width || y >= config.height {
+        return None;
+    }
+    closest_hit(&config.objects, primary_ray(config, x, y), RayKind::Camera).map(|(i, _)| i)
+}
+
+/// A ray from the camera through a random point around pixel `(x, y)`, with the weight
+/// it counts for there, through a random point of the lens if there is one.
+pub(crate) fn camera_sample(config: &Config, x: u32, y: u32, rng: &mut impl Rng) -> (Ray, Float) {
+    let (ray, weight) = filtered_ray(config, x, y, rng);
+    match config.lens {
+        Some(lens) => (lens.focus_ray(ray, camera::axes(config.pov.dir, config.up, config.roll), rng), weight),
+        None => (ray, weight)
+    }
+}
+
+/// One sample of the color seen around pixel `(x, y)`, with the weight it counts for there.
+pub fn sample_pixel(config: &Config, x: u32, y: u32, rng: &mut impl Rng) -> (Color, Float) {
+    let path = Path {
+        lambda: None,
+        diffuse: false,
+        kind: RayKind::Camera,
+        caustic_split: config.caustic_split,
+        medium: config.fog,
+        lights_sampled: false,
+        bounce_pdf: None
+    };
+    let (ray, weight) = camera_sample(config, x, y, rng);
+    if config.spectral {
+        let lambda = spectrum::sample_wavelength(rng.gen());
+        let path = Path { lambda: Some(lambda), ..path };
+        let radiance = get_color(config, ray, config.max_depth, path);
+        (spectrum::to_rgb(radiance.x, lambda), weight)
+    } else {
+        (get_color(config, ray, config.max_depth, path), weight)
+    }
+}
+
+thread_local! {
+    /// Rays this thread has cast since it last added them to `RAYS_CAST`.
+    static RAYS: Cell<u64> = const { Cell::new(0) };
+}
+
+static RAYS_CAST: AtomicU64 = AtomicU64::new(0);
+
+/// How many rays every render in this process has cast against the scene, counted as
+/// rows finish.
+pub fn rays_cast() -> u64 {
+    RAYS_CAST.load(Ordering::Relaxed)
+}
+
+fn flush_rays() {
+    RAYS_CAST.fetch_add(RAYS.with(|rays| rays.replace(0)), Ordering::Relaxed);
+}
 
-            let widthf = config.width as f64;
-            let heightf = config.height as f64;
+/// Renders `config` with `num_tries` samples at every pixel.
+pub fn make_image(config: &Config, profiler: &Profiler) -> Film {
+    make_image_with(config, profiler, None, |_| ())
+}
 
-            let fovx = config.fov;
-            let fovy = fovx * (heightf / widthf);
+/// Like `make_image`, telling `progress` how many samples each row took as it finishes.
+/// With a `seed`, every row draws from a generator seeded from it and the row, so the
+/// same seed renders the same image however the rows are shared between threads.
+pub fn make_image_with(config: &Config, profiler: &Profiler, seed: Option<u64>, progress: impl Fn(u64) + Sync) -> Film {
+    let region = Region::full(config.width, config.height);
+    render_rows(config, profiler, &region, seed, progress, false).0
+}
 
-            let dtheta = - ((2.0 * xf - widthf) / widthf) * fovx;
-            let dphi = - ((2.0 * yf - heightf) / heightf) * fovy;
-            let ray = config.pov.turn(dtheta, dphi);
+/// Like `make_image_with`, only rendering the pixels in `region`, into a film just as big
+/// whose top left pixel is the region's.
+pub fn make_tile(config: &Config, profiler: &Profiler, region: &Region, progress: impl Fn(u64) + Sync) -> Film {
+    render_rows(config, profiler, region, None, progress, false).0
+}
 
-            let mut r = 0.0;
-            let mut g = 0.0;
-            let mut b = 0.0;
-            for _ in 0..config.num_tries {
-                let ray = ray.turn(
-                    (2.0 * rng.gen::<f64>() - 1.0) * config.max_variation, 
-                    (2.0 * rng.gen::<f64>() - 1.0) * config.max_variation);
-                let color = get_color(&config.objects, ray, config.max_depth);
-                r += color.x;
-                g += color.y;
-                b += color.z;
+/// Like `make_image_with`, also counting the work that went into every pixel.
+pub fn make_image_stats(config: &Config, profiler: &Profiler, progress: impl Fn(u64) + Sync) -> (Film, PixelStats) {
+    let region = Region::full(config.width, config.height);
+    let (film, counters) = render_rows(config, profiler, &region, None, progress, true);
+    (film, PixelStats { width: config.width, height: config.height, counters })
+}
+
+/// How `render_with` renders a scene.
+#[derive(Debug, Clone)]
+pub struct RenderOptions {
+    /// Seeds every tile's generator, so the same seed renders the same image however the
+    /// tiles are shared between threads.
+    pub seed: Option<u64>,
+    /// The pixels to render, all of them if `None`; the film is just as big, with its top
+    /// left pixel the region's.
+    pub region: Option<Region>,
+    /// The width and height of the tiles the image is rendered in.
+    pub tile_size: u32
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        RenderOptions { seed: None, region: None, tile_size: 64 }
+    }
+}
+
+/// What an application embedding the renderer hears of a render as it goes, and how it
+/// asks it to stop. Called from the threads rendering, one call at a time.
+pub trait RenderCallbacks: Send {
+    /// `tile` has just been rendered, making `done` of the render's `total` samples.
+    fn tile_done(&mut self, _tile: &Region, _done: u64, _total: u64) {}
+
+    /// A band of rows of the prepass `stage`, `"path guiding"` or `"irradiance cache"`, has
+    /// just been traced, making `done` of the stage's `total` paths.
+    fn prepass_done(&mut self, _stage: &str, _done: u64, _total: u64) {}
+
+    /// Whether to stop, checked before each tile is started and after each band of a
+    /// prepass: tiles already being rendered are finished first.
+    fn cancelled(&mut self) -> bool {
+        false
+    }
+}
+
+/// Renders `config` as `options` says, in tiles, telling `callbacks` as each finishes and
+/// giving up with `RenderError::Cancelled` once it asks to stop.
+pub fn render_with(config: &Config, options: &RenderOptions, callbacks: &mut dyn RenderCallbacks) -> Result<Film, RenderError> {
+    let region = options.region.clone().unwrap_or_else(|| Region::full(config.width, config.height));
+    let trained = prepass(config, &Profiler::new(false), &region, options.seed, &mut |stage, done, total| {
+        callbacks.prepass_done(stage, done, total);
+        !callbacks.cancelled()
+    });
+    if !trained {
+        return Err(RenderError::Cancelled);
+    }
+    let base = options.seed.unwrap_or_else(rand::random);
+    let size = options.tile_size.max(1) as usize;
+    let (columns, rows) = (region.x.clone(), region.y.clone());
+    let tiles: Vec<Region> = rows.clone().step_by(size).flat_map(|y| {
+        let tile_rows = y..(y + size as u32).min(rows.end);
+        let columns = columns.clone();
+        columns.clone().step_by(size).map(move |x| Region { x: x..(x + size as u32).min(columns.end), y: tile_rows.clone() })
+    }).collect();
+
+    let total = region.pixels() * config.num_tries as u64;
+    let done = AtomicU64::new(0);
+    let stopped = AtomicBool::new(false);
+    let callbacks = Mutex::new(callbacks);
+    let films: Vec<Option<(Region, Film)>> = tiles.into_par_iter().map(|tile| {
+        if stopped.load(Ordering::Relaxed) || callbacks.lock().unwrap().cancelled() {
+            stopped.store(true, Ordering::Relaxed);
+            return None;
+        }
+        let mut film = Film::new(tile.x.len() as u32, tile.y.len() as u32);
+        for y in tile.y.clone() {
+            if let Some(seed) = options.seed {
+                random::reseed_span(seed, tile.x.start, y);
+            }
+            irradiance::start_row();
+            for x in tile.x.clone() {
+                render_pixel(config, base, x, y, |color, weight| {
+                    film.add_weighted_sample(x - tile.x.start, y - tile.y.start, color, weight);
+                });
             }
+            config.sampler.finish();
+        }
+        flush_rays();
+        let samples = tile.pixels() * config.num_tries as u64;
+        let done = done.fetch_add(samples, Ordering::Relaxed) + samples;
+        callbacks.lock().unwrap().tile_done(&tile, done, total);
+        Some((tile, film))
+    }).collect();
 
-            Vector3::new(r, g, b)
-        }).collect()
-    }).collect()
+    if stopped.into_inner() {
+        return Err(RenderError::Cancelled);
+    }
+    let mut film = Film::new(region.x.len() as u32, region.y.len() as u32);
+    for (tile, tile_film) in films.into_iter().flatten() {
+        film.add_film_at(&tile_film, tile.x.start - region.x.start, tile.y.start - region.y.start);
+    }
+    Ok(film)
+}
+
+/// Trains whatever the scene learns before rendering `region`. After each band of rows
+/// `progress` is told the stage, how many of its paths are done and how many it traces,
+/// and the prepass stops, returning `false`, once `progress` does.
+fn prepass(
+    config: &Config,
+    profiler: &Profiler,
+    region: &Region,
+    seed: Option<u64>,
+    progress: &mut dyn FnMut(&str, u64, u64) -> bool
+) -> bool {
+    let mut going = true;
+    if let Some(guide) = config.path_guide.as_ref().filter(|guide| !guide.is_trained()) {
+        let (total, mut done) = (guide.paths(config), 0);
+        profiler.span("prepass", || "path guiding".to_string(), || guide.train(config, seed, &mut |paths| {
+            done += paths;
+            going = progress("path guiding", done, total);
+            going
+        }));
+    }
+    if let Some(cache) = config.irradiance_cache.as_ref().filter(|_| going) {
+        let (total, mut done) = (region.pixels(), 0);
+        profiler.span("prepass", || "irradiance cache".to_string(), || cache.fill(config, region, seed, &mut |paths| {
+            done += paths;
+            going = progress("irradiance cache", done, total);
+            going
+        }));
+    }
+    going
+}
+
+/// Takes every sample of pixel `(x, y)`, handing each to `add` with its weight.
+fn render_pixel(config: &Config, base: u64, x: u32, y: u32, mut add: impl FnMut(Color, Float)) {
+    for index in 0..config.num_tries {
+        config.sampler.start_sample(base, x, y, index as u32, config.num_tries as u32);
+        let (color, weight) = sample_pixel(config, x, y, &mut LocalRng);
+        add(color, weight);
+    }
+}
+
+/// Renders every row, with the counts for each pixel if `count` is set.
+fn render_rows(
+    config: &Config,
+    profiler: &Profiler,
+    region: &Region,
+    seed: Option<u64>,
+    progress: impl Fn(u64) + Sync,
+    count: bool
+) -> (Film, Vec<Counters>) {
+    prepass(config, profiler, region, seed, &mut |_, _, _| true);
+    // What every pixel's samples draw from, if the sampler has them draw the same.
+    let base = seed.unwrap_or_else(rand::random);
+    let mut film = Film::new(region.x.len() as u32, region.y.len() as u32);
+    let rows: Vec<Vec<Counters>> = film.par_rows_mut().map(|mut row| {
+        let y = region.y.start + row.y;
+        if let Some(seed) = seed {
+            random::reseed_row(seed, y);
+        }
+        irradiance::start_row();
+        let counters = profiler.span("tile", || format!("row {}", y), || {
+            let mut counters = Vec::with_capacity(if count { config.width as usize } else { 0 });
+            stats::take();
+            for x in region.x.clone() {
+                render_pixel(config, base, x, y, |color, weight| row.add_weighted_sample(x - region.x.start, color, weight));
+                if count {
+                    counters.push(stats::take());
+                }
+            }
+            config.sampler.finish();
+            counters
+        });
+        flush_rays();
+        progress(region.x.len() as u64 * config.num_tries as u64);
+        counters
+    }).collect();
+    (film, rows.concat())
 }
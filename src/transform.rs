@@ -0,0 +1,154 @@
+use crate::linalg::{Aabb, Float, Vector3};
+use crate::shapes::{Hit, Ray, Shape, SurfaceSample};
+
+/// A 3×3 matrix, row by row.
+type Matrix = [[Float; 3]; 3];
+
+fn apply(m: &Matrix, v: Vector3) -> Vector3 {
+    let row = |r: &[Float; 3]| r[0] * v.x + r[1] * v.y + r[2] * v.z;
+    Vector3::new(row(&m[0]), row(&m[1]), row(&m[2]))
+}
+
+fn multiply(a: &Matrix, b: &Matrix) -> Matrix {
+    let mut product = [[0.0; 3]; 3];
+    for (i, row) in product.iter_mut().enumerate() {
+        for (j, value) in row.iter_mut().enumerate() {
+            *value = (0..3).map(|k| a[i][k] * b[k][j]).sum();
+        }
+    }
+    product
+}
+
+fn transpose(m: &Matrix) -> Matrix {
+    [0, 1, 2].map(|i| [0, 1, 2].map(|j| m[j][i]))
+}
+
+fn diagonal(v: Vector3) -> Matrix {
+    [[v.x, 0.0, 0.0], [0.0, v.y, 0.0], [0.0, 0.0, v.z]]
+}
+
+/// Turns by `degrees` about the x, then the y, then the z axis, counterclockwise as seen
+/// looking back down each.
+fn rotation(degrees: Vector3) -> Matrix {
+    let (sx, cx) = degrees.x.to_radians().sin_cos();
+    let (sy, cy) = degrees.y.to_radians().sin_cos();
+    let (sz, cz) = degrees.z.to_radians().sin_cos();
+    let x = [[1.0, 0.0, 0.0], [0.0, cx, -sx], [0.0, sx, cx]];
+    let y = [[cy, 0.0, sy], [0.0, 1.0, 0.0], [-sy, 0.0, cy]];
+    let z = [[cz, -sz, 0.0], [sz, cz, 0.0], [0.0, 0.0, 1.0]];
+    multiply(&z, &multiply(&y, &x))
+}
+
+/// Where an object is put from where its shape was given: scaled along the axes, then
+/// rotated, then translated, all about the origin.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Transform {
+    pub translate: Vector3,
+    /// Degrees turned about the x, then the y, then the z axis.
+    pub rotate: Vector3,
+    /// Positive factors along the x, y and z axes.
+    pub scale: Vector3,
+    /// Rotation times scale, taking directions to the world.
+    linear: Matrix,
+    /// Its inverse, taking directions back to the shape's own space.
+    inverse: Matrix
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Transform::new(Vector3::new(0.0, 0.0, 0.0), Vector3::new(0.0, 0.0, 0.0), Vector3::new(1.0, 1.0, 1.0))
+    }
+}
+
+impl Transform {
+    pub fn new(translate: Vector3, rotate: Vector3, scale: Vector3) -> Transform {
+        let turn = rotation(rotate);
+        let shrink = diagonal(Vector3::new(1.0 / scale.x, 1.0 / scale.y, 1.0 / scale.z));
+        Transform {
+            translate,
+            rotate,
+            scale,
+            linear: multiply(&turn, &diagonal(scale)),
+            inverse: multiply(&shrink, &transpose(&turn))
+        }
+    }
+
+    /// Whether it leaves everything where it is.
+    pub fn is_identity(&self) -> bool {
+        *self == Transform::default()
+    }
+
+    /// Where `point` of the shape ends up.
+    pub fn point(&self, point: Vector3) -> Vector3 {
+        apply(&self.linear, point) + self.translate
+    }
+
+    /// The point of the shape that ends up at `point`.
+    pub fn inverse_point(&self, point: Vector3) -> Vector3 {
+        apply(&self.inverse, point - self.translate)
+    }
+
+    /// `dir` in the shape's own space, without normalizing it.
+    pub fn inverse_vector(&self, dir: Vector3) -> Vector3 {
+        apply(&self.inverse, dir)
+    }
+
+    /// The unit normal of the placed shape where the shape's own is `normal`, which
+    /// stays at right angles to the surface however it is stretched.
+    pub fn normal(&self, normal: Vector3) -> Vector3 {
+        apply(&transpose(&self.inverse), normal).normalize()
+    }
+}
+
+/// A shape placed by a transform, traced by taking rays into the shape's own space.
+pub struct Transformed {
+    pub shape: Box<dyn Shape>,
+    pub transform: Transform
+}
+
+impl Shape for Transformed {
+    fn intersect(&self, ray: Ray, t_max: Float) -> Option<Hit> {
+        // Distances along the shape's rays are `len` times those along the world's.
+        let dir = self.transform.inverse_vector(ray.dir);
+        let len = dir.size();
+        let local = Ray { pos: self.transform.inverse_point(ray.pos), dir: dir.scale(1.0 / len) };
+        let hit = self.shape.intersect(local, t_max * len)?;
+        Some(Hit::new(ray, hit.t / len, self.transform.normal(hit.normal), hit.uv))
+    }
+
+    /// Known only when the shape is stretched the same along every axis.
+    fn area(&self) -> Option<Float> {
+        let s = self.transform.scale;
+        if s.x == s.y && s.y == s.z {
+            self.shape.area().map(|area| area * s.x * s.x)
+        } else {
+            None
+        }
+    }
+
+    /// Like `area`, only when the shape is stretched the same along every axis, which
+    /// leaves points drawn evenly spread.
+    fn sample_surface(&self, u: (Float, Float)) -> Option<SurfaceSample> {
+        let s = self.transform.scale;
+        if s.x != s.y || s.y != s.z {
+            return None;
+        }
+        let sample = self.shape.sample_surface(u)?;
+        Some(SurfaceSample {
+            point: self.transform.point(sample.point),
+            normal: self.transform.normal(sample.normal),
+            pdf: sample.pdf / (s.x * s.x)
+        })
+    }
+
+    fn as_transformed(&self) -> Option<&Transformed> {
+        Some(self)
+    }
+
+    /// The box around the shape's own box, placed: looser than the placed shape's
+    /// tightest box when it's turned, but never too small.
+    fn bounds(&self) -> Option<Aabb> {
+        let corners = self.shape.bounds()?.corners().map(|corner| self.transform.point(corner));
+        Some(corners[1..].iter().fold(Aabb::point(corners[0]), |bounds, &corner| bounds.expand(corner)))
+    }
+}
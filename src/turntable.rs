@@ -0,0 +1,25 @@
+use crate::config::Config;
+use crate::linalg::{Float, Vector3};
+use crate::shapes::Ray;
+use crate::trace::{camera_ray, closest_hit, RayKind};
+
+/// `v` turned `angle` radians about the unit `axis`, counterclockwise as seen from its tip.
+fn rotate(v: Vector3, axis: Vector3, angle: Float) -> Vector3 {
+    let (sin, cos) = angle.sin_cos();
+    v.scale(cos) + axis.cross(v).scale(sin) + axis.scale(axis.dot(v) * (1.0 - cos))
+}
+
+/// The camera `pov` carried `angle` radians around the axis through `pivot` along `up`,
+/// still looking the same way relative to the pivot.
+pub fn orbit(pov: Ray, up: Vector3, pivot: Vector3, angle: Float) -> Ray {
+    let axis = up.normalize();
+    let pos = pivot + rotate(pov.pos - pivot, axis, angle);
+    Ray::new(pos, rotate(pov.dir, axis, angle))
+}
+
+/// What a turntable spins around unless told otherwise: whatever the center of the image
+/// sees, or the origin if that's nothing.
+pub fn default_pivot(config: &Config) -> Vector3 {
+    let center = camera_ray(config, config.width as Float / 2.0, config.height as Float / 2.0);
+    closest_hit(&config.objects, center, RayKind::Camera).map_or(Vector3::new(0.0, 0.0, 0.0), |(_, hit)| hit.point)
+}
@@ -0,0 +1,98 @@
+use std::cell::RefCell;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::{ptr, slice, str};
+
+use crate::config::parse_config;
+use crate::profile::Profiler;
+use crate::trace::make_image;
+
+// Entry points for rendering in a web page, in builds for `wasm32`. Without generated
+// bindings only numbers cross over to JavaScript, so the page copies the scene text into
+// memory it got from `rt_alloc`, calls `render` and reads the pixels from the memory it
+// points to, `width()` by `height()` of them as RGBA bytes; `web/tracer.js` wraps that up.
+// Meshes and other files can't be loaded, and rendering takes the page's one thread.
+
+/// What the last `render` made: the image as `(width, height, rgba)`, or why it failed.
+type Output = Result<(u32, u32, Vec<u8>), String>;
+
+thread_local! {
+    static OUTPUT: RefCell<Output> = const { RefCell::new(Err(String::new())) };
+}
+
+// There is no system for `rand` to draw a seed from here; noise that differs between
+// page loads isn't worth asking JavaScript for, so every seed counts up from a constant.
+fn counting_seed(dest: &mut [u8]) -> Result<(), getrandom::Error> {
+    static NEXT: AtomicU64 = AtomicU64::new(0x2545_f491_4f6c_dd1d);
+    for chunk in dest.chunks_mut(8) {
+        let value = NEXT.fetch_add(0x9e37_79b9_7f4a_7c15, Ordering::Relaxed);
+        chunk.copy_from_slice(&value.to_le_bytes()[..chunk.len()]);
+    }
+    Ok(())
+}
+
+getrandom::register_custom_getrandom!(counting_seed);
+
+/// `len` bytes for the page to write into, to be given back to `rt_free`.
+#[no_mangle]
+pub extern "C" fn rt_alloc(len: usize) -> *mut u8 {
+    let mut bytes = Vec::<u8>::with_capacity(len);
+    let ptr = bytes.as_mut_ptr();
+    std::mem::forget(bytes);
+    ptr
+}
+
+/// Gives back memory from `rt_alloc`.
+///
+/// # Safety
+/// `ptr` and `len` must be exactly what `rt_alloc` was called with and returned.
+#[no_mangle]
+pub unsafe extern "C" fn rt_free(ptr: *mut u8, len: usize) {
+    drop(Vec::from_raw_parts(ptr, 0, len));
+}
+
+/// Renders the scene whose text is the `len` bytes at `scene`, and gives where its pixels
+/// are, or null if it failed, for `error` to explain. The pixels stay until the next call.
+///
+/// # Safety
+/// `scene` must point to `len` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn render(scene: *const u8, len: usize) -> *const u8 {
+    let output = match str::from_utf8(slice::from_raw_parts(scene, len)) {
+        Ok(raw) => parse_config(raw).map_err(|err| err.to_string()).map(|config| {
+            let image = make_image(&config, &Profiler::new(false)).to_rgb8(1.0);
+            let rgba = image.pixels().flat_map(|pixel| [pixel[0], pixel[1], pixel[2], 255]).collect();
+            (config.width, config.height, rgba)
+        }),
+        Err(_) => Err("scene text isn't UTF-8".to_string())
+    };
+    OUTPUT.with(|last| {
+        *last.borrow_mut() = output;
+        match &*last.borrow() {
+            Ok((_, _, rgba)) => rgba.as_ptr(),
+            Err(_) => ptr::null()
+        }
+    })
+}
+
+/// Width of the last image rendered, or 0 if it failed.
+#[no_mangle]
+pub extern "C" fn width() -> u32 {
+    OUTPUT.with(|last| last.borrow().as_ref().map_or(0, |&(width, _, _)| width))
+}
+
+/// Height of the last image rendered, or 0 if it failed.
+#[no_mangle]
+pub extern "C" fn height() -> u32 {
+    OUTPUT.with(|last| last.borrow().as_ref().map_or(0, |&(_, height, _)| height))
+}
+
+/// Where the UTF-8 message for the last failed render is, `error_len()` bytes of it.
+#[no_mangle]
+pub extern "C" fn error() -> *const u8 {
+    OUTPUT.with(|last| last.borrow().as_ref().err().map_or(ptr::null(), |message| message.as_ptr()))
+}
+
+#[no_mangle]
+pub extern "C" fn error_len() -> usize {
+    OUTPUT.with(|last| last.borrow().as_ref().err().map_or(0, String::len))
+}
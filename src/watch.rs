@@ -0,0 +1,74 @@
+use std::ffi::OsString;
+use std::io;
+use std::path::Path;
+use std::sync::mpsc::{channel, Receiver};
+use std::time::Duration;
+
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+
+/// How long a file has to go without changing before it counts as written. Editors
+/// often save in several steps: truncating, writing in pieces, or renaming a temporary
+/// file over the original.
+const SETTLE: Duration = Duration::from_millis(100);
+
+/// Tells when a file changes, from the operating system's notifications rather than by
+/// polling it.
+pub struct FileWatcher {
+    /// Kept alive for as long as notifications are wanted.
+    _watcher: RecommendedWatcher,
+    events: Receiver<notify::Result<Event>>,
+    name: OsString
+}
+
+impl FileWatcher {
+    /// Starts watching the file at `path`. Its directory is watched rather than the file
+    /// itself, so that editors replacing the file with a new one don't end the watch.
+    pub fn new(path: &Path) -> io::Result<FileWatcher> {
+        let to_io = |err: notify::Error| io::Error::other(err.to_string());
+        let name = path.file_name()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "not a file path"))?
+            .to_owned();
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new(".")
+        };
+        let (sender, events) = channel();
+        let mut watcher = notify::recommended_watcher(sender).map_err(to_io)?;
+        watcher.watch(dir, RecursiveMode::NonRecursive).map_err(to_io)?;
+        Ok(FileWatcher { _watcher: watcher, events, name })
+    }
+
+    fn concerns_file(&self, event: &notify::Result<Event>) -> bool {
+        match event {
+            Ok(event) => !matches!(event.kind, EventKind::Access(_))
+                && event.paths.iter().any(|path| path.file_name() == Some(self.name.as_os_str())),
+            // A lost notification may have been about the file.
+            Err(_) => true
+        }
+    }
+
+    /// Whether the file changed since this or `wait` last returned, waiting for the
+    /// change to settle if it did.
+    pub fn changed(&self) -> bool {
+        let changed = self.events.try_iter().fold(false, |changed, event| changed | self.concerns_file(&event));
+        if changed {
+            self.settle();
+        }
+        changed
+    }
+
+    /// Blocks until the file changes and the change settles.
+    pub fn wait(&self) {
+        while let Ok(event) = self.events.recv() {
+            if self.concerns_file(&event) {
+                break;
+            }
+        }
+        self.settle();
+    }
+
+    /// Waits until nothing has happened for `SETTLE`, discarding what does.
+    fn settle(&self) {
+        while self.events.recv_timeout(SETTLE).is_ok() {}
+    }
+}
@@ -0,0 +1,75 @@
+use std::fs;
+
+use graphics::cache::{cache_path, decode, encode, load_cached, source_hash};
+use graphics::config::{parse_config, parse_config_file};
+
+const SCENE: &str = "0 -5 1\n0 1 0\n64 48\n0.6\n4 8\n0.1\n1 1\n\
+                     white 0 opaque plane 0 0 0 0 0 1\n\
+                     rgb(250,200,150) 0.5 mirror sphere 0 0 1 1 roughness=0.3\n\
+                     white 0 glass sphere 2 0 1 0.5 ior=1.7\n";
+
+#[test]
+fn source_hash_is_fnv1a() {
+    assert_eq!(source_hash(b""), 0xcbf2_9ce4_8422_2325);
+    assert_eq!(source_hash(b"a"), 0xaf63_dc4c_8601_ec8c);
+}
+
+#[test]
+fn decoding_gives_back_what_was_encoded() {
+    let config = parse_config(SCENE).unwrap();
+    let bytes = encode(&config, 42, &[]).unwrap();
+    let decoded = decode(&bytes, 42).unwrap();
+
+    assert_eq!(encode(&decoded, 42, &[]).unwrap(), bytes);
+    assert_eq!((decoded.width, decoded.height), (64, 48));
+    assert_eq!((decoded.max_depth, decoded.num_tries), (4, 8));
+    assert_eq!(decoded.objects.len(), 3);
+    assert_eq!(decoded.objects[1].color.x, 250.0);
+    assert_eq!(decoded.objects[2].line, config.objects[2].line);
+}
+
+#[test]
+fn caches_for_other_sources_are_ignored() {
+    let config = parse_config(SCENE).unwrap();
+    let bytes = encode(&config, 42, &[]).unwrap();
+    assert!(decode(&bytes, 43).is_none());
+    assert!(decode(&bytes[..bytes.len() - 1], 42).is_none());
+}
+
+#[test]
+fn meshes_are_cached_until_their_files_change() {
+    let dir = std::env::temp_dir().join(format!("cache-test-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let (scene, model) = (dir.join("scene.txt"), dir.join("quad.obj"));
+    let source = "0 -5 1\n0 1 0\n8 8\n0.6\n4 1\n0\n1 1\n\
+                  white 0 opaque mesh quad.obj 0 0 0 1\n\
+                  white 0 mirror mesh quad.obj 2 0 0 0.5\n";
+    fs::write(&scene, source).unwrap();
+    fs::write(&model, "v 0 0 0\nv 1 0 0\nv 1 0 1\nv 0 0 1\nvn 0 -1 0\nvn 0 -1 1\nf 1//1 2//1 3//2 4//2\n").unwrap();
+
+    let config = load_cached(&scene).unwrap();
+    let bytes = fs::read(cache_path(&scene)).unwrap();
+    let decoded = decode(&bytes, source_hash(source.as_bytes())).unwrap();
+    assert_eq!(decoded.objects.len(), config.objects.len());
+    assert_eq!(encode(&decoded, 0, &[]), encode(&config, 0, &[]));
+    // Vertex normals survive, so smooth meshes stay smooth.
+    assert!(decoded.objects[0].shape.as_mesh().unwrap().data.triangle(0).normals().is_some());
+
+    fs::write(&model, "v 0 0 0\nv 1 0 0\nv 1 0 1\nf 1 2 3\n").unwrap();
+    assert!(decode(&bytes, source_hash(source.as_bytes())).is_none());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn every_golden_scene_can_be_cached() {
+    for entry in fs::read_dir("tests/golden").unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().is_none_or(|ext| ext != "config") {
+            continue;
+        }
+        let config = parse_config_file(&path).unwrap();
+        let bytes = encode(&config, 0, &[]).unwrap_or_else(|| panic!("{} can't be cached", path.display()));
+        let decoded = decode(&bytes, 0).unwrap();
+        assert_eq!(encode(&decoded, 0, &[]).unwrap(), bytes, "{} changes when cached", path.display());
+    }
+}
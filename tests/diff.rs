@@ -0,0 +1,27 @@
+use graphics::config::parse_config;
+use graphics::diff::{diff, Change};
+
+const HEADER: &str = "0 -5 1\n0 1 0\n16 16\n0.6\n4 1\n0\n1 1\nsun white 0 0 -1 1 0.05\n";
+
+fn change(old: &str, new: &str) -> Change {
+    diff(&parse_config(&format!("{}{}", HEADER, old)).unwrap(), &parse_config(&format!("{}{}", HEADER, new)).unwrap())
+}
+
+#[test]
+fn hiding_an_object_from_the_camera_changes_it() {
+    let sphere = "white 0 opaque sphere 0 0 1 1";
+    assert_eq!(change(sphere, sphere), Change::Nothing);
+    assert_eq!(
+        change(sphere, &format!("{} camera_visible=false", sphere)),
+        Change::Objects { old: vec![0], new: vec![0] }
+    );
+}
+
+#[test]
+fn changing_the_irradiance_cache_changes_everything() {
+    let sphere = "white 0 opaque sphere 0 0 1 1";
+    let cached = |accuracy: f64| format!("irradiance_cache {} 64 0.5 10\n{}", accuracy, sphere);
+    assert_eq!(change(&cached(0.2), &cached(0.2)), Change::Nothing);
+    assert_eq!(change(&cached(0.2), &cached(0.1)), Change::Everything);
+    assert_eq!(change(sphere, &cached(0.2)), Change::Everything);
+}
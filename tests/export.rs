@@ -0,0 +1,26 @@
+use std::fs;
+
+use graphics::config::parse_config_file;
+use graphics::export::export_scene;
+
+#[test]
+fn mesh_files_are_named_from_the_exported_scene() {
+    let dir = std::env::temp_dir().join(format!("export-test-{}", std::process::id()));
+    for sub in ["models", "scenes", "out/renders"] {
+        fs::create_dir_all(dir.join(sub)).unwrap();
+    }
+    fs::write(dir.join("models/quad.obj"), "v 0 0 0\nv 1 0 0\nv 1 0 1\nv 0 0 1\nf 1 2 3 4\n").unwrap();
+    fs::write(
+        dir.join("scenes/scene.txt"),
+        "0 -5 1\n0 1 0\n8 8\n0.6\n4 1\n0\n1 1\nwhite 0 opaque mesh ../models/quad.obj 0 0 0 1\n"
+    ).unwrap();
+
+    let config = parse_config_file(&dir.join("scenes/scene.txt")).unwrap();
+    export_scene(&config, &dir.join("out/renders/scene.txt")).unwrap();
+    let text = fs::read_to_string(dir.join("out/renders/scene.txt")).unwrap();
+    assert!(text.contains(" mesh ../../models/quad.obj "), "{}", text);
+
+    // Re-reading the export finds the same file.
+    assert!(parse_config_file(&dir.join("out/renders/scene.txt")).is_ok());
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,68 @@
+use std::convert::TryInto;
+use std::fs;
+
+use graphics::output::write_multipart_exr;
+
+fn int(bytes: &[u8], at: usize) -> i32 {
+    i32::from_le_bytes(bytes[at..at + 4].try_into().unwrap())
+}
+
+/// A null-terminated string at `at`, and where the next thing starts.
+fn text(bytes: &[u8], at: usize) -> (String, usize) {
+    let end = at + bytes[at..].iter().position(|&byte| byte == 0).unwrap();
+    (String::from_utf8(bytes[at..end].to_vec()).unwrap(), end + 1)
+}
+
+#[test]
+fn passes_become_parts_that_point_at_their_own_scanlines() {
+    let (width, height) = (3, 2);
+    let channel = |name: &str, value: f32| (name.to_string(), vec![value; width * height]);
+    let parts = vec![
+        ("rgba".to_string(), vec![channel("R", 1.0), channel("G", 2.0), channel("B", 3.0)]),
+        ("object_id".to_string(), vec![channel("object_id.Y", 4.0)])
+    ];
+    let path = std::env::temp_dir().join(format!("multipart-{}.exr", std::process::id()));
+    write_multipart_exr(&path, width as u32, height as u32, &parts, &[("note".to_string(), "hi".to_string())]).unwrap();
+    let bytes = fs::read(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+
+    assert_eq!(&bytes[..4], &[0x76, 0x2f, 0x31, 0x01]);
+    assert_eq!(int(&bytes, 4) & 0x1000, 0x1000, "the multi-part flag is set");
+
+    // Headers, each a list of attributes, until an empty one.
+    let mut at = 8;
+    let mut headers = vec![];
+    while bytes[at] != 0 {
+        let mut header = vec![];
+        while bytes[at] != 0 {
+            let (name, next) = text(&bytes, at);
+            let (_, next) = text(&bytes, next);
+            let size = int(&bytes, next) as usize;
+            header.push((name, bytes[next + 4..next + 4 + size].to_vec()));
+            at = next + 4 + size;
+        }
+        headers.push(header);
+        at += 1;
+    }
+    at += 1;
+    let value = |header: &Vec<(String, Vec<u8>)>, name: &str| header.iter().find(|(key, _)| key == name).unwrap().1.clone();
+    assert_eq!(headers.len(), 2);
+    assert_eq!(value(&headers[1], "name"), b"object_id");
+    assert_eq!(value(&headers[1], "type"), b"scanlineimage");
+    assert_eq!(value(&headers[0], "note"), b"hi");
+
+    // Each part's offsets lead to its own scanlines, in order.
+    for (part, (_, channels)) in parts.iter().enumerate() {
+        assert_eq!(int(&value(&headers[part], "chunkCount"), 0), height as i32);
+        for y in 0..height {
+            let offset = u64::from_le_bytes(bytes[at..at + 8].try_into().unwrap()) as usize;
+            at += 8;
+            assert_eq!(int(&bytes, offset), part as i32);
+            assert_eq!(int(&bytes, offset + 4), y as i32);
+            assert_eq!(int(&bytes, offset + 8) as usize, channels.len() * width * 4);
+            // Channels are sorted by name, so B comes first.
+            let first = f32::from_le_bytes(bytes[offset + 12..offset + 16].try_into().unwrap());
+            assert_eq!(first, if part == 0 { 3.0 } else { 4.0 });
+        }
+    }
+}
@@ -0,0 +1,17 @@
+use std::ffi::CStr;
+
+use graphics::ffi::*;
+
+#[test]
+fn spheres_with_nan_or_negative_values_are_refused() {
+    unsafe {
+        let scene = rt_scene_new(8, 8);
+        assert_eq!(rt_scene_add_sphere(scene, 0.0, 5.0, 0.0, 1.0, 1.0, 1.0, 1.0, 0.0, RT_DIFFUSE), 0);
+        for (radius, red, emission) in [(f64::NAN, 1.0, 0.0), (1.0, f64::NAN, 0.0), (1.0, -0.5, 0.0),
+                                        (1.0, 1.0, f64::NAN), (1.0, 1.0, -1.0), (1.0, 1.0, f64::INFINITY)] {
+            assert_eq!(rt_scene_add_sphere(scene, 0.0, 5.0, 0.0, radius, red, 1.0, 1.0, emission, RT_DIFFUSE), -1);
+            assert!(!CStr::from_ptr(rt_last_error()).to_bytes().is_empty());
+        }
+        rt_scene_free(scene);
+    }
+}
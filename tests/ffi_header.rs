@@ -0,0 +1,138 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+// `include/graphics.h` is written by hand, so this keeps it declaring what `src/ffi.rs`
+// exports: every function with the same C types and parameter names, and every constant
+// with the same value.
+
+/// A function's return type and its parameters' types and names, all as C spells them.
+type Signature = (String, Vec<(String, String)>);
+
+/// `c_type` with a space either side of every `*` and single spaces between words.
+fn normalized(c_type: &str) -> String {
+    c_type.replace('*', " * ").split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// The C spelling of the Rust type `rust`.
+fn c_type(rust: &str) -> String {
+    let rust = rust.trim();
+    if let Some(pointee) = rust.strip_prefix("*const") {
+        return normalized(&format!("const {} *", c_type(pointee)));
+    }
+    if let Some(pointee) = rust.strip_prefix("*mut") {
+        return normalized(&format!("{} *", c_type(pointee)));
+    }
+    match rust {
+        "" => "void",
+        "c_char" => "char",
+        "c_int" => "int",
+        "u8" => "uint8_t",
+        "u16" => "uint16_t",
+        "u32" => "uint32_t",
+        "f64" => "double",
+        "usize" => "size_t",
+        other => other
+    }.to_string()
+}
+
+/// The text after `start` in `text` up to the first `end` after it, and the rest.
+fn between<'a>(text: &'a str, start: &str, end: &str) -> Option<(&'a str, &'a str)> {
+    let from = text.find(start)? + start.len();
+    let to = from + text[from..].find(end)?;
+    Some((&text[from..to], &text[to + end.len()..]))
+}
+
+/// The functions and constants `src/ffi.rs` exports.
+fn exported(source: &str) -> (BTreeMap<String, Signature>, BTreeMap<String, String>) {
+    let mut functions = BTreeMap::new();
+    let mut rest = source;
+    while let Some((name, after)) = between(rest, "extern \"C\" fn ", "(") {
+        let (params, after) = between(after, "", ")").unwrap();
+        let (ret, after) = between(after, "", "{").unwrap();
+        let params = params.split(',')
+            .filter(|param| !param.trim().is_empty())
+            .map(|param| {
+                let (name, rust) = param.split_once(':').unwrap();
+                (c_type(rust), name.trim().to_string())
+            })
+            .collect();
+        let ret = c_type(ret.trim().trim_start_matches("->"));
+        functions.insert(name.trim().to_string(), (ret, params));
+        rest = after;
+    }
+    let constants = source.lines()
+        .filter_map(|line| line.strip_prefix("pub const "))
+        .map(|line| {
+            let (name, value) = line.split_once(':').unwrap();
+            let value = value.split_once('=').unwrap().1.trim().trim_end_matches(';');
+            (name.trim().to_string(), value.to_string())
+        })
+        .collect();
+    (functions, constants)
+}
+
+/// The functions and constants `include/graphics.h` declares.
+fn declared(header: &str) -> (BTreeMap<String, Signature>, BTreeMap<String, String>) {
+    let mut code = String::new();
+    let mut rest = header;
+    while let Some(start) = rest.find("/*") {
+        code.push_str(&rest[..start]);
+        rest = &rest[start + rest[start..].find("*/").unwrap() + 2..];
+    }
+    code.push_str(rest);
+
+    let mut constants = BTreeMap::new();
+    let mut declarations = String::new();
+    for line in code.lines() {
+        let mut words = line.split_whitespace();
+        match words.next() {
+            Some("#define") => if let (Some(name), Some(value)) = (words.next(), words.next()) {
+                constants.insert(name.to_string(), value.to_string());
+            },
+            Some(word) if word.starts_with('#') || word == "extern" || word == "}" => (),
+            _ => {
+                declarations.push_str(line);
+                declarations.push(' ');
+            }
+        }
+    }
+
+    let functions = declarations.split(';')
+        .filter(|declaration| declaration.contains('('))
+        .map(|declaration| {
+            let (head, params) = declaration.split_once('(').unwrap();
+            let head = normalized(head);
+            let (ret, name) = head.rsplit_once(' ').unwrap();
+            let params = params.trim().trim_end_matches(')');
+            let params = if params.trim() == "void" { vec![] } else {
+                params.split(',')
+                    .map(|param| {
+                        let param = normalized(param);
+                        let (c_type, name) = param.rsplit_once(' ').unwrap();
+                        (c_type.to_string(), name.to_string())
+                    })
+                    .collect()
+            };
+            (name.to_string(), (ret.to_string(), params))
+        })
+        .collect();
+    (functions, constants)
+}
+
+#[test]
+fn header_declares_the_exports() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let (functions, constants) = exported(&fs::read_to_string(root.join("src/ffi.rs")).unwrap());
+    let (declared_functions, declared_constants) = declared(&fs::read_to_string(root.join("include/graphics.h")).unwrap());
+    assert!(!functions.is_empty(), "no functions found in src/ffi.rs");
+    for (name, signature) in &functions {
+        assert_eq!(declared_functions.get(name), Some(signature), "include/graphics.h declares {} differently", name);
+    }
+    for name in declared_functions.keys() {
+        assert!(functions.contains_key(name), "include/graphics.h declares {}, which src/ffi.rs doesn't export", name);
+    }
+    for (name, value) in &constants {
+        assert_eq!(declared_constants.get(name), Some(value), "include/graphics.h defines {} differently", name);
+    }
+}
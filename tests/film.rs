@@ -0,0 +1,78 @@
+use graphics::film::{Film, Region, SharedFilm};
+use graphics::filter::Filter;
+use graphics::linalg::Float;
+use graphics::trace::Color;
+
+const WIDTH: u32 = 24;
+const HEIGHT: u32 = 16;
+/// Samples taken across each side of a pixel, so every pixel takes its square.
+const STEPS: u32 = 4;
+
+/// A scene of smooth shading and hard stripes, seen at `(x, y)` in pixels.
+fn scene(x: Float, y: Float) -> Color {
+    let stripe = if (x / 3.0).floor() as i64 % 2 == 0 { 200.0 } else { 20.0 };
+    Color::new(stripe, 255.0 * y / HEIGHT as Float, 128.0)
+}
+
+/// Every sample of every pixel, spread evenly over it, with where it was taken.
+fn samples() -> impl Iterator<Item = (Float, Float, Color)> {
+    (0..HEIGHT * STEPS).flat_map(|row| (0..WIDTH * STEPS).map(move |column| {
+        let x = (column as Float + 0.5) / STEPS as Float;
+        let y = (row as Float + 0.5) / STEPS as Float;
+        (x, y, scene(x, y))
+    }))
+}
+
+/// The mean of every pixel as a render scales it, by one over the samples each took.
+fn mean(film: &Film) -> Color {
+    let total = film.pixels().into_iter().fold(Color::BLACK, |sum, pixel| sum + pixel);
+    total.scale(1.0 / (WIDTH * HEIGHT * STEPS * STEPS) as Float)
+}
+
+fn unfiltered() -> Film {
+    let mut film = Film::new(WIDTH, HEIGHT);
+    for (x, y, color) in samples() {
+        film.add_sample(x as u32, y as u32, color);
+    }
+    film
+}
+
+fn assert_close(filtered: Color, unfiltered: Color, how: &str) {
+    let gap = filtered - unfiltered;
+    let off = gap.x.abs().max(gap.y.abs()).max(gap.z.abs());
+    assert!(off < 0.01 * 255.0, "{} is {:?} on average against {:?} unfiltered", how, filtered, unfiltered);
+}
+
+// A filter spreads each sample over its neighbors but still counts it once, so filtering
+// moves light around without making the image brighter.
+#[test]
+fn filtering_keeps_brightness() {
+    let expected = mean(&unfiltered());
+    for filter in [Filter::Box, Filter::Tent, Filter::Gaussian, Filter::Mitchell] {
+        let mut film = Film::new(WIDTH, HEIGHT);
+        for (x, y, color) in samples() {
+            film.add_filtered_sample(x, y, color, filter);
+        }
+        assert_eq!(film.min_samples(), STEPS * STEPS, "{} counted the wrong samples", filter);
+        assert_close(mean(&film), expected, &format!("a {} filtered film", filter));
+
+        let mut tiled = Film::new(WIDTH, HEIGHT);
+        for top in (0..HEIGHT).step_by(8) {
+            for left in (0..WIDTH).step_by(8) {
+                let region = Region { x: left..left + 8, y: top..top + 8 };
+                let mut tile = tiled.tile(&region, filter);
+                for (x, y, color) in samples().filter(|&(x, y, _)| region.contains(x as u32, y as u32)) {
+                    tile.add_filtered_sample(x, y, color, filter);
+                }
+                tiled.merge_tile(&tile);
+            }
+        }
+        assert_close(mean(&tiled), expected, &format!("a {} filtered film in tiles", filter));
+
+        let shared = SharedFilm::new(WIDTH, HEIGHT);
+        for (x, y, color) in samples() {
+            shared.add_filtered_sample(x, y, color, filter);
+        }
+        assert_close(mean(&shared.into_film()), expected, &format!("a shared {} filtered film", filter));
+    }
+}
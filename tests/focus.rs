@@ -0,0 +1,71 @@
+use graphics::cache::{decode, encode};
+use graphics::camera::{key_at, FocusKey};
+use graphics::config::parse_config;
+use graphics::linalg::Float;
+
+// Focus keys set a thin lens's focal length and focus distance over an animation's
+// frames, and the field of view breathes with them as a real lens's does.
+
+fn key(frame: u32, focal_length: Float, focus: Float) -> FocusKey {
+    FocusKey { frame, focal_length, focus }
+}
+
+fn scene(keys: &str) -> String {
+    format!("0 0 0\n0 1 0\n8 8\n40 deg\n1 1\n0\n1 1\nlens 0.01 5 0 0\n{}white 0 opaque sphere 0 5 0 1\n", keys)
+}
+
+#[test]
+fn focusing_closer_narrows_the_view() {
+    // A 50 mm lens on a full-frame sensor sees about 39.6 degrees across at infinity.
+    let far = key(0, 50.0, 1e9).fov();
+    assert!(((2.0 * far).to_degrees() - 39.6).abs() < 0.05, "{}", (2.0 * far).to_degrees());
+    let near = key(0, 50.0, 0.5).fov();
+    assert!(near < far);
+    // At half a meter the lens sits 50/0.9 mm from the sensor.
+    assert!(((near.tan() * 0.05 / 0.9) - 0.018).abs() < 1e-6);
+}
+
+#[test]
+fn keys_are_eased_between_and_held_outside() {
+    let keys = [key(10, 35.0, 2.0), key(20, 85.0, 6.0)];
+    assert_eq!(key_at(&keys, 0), Some(key(0, 35.0, 2.0)));
+    assert_eq!(key_at(&keys, 15), Some(key(15, 60.0, 4.0)));
+    assert_eq!(key_at(&keys, 30), Some(key(30, 85.0, 6.0)));
+    assert_eq!(key_at(&[], 5), None);
+}
+
+#[test]
+fn frames_follow_the_keys() {
+    let mut config = parse_config(&scene("focus_key 24 85 1.5\nfocus_key 0 35 8\n")).unwrap();
+    // Stills are the first frame.
+    assert_eq!(config.fov, key(0, 35.0, 8.0).fov());
+    assert_eq!(config.lens.unwrap().focus, 8.0);
+
+    config.focus_at(12);
+    let middle = key(12, 60.0, 4.75);
+    assert!((config.fov - middle.fov()).abs() < 1e-9);
+    assert_eq!(config.lens.unwrap().focus, middle.focus);
+    assert_eq!(config.lens.unwrap().radius, 0.01);
+
+    // Without keys, the header's field of view and the lens's focus are left be.
+    let mut config = parse_config(&scene("")).unwrap();
+    config.focus_at(12);
+    assert!(((2.0 * config.fov).to_degrees() - 40.0).abs() < 1e-9);
+    assert_eq!(config.lens.unwrap().focus, 5.0);
+}
+
+#[test]
+fn keys_are_saved_with_the_scene() {
+    let config = parse_config(&scene("focus_key 0 35 8\nfocus_key 24 85 1.5\n")).unwrap();
+    let exported = parse_config(&config.to_string()).unwrap();
+    assert_eq!(exported.focus_keys, config.focus_keys);
+    let cached = decode(&encode(&config, 0, &[]).unwrap(), 0).unwrap();
+    assert_eq!(cached.focus_keys, config.focus_keys);
+}
+
+#[test]
+fn bad_keys_are_refused() {
+    for keys in ["focus_key -1 50 2\n", "focus_key 0.5 50 2\n", "focus_key 0 0 2\n", "focus_key 0 50 0.05\n", "focus_key 3 50 2\nfocus_key 3 85 4\n"] {
+        assert!(parse_config(&scene(keys)).is_err(), "{:?} was taken", keys);
+    }
+}
@@ -0,0 +1,45 @@
+use graphics::config::parse_config;
+use graphics::linalg::Float;
+use graphics::profile::Profiler;
+use graphics::trace::make_image;
+
+// A white furnace: inside a sphere glowing evenly all over, anything that reflects all the
+// light reaching it looks just like the glow around it, so a white metal disappears
+// however rough it is, unless light is lost between its microfacets.
+
+const SIZE: usize = 32;
+
+/// The mean of the middle of the image, where a white metal ball of `roughness` is, over
+/// the mean of its edges, where only the glow is.
+fn furnace(roughness: Float) -> Float {
+    // The glowing sphere all but absorbs what reaches it, so every path sees the same
+    // glow however many bounces it has left.
+    let scene = format!(
+        "0 0 0\n0 1 0\n{size} {size}\n0.6\n3 256\n0\n1 1\n\
+         rgb(1,1,1) 127.5 opaque sphere 0 0 0 50\n\
+         white 0 mirror sphere 0 5 0 1.5 roughness={roughness}\n",
+        size = SIZE, roughness = roughness
+    );
+    let config = parse_config(&scene).unwrap();
+    let film = make_image(&config, &Profiler::new(false));
+    let pixels = film.pixels();
+
+    let mean = |inside: &dyn Fn(Float, Float) -> bool| {
+        let chosen: Vec<Float> = (0..SIZE * SIZE)
+            .filter(|i| inside((i % SIZE) as Float - SIZE as Float / 2.0, (i / SIZE) as Float - SIZE as Float / 2.0))
+            .map(|i| pixels[i].x + pixels[i].y + pixels[i].z)
+            .collect();
+        chosen.iter().sum::<Float>() / chosen.len() as Float
+    };
+    let middle = mean(&|x, y| x.hypot(y) < SIZE as Float / 6.0);
+    let edge = mean(&|x, y| x.abs().max(y.abs()) > SIZE as Float / 2.0 - 3.0);
+    middle / edge
+}
+
+#[test]
+fn rough_metals_keep_their_energy() {
+    for roughness in [0.0, 0.3, 0.6, 1.0] {
+        let ratio = furnace(roughness);
+        assert!((ratio - 1.0).abs() < 0.03, "a white metal of roughness {} reflects {:.3} of the light reaching it", roughness, ratio);
+    }
+}
@@ -0,0 +1,25 @@
+use std::path::Path;
+
+use graphics::golden::{check, scenes};
+
+// The references were rendered at double precision, which `f32` builds only come near.
+#[test]
+#[cfg_attr(feature = "f32", ignore)]
+fn golden_images_match() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden");
+    let scenes = scenes(&dir).unwrap();
+    assert!(!scenes.is_empty(), "no golden scenes in {}", dir.display());
+    let failures: Vec<String> = scenes.iter()
+        .filter_map(|scene| match check(scene, false) {
+            Ok(comparison) if comparison.matches() => None,
+            Ok(comparison) => Some(format!("{}: {}", scene.display(), comparison)),
+            Err(err) => Some(format!("{}: {:#}", scene.display(), err))
+        })
+        .collect();
+    assert!(
+        failures.is_empty(),
+        "{}\nIf the new images are right, update the references with \
+         `cargo run --release -- tests/golden --compare --update`",
+        failures.join("\n")
+    );
+}
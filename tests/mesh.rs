@@ -0,0 +1,142 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use graphics::linalg::{Float, Vector3};
+use graphics::mesh::{Accel, Mesh, MeshData, Model};
+use graphics::shapes::{Ray, Shape, Triangle};
+
+// Faces keep indices into their model's vertices and build triangles from them, so each
+// format is checked to share its vertices out the same way.
+
+/// A square folded along its diagonal, as two triangles sharing an edge, and a third
+/// with no area, which loading drops.
+const POSITIONS: [[f32; 3]; 5] = [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [1.0, 1.0, 0.5], [0.0, 1.0, 0.0], [2.0, 0.0, 0.0]];
+const NORMALS: [[f32; 3]; 5] = [[0.0, 0.0, 1.0], [0.0, -0.5, 1.0], [0.0, 0.0, 1.0], [0.5, 0.0, 1.0], [0.0, 0.0, 1.0]];
+const FACES: [[u32; 3]; 3] = [[0, 1, 2], [0, 2, 3], [0, 1, 4]];
+
+/// A fresh file `name` in a directory of this test's own.
+fn path(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("graphics-mesh-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    dir.join(name)
+}
+
+fn load(path: &Path) -> Vec<Triangle> {
+    let model = Model::load(path, Accel::default()).unwrap();
+    model.parts.iter().flat_map(|part| part.data.triangles()).collect()
+}
+
+fn obj() -> String {
+    let mut text = String::new();
+    for [x, y, z] in POSITIONS {
+        text += &format!("v {} {} {}\n", x, y, z);
+    }
+    for [x, y, z] in NORMALS {
+        text += &format!("vn {} {} {}\n", x, y, z);
+    }
+    for face in FACES {
+        let [a, b, c] = face.map(|i| i + 1);
+        text += &format!("f {0}//{0} {1}//{1} {2}//{2}\n", a, b, c);
+    }
+    text
+}
+
+fn ply(format: &str, big_endian: bool) -> Vec<u8> {
+    let mut bytes = format!(
+        "ply\nformat {} 1.0\nelement vertex {}\nproperty float x\nproperty float y\nproperty float z\n\
+         property float nx\nproperty float ny\nproperty float nz\nelement face {}\n\
+         property list uchar int vertex_indices\nend_header\n",
+        format, POSITIONS.len(), FACES.len()
+    ).into_bytes();
+    let float = |value: f32| if big_endian { value.to_be_bytes() } else { value.to_le_bytes() };
+    for (position, normal) in POSITIONS.iter().zip(NORMALS) {
+        for value in position.iter().chain(&normal) {
+            match format {
+                "ascii" => bytes.extend(format!("{} ", value).bytes()),
+                _ => bytes.extend(float(*value))
+            }
+        }
+        if format == "ascii" {
+            bytes.push(b'\n');
+        }
+    }
+    for face in FACES {
+        match format {
+            "ascii" => bytes.extend(format!("3 {} {} {}\n", face[0], face[1], face[2]).bytes()),
+            _ => {
+                bytes.push(3);
+                for index in face {
+                    bytes.extend(if big_endian { (index as i32).to_be_bytes() } else { (index as i32).to_le_bytes() });
+                }
+            }
+        }
+    }
+    bytes
+}
+
+fn stl() -> Vec<u8> {
+    let mut bytes = vec![0; 80];
+    bytes.extend((FACES.len() as u32).to_le_bytes());
+    for face in FACES {
+        bytes.extend([0; 12]);
+        for index in face {
+            bytes.extend(POSITIONS[index as usize].iter().flat_map(|value| value.to_le_bytes()));
+        }
+        bytes.extend([0; 2]);
+    }
+    bytes
+}
+
+#[test]
+fn formats_load_the_same_triangles() {
+    let obj_path = path("square.obj");
+    fs::write(&obj_path, obj()).unwrap();
+    let expected = load(&obj_path);
+    assert_eq!(expected.len(), 2, "the face without area should be dropped");
+    assert!(expected.iter().all(|triangle| triangle.normals().is_some()));
+
+    for (name, format, big_endian) in [("ascii.ply", "ascii", false), ("le.ply", "binary_little_endian", false), ("be.ply", "binary_big_endian", true)] {
+        let ply_path = path(name);
+        fs::write(&ply_path, ply(format, big_endian)).unwrap();
+        assert_eq!(load(&ply_path), expected, "{} loads differently", name);
+    }
+
+    let stl_path = path("square.stl");
+    fs::write(&stl_path, stl()).unwrap();
+    let flat: Vec<_> = expected.iter().map(|triangle| triangle.vertices()).collect();
+    assert_eq!(load(&stl_path).iter().map(Triangle::vertices).collect::<Vec<_>>(), flat);
+}
+
+#[test]
+fn saved_obj_loads_back() {
+    let obj_path = path("saved-from.obj");
+    fs::write(&obj_path, obj()).unwrap();
+    let model = Model::load(&obj_path, Accel::KdTree).unwrap();
+    let data: &MeshData = &model.parts[0].data;
+
+    let saved = path("saved.obj");
+    data.save_obj(&saved).unwrap();
+    assert_eq!(load(&saved), data.triangles().collect::<Vec<_>>());
+    // Only the four corners the kept faces use are written.
+    assert_eq!(fs::read_to_string(&saved).unwrap().lines().filter(|line| line.starts_with("v ")).count(), 4);
+}
+
+#[test]
+fn trees_test_faces_without_keeping_triangles() {
+    let obj_path = path("trees.obj");
+    fs::write(&obj_path, obj()).unwrap();
+    let mesh = |accel| {
+        let data = Model::load(&obj_path, accel).unwrap().parts.remove(0).data;
+        Mesh { data, file: None, offset: Vector3::new(0.0, 0.0, 0.0), scale: 1.0 }
+    };
+    let (bvh, kd) = (mesh(Accel::Bvh), mesh(Accel::KdTree));
+    for i in 0..100 {
+        let (x, y) = ((i % 10) as Float / 9.0, (i / 10) as Float / 9.0);
+        let ray = Ray::new(Vector3::new(x * 1.2 - 0.1, y * 1.2 - 0.1, 2.0), Vector3::new(0.0, 0.0, -1.0));
+        let (a, b) = (bvh.intersect(ray, Float::INFINITY), kd.intersect(ray, Float::INFINITY));
+        assert_eq!(a.map(|hit| (hit.t, hit.normal)), b.map(|hit| (hit.t, hit.normal)), "ray {} hits differently", i);
+    }
+    // Leaves are packed from the faces as they're reached, so the tree adds little to them.
+    let triangles = bvh.data.len() * std::mem::size_of::<Triangle>();
+    assert!(bvh.data.memory() < triangles, "{} bytes for {} of triangles", bvh.data.memory(), triangles);
+}
@@ -0,0 +1,84 @@
+use graphics::config::{parse_config, register_shape, ConfigError, Loader, ShapeArgs};
+use graphics::linalg::{Float, Vector3};
+use graphics::shapes::{Hit, Ray, Shape, Sphere};
+
+const HEADER: &str = "0 -5 1\n0 1 0\n8 8\n0.6\n4 1\n0\n1 1\n";
+
+/// A ball from its center and radius, for library shapes traced as spheres.
+fn ball(args: &[&str]) -> Result<Sphere, (usize, String)> {
+    let usage = "<x> <y> <z> <radius>";
+    let mut values = [0.0; 4];
+    for (i, value) in values.iter_mut().enumerate() {
+        *value = args.get(i).and_then(|arg| arg.parse().ok()).ok_or_else(|| (i, usage.to_string()))?;
+    }
+    if args.len() > 4 {
+        return Err((4, "end of line".to_string()));
+    }
+    Ok(Sphere { center: Vector3::new(values[0], values[1], values[2]), radius: values[3] })
+}
+
+/// A library's shape.
+struct Blob(Sphere);
+
+impl Shape for Blob {
+    fn intersect(&self, ray: Ray, t_max: Float) -> Option<Hit> {
+        self.0.intersect(ray, t_max)
+    }
+}
+
+impl ShapeArgs for Blob {
+    fn name() -> String {
+        "blob".to_string()
+    }
+
+    fn from_args(args: &[&str], _: &Loader) -> Result<Self, (usize, String)> {
+        ball(args).map(Blob)
+    }
+}
+
+/// Takes over the built-in `plane`.
+struct Flat(Sphere);
+
+impl Shape for Flat {
+    fn intersect(&self, ray: Ray, t_max: Float) -> Option<Hit> {
+        self.0.intersect(ray, t_max)
+    }
+}
+
+impl ShapeArgs for Flat {
+    fn name() -> String {
+        "plane".to_string()
+    }
+
+    fn from_args(args: &[&str], _: &Loader) -> Result<Self, (usize, String)> {
+        ball(args).map(Flat)
+    }
+}
+
+#[test]
+fn registered_shapes_can_be_named_by_object_lines() {
+    register_shape::<Blob>();
+    let config = parse_config(&format!("{}white 0 opaque blob 0 3 1 0.5 translate=1,0,0\n", HEADER)).unwrap();
+    assert_eq!(config.objects.len(), 1);
+    let hit = config.objects[0].intersect(Ray { pos: Vector3::new(1.0, 0.0, 1.0), dir: Vector3::new(0.0, 1.0, 0.0) }, Float::INFINITY);
+    assert!((hit.unwrap().t - 2.5).abs() < 1e-6);
+
+    // The error points at the word the shape says is wrong.
+    let line = "white 0 opaque blob 0 3 oops 0.5";
+    match parse_config(&format!("{}{}\n", HEADER, line)) {
+        Err(ConfigError::InvalidShape(err)) => {
+            assert_eq!(err.column, line.find("oops").unwrap() + 1);
+            assert_eq!(err.found, "\"oops\"");
+        }
+        other => panic!("expected a shape error, got {:?}", other.map(|_| ()))
+    }
+}
+
+#[test]
+fn registering_a_built_in_name_replaces_it() {
+    let line = "white 0 opaque plane 0 3 1 0.5\n";
+    assert!(parse_config(&format!("{}{}", HEADER, line)).is_err(), "the built-in plane takes six numbers");
+    register_shape::<Flat>();
+    let config = parse_config(&format!("{}{}", HEADER, line)).unwrap();
+    assert!(config.objects[0].shape.params().is_none());
+}
@@ -0,0 +1,30 @@
+// Renders scenes in the browser with the tracer built for WebAssembly:
+//
+//     cargo build --lib --release --target wasm32-unknown-unknown
+//
+// which makes target/wasm32-unknown-unknown/release/graphics.wasm. Scenes are given as
+// the text of a scene file; meshes and other files can't be loaded. Rendering blocks the
+// thread it runs on, so pages should call it from a worker.
+
+export async function loadTracer(url = "graphics.wasm") {
+    const { instance } = await WebAssembly.instantiateStreaming(fetch(url), {});
+    const wasm = instance.exports;
+    const bytes = (ptr, len) => new Uint8Array(wasm.memory.buffer, ptr, len);
+
+    return {
+        // The scene rendered as an ImageData, or an Error saying what's wrong with it.
+        render(scene) {
+            const text = new TextEncoder().encode(scene);
+            const ptr = wasm.rt_alloc(text.length);
+            bytes(ptr, text.length).set(text);
+            const pixels = wasm.render(ptr, text.length);
+            wasm.rt_free(ptr, text.length);
+            if (pixels === 0) {
+                throw new Error(new TextDecoder().decode(bytes(wasm.error(), wasm.error_len())));
+            }
+            const width = wasm.width(), height = wasm.height();
+            const rgba = new Uint8ClampedArray(wasm.memory.buffer, pixels, width * height * 4).slice();
+            return new ImageData(rgba, width, height);
+        }
+    };
+}